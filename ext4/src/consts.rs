@@ -17,6 +17,11 @@ pub const EXT_UNWRITTEN_MAX_LEN: u16 = 65535;
 
 pub const EXT4_GOOD_OLD_INODE_SIZE: u16 = 128;
 
+/// 块组的inode表/位图还没有初始化
+pub const EXT4_BG_INODE_UNINIT: u16 = 0x0001;
+/// 块组的块位图还没有初始化
+pub const EXT4_BG_BLOCK_UNINIT: u16 = 0x0002;
+
 pub const EXT4_INODE_MODE_FIFO: usize = 0x1000;
 pub const EXT4_INODE_MODE_CHARDEV: usize = 0x2000;
 pub const EXT4_INODE_MODE_DIRECTORY: usize = 0x4000;
@@ -31,6 +36,34 @@ pub const EXT_MAX_BLOCKS: Ext4Lblk = core::u32::MAX;
 
 pub const EXT4_SUPERBLOCK_OS_HURD: u32 = 1;
 
+pub const EXT4_FEATURE_COMPAT_HAS_JOURNAL: u32 = 0x0004;
+/// 日志中有还没写回原位置的事务
+pub const EXT4_FEATURE_INCOMPAT_RECOVER: u32 = 0x0004;
+
+/// jbd2日志块的魔数, 日志中的数值都是大端序
+pub const JBD2_MAGIC_NUMBER: u32 = 0xC03B3998;
+
+pub const JBD2_DESCRIPTOR_BLOCK: u32 = 1;
+pub const JBD2_COMMIT_BLOCK: u32 = 2;
+pub const JBD2_SUPERBLOCK_V1: u32 = 3;
+pub const JBD2_SUPERBLOCK_V2: u32 = 4;
+pub const JBD2_REVOKE_BLOCK: u32 = 5;
+
+/// 描述符块中tag的标志
+pub const JBD2_FLAG_ESCAPE: u16 = 1;
+pub const JBD2_FLAG_SAME_UUID: u16 = 2;
+pub const JBD2_FLAG_LAST_TAG: u16 = 8;
+
+pub const JBD2_FEATURE_INCOMPAT_REVOKE: u32 = 0x1;
+pub const JBD2_FEATURE_INCOMPAT_64BIT: u32 = 0x2;
+pub const JBD2_FEATURE_INCOMPAT_CSUM_V2: u32 = 0x8;
+pub const JBD2_FEATURE_INCOMPAT_CSUM_V3: u32 = 0x10;
+
+/// 一个只修改少量元数据的事务预留的日志块数
+pub const EXT4_TRANS_CREDITS: usize = 32;
+/// 写入文件时每这么多个块再预留一个日志块, 用于extent节点分裂和位图
+pub const EXT4_WRITE_BLOCKS_PER_CREDIT: usize = 64;
+
 /// Maximum bytes in a path
 pub const PATH_MAX: usize = 4096;
 
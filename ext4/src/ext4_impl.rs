@@ -19,21 +19,17 @@ use crate::BLOCK_SIZE;
 use crate::Ext4;
 
 impl Ext4 {
+    /// 分配一个inode, 没有空闲inode时`index`为0
     pub fn ext4_ialloc_alloc_inode(&self, index: &mut u32, is_dir: bool) {
         log::trace!("ext4_ialloc_alloc_inode");
-        let mut bgid = self.last_inode_bg_id;
-        let bg_count = self.super_block.block_groups_count();
-
-        while bgid <= bg_count {
-            if bgid == bg_count {
-                bgid = 0;
-                continue;
-            }
-
-            let block_device = self.block_device.clone();
+        let block_device = self.block_device.clone();
+        let raw_data = self.block_device.read_offset(BASE_OFFSET);
+        let mut super_block = Ext4Superblock::try_from(raw_data).unwrap();
+        let bg_count = super_block.block_groups_count();
 
-            let raw_data = self.block_device.read_offset(BASE_OFFSET);
-            let mut super_block = Ext4Superblock::try_from(raw_data).unwrap();
+        *index = 0;
+        for i in 0..bg_count {
+            let bgid = (self.last_inode_bg_id + i) % bg_count;
 
             let mut bg =
                 Ext4BlockGroup::load(block_device.clone(), &super_block, bgid as usize).unwrap();
@@ -41,66 +37,73 @@ impl Ext4 {
             let mut free_inodes = bg.get_free_inodes_count();
             let mut used_dirs = bg.get_used_dirs_count(&super_block);
 
-            if free_inodes > 0 {
-                let inode_bitmap_block = bg.get_inode_bitmap_block(&super_block);
-
-                let mut raw_data = self
-                    .block_device
-                    .read_offset(inode_bitmap_block as usize * BLOCK_SIZE);
+            if free_inodes == 0 {
+                continue;
+            }
 
-                let inodes_in_bg = super_block.get_inodes_in_group_cnt(bgid);
+            let inode_bitmap_block = bg.get_inode_bitmap_block(&super_block);
 
-                // let bitmap_size: u32 = inodes_in_bg / 0x8;
+            let inodes_in_bg = super_block.get_inodes_in_group_cnt(bgid);
 
-                let mut bitmap_data = &mut raw_data[..];
+            // 未初始化的块组的inode位图在磁盘上可能是垃圾, 视为全空
+            let mut raw_data = if bg.flags & EXT4_BG_INODE_UNINIT != 0 {
+                bg.flags &= !EXT4_BG_INODE_UNINIT;
+                let mut data = vec![0u8; BLOCK_SIZE];
+                // 位图中超出inode数量的部分置1
+                for bit in inodes_in_bg..(BLOCK_SIZE * 8) as u32 {
+                    ext4_bmap_bit_set(&mut data, bit);
+                }
+                data
+            } else {
+                self.block_device
+                    .read_offset(inode_bitmap_block as usize * BLOCK_SIZE)
+            };
 
-                let mut idx_in_bg = 0 as u32;
+            let bitmap_data = &mut raw_data[..];
 
-                // log::info!("bitmap {:x?}", bitmap_data);
-                ext4_bmap_bit_find_clr(bitmap_data, 0, inodes_in_bg, &mut idx_in_bg);
-                ext4_bmap_bit_set(&mut bitmap_data, idx_in_bg);
+            let mut idx_in_bg = 0 as u32;
 
-                // update bitmap in disk
-                self.block_device
-                    .write_offset(inode_bitmap_block as usize * BLOCK_SIZE, &bitmap_data);
+            if !ext4_bmap_bit_find_clr(bitmap_data, 0, inodes_in_bg, &mut idx_in_bg) {
+                continue;
+            }
+            ext4_bmap_bit_set(bitmap_data, idx_in_bg);
 
-                bg.set_block_group_ialloc_bitmap_csum(&super_block, &bitmap_data);
+            // update bitmap in disk
+            self.block_device
+                .write_offset(inode_bitmap_block as usize * BLOCK_SIZE, &bitmap_data);
 
-                /* Modify filesystem counters */
-                free_inodes -= 1;
-                bg.set_free_inodes_count(&super_block, free_inodes);
+            bg.set_block_group_ialloc_bitmap_csum(&super_block, &bitmap_data);
 
-                /* Increment used directories counter */
-                if is_dir {
-                    used_dirs += 1;
-                    bg.set_used_dirs_count(&super_block, used_dirs);
-                }
+            /* Modify filesystem counters */
+            free_inodes -= 1;
+            bg.set_free_inodes_count(&super_block, free_inodes);
 
-                /* Decrease unused inodes count */
-                let mut unused = bg.get_itable_unused(&super_block);
-                let free = inodes_in_bg - unused as u32;
-                if idx_in_bg >= free {
-                    unused = inodes_in_bg - (idx_in_bg + 1);
-                    bg.set_itable_unused(&super_block, unused);
-                }
+            /* Increment used directories counter */
+            if is_dir {
+                used_dirs += 1;
+                bg.set_used_dirs_count(&super_block, used_dirs);
+            }
 
-                bg.sync_to_disk_with_csum(block_device.clone(), bgid as usize, &super_block);
-                // bg.sync_block_group_to_disk(block_device.clone(), bgid as usize, &super_block);
+            /* Decrease unused inodes count */
+            let mut unused = bg.get_itable_unused(&super_block);
+            let free = inodes_in_bg - unused as u32;
+            if idx_in_bg >= free {
+                unused = inodes_in_bg - (idx_in_bg + 1);
+                bg.set_itable_unused(&super_block, unused);
+            }
 
-                /* Update superblock */
-                super_block.decrease_free_inodes_count();
-                // super_block.sync_super_block_to_disk(block_device.clone());
+            bg.sync_to_disk_with_csum(block_device.clone(), bgid as usize, &super_block);
 
-                /* Compute the absolute i-nodex number */
-                let inodes_per_group = super_block.inodes_per_group();
-                let inode_num = bgid * inodes_per_group + (idx_in_bg + 1);
-                *index = inode_num;
+            /* Update superblock */
+            super_block.decrease_free_inodes_count();
+            super_block.sync_to_disk_with_csum(block_device.clone());
 
-                // log::info!("alloc inode {:x?}", inode_num);
-                return;
-            }
+            /* Compute the absolute i-nodex number */
+            let inodes_per_group = super_block.inodes_per_group();
+            let inode_num = bgid * inodes_per_group + (idx_in_bg + 1);
+            *index = inode_num;
 
-            bgid += 1;
+            return;
         }
         log::info!("no free inode");
     }
@@ -129,6 +132,9 @@ impl Ext4 {
         );
         /* Add entry to parent directory */
         let r = self.ext4_dir_add_entry(parent, child, name, name_len);
+        if r != EOK {
+            return r;
+        }
 
         /* Fill new dir -> add '.' and '..' entries.
          * Also newly allocated inode should have 0 link count.
@@ -148,9 +154,9 @@ impl Ext4 {
             child_inode_ref.inner.inode = child.inner.inode.clone();
 
             let r = self.ext4_dir_add_entry(&mut child_inode_ref, child, ".", 1);
-            child.inner.inode.size = child_inode_ref.inner.inode.size;
-            child.inner.inode.block = child_inode_ref.inner.inode.block;
             let r = self.ext4_dir_add_entry(&mut child_inode_ref, parent, "..", 2);
+            // 新目录块的映射, 大小和块数都记在child_inode_ref中
+            child.inner.inode = child_inode_ref.inner.inode;
 
             child.inner.inode.links_count = 2;
             parent.inner.inode.links_count += 1;
@@ -228,10 +234,14 @@ impl Ext4 {
 
         // ext4_fs_append_inode_dblk(parent, &mut (iblock as u32), &mut fblock);
         parent.append_inode_dblk(&mut (iblock as u32), &mut fblock);
+        if fblock == 0 {
+            return ENOSPC;
+        }
 
         /* Load new block */
+        // 新分配的块中可能有被释放的文件留下的数据
         let block_device = self.block_device.clone();
-        let mut data = block_device.read_offset(fblock as usize * BLOCK_SIZE);
+        let mut data = vec![0u8; BLOCK_SIZE];
         let mut ext4_block = Ext4Block {
             logical_block_id: iblock,
             disk_block_id: fblock,
@@ -267,55 +277,61 @@ impl Ext4 {
         name_len: u32,
     ) -> usize {
         log::trace!("dir_try_insert_entry");
-        let mut required_len = core::mem::size_of::<Ext4DirEntry>() + name_len as usize;
+        let mut required_len = size_of::<Ext4FakeDirEntry>() + name_len as usize;
 
         if required_len % 4 != 0 {
             required_len += 4 - required_len % 4;
         }
 
         let mut offset = 0;
+        let tail_offset = BLOCK_SIZE - size_of::<Ext4DirEntryTail>();
 
-        while offset < dst_blk.block_data.len() {
+        while offset < tail_offset {
             let mut de = Ext4DirEntry::try_from(&dst_blk.block_data[offset..]).unwrap();
-            if de.inode == 0 {
-                continue;
+            let rec_len = de.entry_len as usize;
+            if rec_len == 0 {
+                break;
             }
-            let inode = de.inode;
-            let rec_len = de.entry_len;
 
-            // 如果是有效的目录项，尝试分割它
-            if inode != 0 {
-                let used_len = de.name_len as usize;
-                let mut sz = core::mem::size_of::<Ext4FakeDirEntry>() + used_len as usize;
-
-                if used_len % 4 != 0 {
-                    sz += 4 - used_len % 4;
+            // 空闲的目录项(被删除的块首目录项)可以直接复用
+            let sz = if de.inode == 0 {
+                0
+            } else {
+                let mut sz = size_of::<Ext4FakeDirEntry>() + de.name_len as usize;
+                if sz % 4 != 0 {
+                    sz += 4 - sz % 4;
                 }
+                sz
+            };
 
-                let free_space = rec_len as usize - sz;
-
-                // 如果有足够的空闲空间
-                if free_space >= required_len {
-                    let mut new_entry = Ext4DirEntry::default();
-
+            // 如果有足够的空闲空间, 分割它
+            if rec_len >= sz + required_len {
+                let mut new_entry = Ext4DirEntry::default();
+                self.dir_write_entry(
+                    &mut new_entry,
+                    (rec_len - sz) as u16,
+                    &child,
+                    name,
+                    name_len,
+                );
+
+                // update parent_de and new_de to blk_data
+                if sz != 0 {
                     de.entry_len = sz as u16;
-                    self.dir_write_entry(&mut new_entry, free_space as u16, &child, name, name_len);
-
-                    // update parent_de and new_de to blk_data
                     de.copy_to_slice(&mut dst_blk.block_data, offset);
-                    new_entry.copy_to_slice(&mut dst_blk.block_data, offset + sz);
+                }
+                new_entry.copy_to_slice(&mut dst_blk.block_data, offset + sz);
 
-                    // set tail csum
-                    parent.ext4_dir_set_csum(dst_blk);
+                // set tail csum
+                parent.ext4_dir_set_csum(dst_blk);
 
-                    // sync to disk
-                    let block_device = self.block_device.clone();
-                    dst_blk.sync_blk_to_disk(block_device.clone());
+                // sync to disk
+                let block_device = self.block_device.clone();
+                dst_blk.sync_blk_to_disk(block_device.clone());
 
-                    return EOK;
-                }
+                return EOK;
             }
-            offset = offset + de.entry_len as usize;
+            offset += rec_len;
         }
 
         ENOSPC
@@ -441,16 +457,13 @@ impl Ext4 {
         let mut last_de_offset = 0;
         while offset < block.block_data.len() - core::mem::size_of::<Ext4DirEntryTail>() {
             let de = Ext4DirEntry::try_from(&block.block_data[offset..]).unwrap();
-
-            if de.inode == 0 {
-                continue;
+            if de.entry_len == 0 {
+                break;
             }
 
-            let s = get_name(de.name, de.name_len as usize);
-
-            if let Ok(s) = s {
-                if name_len == de.name_len as u32 {
-                    if name.to_string() == s {
+            if de.inode != 0 && name_len == de.name_len as u32 {
+                if let Ok(s) = get_name(de.name, de.name_len as usize) {
+                    if name == s {
                         result.dentry = de;
                         result.offset = offset;
                         result.last_offset = last_de_offset;
@@ -474,7 +487,10 @@ impl Ext4 {
         let mut offset = 0;
         while offset < BLOCK_SIZE - core::mem::size_of::<Ext4DirEntryTail>() {
             let de = Ext4DirEntry::try_from(&block.block_data[offset..]).unwrap();
-            if !de.unused() && de.compare_name(name) {
+            if de.entry_len() == 0 {
+                break;
+            }
+            if !de.unused() && de.get_name_len() == name.len() && de.compare_name(name) {
                 return Ok(de);
             }
             offset += de.entry_len() as usize;
@@ -524,11 +540,11 @@ impl Ext4 {
     }
 
     fn ext4_ialloc_get_bgid_of_inode(&self, inode_index: u32) -> u32 {
-        inode_index / self.super_block.inodes_per_group()
+        (inode_index - 1) / self.super_block.inodes_per_group()
     }
 
     fn ext4_ialloc_inode_to_bgidx(&self, inode_index: u32) -> u32 {
-        inode_index % self.super_block.inodes_per_group()
+        (inode_index - 1) % self.super_block.inodes_per_group()
     }
 
     pub fn ext4_ialloc_free_inode(&self, index: u32, is_dir: bool) {
@@ -536,12 +552,12 @@ impl Ext4 {
         let bgid = self.ext4_ialloc_get_bgid_of_inode(index);
         let block_device = self.block_device.clone();
         let raw_data = self.block_device.read_offset(BASE_OFFSET);
-        let super_block = Ext4Superblock::try_from(raw_data).unwrap();
+        let mut super_block = Ext4Superblock::try_from(raw_data).unwrap();
         let mut bg =
             Ext4BlockGroup::load(block_device.clone(), &super_block, bgid as usize).unwrap();
 
         // Load inode bitmap block
-        let inode_bitmap_block = bg.get_inode_bitmap_block(&self.super_block);
+        let inode_bitmap_block = bg.get_inode_bitmap_block(&super_block);
         let mut bitmap_data = self
             .block_device
             .read_offset(inode_bitmap_block as usize * BLOCK_SIZE);
@@ -558,19 +574,22 @@ impl Ext4 {
 
         // Update free inodes count in block group
         let free_inodes = bg.get_free_inodes_count() + 1;
-        bg.set_free_inodes_count(&self.super_block, free_inodes);
+        bg.set_free_inodes_count(&super_block, free_inodes);
 
         // If inode was a directory, decrement the used directories count
         if is_dir {
-            let used_dirs = bg.get_used_dirs_count(&self.super_block) - 1;
-            bg.set_used_dirs_count(&self.super_block, used_dirs);
+            let used_dirs = bg.get_used_dirs_count(&super_block) - 1;
+            bg.set_used_dirs_count(&super_block, used_dirs);
         }
 
         bg.sync_to_disk_with_csum(block_device.clone(), bgid as usize, &super_block);
-        // bg.sync_block_group_to_disk(block_device.clone(), bgid as usize, &super_block);
+
+        super_block.increase_free_inodes_count();
+        super_block.sync_to_disk_with_csum(block_device);
     }
 
-    #[allow(unused)]
+    /// 删除`parent`中名为`name`的目录项并减少`child`的链接数, 不释放inode.
+    /// 目录的`.`和父目录中的项同时失效, 链接数直接归零
     pub fn ext4_unlink(
         &self,
         parent: &mut Ext4InodeRef,
@@ -579,13 +598,27 @@ impl Ext4 {
         name_len: u32,
     ) -> usize {
         /* Remove entry from parent directory */
-        self.ext4_dir_remove_entry_new(parent, name, name_len);
+        if self.ext4_dir_remove_entry_new(parent, name, name_len) != EOK {
+            return ENOENT;
+        }
 
-        self.ext4_ialloc_free_inode(child.inode_num, false);
+        if child.is_dir() {
+            // 子目录的`..`不再指向父目录
+            let links = parent.inner.inode.ext4_inode_get_links_cnt();
+            parent.inner.inode.ext4_inode_set_links_cnt(links.saturating_sub(1));
+            parent.write_back_inode();
+            child.inner.inode.ext4_inode_set_links_cnt(0);
+        } else {
+            let links = child.inner.inode.ext4_inode_get_links_cnt();
+            child.inner.inode.ext4_inode_set_links_cnt(links.saturating_sub(1));
+        }
+        child.write_back_inode();
 
         EOK
     }
-    pub fn ext4_dir_remove_entry_new(&self, parent: &mut Ext4InodeRef, path: &str, len: u32) {
+
+    /// 删除目录项. 块中第一个目录项只标记为空闲, 其余的并入前一个目录项
+    pub fn ext4_dir_remove_entry_new(&self, parent: &mut Ext4InodeRef, path: &str, len: u32) -> usize {
         let mut data: Vec<u8> = Vec::with_capacity(BLOCK_SIZE);
         let ext4_blk = Ext4Block {
             logical_block_id: 0,
@@ -596,14 +629,15 @@ impl Ext4 {
         let de = Ext4DirEntry::default();
         let mut dir_search_result = Ext4DirSearchResult::new(ext4_blk, de);
 
-        let _r = self.dir_find_entry_new(
+        let r = self.dir_find_entry_new(
             parent,
             &path[..len as usize],
             len as u32,
             &mut dir_search_result,
         );
-
-        dir_search_result.dentry.inode = 0;
+        if r.is_err() {
+            return ENOENT;
+        }
 
         // load_block
         let mut data = parent
@@ -617,23 +651,66 @@ impl Ext4 {
             dirty: false,
         };
 
-        let mut pde =
-            Ext4DirEntry::from_u8(&mut ext4_block.block_data[dir_search_result.last_offset..]);
-
-        let de_del =
+        let mut de_del =
             Ext4DirEntry::from_u8(&mut ext4_block.block_data[dir_search_result.offset..]);
 
-        pde.entry_len += de_del.entry_len;
+        if dir_search_result.offset == 0 {
+            de_del.set_unused();
+            de_del.copy_to_slice(&mut ext4_block.block_data, 0);
+        } else {
+            let mut pde =
+                Ext4DirEntry::from_u8(&mut ext4_block.block_data[dir_search_result.last_offset..]);
+            pde.entry_len += de_del.entry_len;
+            pde.copy_to_slice(&mut ext4_block.block_data, dir_search_result.last_offset);
+        }
+
+        parent.ext4_dir_set_csum(&mut ext4_block);
+        ext4_block.sync_blk_to_disk(self.block_device.clone());
+        EOK
+    }
 
-        let tmp_de_ptr = &pde as *const _ as *const u8;
-        let tmp_de_slice = unsafe {
-            core::slice::from_raw_parts(tmp_de_ptr, core::mem::size_of::<Ext4DirEntry>())
+    /// 把目录项`name`原地改为指向`child`, 目录项长度和文件名不变
+    pub fn ext4_dir_set_entry(
+        &self,
+        parent: &mut Ext4InodeRef,
+        name: &str,
+        child: &Ext4InodeRef,
+    ) -> usize {
+        let mut data: Vec<u8> = Vec::with_capacity(BLOCK_SIZE);
+        let ext4_blk = Ext4Block {
+            logical_block_id: 0,
+            disk_block_id: 0,
+            block_data: &mut data,
+            dirty: true,
         };
-        ext4_block.block_data[dir_search_result.last_offset
-            ..dir_search_result.last_offset + core::mem::size_of::<Ext4DirEntry>()]
-            .copy_from_slice(tmp_de_slice);
+        let de = Ext4DirEntry::default();
+        let mut dir_search_result = Ext4DirSearchResult::new(ext4_blk, de);
+
+        let r = self.dir_find_entry_new(parent, name, name.len() as u32, &mut dir_search_result);
+        if r.is_err() {
+            return ENOENT;
+        }
+
+        // load_block
+        let mut data = parent
+            .fs()
+            .block_device
+            .read_offset(dir_search_result.block_id as usize * BLOCK_SIZE);
+        let mut ext4_block = Ext4Block {
+            logical_block_id: 0,
+            disk_block_id: dir_search_result.block_id as u64,
+            block_data: &mut data,
+            dirty: false,
+        };
+
+        let offset = dir_search_result.offset;
+        let mut de = Ext4DirEntry::from_u8(&mut ext4_block.block_data[offset..]);
+        let entry_len = de.entry_len;
+        self.dir_write_entry(&mut de, entry_len, child, name, name.len() as u32);
+        de.copy_to_slice(&mut ext4_block.block_data, offset);
 
         parent.ext4_dir_set_csum(&mut ext4_block);
         ext4_block.sync_blk_to_disk(self.block_device.clone());
+        EOK
     }
 }
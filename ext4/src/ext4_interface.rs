@@ -6,6 +6,7 @@ use core::*;
 
 use crate::consts::*;
 use crate::ext4_structs::*;
+use crate::jbd2::*;
 use crate::prelude::*;
 use crate::return_errno_with_message;
 use crate::utils::*;
@@ -24,6 +25,8 @@ pub trait Jbd2: Send + Sync + Any + Debug {
 pub trait BlockDevice: Send + Sync + Any {
     fn read_offset(&self, offset: usize) -> Vec<u8>;
     fn write_offset(&self, offset: usize, data: &[u8]);
+    /// 把缓存中的写入落到磁盘上, 日志用它保证写入顺序
+    fn flush(&self) {}
 }
 
 // impl dyn BlockDevice {
@@ -34,6 +37,8 @@ pub trait BlockDevice: Send + Sync + Any {
 
 pub struct Ext4 {
     pub block_device: Arc<dyn BlockDevice>,
+    /// `block_device`带日志的视图, 用于开始和结束事务
+    pub journal: Arc<JournalDevice>,
     pub super_block: Ext4Superblock,
     pub block_groups: Vec<Ext4BlockGroup>,
     pub inodes_per_group: u32,
@@ -49,6 +54,53 @@ impl Ext4 {
     #[allow(unused)]
     /// Opens and loads an Ext4 from the `block_device`.
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Self> {
+        let journal = Arc::new(JournalDevice::new(block_device));
+        let ext4 = Self::load(journal.clone());
+        let jbd2 = match ext4.ext4_load_journal() {
+            Some(jbd2) => jbd2,
+            None => return ext4,
+        };
+        drop(ext4);
+
+        // 上次没有正常卸载时日志中可能有已经提交但没有写回的事务, 重放后重新加载
+        let mut jbd2 = jbd2;
+        jbd2.recover();
+        jbd2.journal_start();
+        journal.set_journal(jbd2);
+        Self::load(journal)
+    }
+
+    /// 读取日志inode, 文件系统没有日志或者日志格式不支持时返回None
+    fn ext4_load_journal(&self) -> Option<Jbd2Journal> {
+        let journal_ino = self.super_block.journal_inode_number();
+        if !self.super_block.has_journal() || journal_ino == 0 {
+            return None;
+        }
+        let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), journal_ino);
+        if inode_ref.inner.inode.flags & EXT4_INODE_FLAG_EXTENTS as u32 == 0 {
+            log::warn!("[Ext4] journal inode without extents is not supported");
+            return None;
+        }
+        let blocks_count = inode_ref.inner.inode.inode_get_size() as usize / BLOCK_SIZE;
+        let blocks: Vec<u64> = (0..blocks_count)
+            .map(|lblk| inode_ref.get_pblock(&mut (lblk as u32)))
+            .collect();
+        if blocks.is_empty() || blocks.contains(&0) {
+            log::warn!("[Ext4] journal inode is not fully mapped");
+            return None;
+        }
+
+        let jbd2 = Jbd2Journal::new(self.journal.device(), blocks);
+        if !jbd2.is_valid() {
+            log::warn!("[Ext4] unsupported journal superblock");
+            return None;
+        }
+        Some(jbd2)
+    }
+
+    #[allow(unused)]
+    fn load(journal: Arc<JournalDevice>) -> Arc<Self> {
+        let block_device: Arc<dyn BlockDevice> = journal.clone();
         // Load the superblock
         // TODO: if the main superblock is corrupted, should we load the backup?
         let raw_data = block_device.read_offset(BASE_OFFSET);
@@ -81,6 +133,7 @@ impl Ext4 {
             inode_size: inode_size as usize,
             block_groups: load_block_groups(weak_ref.clone(), block_device.clone()).unwrap(),
             block_device,
+            journal,
             self_ref: weak_ref.clone(),
             mount_point: mount_point,
             last_inode_bg_id: 0,
@@ -102,11 +155,48 @@ impl Ext4 {
         }
     }
 
-    // start transaction
-    pub fn ext4_trans_start(&self) {}
+    /// 开始事务. 事务可以嵌套, 最外层的事务结束时才提交. 事务进行中其他执行流的事务
+    /// 等待它结束. 日志放不下预留的块数时返回ENOSPC
+    pub fn ext4_trans_start(&self) -> Result<usize> {
+        self.journal.trans_start(EXT4_TRANS_CREDITS)
+    }
 
-    // stop transaction
-    pub fn ext4_trans_abort(&self) {}
+    /// 结束并提交事务, 修改超出日志容量时整个放弃并返回ENOSPC
+    pub fn ext4_trans_stop(&self) -> Result<usize> {
+        self.journal.trans_stop()
+    }
+
+    /// 放弃事务, 最外层事务中的修改都不会写入磁盘
+    pub fn ext4_trans_abort(&self) {
+        self.journal.trans_abort();
+    }
+
+    /// 根据操作的结果结束`ext4_trans_start`开始的事务
+    fn ext4_trans_finish<T>(&self, r: Result<T>) -> Result<T> {
+        match r {
+            Ok(v) => self.ext4_trans_stop().map(|_| v),
+            Err(e) => {
+                self.ext4_trans_abort();
+                Err(e)
+            }
+        }
+    }
+
+    /// 在一个事务中执行`f`, 成功时提交, 失败时放弃
+    pub fn ext4_transaction<T>(&self, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        self.ext4_transaction_credits(EXT4_TRANS_CREDITS, f)
+    }
+
+    /// 同`ext4_transaction`, 预留`credits`个日志块
+    fn ext4_transaction_credits<T>(
+        &self,
+        credits: usize,
+        f: impl FnOnce() -> Result<T>,
+    ) -> Result<T> {
+        self.journal.trans_start(credits)?;
+        let r = f();
+        self.ext4_trans_finish(r)
+    }
 
     pub fn update_super_block(&mut self) {
         let raw_data = self.block_device.read_offset(BASE_OFFSET);
@@ -140,13 +230,17 @@ impl Ext4 {
         }
 
         if iflags & O_CREAT != 0 {
-            self.ext4_trans_start();
+            self.ext4_trans_start()?;
         }
 
         let mut root_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), 2);
 
         let r = self.ext4_generic_open(file, path, iflags, filetype.bits(), &mut root_inode_ref);
 
+        if iflags & O_CREAT != 0 {
+            return self.ext4_trans_finish(r);
+        }
+
         r
     }
 
@@ -178,7 +272,7 @@ impl Ext4 {
         iflags = self.ext4_parse_flags(flags).unwrap();
 
         if iflags & O_CREAT != 0 {
-            self.ext4_trans_start();
+            self.ext4_trans_start()?;
         }
 
         let mut root_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), 2);
@@ -192,6 +286,10 @@ impl Ext4 {
         );
 
         // log::info!("dir mk done");
+        if iflags & O_CREAT != 0 {
+            return self.ext4_trans_finish(r);
+        }
+
         r
     }
 
@@ -291,7 +389,6 @@ impl Ext4 {
 
                 self.ext4_fs_put_inode_ref_csum(&mut search_parent);
                 self.ext4_fs_put_inode_ref_csum(&mut child_inode_ref);
-                self.ext4_fs_put_inode_ref_csum(parent_inode);
 
                 continue;
             }
@@ -413,7 +510,6 @@ impl Ext4 {
 
                 self.ext4_fs_put_inode_ref_csum(&mut current_inode_ref);
                 self.ext4_fs_put_inode_ref_csum(&mut new_inode_ref);
-                self.ext4_fs_put_inode_ref_csum(parent_inode);
 
                 current_inode_ref = new_inode_ref; // Continue with the new inode
                 continue;
@@ -466,7 +562,7 @@ impl Ext4 {
         }
 
         if iflags & O_CREAT != 0 {
-            self.ext4_trans_start();
+            self.ext4_trans_start()?;
         }
 
         let mut root_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), 2);
@@ -480,6 +576,10 @@ impl Ext4 {
             &mut root_inode_ref,
             &mut name_off,
         );
+        if iflags & O_CREAT != 0 {
+            return self.ext4_trans_finish(r);
+        }
+
         r
     }
 
@@ -632,79 +732,90 @@ impl Ext4 {
         return Ok(EOK);
     }
 
-    // pub fn ext4_file_write(&self, ext4_file: &mut Ext4File, data: &[u8], size: usize) {
-    //     let super_block_data = self.block_device.read_offset(BASE_OFFSET);
-    //     let super_block = Ext4Superblock::try_from(super_block_data).unwrap();
-    //     let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ext4_file.inode);
-    //     let block_size = super_block.block_size() as usize;
-    //     let iblock_last = ext4_file.fpos as usize + size / block_size;
-    //     let mut iblk_idx = ext4_file.fpos as usize / block_size;
-    //     let ifile_blocks = ext4_file.fsize as usize + block_size - 1 / block_size;
-    // }
-
-    pub fn ext4_file_write(&self, ext4_file: &mut Ext4File, data: &[u8], size: usize) {
-        let super_block_data = self.block_device.read_offset(BASE_OFFSET);
-        let super_block = Ext4Superblock::try_from(super_block_data).unwrap();
-        let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ext4_file.inode);
-        let block_size = super_block.block_size() as usize;
-        let iblock_last = ext4_file.fpos as usize + size / block_size;
-        let mut iblk_idx = ext4_file.fpos as usize / block_size;
-        let ifile_blocks = ext4_file.fsize as usize + block_size - 1 / block_size;
-
-        let mut fblk = 0;
-        let mut fblock_start = 0;
-        let mut fblock_count = 0;
-
-        let mut size = size;
-        while size >= block_size {
-            while iblk_idx < iblock_last {
-                if iblk_idx < ifile_blocks {
-                    // ext4_fs_append_inode_dblk(&mut inode_ref, &mut (iblk_idx as u32), &mut fblk);
-                    inode_ref.append_inode_dblk(&mut (iblk_idx as u32), &mut fblk);
-                }
+    /// 从`ext4_file.fpos`开始写入`data`的前`size`字节, 没有映射的块在写入时分配,
+    /// 跳过的块保持为空洞. 文件变长时更新i_size, 返回实际写入的字节数
+    pub fn ext4_file_write(
+        &self,
+        ext4_file: &mut Ext4File,
+        data: &[u8],
+        size: usize,
+    ) -> Result<usize> {
+        let size = core::cmp::min(size, data.len());
+        let blocks = (ext4_file.fpos % BLOCK_SIZE + size).div_ceil(BLOCK_SIZE);
+        let credits = EXT4_TRANS_CREDITS + blocks / EXT4_WRITE_BLOCKS_PER_CREDIT;
+        self.ext4_transaction_credits(credits, || {
+            if size == 0 {
+                return Ok(0);
+            }
 
-                iblk_idx += 1;
+            let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ext4_file.inode);
+            let start = ext4_file.fpos;
+            let end = start + size;
+            let mut pos = start;
+
+            while pos < end {
+                let mut iblock = (pos / BLOCK_SIZE) as Ext4Lblk;
+                let block_offset = pos % BLOCK_SIZE;
+                let len = core::cmp::min(BLOCK_SIZE - block_offset, end - pos);
+                let src = &data[pos - start..pos - start + len];
+
+                let mut fblock = inode_ref.get_pblock(&mut iblock);
+                let mut block_data = if fblock == 0 {
+                    fblock = match inode_ref.get_or_alloc_pblock(iblock) {
+                        Ok(fblock) => fblock,
+                        // 已经写入了一部分时返回写入的字节数
+                        Err(e) if pos == start => return Err(e),
+                        Err(_) => break,
+                    };
+                    // 新分配的块里可能残留被释放文件的数据, 没写到的部分要清零
+                    vec![0u8; BLOCK_SIZE]
+                } else if len < BLOCK_SIZE {
+                    self.block_device.read_offset(fblock as usize * BLOCK_SIZE)
+                } else {
+                    Vec::new()
+                };
 
-                if fblock_start == 0 {
-                    fblock_start = fblk;
+                // 文件数据不记入日志, 只有元数据的修改在事务中
+                let offset = fblock as usize * BLOCK_SIZE;
+                if len == BLOCK_SIZE {
+                    self.journal.write_data(offset, src);
+                } else {
+                    block_data[block_offset..block_offset + len].copy_from_slice(src);
+                    self.journal.write_data(offset, &block_data);
                 }
-                fblock_count += 1;
+                pos += len;
             }
-            size -= block_size;
-        }
 
-        for i in 0..fblock_count {
-            log::debug!("[Ext4::ext4_file_write] write data. fblock_count: {}", fblock_count);
-            let idx = i * BLOCK_SIZE as usize;
-            let offset = (fblock_start as usize + i as usize) * BLOCK_SIZE;
-            self.block_device
-                .write_offset(offset, &data[idx..(idx + BLOCK_SIZE as usize)]);
-        }
-        // inode_ref.inner.inode.size = fblock_count as u32 * BLOCK_SIZE as u32;
-        inode_ref.write_back_inode();
-        // let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ext4_file.inode);
-        let mut root_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), 2);
-        root_inode_ref.write_back_inode();
+            if pos as u64 > inode_ref.inner.inode.inode_get_size() {
+                inode_ref.inner.inode.ext4_inode_set_size(pos as u64);
+            }
+            inode_ref.write_back_inode();
+
+            ext4_file.fpos = pos;
+            ext4_file.fsize = inode_ref.inner.inode.inode_get_size();
+            Ok(pos - start)
+        })
     }
 
     pub fn ext4_follow_symlink(&self, ext4_file: &Ext4File) -> String {
-        let inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ext4_file.inode);
-        let ext4_inode = inode_ref.inner.inode;
+        let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ext4_file.inode);
 
-        let size = ext4_inode.inode_get_size();
+        let size = inode_ref.inner.inode.inode_get_size() as usize;
         // log::debug!("[Ext4::ext4_follow_symlink] size: {}", size);
-        if size > core::mem::size_of::<[u32; 15]>() as u64 {
-            core::panic!("[Ext4::ext4_follow_symlink] long symlink");
-        }
 
-        let inline_data = ext4_inode.block;
-        let inline_data_bytes = unsafe {
-            slice::from_raw_parts(
-                inline_data.as_ptr() as *const u8,
-                inline_data.len() * core::mem::size_of::<u32>(),
-            )
+        // 长的目标存在数据块中, 短的直接存在inode里
+        let target = if inode_ref.inner.inode.flags & EXT4_INODE_FLAG_EXTENTS as u32 != 0 {
+            let mut iblock = 0;
+            let fblock = inode_ref.get_pblock(&mut iblock);
+            let mut data = self.block_device.read_offset(fblock as usize * BLOCK_SIZE);
+            data.truncate(size.min(BLOCK_SIZE));
+            data
+        } else {
+            let inline_data = inode_ref.inner.inode.extent_root_mut();
+            inline_data[..size.min(inline_data.len())].to_vec()
         };
-        str::from_utf8(inline_data_bytes)
+
+        String::from_utf8(target)
             .unwrap()
             .trim_end_matches('\0')
             .to_string()
@@ -751,113 +862,323 @@ impl Ext4 {
     }
 
     pub fn ext4_trunc_inode(&self, inode_ref: &mut Ext4InodeRef, new_size: u64) -> Result<usize> {
-        let inode_size = inode_ref.inner.inode.inode_get_size();
-
-        if inode_size > new_size {
-            let _r = inode_ref.truncate_inode(new_size);
-        }
-
-        return_errno_with_message!(Errnum::ENOTSUP, "not support");
+        self.ext4_transaction(|| inode_ref.truncate_inode(new_size))
     }
 
     #[allow(unused)]
     pub fn ext4_file_remove(&self, path: &str) -> Result<usize> {
-        let mut name_off = 0;
+        self.ext4_transaction(|| {
+            let mut name_off = 0;
 
-        let mut file = Ext4File::new();
+            let mut file = Ext4File::new();
 
-        let mut ptr = Box::new(self.mount_point.clone());
+            file.mp = self.mount_point.clone();
 
-        file.mp = self.mount_point.clone();
+            let mut iflags = O_RDONLY;
 
-        let mut iflags = O_RDONLY;
+            let mut filetype = DirEntryType::EXT4_DE_UNKNOWN;
 
-        let mut filetype = DirEntryType::EXT4_DE_UNKNOWN;
+            // 打开后是目标文件的父目录
+            let mut parent_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), 2);
 
-        let mut root_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), 2);
+            self.ext4_generic_open2(
+                &mut file,
+                path,
+                iflags,
+                filetype.bits(),
+                &mut parent_inode_ref,
+                &mut name_off,
+            )?;
 
-        let r = self.ext4_generic_open2(
-            &mut file,
-            path,
-            iflags,
-            filetype.bits(),
-            &mut root_inode_ref,
-            &mut name_off,
-        );
+            self.ext4_file_close(&mut file);
 
-        let child_inode = file.inode;
+            let mut is_goal = false;
+            let p = &path[name_off as usize..];
+            let len = path_check_new(p, &mut is_goal);
 
-        self.ext4_file_close(&mut file);
+            let (ino, links) = self.ext4_unlink_entry(parent_inode_ref.inode_num, &p[..len])?;
+            if links == 0 {
+                self.ext4_free_inode(ino)?;
+            }
 
-        let mut child_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), child_inode);
+            return Ok(EOK);
+        })
+    }
 
-        let link_count = child_inode_ref.inner.inode.ext4_inode_get_links_cnt();
+    #[allow(unused)]
+    pub fn ext4_dir_remove(&self, parent_inode: u32, path: &str) -> Result<usize> {
+        self.ext4_transaction(|| {
+            let (ino, links) = self.ext4_unlink_entry(parent_inode, path)?;
+            if links == 0 {
+                self.ext4_free_inode(ino)?;
+            }
 
-        if link_count == 1 {
-            let mp = self.mount_point.clone();
-            self.ext4_trunc_inode(&mut child_inode_ref, 0x0);
-        }
+            return Ok(EOK);
+        })
+    }
 
-        // after trunc
-        let mut child_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), child_inode);
+    /// 在目录`parent`中新建名为`name`的inode, `mode`包含文件类型和权限位,
+    /// 返回新inode的编号
+    pub fn ext4_create(&self, parent: u32, name: &str, mode: u16) -> Result<u32> {
+        self.ext4_transaction(|| {
+            let mut parent_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), parent);
 
-        let mut is_goal = false;
-        let p = &path[name_off as usize..];
-        let len = path_check_new(p, &mut is_goal);
-        let r = self.ext4_unlink(
-            &mut root_inode_ref,
-            &mut child_inode_ref,
-            &p[..len as usize],
-            len as u32,
-        );
+            if !parent_inode_ref.is_dir() {
+                return_errno_with_message!(Errnum::ENOTDIR, "parent is not a dir");
+            }
+            if name.is_empty() || name.len() > 255 {
+                return_errno_with_message!(Errnum::EINVAL, "invalid name");
+            }
+            if self
+                .ext4_dir_find_entry_new(&mut parent_inode_ref, name)
+                .is_ok()
+            {
+                return_errno_with_message!(Errnum::EEXIST, "file exists");
+            }
 
-        self.ext4_fs_put_inode_ref_csum(&mut root_inode_ref);
+            let filetype = match mode as usize & EXT4_INODE_MODE_TYPE_MASK as usize {
+                EXT4_INODE_MODE_DIRECTORY => DirEntryType::EXT4_DE_DIR,
+                EXT4_INODE_MODE_SOFTLINK => DirEntryType::EXT4_DE_SYMLINK,
+                EXT4_INODE_MODE_CHARDEV => DirEntryType::EXT4_DE_CHRDEV,
+                EXT4_INODE_MODE_BLOCKDEV => DirEntryType::EXT4_DE_BLKDEV,
+                EXT4_INODE_MODE_FIFO => DirEntryType::EXT4_DE_FIFO,
+                EXT4_INODE_MODE_SOCKET => DirEntryType::EXT4_DE_SOCK,
+                _ => DirEntryType::EXT4_DE_REG_FILE,
+            };
 
-        return Ok(EOK);
+            let mut child_inode_ref = Ext4InodeRef::new(self.self_ref.clone());
+            child_inode_ref.ext4_fs_alloc_inode(filetype.bits());
+            if child_inode_ref.inode_num == 0 {
+                return_errno_with_message!(Errnum::ENOSPC, "alloc inode fail");
+            }
+
+            child_inode_ref.inner.inode.ext4_inode_set_mode(mode);
+            child_inode_ref.ext4_fs_inode_blocks_init();
+
+            let r = self.ext4_link(
+                &mut parent_inode_ref,
+                &mut child_inode_ref,
+                name,
+                name.len() as u32,
+            );
+            if r != EOK {
+                self.ext4_ialloc_free_inode(child_inode_ref.inode_num, filetype == DirEntryType::EXT4_DE_DIR);
+                return_errno_with_message!(Errnum::ENOSPC, "link fail");
+            }
+
+            self.ext4_fs_put_inode_ref_csum(&mut parent_inode_ref);
+            self.ext4_fs_put_inode_ref_csum(&mut child_inode_ref);
+
+            Ok(child_inode_ref.inode_num)
+        })
     }
 
-    #[allow(unused)]
-    pub fn ext4_dir_remove(&self, parent_inode: u32, path: &str) -> Result<usize> {
-        /*Check if exist.*/
-        let mut name_off = 0;
+    /// 在目录`parent`中新建指向`target`的符号链接. 短于60字节的目标直接存在inode中,
+    /// 否则存在一个数据块中
+    pub fn ext4_symlink(&self, parent: u32, name: &str, target: &str) -> Result<u32> {
+        self.ext4_transaction(|| {
+            if target.is_empty() || target.len() >= BLOCK_SIZE {
+                return_errno_with_message!(Errnum::EINVAL, "invalid symlink target");
+            }
 
-        let mut file = Ext4File::new();
+            let mode = EXT4_INODE_MODE_SOFTLINK as u16 | 0o777;
+            let ino = self.ext4_create(parent, name, mode)?;
+            let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ino);
 
-        file.mp = self.mount_point.clone();
+            let inline_len = core::mem::size_of::<[u32; 15]>();
+            if target.len() < inline_len {
+                let inline_data = inode_ref.inner.inode.extent_root_mut();
+                inline_data.fill(0);
+                inline_data[..target.len()].copy_from_slice(target.as_bytes());
+            } else {
+                inode_ref
+                    .inner
+                    .inode
+                    .ext4_inode_set_flags(EXT4_INODE_FLAG_EXTENTS as u32);
+                inode_ref.inner.inode.ext4_extent_tree_init();
+
+                let fblock = inode_ref.get_or_alloc_pblock(0)?;
+                let mut data = vec![0u8; BLOCK_SIZE];
+                data[..target.len()].copy_from_slice(target.as_bytes());
+                self.block_device
+                    .write_offset(fblock as usize * BLOCK_SIZE, &data);
+            }
 
-        let mut iflags = O_RDONLY;
+            inode_ref.inner.inode.ext4_inode_set_size(target.len() as u64);
+            inode_ref.write_back_inode();
 
-        let mut filetype = DirEntryType::EXT4_DE_UNKNOWN;
+            Ok(ino)
+        })
+    }
 
-        let mut parent_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), parent_inode);
+    /// 在目录`parent`中新建设备文件, `rdev`使用Linux的设备号编码
+    pub fn ext4_mknod(&self, parent: u32, name: &str, mode: u16, rdev: u32) -> Result<u32> {
+        self.ext4_transaction(|| {
+            let ino = self.ext4_create(parent, name, mode)?;
 
-        let r = self.ext4_dir_find_entry_new(&mut parent_inode_ref, path)?;
+            let file_type = mode as usize & EXT4_INODE_MODE_TYPE_MASK as usize;
+            if file_type == EXT4_INODE_MODE_CHARDEV || file_type == EXT4_INODE_MODE_BLOCKDEV {
+                let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ino);
+                inode_ref.inner.inode.ext4_inode_set_rdev(rdev);
+                inode_ref.write_back_inode();
+            }
 
-        let mut child_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), r.inode);
+            Ok(ino)
+        })
+    }
 
-        if child_inode_ref.has_children() {
-            return_errno_with_message!(Errnum::ENOTSUP, "rm dir with children not supported");
-        }
+    /// 修改inode的属主, 所属组和权限位, 用于chmod/chown和新建文件
+    pub fn ext4_set_attr(&self, ino: u32, uid: u32, gid: u32, perm: u16) -> Result<usize> {
+        self.ext4_transaction(|| {
+            let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ino);
+            inode_ref.inner.inode.ext4_inode_set_owner(uid, gid);
+            inode_ref.inner.inode.ext4_inode_set_perm(perm);
+            inode_ref.write_back_inode();
+            Ok(EOK)
+        })
+    }
 
-        /* Truncate */
-        self.ext4_trunc_inode(&mut child_inode_ref, 0);
+    /// 把文件截断或扩展到`size`字节
+    pub fn ext4_truncate(&self, ino: u32, size: u64) -> Result<usize> {
+        let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ino);
+        self.ext4_trunc_inode(&mut inode_ref, size)
+    }
 
-        self.ext4_unlink(
-            &mut parent_inode_ref,
-            &mut child_inode_ref,
-            path,
-            path.len() as u32,
-        );
+    /// 删除目录`parent`中的`name`, 返回对应的inode编号和它剩下的链接数.
+    /// 链接数为0时inode还没有释放, 调用者在不再使用它之后调用`ext4_free_inode`
+    pub fn ext4_unlink_entry(&self, parent: u32, name: &str) -> Result<(u32, u16)> {
+        self.ext4_transaction(|| {
+            let mut parent_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), parent);
 
-        self.ext4_fs_put_inode_ref_csum(&mut parent_inode_ref);
+            let de = self.ext4_dir_find_entry_new(&mut parent_inode_ref, name)?;
+            let mut child_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), de.inode);
 
-        // to do
+            if child_inode_ref.has_children() {
+                return_errno_with_message!(Errnum::ENOTEMPTY, "dir not empty");
+            }
 
-        // ext4_inode_set_del_time
-        // ext4_inode_set_links_cnt
-        // ext4_fs_free_inode(&child)
+            let r = self.ext4_unlink(
+                &mut parent_inode_ref,
+                &mut child_inode_ref,
+                name,
+                name.len() as u32,
+            );
+            if r != EOK {
+                return_errno_with_message!(Errnum::ENOENT, "unlink fail");
+            }
 
-        return Ok(EOK);
+            Ok((
+                child_inode_ref.inode_num,
+                child_inode_ref.inner.inode.ext4_inode_get_links_cnt(),
+            ))
+        })
+    }
+
+    /// 把目录`old_parent`中的`old_name`移动到目录`new_parent`中并命名为`new_name`.
+    /// `exchange`为true时交换两个已有的目录项, 否则`new_name`必须不存在,
+    /// 要替换的目标由调用者在同一个事务中先用`ext4_unlink_entry`删除
+    pub fn ext4_rename(
+        &self,
+        old_parent: u32,
+        old_name: &str,
+        new_parent: u32,
+        new_name: &str,
+        exchange: bool,
+    ) -> Result<usize> {
+        self.ext4_transaction(|| {
+            // 两个父目录可能是同一个inode, 每一步都重新读取, 避免旧的副本覆盖新写入的inode
+            let get = |ino| Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ino);
+            // 子目录的`..`改为指向新的父目录, 同时把一个链接数从`from`转给`to`
+            let move_dir = |dir: &Ext4InodeRef, from: u32, to: u32| {
+                self.ext4_dir_set_entry(&mut get(dir.inode_num), "..", &get(to));
+                let mut from_ref = get(from);
+                let links = from_ref.inner.inode.ext4_inode_get_links_cnt();
+                from_ref.inner.inode.ext4_inode_set_links_cnt(links.saturating_sub(1));
+                from_ref.write_back_inode();
+                let mut to_ref = get(to);
+                let links = to_ref.inner.inode.ext4_inode_get_links_cnt();
+                to_ref.inner.inode.ext4_inode_set_links_cnt(links + 1);
+                to_ref.write_back_inode();
+            };
+
+            if !get(new_parent).is_dir() {
+                return_errno_with_message!(Errnum::ENOTDIR, "parent is not a dir");
+            }
+            if new_name.is_empty() || new_name.len() > 255 {
+                return_errno_with_message!(Errnum::EINVAL, "invalid name");
+            }
+            let src = self.ext4_dir_find_entry_new(&mut get(old_parent), old_name)?;
+            let dst = self.ext4_dir_find_entry_new(&mut get(new_parent), new_name);
+            let mut src_ref = get(src.inode);
+
+            if exchange {
+                let Ok(dst) = dst else {
+                    return_errno_with_message!(Errnum::ENOENT, "rename target not found");
+                };
+                let dst_ref = get(dst.inode);
+                self.ext4_dir_set_entry(&mut get(old_parent), old_name, &dst_ref);
+                self.ext4_dir_set_entry(&mut get(new_parent), new_name, &src_ref);
+                if old_parent != new_parent {
+                    if src_ref.is_dir() {
+                        move_dir(&src_ref, old_parent, new_parent);
+                    }
+                    if dst_ref.is_dir() {
+                        move_dir(&dst_ref, new_parent, old_parent);
+                    }
+                }
+            } else {
+                if dst.is_ok() {
+                    return_errno_with_message!(Errnum::EEXIST, "rename target exists");
+                }
+                let mut new_parent_ref = get(new_parent);
+                let r = self.ext4_dir_add_entry(
+                    &mut new_parent_ref,
+                    &mut src_ref,
+                    new_name,
+                    new_name.len() as u32,
+                );
+                if r != EOK {
+                    return_errno_with_message!(Errnum::ENOSPC, "add dir entry fail");
+                }
+                // 目录可能追加了新块
+                self.ext4_fs_put_inode_ref_csum(&mut new_parent_ref);
+                self.ext4_dir_remove_entry_new(
+                    &mut get(old_parent),
+                    old_name,
+                    old_name.len() as u32,
+                );
+                if src_ref.is_dir() && old_parent != new_parent {
+                    move_dir(&src_ref, old_parent, new_parent);
+                }
+            }
+            Ok(EOK)
+        })
+    }
+
+    /// 释放链接数已经为0的inode和它的所有数据块
+    pub fn ext4_free_inode(&self, ino: u32) -> Result<usize> {
+        self.ext4_transaction(|| {
+            let mut inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), ino);
+            if inode_ref.inner.inode.ext4_inode_get_links_cnt() != 0 {
+                return_errno_with_message!(Errnum::EINVAL, "inode still linked");
+            }
+
+            let is_dir = inode_ref.is_dir();
+            inode_ref.extent_remove_space(0)?;
+
+            // 库中没有时钟, 用最后一次改变inode的时间作为删除时间.
+            // dtime小于inode总数时会被e2fsck当成孤儿链表的下一项
+            let mut dtime = inode_ref.inner.inode.ext4_inode_get_ctime();
+            if dtime < self.super_block.inodes_count {
+                dtime = self.super_block.mkfs_time();
+            }
+            inode_ref.inner.inode.ext4_inode_set_del_time(dtime);
+            inode_ref.inner.inode.ext4_inode_set_size(0);
+            inode_ref.write_back_inode();
+
+            self.ext4_ialloc_free_inode(ino, is_dir);
+            Ok(EOK)
+        })
     }
 
     #[allow(unused)]
@@ -886,7 +1207,7 @@ impl Ext4 {
         }
 
         if iflags & O_CREAT != 0 {
-            self.ext4_trans_start();
+            self.ext4_trans_start()?;
         }
 
         // let mut parent_inode_ref = Ext4InodeRef::get_inode_ref(self.self_ref.clone(), parent_inode);
@@ -900,6 +1221,10 @@ impl Ext4 {
             parent_inode,
             &mut name_off,
         );
+        if iflags & O_CREAT != 0 {
+            return self.ext4_trans_finish(r);
+        }
+
         r
     }
 
@@ -86,9 +86,9 @@ impl Ext4BlockGroup {
 
     /// Set the count of used directories in this block group.
     pub fn set_used_dirs_count(&mut self, s: &Ext4Superblock, cnt: u32){
-        self.itable_unused_lo = ((cnt << 16) >> 16) as u16;
+        self.used_dirs_count_lo = ((cnt << 16) >> 16) as u16;
         if s.desc_size() > EXT4_MIN_BLOCK_GROUP_DESCRIPTOR_SIZE {
-            self.itable_unused_hi = (cnt >> 16) as u16;
+            self.used_dirs_count_hi = (cnt >> 16) as u16;
         }
     }
 
@@ -285,7 +285,7 @@ impl Ext4BlockGroup {
 pub fn ext4_inodes_in_group_cnt(bgid: u32, s: &Ext4Superblock) -> u32 {
     let block_group_count = s.block_groups_count();
     let inodes_per_group = s.inodes_per_group;
-    let total_inodes = ((s.inodes_count as u64) << 32) as u32;
+    let total_inodes = s.inodes_count;
 
     if bgid < block_group_count - 1 {
         inodes_per_group
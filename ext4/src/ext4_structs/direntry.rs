@@ -65,15 +65,26 @@ impl Default for Ext4DirEntry {
 impl<T> TryFrom<&[T]> for Ext4DirEntry {
     type Error = u64;
     fn try_from(data: &[T]) -> core::result::Result<Self, u64> {
-        let data = data;
-        Ok(unsafe { core::ptr::read(data.as_ptr() as *const _) })
+        // 块末尾的目录项之后可能不足一个完整的结构体, 只读取剩下的部分
+        let len = core::cmp::min(
+            data.len() * core::mem::size_of::<T>(),
+            core::mem::size_of::<Ext4DirEntry>(),
+        );
+        let mut entry = Ext4DirEntry::default();
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                &mut entry as *mut Ext4DirEntry as *mut u8,
+                len,
+            );
+        }
+        Ok(entry)
     }
 }
 
 impl Ext4DirEntry {
     pub fn from_u8(data: &mut [u8]) -> Self {
-        let entry = unsafe { core::ptr::read(data.as_mut_ptr() as *mut Ext4DirEntry) };
-        entry
+        Ext4DirEntry::try_from(&data[..]).unwrap()
     }
 }
 
@@ -149,23 +160,17 @@ impl Ext4DirEntry {
         );
         // assert_eq!(dst_blk.block_data[offset..offset + core::mem::size_of::<Ext4DirEntry>()], data[..]);
     }
+    /// 只写入目录项头部和文件名, 不覆盖之后的目录项
     pub fn copy_to_slice(&self, array: &mut [u8], offset: usize) {
         let de_ptr = self as *const Ext4DirEntry as *const u8;
-        let array_ptr = array as *mut [u8] as *mut u8;
-        let count = core::mem::size_of::<Ext4DirEntry>() / core::mem::size_of::<u8>();
-        unsafe {
-            core::ptr::copy_nonoverlapping(de_ptr, array_ptr.add(offset), count);
-        }
+        let count = core::mem::size_of::<Ext4FakeDirEntry>() + self.name_len as usize;
+        let src = unsafe { core::slice::from_raw_parts(de_ptr, count) };
+        array[offset..offset + count].copy_from_slice(src);
     }
 }
 
 pub fn copy_dir_entry_to_array(header: &Ext4DirEntry, array: &mut [u8], offset: usize) {
-    unsafe {
-        let de_ptr = header as *const Ext4DirEntry as *const u8;
-        let array_ptr = array as *mut [u8] as *mut u8;
-        let count = core::mem::size_of::<Ext4DirEntry>() / core::mem::size_of::<u8>();
-        core::ptr::copy_nonoverlapping(de_ptr, array_ptr.add(offset), count);
-    }
+    header.copy_to_slice(array, offset);
 }
 
 pub fn copy_diren_tail_to_array(dir_en: &Ext4DirEntryTail, array: &mut [u8], offset: usize) {
@@ -102,16 +102,17 @@ impl<T> TryFrom<&[T]> for Ext4ExtentHeader {
 impl<T> TryFrom<&[T]> for Ext4ExtentIndex {
     type Error = u64;
     fn try_from(data: &[T]) -> core::result::Result<Self, u64> {
-        let data = &data[..size_of::<Ext4ExtentIndex>()];
-        Ok(unsafe { core::ptr::read(data.as_ptr() as *const _) })
+        // 长度按T计算, inode中的根节点是u32数组
+        let data = &data[..size_of::<Ext4ExtentIndex>().div_ceil(size_of::<T>())];
+        Ok(unsafe { core::ptr::read_unaligned(data.as_ptr() as *const _) })
     }
 }
 
 impl<T> TryFrom<&[T]> for Ext4Extent {
     type Error = u64;
     fn try_from(data: &[T]) -> core::result::Result<Self, u64> {
-        let data = &data[..];
-        Ok(unsafe { core::ptr::read(data.as_ptr() as *const _) })
+        let data = &data[..size_of::<Ext4Extent>().div_ceil(size_of::<T>())];
+        Ok(unsafe { core::ptr::read_unaligned(data.as_ptr() as *const _) })
     }
 }
 
@@ -135,6 +136,11 @@ impl Ext4ExtentIndex {
 
         pblock
     }
+
+    pub fn store_pblock(&mut self, pblock: u64) {
+        self.leaf_lo = pblock as u32;
+        self.leaf_hi = (pblock >> 32) as u16;
+    }
 }
 
 
@@ -189,19 +195,25 @@ impl Ext4Extent {
 
     pub fn can_append(&self, next: &Self) -> bool {
         self.first_block + self.get_actual_len() as u32 == next.first_block
+            && self.pblock() + self.get_actual_len() as u32 == next.pblock()
             && if self.is_unwritten() {
-                self.get_actual_len() + next.get_actual_len() <= EXT_UNWRITTEN_MAX_LEN
+                self.get_actual_len() as u32 + next.get_actual_len() as u32
+                    <= EXT_UNWRITTEN_MAX_LEN as u32
             } else {
-                self.get_actual_len() + next.get_actual_len() <= EXT_INIT_MAX_LEN
+                self.get_actual_len() as u32 + next.get_actual_len() as u32
+                    <= EXT_INIT_MAX_LEN as u32
             }
     }
 
     pub fn can_prepend(&self, prev: &Self) -> bool {
         prev.first_block + prev.get_actual_len() as u32 == self.first_block
+            && prev.pblock() + prev.get_actual_len() as u32 == self.pblock()
             && if self.is_unwritten() {
-                self.get_actual_len() + prev.get_actual_len() <= EXT_UNWRITTEN_MAX_LEN
+                self.get_actual_len() as u32 + prev.get_actual_len() as u32
+                    <= EXT_UNWRITTEN_MAX_LEN as u32
             } else {
-                self.get_actual_len() + prev.get_actual_len() <= EXT_INIT_MAX_LEN
+                self.get_actual_len() as u32 + prev.get_actual_len() as u32
+                    <= EXT_INIT_MAX_LEN as u32
             }
     }
     /// Marks the extent as unwritten.
@@ -211,7 +223,7 @@ impl Ext4Extent {
 
     pub fn store_pblock(&mut self, pblock: u64) {
         self.start_lo = pblock as u32 & 0xffffffff;
-        self.start_hi = (((pblock as u32) << 31) << 1) as u16;
+        self.start_hi = (pblock >> 32) as u16;
     }
 }
 
@@ -293,18 +305,23 @@ impl Ext4ExtentPath {
                 self.header.add(1) as *mut Ext4Extent // Point to the first extent
             };
 
-            // Iterate over all extents
+            // Find the last extent starting at or before the block
+            self.extent = core::ptr::null_mut();
             for _i in 0..header.entries_count {
                 let ext = unsafe { &*extent };
-                // Check if the block number falls within this extent
-                if block >= ext.first_block && block <= (ext.first_block + ext.block_count as u32) {
-                    self.extent = extent;
-                    return true;
+                if ext.first_block > block {
+                    break;
                 }
+                self.extent = extent;
 
                 // Move to the next extent
                 extent = unsafe { extent.add(1) };
             }
+
+            if let Some(ext) = unsafe { self.extent.as_ref() } {
+                // Check if the block number falls within this extent
+                return block < ext.first_block + ext.get_actual_len() as u32;
+            }
         }
 
         false
@@ -394,6 +411,54 @@ pub struct ExtentTreeNode {
 }
 
 impl ExtentTreeNode {
+    /// 一个磁盘块能放下的表项数, 块末尾留4字节的校验和
+    pub const BLOCK_MAX_ENTRIES: u16 =
+        ((BLOCK_SIZE - size_of::<Ext4ExtentHeader>()) / size_of::<Ext4Extent>()) as u16;
+
+    /// 新的空节点, 用于分裂时的新块
+    pub fn new(depth: u16, max_entries: u16) -> Self {
+        Self {
+            header: Ext4ExtentHeader::new(EXT4_EXTENT_MAGIC, 0, max_entries, depth, 0),
+            extents: Vec::new(),
+            indexes: Vec::new(),
+        }
+    }
+
+    pub fn entries_len(&self) -> usize {
+        self.extents.len() + self.indexes.len()
+    }
+
+    /// 插入后表项比节点能放下的多一个, 需要分裂
+    pub fn is_overfull(&self) -> bool {
+        self.entries_len() > self.header.max_entries_count as usize
+    }
+
+    /// 第`i`个表项的起始逻辑块
+    pub fn entry_first_block(&self, i: usize) -> u32 {
+        if self.header.depth == 0 {
+            self.extents[i].first_block
+        } else {
+            self.indexes[i].first_block
+        }
+    }
+
+    /// 把第`at`个及之后的表项移到一个新节点中
+    pub fn split_off(&mut self, at: usize) -> Self {
+        let mut node = Self::new(self.header.depth, self.header.max_entries_count);
+        if self.header.depth == 0 {
+            node.extents = self.extents.split_off(at);
+        } else {
+            node.indexes = self.indexes.split_off(at);
+        }
+        node
+    }
+
+    /// 把`other`的表项接在这个节点后面
+    pub fn append(&mut self, other: &mut Self) {
+        self.extents.append(&mut other.extents);
+        self.indexes.append(&mut other.indexes);
+    }
+
     pub fn load_from_header(data: &[u32]) -> Self {
         let extent_header = Ext4ExtentHeader::try_from(data).unwrap();
         let mut extents: Vec<Ext4Extent> = Vec::new();
@@ -447,6 +512,25 @@ impl ExtentTreeNode {
         }
     }
 
+    /// 把节点写入`data`(inode中的根节点或磁盘上的一个块), 同时更新表项数
+    pub fn store(&mut self, data: &mut [u8]) {
+        self.header.entries_count = (self.extents.len() + self.indexes.len()) as u16;
+        let entry_size = size_of::<Ext4Extent>();
+        assert!((self.header.entries_count as usize + 1) * entry_size <= data.len());
+        unsafe {
+            let base = data.as_mut_ptr();
+            core::ptr::write_unaligned(base as *mut Ext4ExtentHeader, self.header);
+            for (i, extent) in self.extents.iter().enumerate() {
+                let ptr = base.add((i + 1) * entry_size) as *mut Ext4Extent;
+                core::ptr::write_unaligned(ptr, *extent);
+            }
+            for (i, index) in self.indexes.iter().enumerate() {
+                let ptr = base.add((i + 1) * entry_size) as *mut Ext4ExtentIndex;
+                core::ptr::write_unaligned(ptr, *index);
+            }
+        }
+    }
+
     pub fn find_extent(
         &self,
         block_id: Ext4Lblk,
@@ -457,7 +541,7 @@ impl ExtentTreeNode {
             // 叶节点
             for extent in &self.extents {
                 if block_id >= extent.first_block
-                    && block_id < extent.first_block + extent.block_count as u32
+                    && block_id < extent.first_block + extent.get_actual_len() as u32
                 {
                     path.push(Ext4ExtentPathNew {
                         depth: self.header.depth as usize,
@@ -471,23 +555,26 @@ impl ExtentTreeNode {
                 }
             }
         } else {
-            // 索引节点
-            for index in &self.indexes {
-                if block_id >= index.first_block {
-                    let node_data = block_device.read_offset(index.leaf_lo as usize * BLOCK_SIZE);
-                    let child_node = self.load_node(&node_data);
-
-                    path.push(Ext4ExtentPathNew {
-                        depth: self.header.depth as usize,
-                        first_block: index.first_block,
-                        block_count: index.leaf_lo as u16,
-                        start_lo: index.leaf_hi as u32,
-                        start_hi: index.padding,
-                        p_block: Some(index.pblock() as u64),
-                    });
-                    return child_node.find_extent(block_id, block_device.clone(), path);
-                }
+            // 索引节点, 找最后一个起始块不大于block_id的索引
+            if let Some(index) = self
+                .indexes
+                .iter()
+                .rev()
+                .find(|index| index.first_block <= block_id)
+            {
+                let node_data = block_device.read_offset(index.pblock() as usize * BLOCK_SIZE);
+                let child_node = self.load_node(&node_data);
+
+                path.push(Ext4ExtentPathNew {
+                    depth: self.header.depth as usize,
+                    first_block: index.first_block,
+                    block_count: index.leaf_lo as u16,
+                    start_lo: index.leaf_hi as u32,
+                    start_hi: index.padding,
+                    p_block: Some(index.pblock() as u64),
+                });
+                return child_node.find_extent(block_id, block_device.clone(), path);
             }
         }
-    }    
-}
\ No newline at end of file
+    }
+}
@@ -83,7 +83,7 @@ impl Ext4Inode {
     }
 
     pub fn ext4_inode_set_mode(&mut self, mode: u16) {
-        self.mode |= mode;
+        self.mode = mode;
     }
 
     pub fn ext4_inode_set_links_cnt(&mut self, cnt: u16) {
@@ -102,6 +102,19 @@ impl Ext4Inode {
         self.gid = gid;
     }
 
+    /// 32位的属主和所属组, 高16位放在osd2中
+    pub fn ext4_inode_set_owner(&mut self, uid: u32, gid: u32) {
+        self.uid = uid as u16;
+        self.osd2.l_i_uid_high = (uid >> 16) as u16;
+        self.gid = gid as u16;
+        self.osd2.l_i_gid_high = (gid >> 16) as u16;
+    }
+
+    /// 修改权限位(mode的低12位), 保留文件类型
+    pub fn ext4_inode_set_perm(&mut self, perm: u16) {
+        self.mode = (self.mode & !0o7777) | (perm & 0o7777);
+    }
+
     pub fn ext4_inode_set_size(&mut self, size: u64) {
         self.size = ((size << 32) >> 32) as u32;
         self.size_hi = (size >> 32) as u32;
@@ -150,6 +163,30 @@ impl Ext4Inode {
         self.blocks = blocks_count;
     }
 
+    /// 设备号按Linux的方式存放: 主次设备号都小于256时用旧格式放在block[0],
+    /// 否则用新格式放在block[1]
+    pub fn ext4_inode_set_rdev(&mut self, rdev: u32) {
+        let major = (rdev >> 8) & 0xfff;
+        let minor = (rdev & 0xff) | ((rdev >> 12) & 0xfff00);
+        if major < 256 && minor < 256 {
+            self.block[0] = (major << 8) | minor;
+            self.block[1] = 0;
+        } else {
+            self.block[0] = 0;
+            self.block[1] = rdev;
+        }
+    }
+
+    pub fn ext4_inode_get_rdev(&self) -> u32 {
+        if self.block[0] != 0 {
+            let major = (self.block[0] >> 8) & 0xff;
+            let minor = self.block[0] & 0xff;
+            (major << 8) | minor
+        } else {
+            self.block[1]
+        }
+    }
+
     pub fn ext4_inode_set_generation(&mut self, generation: u32) {
         self.generation = generation;
     }
@@ -450,6 +487,16 @@ impl Ext4Inode {
         &mut self.block as *mut [u32; 15] as *mut Ext4ExtentHeader
     }
 
+    /// inode中extent树根节点所在的60字节
+    pub fn extent_root_mut(&mut self) -> &mut [u8] {
+        unsafe {
+            core::slice::from_raw_parts_mut(
+                self.block.as_mut_ptr() as *mut u8,
+                core::mem::size_of::<[u32; 15]>(),
+            )
+        }
+    }
+
     /// Get the depth of the extent tree from an inode.
     pub unsafe fn extent_depth(&self) -> u16 {
         (*self.extent_header()).depth
@@ -530,74 +577,57 @@ impl Ext4InodeRef {
         *result = 0;
         *blocks_count = 0;
 
-        let mut path: Option<Vec<Ext4ExtentPath>> = None;
-        // let err = crate::ext4_find_extent(self, iblock, &mut path, 0);
-        let err = self.find_extent(iblock, &mut path, 0);
-
-        // Ensure find_extent was successful
-        if err != EOK {
+        self.get_blocks_new(iblock, max_blocks, result, blocks_count);
+        if *result != 0 || !create {
             return;
         }
-        // Unwrap the path safely after checking it is Some
-        let path = path
-            .as_mut()
-            .expect("Path should not be None after successful find_extent");
-
-        let depth = unsafe { self.inner.inode.extent_depth() } as usize;
-        // Safely access the desired depth element if it exists
-        if let Some(extent_path) = path.get(depth) {
-            if let Some(ex) = unsafe { extent_path.extent.as_ref() } {
-                // Safely obtain a reference to extent
-                let ee_block = ex.first_block;
-                let ee_start = ex.pblock();
-                let ee_len = ex.get_actual_len();
-
-                if iblock >= ee_block && iblock < ee_block + ee_len as u32 {
-                    let allocated = ee_len - (iblock - ee_block) as u16;
-                    *blocks_count = allocated as u32;
-
-                    if !create || ex.is_unwritten() {
-                        *result = (iblock - ee_block + ee_start) as u64;
-                        return; // Early return if no new extent needed
-                    }
-                }
-            }
-        }
-        if create {
-            let mut allocated: u32 = 0;
-            let next = EXT_MAX_BLOCKS;
 
-            allocated = next - iblock;
-            if allocated > max_blocks {
-                allocated = max_blocks;
+        // 紧接着前一个逻辑块的物理块分配, 新块可以并入已有的extent
+        let mut goal = 0;
+        if iblock > 0 {
+            let mut prev_count = 0;
+            self.get_blocks_new(iblock - 1, 1, &mut goal, &mut prev_count);
+            if goal != 0 {
+                goal += 1;
             }
+        }
 
-            let mut newex: Ext4Extent = Ext4Extent::default();
-
-            let goal = 0;
-
-            let mut alloc_block = 0;
-            alloc_block = self.balloc_alloc_block(goal as u64);
+        let alloc_block = self.balloc_alloc_block(goal);
+        if alloc_block == 0 {
+            log::info!("no free block");
+            return;
+        }
 
-            *result = alloc_block;
+        // 创建并插入新的extent
+        let mut newex: Ext4Extent = Ext4Extent::default();
+        newex.first_block = iblock;
+        newex.store_pblock(alloc_block);
+        newex.block_count = 1;
 
-            // 创建并插入新的extent
-            newex.first_block = iblock;
-            newex.start_lo = alloc_block as u32 & 0xffffffff;
-            newex.start_hi = (((alloc_block as u32) << 31) << 1) as u16;
-            newex.block_count = allocated as u16;
+        if self.insert_extent(&newex) != EOK {
+            self.balloc_free_blocks(alloc_block, 1);
+            return;
+        }
 
-            // crate::ext4_ext_insert_extent(self,  &mut path[0], &newex, 0);
+        *result = alloc_block;
+        *blocks_count = 1;
+    }
 
-            self.insert_extent(&mut path[0], &newex, 0);
-            // self.allocate_and_insert_new_extent(iblock, max_blocks, result, blocks_count);
+    /// 逻辑块`iblock`对应的物理块, 还没有映射时分配一个新块
+    pub fn get_or_alloc_pblock(&mut self, iblock: Ext4Lblk) -> Result<Ext4Fsblk> {
+        let mut fblock = 0;
+        let mut blocks_count = 0;
+        self.get_blocks(iblock, 1, &mut fblock, true, &mut blocks_count);
+        if fblock == 0 {
+            return_errno_with_message!(Errnum::ENOSPC, "alloc block fail");
         }
+        Ok(fblock)
     }
 
+    /// 从`goal`开始找一个空闲块并分配给这个inode, 磁盘已满时返回0
     #[allow(unused)]
     pub fn balloc_alloc_block(&mut self, goal: Ext4Fsblk) -> u64 {
         log::trace!("balloc_alloc_block");
-        // let mut fblock = 0;
 
         let fs = self.fs();
 
@@ -606,133 +636,260 @@ impl Ext4InodeRef {
         let super_block_data = block_device.read_offset(crate::BASE_OFFSET);
         let mut super_block = Ext4Superblock::try_from(super_block_data).unwrap();
 
-        // let inodes_per_group = super_block.inodes_per_group();
         let blocks_per_group = super_block.blocks_per_group();
+        let first_data_block = super_block.first_data_block as u64;
+        let bg_count = super_block.block_groups_count();
+
+        let goal = if goal < first_data_block || goal >= super_block.blocks_count() as u64 {
+            first_data_block
+        } else {
+            goal
+        };
+        let goal_bgid = ((goal - first_data_block) / blocks_per_group as u64) as u32;
+        let goal_idx = ((goal - first_data_block) % blocks_per_group as u64) as u32;
 
-        let bgid = goal / blocks_per_group as u64;
-        let idx_in_bg = goal % blocks_per_group as u64;
+        // 目标块组先从goal开始找, 绕一圈后再从头找一次
+        for i in 0..=bg_count {
+            let bgid = (goal_bgid + i) % bg_count;
 
-        let mut bg =
-            Ext4BlockGroup::load(block_device.clone(), &super_block, bgid as usize).unwrap();
+            let mut bg =
+                Ext4BlockGroup::load(block_device.clone(), &super_block, bgid as usize).unwrap();
 
-        let block_bitmap_block = bg.get_block_bitmap_block(&super_block);
-        let mut raw_data = block_device.read_offset(block_bitmap_block as usize * BLOCK_SIZE);
-        let mut data: &mut Vec<u8> = &mut raw_data;
-        let mut rel_blk_idx = 0 as u32;
-        // let blk_in_bg = bg.ext4_blocks_in_group_cnt(&super_block);
+            if bg.get_free_blocks_count() == 0 {
+                continue;
+            }
+            // 未初始化的块组没有块位图, 不从中分配
+            if bg.flags & EXT4_BG_BLOCK_UNINIT != 0 {
+                continue;
+            }
 
-        ext4_bmap_bit_find_clr(data, idx_in_bg as u32, 0x8000, &mut rel_blk_idx);
-        // fblock = rel_blk_idx as u64;
-        ext4_bmap_bit_set(&mut data, rel_blk_idx);
+            let blocks_in_bg = super_block.get_blocks_in_group_cnt(bgid);
+            let start = if i == 0 { goal_idx } else { 0 };
 
-        bg.set_block_group_balloc_bitmap_csum(&super_block, &data);
-        block_device.write_offset(block_bitmap_block as usize * BLOCK_SIZE, &data);
+            let block_bitmap_block = bg.get_block_bitmap_block(&super_block);
+            let mut data = block_device.read_offset(block_bitmap_block as usize * BLOCK_SIZE);
+            let mut rel_blk_idx = 0 as u32;
 
-        /* Update superblock free blocks count */
-        let mut super_blk_free_blocks = super_block.free_blocks_count();
-        super_blk_free_blocks -= 1;
-        super_block.set_free_blocks_count(super_blk_free_blocks);
-        super_block.sync_to_disk_with_csum(block_device.clone());
+            if start >= blocks_in_bg
+                || !ext4_bmap_bit_find_clr(&data, start, blocks_in_bg, &mut rel_blk_idx)
+            {
+                continue;
+            }
+            ext4_bmap_bit_set(&mut data, rel_blk_idx);
 
-        /* Update inode blocks (different block size!) count */
-        let mut inode_blocks = self.inner.inode.ext4_inode_get_blocks_count();
-        inode_blocks += 8;
-        self.inner
-            .inode
-            .ext4_inode_set_blocks_count(inode_blocks as u32);
-        self.write_back_inode();
+            bg.set_block_group_balloc_bitmap_csum(&super_block, &data);
+            block_device.write_offset(block_bitmap_block as usize * BLOCK_SIZE, &data);
+
+            /* Update superblock free blocks count */
+            let mut super_blk_free_blocks = super_block.free_blocks_count();
+            super_blk_free_blocks -= 1;
+            super_block.set_free_blocks_count(super_blk_free_blocks);
+            super_block.sync_to_disk_with_csum(block_device.clone());
 
-        /* Update block group free blocks count */
-        let mut fb_cnt = bg.get_free_blocks_count();
-        fb_cnt -= 1;
-        bg.set_free_blocks_count(fb_cnt as u32);
-        bg.sync_to_disk_with_csum(block_device, bgid as usize, &super_block);
+            /* Update inode blocks (different block size!) count */
+            let mut inode_blocks = self.inner.inode.ext4_inode_get_blocks_count();
+            inode_blocks += (BLOCK_SIZE / EXT4_INODE_BLOCK_SIZE) as u64;
+            self.inner
+                .inode
+                .ext4_inode_set_blocks_count(inode_blocks as u32);
+            self.write_back_inode();
 
-        rel_blk_idx as u64
+            /* Update block group free blocks count */
+            let mut fb_cnt = bg.get_free_blocks_count();
+            fb_cnt -= 1;
+            bg.set_free_blocks_count(fb_cnt as u32);
+            bg.sync_to_disk_with_csum(block_device, bgid as usize, &super_block);
+
+            return first_data_block
+                + bgid as u64 * blocks_per_group as u64
+                + rel_blk_idx as u64;
+        }
+
+        0
     }
 
     /// Inserts a new extent into the inode's data structure.
-    pub fn insert_extent(&mut self, path: &mut Ext4ExtentPath, newext: &Ext4Extent, flags: i32) {
-        let depth = unsafe { self.inner.inode.extent_depth() };
-        let mut need_split = false;
+    pub fn insert_extent(&mut self, newext: &Ext4Extent) -> usize {
+        let mut root = ExtentTreeNode::load_from_header(&self.inner.inode.block[..]);
+
+        // 最坏情况下从叶子到根每层都要分裂一次, 先确认空闲块够用,
+        // 避免下层已经写回磁盘后上层分配失败
+        let super_block_data = self.fs().block_device.read_offset(crate::BASE_OFFSET);
+        let super_block = Ext4Superblock::try_from(super_block_data).unwrap();
+        if super_block.free_blocks_count() < root.header.depth as u64 + 1 {
+            return Errnum::ENOSPC as usize;
+        }
 
-        self.insert_leaf(path, depth, newext, flags, &mut need_split);
+        let r = self.insert_in_node(&mut root, newext);
+        if r != EOK {
+            return r;
+        }
 
-        self.write_back_inode_without_csum();
+        if root.is_overfull() {
+            let r = self.ext_grow_indepth(&mut root);
+            if r != EOK {
+                return r;
+            }
+        }
+
+        root.store(self.inner.inode.extent_root_mut());
+        self.write_back_inode();
+        EOK
     }
 
-    #[allow(unused)]
-    /// Handles the leaf insertion logic for extents, considering append and prepend scenarios.
-    fn insert_leaf(
+    /// 把`newext`插入以`node`为根的子树, 修改过的子节点直接写回磁盘.
+    /// `node`本身由调用者写回, 返回时它可能多出一个表项, 由调用者分裂
+    fn insert_in_node(&mut self, node: &mut ExtentTreeNode, newext: &Ext4Extent) -> usize {
+        if node.header.depth == 0 {
+            return self.insert_leaf(node, newext);
+        }
+
+        // 最后一个起始块不大于新extent的索引, 新extent在最左边时用第一个索引
+        let pos = node
+            .indexes
+            .iter()
+            .rposition(|index| index.first_block <= newext.first_block)
+            .unwrap_or(0);
+
+        let child_block = node.indexes[pos].pblock();
+        let data = self
+            .fs()
+            .block_device
+            .read_offset(child_block as usize * BLOCK_SIZE);
+        let mut child = node.load_node(&data);
+
+        let r = self.insert_in_node(&mut child, newext);
+        if r != EOK {
+            return r;
+        }
+
+        if newext.first_block < node.indexes[pos].first_block {
+            node.indexes[pos].first_block = newext.first_block;
+        }
+
+        if child.is_overfull() {
+            let r = self.ext_split_node(node, pos, &mut child, newext);
+            if r != EOK {
+                return r;
+            }
+        }
+
+        self.ext_write_node(child_block, &mut child);
+        EOK
+    }
+
+    /// 把`node`的第`pos`个子节点`child`的后半部分移到新块中,
+    /// 新块的索引插在`pos`之后
+    fn ext_split_node(
         &mut self,
-        path: &mut Ext4ExtentPath,
-        _depth: u16,
+        node: &mut ExtentTreeNode,
+        pos: usize,
+        child: &mut ExtentTreeNode,
         newext: &Ext4Extent,
-        _flags: i32,
-        need_split: &mut bool,
     ) -> usize {
-        // Ensure we are working with a valid extent header and existing extent
-        if let Some(eh) = unsafe { path.header.as_mut() } {
-            let ex = path.extent;
-
-            // Manage appending new extents or adjusting existing ones
-            if let Some(current_ext) = unsafe { ex.as_mut() } {
-                // Calculate disk block for the new extent
-                let diskblock = newext.pblock();
-
-                // Append new extent to existing one if possible
-                if current_ext.can_append(newext) {
-                    if current_ext.is_unwritten() {
-                        unsafe {
-                            Ext4Extent::mark_unwritten(current_ext);
-                        }
-                    }
-
-                    // Update the block count to include the new extent
-                    current_ext.block_count += newext.get_actual_len();
-                    path.p_block = diskblock as u64;
-                    return EOK; // Successful append
-                }
+        let new_block = self.balloc_alloc_block(node.indexes[pos].pblock() + 1);
+        if new_block == 0 {
+            return Errnum::ENOSPC as usize;
+        }
 
-                // Prepend new extent to existing one if possible
-                if current_ext.can_prepend(newext) {
-                    current_ext.first_block = newext.first_block;
-                    current_ext.block_count += newext.get_actual_len();
-                    path.p_block = diskblock as u64;
-
-                    if current_ext.is_unwritten() {
-                        unsafe {
-                            Ext4Extent::mark_unwritten(current_ext);
-                        }
-                    }
-                    return EOK; // Successful prepend
-                }
-            }
+        // 顺序写文件时新表项总在最后, 只移出最后一个表项, 让前面的节点保持满的;
+        // 否则从中间分开
+        let len = child.entries_len();
+        let at = if child.entry_first_block(len - 2) <= newext.first_block {
+            len - 1
+        } else {
+            len / 2
+        };
+        let mut sibling = child.split_off(at);
 
-            // If no existing extent to append or prepend, we need to insert a new extent
-            unsafe {
-                if eh.entries_count == eh.max_entries_count {
-                    *need_split = true;
-                    return EIO; // Indicate error due to max entries reached
-                }
+        let mut index = Ext4ExtentIndex::default();
+        index.first_block = sibling.entry_first_block(0);
+        index.store_pblock(new_block);
+        self.ext_write_node(new_block, &mut sibling);
+        node.indexes.insert(pos + 1, index);
+        EOK
+    }
 
-                // Insert the new extent if there is space
-                let first_extent = Ext4ExtentHeader::first_extent_mut(eh);
-                if ex.is_null() {
-                    path.extent = first_extent;
-                    *first_extent = *newext;
-                } else {
-                    // Handling inserting after the current extent
-                    let next_extent = ex.add(1);
-                    path.extent = next_extent;
-                    *next_extent = *newext;
-                }
+    /// inode中的根节点放不下了, 把它的内容移到一个新块中, 根节点变成
+    /// 只有一个索引的索引节点, 树加深一层
+    fn ext_grow_indepth(&mut self, root: &mut ExtentTreeNode) -> usize {
+        let new_block = self.balloc_alloc_block(0);
+        if new_block == 0 {
+            return Errnum::ENOSPC as usize;
+        }
+
+        let mut child = ExtentTreeNode::new(root.header.depth, ExtentTreeNode::BLOCK_MAX_ENTRIES);
+        child.append(root);
+
+        let mut index = Ext4ExtentIndex::default();
+        index.first_block = child.entry_first_block(0);
+        index.store_pblock(new_block);
+        self.ext_write_node(new_block, &mut child);
 
-                eh.entries_count += 1;
+        root.header.depth += 1;
+        root.indexes.push(index);
+        EOK
+    }
+
+    /// 把extent树节点写到块`block`中并设置校验和
+    fn ext_write_node(&self, block: Ext4Fsblk, node: &mut ExtentTreeNode) {
+        let mut data = vec![0u8; BLOCK_SIZE];
+        node.store(&mut data);
+        self.ext4_extent_block_csum_set(&mut data);
+        self.fs()
+            .block_device
+            .write_offset(block as usize * BLOCK_SIZE, &data);
+    }
+
+    /// 在叶子中按起始块顺序插入`newext`, 能和相邻的extent合并时直接合并.
+    /// 叶子满时多插入的一个表项由上层分裂出去
+    fn insert_leaf(&mut self, node: &mut ExtentTreeNode, newext: &Ext4Extent) -> usize {
+        // extents按起始块排序, 找到新extent的位置
+        let pos = node
+            .extents
+            .iter()
+            .position(|ex| ex.first_block > newext.first_block)
+            .unwrap_or(node.extents.len());
+
+        // Append new extent to the previous one if possible
+        if pos > 0 && node.extents[pos - 1].can_append(newext) {
+            node.extents[pos - 1].block_count += newext.get_actual_len();
+
+            // 填上空洞后可能和后一个extent也连起来了
+            if pos < node.extents.len() && node.extents[pos - 1].can_append(&node.extents[pos]) {
+                let next = node.extents.remove(pos);
+                node.extents[pos - 1].block_count += next.get_actual_len();
             }
+            return EOK;
+        }
+
+        // Prepend new extent to the next one if possible
+        if pos < node.extents.len() && node.extents[pos].can_prepend(newext) {
+            let next = &mut node.extents[pos];
+            next.first_block = newext.first_block;
+            next.store_pblock(newext.pblock() as u64);
+            next.block_count += newext.get_actual_len();
+            return EOK;
+        }
+
+        node.extents.insert(pos, *newext);
+        EOK
+    }
+
+    /// 设置磁盘上extent树节点末尾的校验和
+    pub fn ext4_extent_block_csum_set(&self, data: &mut [u8]) {
+        let header = Ext4ExtentHeader::try_from(&data[..]).unwrap();
+        let tail_offset = (header.max_entries_count as usize + 1) * size_of::<Ext4Extent>();
+        if tail_offset + 4 > data.len() {
+            return;
         }
 
-        EOK // Indicate successful operation
+        let uuid = self.fs().super_block.uuid;
+        let mut csum = ext4_crc32c(EXT4_CRC32_INIT, &uuid, uuid.len() as u32);
+        csum = ext4_crc32c(csum, &self.inode_num.to_le_bytes(), 4);
+        csum = ext4_crc32c(csum, &self.inner.inode.generation.to_le_bytes(), 4);
+        csum = ext4_crc32c(csum, &data[..tail_offset], tail_offset as u32);
+        data[tail_offset..tail_offset + 4].copy_from_slice(&csum.to_le_bytes());
     }
 
     #[allow(unused)]
@@ -747,7 +904,7 @@ impl Ext4InodeRef {
 
         let mut blocks_count = 0;
         // crate::ext4_extent_get_blocks(self,*iblock, 1, &mut current_fsblk, false, &mut blocks_count);
-        self.get_blocks_new(*iblock, 1, &mut current_fsblk, false, &mut blocks_count);
+        self.get_blocks_new(*iblock, 1, &mut current_fsblk, &mut blocks_count);
 
         current_block = current_fsblk;
         *fblock = current_block;
@@ -762,7 +919,7 @@ impl Ext4InodeRef {
 
         let mut blocks_count = 0;
 
-        self.get_blocks_new(*iblock, 1, &mut current_fsblk, false, &mut blocks_count);
+        self.get_blocks_new(*iblock, 1, &mut current_fsblk, &mut blocks_count);
 
         current_fsblk
     }
@@ -802,6 +959,9 @@ impl Ext4InodeRef {
 
         current_block = current_fsblk;
         *fblock = current_block;
+        if current_block == 0 {
+            return;
+        }
 
         log::debug!("[Ext4InodeRef::append_inode_dblk] inode size: {} -> {}", inode_size, inode_size + BLOCK_SIZE as u64);
         self.inner
@@ -969,69 +1129,38 @@ impl Ext4InodeRef {
 
         path
     }
-
     #[allow(unused)]
-
-    /// Gets blocks from an inode and manages extent creation if necessary.
+    /// 查找逻辑块`iblock`的映射, 没有映射(空洞)时`result`为0
     pub fn get_blocks_new(
         &mut self,
         iblock: Ext4Lblk,
         max_blocks: u32,
         result: &mut Ext4Fsblk,
-        create: bool,
         blocks_count: &mut u32,
     ) {
         *result = 0;
         *blocks_count = 0;
 
-        let mut path: Vec<Ext4ExtentPathNew> = self.find_extent_new(iblock);
-
-        let last = path.last().unwrap();
-
-        if let Some(pblock) = last.p_block {
-            let ee_start = pblock as u32;
-            let ee_block = last.first_block as u32;
-            let ee_len = last.block_count as u32;
-
-            if iblock >= ee_block && iblock < ee_block + ee_len as u32 {
-                let allocated = ee_len - (iblock - ee_block) as u32;
-                *blocks_count = allocated as u32;
-            }
-
-            let ex = Ext4Extent {
-                first_block: ee_block,
-                block_count: ee_len as u16,
-                start_hi: last.start_hi,
-                start_lo: last.start_lo,
-            };
-            if !create || ex.is_unwritten() {
-                *result = (iblock - ee_block + ee_start) as u64;
-                return; // Early return if no new extent needed
-            }
-        }
-        if create {
-            let mut allocated: u32 = 0;
-            let next = EXT_MAX_BLOCKS;
-
-            allocated = next - iblock;
-            if allocated > max_blocks {
-                allocated = max_blocks;
-            }
-
-            let mut newex: Ext4Extent = Ext4Extent::default();
-
-            let goal = 0;
+        let path: Vec<Ext4ExtentPathNew> = self.find_extent_new(iblock);
 
-            let mut alloc_block = 0;
-            alloc_block = self.balloc_alloc_block(goal as u64);
+        // 只有路径终止于叶子中的extent时才有映射
+        let last = match path.last() {
+            Some(last) if last.depth == 0 => last,
+            _ => return,
+        };
 
-            *result = alloc_block;
+        let ex = Ext4Extent {
+            first_block: last.first_block,
+            block_count: last.block_count,
+            start_hi: last.start_hi,
+            start_lo: last.start_lo,
+        };
+        let ee_block = ex.first_block;
+        let ee_len = ex.get_actual_len() as u32;
 
-            // 创建并插入新的extent
-            newex.first_block = iblock;
-            newex.start_lo = alloc_block as u32 & 0xffffffff;
-            newex.start_hi = (((alloc_block as u32) << 31) << 1) as u16;
-            newex.block_count = allocated as u16;
+        if iblock >= ee_block && iblock < ee_block + ee_len {
+            *blocks_count = core::cmp::min(ee_len - (iblock - ee_block), max_blocks);
+            *result = (iblock - ee_block) as u64 + ex.pblock() as u64;
         }
     }
 
@@ -1045,16 +1174,10 @@ impl Ext4InodeRef {
 
 impl Ext4InodeRef {
     pub fn ext4_dir_set_csum(&self, dst_blk: &mut Ext4Block) {
-        let parent_de = Ext4DirEntry::try_from(&dst_blk.block_data[..]).unwrap();
         let mut tail = Ext4DirEntryTail::from(&mut dst_blk.block_data, BLOCK_SIZE).unwrap();
 
-        let ino_gen = self.inner.inode.generation;
-        tail.ext4_dir_set_csum(
-            &self.fs().super_block,
-            &parent_de,
-            &dst_blk.block_data[..],
-            ino_gen,
-        );
+        // 校验和用的是目录自身的inode号, 不是块中第一个目录项的
+        tail.checksum = self.ext4_dir_get_csum(&self.fs().super_block, &dst_blk.block_data[..]);
 
         tail.copy_to_slice(&mut dst_blk.block_data);
     }
@@ -1062,107 +1185,72 @@ impl Ext4InodeRef {
 
 impl Ext4InodeRef {
     pub fn truncate_inode(&mut self, new_size: u64) -> Result<usize> {
-        let new_size = new_size as usize;
-
-        let old_size = self.inner.inode.inode_get_size() as usize;
+        let old_size = self.inner.inode.inode_get_size();
 
         if old_size == new_size {
             return Ok(EOK);
         }
 
-        let block_size = BLOCK_SIZE;
+        let block_size = BLOCK_SIZE as u64;
         let new_blocks_cnt = ((new_size + block_size - 1) / block_size) as u32;
-        let old_blocks_cnt = ((old_size + block_size - 1) / block_size) as u32;
-        let diff_blocks_cnt = old_blocks_cnt - new_blocks_cnt;
 
-        if diff_blocks_cnt > 0 {
-            let _r = self.extent_remove_space(new_blocks_cnt, EXT_MAX_BLOCKS as u32);
+        // 变大时只改大小, 中间是空洞
+        if new_size < old_size {
+            self.extent_remove_space(new_blocks_cnt)?;
         }
 
-        self.inner.inode.ext4_inode_set_size(new_size as u64);
+        // 较小的大小落在块中间时, 把块内之后的部分清零, 以后变大时才读到0
+        let keep = new_size.min(old_size);
+        if keep % block_size != 0 {
+            let pblock = self.get_pblock(&mut ((keep / block_size) as u32));
+            if pblock != 0 {
+                let offset = pblock as usize * BLOCK_SIZE;
+                let mut data = self.fs().block_device.read_offset(offset);
+                data[(keep % block_size) as usize..BLOCK_SIZE].fill(0);
+                self.fs().block_device.write_offset(offset, &data[..BLOCK_SIZE]);
+            }
+        }
+
+        self.inner.inode.ext4_inode_set_size(new_size);
         self.write_back_inode();
 
-        // return_errno_with_message!(Errnum::ENOTSUP, "not support");
         return Ok(EOK);
     }
 
-    pub fn extent_remove_space(&mut self, from: u32, to: u32) -> Result<usize> {
-        let depth = unsafe { self.inner.inode.extent_depth() } as usize;
-        let mut path: Option<Vec<Ext4ExtentPath>> = None;
-        let err = self.find_extent(from.into(), &mut path, 0);
-
-        if err != EOK {
-            return_errno_with_message!(
-                Errnum::ENOTSUP,
-                "extent_remove_space ext4_find_extent fail"
-            );
-        }
-
-        let path = path
-            .as_mut()
-            .expect("Path should not be None after successful find_extent");
-        let mut extent = unsafe { *path[depth].extent };
-
-        let in_range = (from..=to).contains(&(extent.first_block.into()));
-
-        if !in_range {
+    /// 释放逻辑块`from`及之后映射的所有块
+    pub fn extent_remove_space(&mut self, from: u32) -> Result<usize> {
+        // 快速符号链接等没有extent树
+        if self.inner.inode.flags & EXT4_INODE_FLAG_EXTENTS as u32 == 0 {
             return Ok(EOK);
         }
 
-        // remove_space inside the range of this extent
-        if (extent.first_block < from)
-            && (to < (extent.first_block + extent.block_count as u32 - 1))
-        {
-            let mut newex: Ext4Extent = Ext4Extent::default();
-            let unwritten = extent.is_unwritten();
-            let ee_block = extent.first_block;
-            let block_count = extent.block_count;
+        let mut root = ExtentTreeNode::load_from_header(&self.inner.inode.block[..]);
+        self.remove_space_in_node(&mut root, from)?;
 
-            let newblock = to + 1 - ee_block + extent.pblock();
-
-            extent.block_count = from as u16 - ee_block as u16;
-
-            if unwritten {
-                extent.mark_unwritten();
+        // 根节点只剩一个子节点并且放得下它的表项时, 把子节点提上来, 树变浅一层
+        while root.header.depth > 0 && root.indexes.len() == 1 {
+            let child_block = root.indexes[0].pblock();
+            let data = self
+                .fs()
+                .block_device
+                .read_offset(child_block as usize * BLOCK_SIZE);
+            let mut child = root.load_node(&data);
+            if child.entries_len() > root.header.max_entries_count as usize {
+                break;
             }
-
-            newex.first_block = to + 1;
-            newex.block_count = (ee_block + block_count as u32 - 1 - to) as u16;
-            newex.start_lo = newblock as u32 & 0xffffffff;
-            newex.start_hi = (((newblock as u32) << 31) << 1) as u16;
-
-            self.insert_extent(&mut path[0], &newex, 0);
+            root.indexes.clear();
+            root.header.depth = child.header.depth;
+            root.append(&mut child);
+            self.balloc_free_blocks(child_block, 1);
         }
 
-        if depth == 0 {
-            let header = Ext4ExtentHeader::try_from(&self.inner.inode.block[..]).unwrap();
-
-            let first_ex = Ext4Extent::try_from(&self.inner.inode.block[3..]).unwrap();
-            let entry_count = header.entries_count as usize;
-            let idx = entry_count * 3 as usize;
-            let last_ex = Ext4Extent::try_from(&self.inner.inode.block[idx..]).unwrap();
-
-            let mut leaf_from = first_ex.first_block;
-            let mut leaf_to = last_ex.first_block + last_ex.get_actual_len() as u32 - 1;
-
-            if leaf_from < from {
-                leaf_from = from;
-            }
-            if leaf_to > to {
-                leaf_to = to;
-            }
-            let _r = self.ext_remove_leaf(path, leaf_from, leaf_to);
-
-            // let node =  ExtentTreeNode::load_from_header(&self.inner.inode.block[..]);
+        if root.entries_len() == 0 {
+            // 整棵树都释放了, 根节点重新变成叶子
+            root.header.depth = 0;
         }
+        root.store(self.inner.inode.extent_root_mut());
+        self.write_back_inode();
 
-        let header = Ext4ExtentHeader::try_from(&self.inner.inode.block[..]).unwrap();
-
-        if header.entries_count == 0 {
-            let _header = Ext4ExtentHeader::try_from_u32(&mut self.inner.inode.block[..]);
-            self.inner.inode.block[3..].fill(0);
-            self.write_back_inode();
-        }
         Ok(EOK)
     }
 
@@ -1173,11 +1261,6 @@ impl Ext4InodeRef {
     // | extent 0 (10-14)   | extent 1 (20-29)   | extent 2 (35-42)   |
     // +------------------+------------------+------------------+
 
-    // extent 0 (10-14): (不在范围)
-    // +------------------+------------------+------------------+
-    // | extent 0 (10-14)   | extent 1 (20-29)   | extent 2 (35-42)   |
-    // +------------------+------------------+------------------+
-
     // 处理extent 1 (20-29):（在范围, 删除）
     // +------------------+------------------+------------------+
     // | extent 0 (10-14)   | 删除 (空)        | extent 2 (35-42)   |
@@ -1188,159 +1271,93 @@ impl Ext4InodeRef {
     // | extent 0 (10-14)   | extent 2 (35-42)   | (空)             |
     // +------------------+------------------+------------------+
 
-    // 最终结果:
-    // +------------------+------------------+------------------+
-    // | extent 0 (10-14)   | extent 1 (35-42)   | (空)             |
-    // +------------------+------------------+------------------+
-
-    pub fn ext_remove_leaf(
-        &mut self,
-        path: &mut Vec<Ext4ExtentPath>,
-        from: u32,
-        to: u32,
-    ) -> Result<usize> {
-        let depth = unsafe { self.inner.inode.extent_depth() } as usize;
-
-        let mut header = unsafe { *path[depth].header };
-
-        // let mut first = Ext4Extent::try_from(&self.inner.inode.block[3..]).unwrap();
-
-        // let mut first_ex = &mut first as *mut Ext4Extent;
-
-        // (first as *mut Ext4Extent as *mut u8) as *mut Ext4Extent
-
-        let mut new_entry_count = header.entries_count;
-
-        let entry_count = header.entries_count;
-        let start_ex = 0;
-
-        let mut ex2: Ext4Extent = Ext4Extent::default();
-
-        for i in 0..entry_count as usize {
-            let idx = 3 + i * 3;
-            let mut ex = Ext4Extent::try_from(&self.inner.inode.block[idx..]).unwrap();
+    /// 释放以`node`为根的子树中逻辑块`from`及之后的块, 修改过的子节点直接写回磁盘,
+    /// 变空的子节点连同索引一起删除
+    fn remove_space_in_node(&mut self, node: &mut ExtentTreeNode, from: u32) -> Result<usize> {
+        if node.header.depth == 0 {
+            return self.ext_remove_leaf(node, from);
+        }
 
-            if ex.first_block > to {
-                break;
+        let mut i = 0;
+        while i < node.indexes.len() {
+            // 下一个子树的起始块不大于from时, 这个子树完全在from之前
+            if i + 1 < node.indexes.len() && node.indexes[i + 1].first_block <= from {
+                i += 1;
+                continue;
             }
 
-            let mut new_len = 0;
-            let mut start = ex.first_block;
-            let mut new_start = ex.first_block;
-
-            let mut len = ex.get_actual_len();
-            let mut newblock = ex.pblock();
-
-            if start < from {
-                len -= from as u16 - start as u16;
-                new_len = from - start;
-                start = from;
-            } else {
-                if start + len as u32 - 1 > to {
-                    new_len = start + len as u32 - 1 - to;
-                    len -= new_len as u16;
-
-                    new_start = to + 1;
+            let child_block = node.indexes[i].pblock();
+            let data = self
+                .fs()
+                .block_device
+                .read_offset(child_block as usize * BLOCK_SIZE);
+            let mut child = node.load_node(&data);
 
-                    newblock += to + 1 - start;
+            self.remove_space_in_node(&mut child, from)?;
 
-                    ex2 = ex;
-                }
+            if child.entries_len() == 0 {
+                self.ext_remove_idx(node, i);
+                continue;
             }
 
-            self.ext_remove_blocks(&mut ex, start, start + len as u32 - 1);
-
-            ex.first_block = new_start;
-
-            if new_len == 0 {
-                new_entry_count -= 1;
-            } else {
-                let unwritten = ex.is_unwritten();
-                ex.store_pblock(newblock as u64);
-                ex.block_count = new_len as u16;
-
-                if unwritten {
-                    ex.mark_unwritten();
+            // 和左边的兄弟节点合起来放得下时并入兄弟节点, 释放这个子节点
+            if i > 0 {
+                let left_block = node.indexes[i - 1].pblock();
+                let left_data = self
+                    .fs()
+                    .block_device
+                    .read_offset(left_block as usize * BLOCK_SIZE);
+                let mut left = node.load_node(&left_data);
+                if left.entries_len() + child.entries_len()
+                    <= left.header.max_entries_count as usize
+                {
+                    left.append(&mut child);
+                    self.ext_write_node(left_block, &mut left);
+                    self.ext_remove_idx(node, i);
+                    continue;
                 }
             }
-        }
-
-        // Move any remaining extents to the starting position of the node.
-        if ex2.first_block > 0 {
-            let start_index = 3 + start_ex * 3;
-            let end_index = 3 + entry_count as usize * 3;
-            let remaining_extents: Vec<u32> =
-                self.inner.inode.block[start_index..end_index].to_vec();
-            self.inner.inode.block[3..3 + remaining_extents.len()]
-                .copy_from_slice(&remaining_extents);
-        }
-
-        // Update the entries count in the header
-        header.entries_count = new_entry_count;
-
-        // Fix the indexes if the extent pointer is at the first extent
-        if start_ex == 0 && new_entry_count > 0 {
-            self.ext_correct_indexes(path)?;
-        }
 
-        /* if this leaf is free, then we should
-         * remove it from index block above */
-
-        // fixme leaf is root should not be removed
-        if new_entry_count == 0 {
-            let mut header = Ext4ExtentHeader::try_from_u32(&mut self.inner.inode.block[..]);
-            header.entries_count = 0;
-            unsafe {
-                let header_ptr = &header as *const Ext4ExtentHeader as *const u32;
-                let array_ptr = &mut self.inner.inode.block as *mut [u32; 15] as *mut u32;
-                core::ptr::copy_nonoverlapping(header_ptr, array_ptr, 3);
-            }
+            self.ext_write_node(child_block, &mut child);
+            i += 1;
         }
 
         Ok(EOK)
     }
 
-    fn ext_correct_indexes(&mut self, path: &mut Vec<Ext4ExtentPath>) -> Result<usize> {
-        let depth = unsafe { self.inner.inode.extent_depth() } as usize;
-
-        let ex = path[depth].extent;
-        let header = path[depth].header;
-
-        if ex.is_null() || header.is_null() {
-            return_errno_with_message!(Errnum::EIO, "no header");
-        }
-
-        // let mut header = unsafe { *path[depth].header };
+    /// 截断叶子中逻辑块`from`及之后的extent并释放对应的块
+    pub fn ext_remove_leaf(&mut self, node: &mut ExtentTreeNode, from: u32) -> Result<usize> {
+        let mut i = 0;
+        while i < node.extents.len() {
+            let mut ex = node.extents[i];
+            let first = ex.first_block;
+            let last = first + ex.get_actual_len() as u32 - 1;
 
-        if depth == 0 {
-            // there is no tree at all
-            return Ok(EOK);
-        }
-
-        if ex != unsafe { (*path[depth].header).first_extent_mut() } {
-            // we correct tree if first leaf got modified only
-            return Ok(EOK);
-        }
+            if first >= from {
+                self.ext_remove_blocks(&mut ex, first, last);
+                node.extents.remove(i);
+                continue;
+            }
 
-        let mut k = depth - 1;
-        let border = unsafe { *ex }.first_block;
-        unsafe { *path[k].index }.first_block = border;
+            if last >= from {
+                self.ext_remove_blocks(&mut ex, from, last);
 
-        while k > 0 {
-            // change all left-side indexes
-            if path[k + 1].index != unsafe { (*path[k + 1].header).first_extent_index_mut() } {
-                break;
+                let unwritten = ex.is_unwritten();
+                node.extents[i].block_count = (from - first) as u16;
+                if unwritten {
+                    node.extents[i].mark_unwritten();
+                }
             }
-            unsafe { *path[k].index }.first_block = border;
-            k -= 1;
+            i += 1;
         }
 
         Ok(EOK)
     }
 
-    #[allow(unused)]
-    fn ext_remove_idx(&mut self, _path: &mut Vec<Ext4ExtentPath>, _depth: i32) -> Result<usize> {
-        Ok(EOK)
+    /// 删除`node`的第`i`个索引并释放它指向的节点块
+    fn ext_remove_idx(&mut self, node: &mut ExtentTreeNode, i: usize) {
+        let index = node.indexes.remove(i);
+        self.balloc_free_blocks(index.pblock(), 1);
     }
 
     pub fn ext_remove_blocks(&mut self, ex: &mut Ext4Extent, from: u32, to: u32) {
@@ -1355,7 +1372,7 @@ impl Ext4InodeRef {
 
     #[allow(unused)]
     pub fn balloc_free_blocks(&mut self, start: Ext4Fsblk, count: u32) {
-        let mut count = count as usize;
+        let mut count = count as u64;
         let mut start = start;
 
         let fs = self.fs();
@@ -1365,73 +1382,56 @@ impl Ext4InodeRef {
         let super_block_data = block_device.read_offset(crate::BASE_OFFSET);
         let mut super_block = Ext4Superblock::try_from(super_block_data).unwrap();
 
-        // let inodes_per_group = super_block.inodes_per_group();
-        let blocks_per_group = super_block.blocks_per_group();
-
-        let bgid = start / blocks_per_group as u64;
-
-        let mut bg_first = start / blocks_per_group as u64;
-        let mut bg_last = (start + count as u64 - 1) / blocks_per_group as u64;
+        let blocks_per_group = super_block.blocks_per_group() as u64;
+        let first_data_block = super_block.first_data_block as u64;
 
-        while bg_first <= bg_last {
-            let idx_in_bg = start % blocks_per_group as u64;
+        // 一次处理一个块组中的部分
+        while count > 0 {
+            let bgid = (start - first_data_block) / blocks_per_group;
+            let idx_in_bg = (start - first_data_block) % blocks_per_group;
+            let free_cnt = core::cmp::min(count, blocks_per_group - idx_in_bg);
 
             let mut bg =
-                Ext4BlockGroup::load(block_device.clone(), &super_block, bg_first as usize)
-                    .unwrap();
+                Ext4BlockGroup::load(block_device.clone(), &super_block, bgid as usize).unwrap();
 
             let block_bitmap_block = bg.get_block_bitmap_block(&super_block);
-            let mut raw_data = block_device.read_offset(block_bitmap_block as usize * BLOCK_SIZE);
-            let mut data: &mut Vec<u8> = &mut raw_data;
+            let mut data = block_device.read_offset(block_bitmap_block as usize * BLOCK_SIZE);
 
-            let mut free_cnt = BLOCK_SIZE * 8 - idx_in_bg as usize;
-
-            if count as usize > free_cnt {
-            } else {
-                free_cnt = count as usize;
-            }
-
-            ext4_bmap_bits_free(&mut data, idx_in_bg as u32, free_cnt as u32);
-
-            count -= free_cnt;
-            start += free_cnt as u64;
+            ext4_bmap_bits_free(
+                &mut data,
+                idx_in_bg as u32,
+                (idx_in_bg + free_cnt - 1) as u32,
+            );
 
             bg.set_block_group_balloc_bitmap_csum(&super_block, &data);
             block_device.write_offset(block_bitmap_block as usize * BLOCK_SIZE, &data);
 
             /* Update superblock free blocks count */
-            let mut super_blk_free_blocks = super_block.free_blocks_count();
-
-            // fixme
-            super_blk_free_blocks += free_cnt as u64;
+            let super_blk_free_blocks = super_block.free_blocks_count() + free_cnt;
             super_block.set_free_blocks_count(super_blk_free_blocks);
             super_block.sync_to_disk_with_csum(block_device.clone());
 
-            let super_block_data = block_device.read_offset(crate::BASE_OFFSET);
-            let mut super_block = Ext4Superblock::try_from(super_block_data).unwrap();
-
             /* Update inode blocks (different block size!) count */
             let mut inode_blocks = self.inner.inode.ext4_inode_get_blocks_count();
-
-            inode_blocks -= (free_cnt as usize * (BLOCK_SIZE / EXT4_INODE_BLOCK_SIZE)) as u64;
+            inode_blocks -= free_cnt * (BLOCK_SIZE / EXT4_INODE_BLOCK_SIZE) as u64;
             self.inner
                 .inode
                 .ext4_inode_set_blocks_count(inode_blocks as u32);
             self.write_back_inode();
 
             /* Update block group free blocks count */
-            let mut fb_cnt = bg.get_free_blocks_count();
-            fb_cnt += free_cnt as u64;
+            let fb_cnt = bg.get_free_blocks_count() + free_cnt;
             bg.set_free_blocks_count(fb_cnt as u32);
             bg.sync_to_disk_with_csum(block_device.clone(), bgid as usize, &super_block);
 
-            bg_first += 1;
+            count -= free_cnt;
+            start += free_cnt;
         }
     }
 
     pub fn ext4_dir_get_csum(&self, s: &Ext4Superblock, blk_data: &[u8]) -> u32 {
         let ino_index = self.inode_num;
-        let ino_gen = 0 as u32;
+        let ino_gen = self.inner.inode.generation;
 
         let mut csum;
 
@@ -1440,11 +1440,9 @@ impl Ext4InodeRef {
         csum = ext4_crc32c(EXT4_CRC32_INIT, &uuid, uuid.len() as u32);
         csum = ext4_crc32c(csum, &ino_index.to_le_bytes(), 4);
         csum = ext4_crc32c(csum, &ino_gen.to_le_bytes(), 4);
-        let mut data = [0u8; 0xff4];
-        unsafe {
-            core::ptr::copy_nonoverlapping(blk_data.as_ptr(), data.as_mut_ptr(), blk_data.len());
-        }
-        csum = ext4_crc32c(csum, &data[..], 0xff4);
+        // 不包括块末尾的tail
+        let len = BLOCK_SIZE - size_of::<Ext4DirEntryTail>();
+        csum = ext4_crc32c(csum, &blk_data[..len], len as u32);
         csum
     }
 
@@ -1516,10 +1514,14 @@ impl Ext4InodeRef {
         while iblock < total_blocks {
             let path: Vec<Ext4ExtentPathNew> = self.find_extent_foo(iblock);
 
-            if path.len() == 0 {
-                return false;
-            }
-            let last = path.last().unwrap();
+            // 空洞中没有目录项
+            let last = match path.last() {
+                Some(last) if last.depth == 0 => last,
+                _ => {
+                    iblock += 1;
+                    continue;
+                }
+            };
             if let Some(pblock) = last.p_block {
                 let ee_start = pblock as u32;
                 let ee_block = last.first_block as u32;
@@ -1541,6 +1543,9 @@ impl Ext4InodeRef {
             let mut offset = 0;
             while offset < ext4_block.block_data.len() {
                 let de = Ext4DirEntry::try_from(&ext4_block.block_data[offset..]).unwrap();
+                if de.entry_len == 0 {
+                    break;
+                }
                 offset = offset + de.entry_len as usize;
                 if de.inode == 0 {
                     continue;
@@ -1565,3 +1570,23 @@ impl Ext4InodeRef {
         self.inode_has_entry()
     }
 }
+
+#[cfg(test)]
+mod rdev_tests {
+    use super::*;
+    #[test]
+    fn test_ext4_inode_rdev() {
+        let mut inode = Ext4Inode::default();
+
+        // 主次设备号都小于256, 用旧格式
+        inode.ext4_inode_set_rdev((1 << 8) | 3);
+        assert_eq!(inode.block[0], 0x0103);
+        assert_eq!(inode.ext4_inode_get_rdev(), (1 << 8) | 3);
+
+        // 主设备号超过255, 用新格式
+        let rdev = (300 << 8) | 3;
+        inode.ext4_inode_set_rdev(rdev);
+        assert_eq!(inode.block[0], 0);
+        assert_eq!(inode.ext4_inode_get_rdev(), rdev);
+    }
+}
@@ -177,15 +177,48 @@ impl Ext4Superblock {
 
     /// Returns the number of block groups.
     pub fn block_groups_count(&self) -> u32 {
-        let cnt = (((self.blocks_count_hi.to_le() as u64) << 32) as u32 | self.blocks_count_lo)
-            / self.blocks_per_group;
+        // 最后一个块组可能不满
+        let data_blocks = self.blocks_count() - self.first_data_block;
+        let cnt = (data_blocks + self.blocks_per_group - 1) / self.blocks_per_group;
         if cnt == 0 {
             1
         } else {
             cnt
-        } 
+        }
+    }
+
+    /// Returns the number of blocks in block group `bgid`, the last group may be smaller.
+    pub fn get_blocks_in_group_cnt(&self, bgid: u32) -> u32 {
+        let block_group_count = self.block_groups_count();
+        if bgid < block_group_count - 1 {
+            self.blocks_per_group
+        } else {
+            self.blocks_count() - self.first_data_block - (block_group_count - 1) * self.blocks_per_group
+        }
+    }
+
+    pub fn mkfs_time(&self) -> u32 {
+        self.mkfs_time
+    }
+
+    pub fn has_journal(&self) -> bool {
+        self.features_compatible & EXT4_FEATURE_COMPAT_HAS_JOURNAL != 0
+    }
+
+    pub fn journal_inode_number(&self) -> u32 {
+        self.journal_inode_number
+    }
+
+    pub fn needs_recovery(&self) -> bool {
+        self.features_incompatible & EXT4_FEATURE_INCOMPAT_RECOVER != 0
+    }
 
-        // cnt
+    pub fn set_needs_recovery(&mut self, recover: bool) {
+        if recover {
+            self.features_incompatible |= EXT4_FEATURE_INCOMPAT_RECOVER;
+        } else {
+            self.features_incompatible &= !EXT4_FEATURE_INCOMPAT_RECOVER;
+        }
     }
 
     pub fn blocks_count(&self) -> u32 {
@@ -210,7 +243,7 @@ impl Ext4Superblock {
         let block_group_count = self.block_groups_count();
         let inodes_per_group = self.inodes_per_group;
 
-        let total_inodes = ((self.inodes_count as u64) << 32) as u32;
+        let total_inodes = self.inodes_count;
         if bgid < block_group_count - 1 {
             inodes_per_group
         } else {
@@ -222,6 +255,10 @@ impl Ext4Superblock {
         self.free_inodes_count -= 1;
     }
 
+    pub fn increase_free_inodes_count(&mut self) {
+        self.free_inodes_count += 1;
+    }
+
     pub fn free_blocks_count(&self) -> u64 {
         self.free_blocks_count_lo as u64 | ((self.free_blocks_count_hi as u64) << 32).to_le()
     }
@@ -239,21 +276,24 @@ impl Ext4Superblock {
         block_device.write_offset(BASE_OFFSET, data);
     }
 
-    pub fn sync_to_disk_with_csum(&mut self, block_device: Arc<dyn BlockDevice>) {
+    pub fn set_checksum(&mut self) {
         let data = unsafe {
             core::slice::from_raw_parts(self as *const _ as *const u8, size_of::<Ext4Superblock>())
         };
-        let checksum = ext4_crc32c(
-            EXT4_CRC32_INIT,
-            &data,
-            0x3fc,
-        );
+        let checksum = ext4_crc32c(EXT4_CRC32_INIT, &data, 0x3fc);
 
         self.checksum = checksum;
-        let data = unsafe {
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
             core::slice::from_raw_parts(self as *const _ as *const u8, size_of::<Ext4Superblock>())
-        };
-        block_device.write_offset(BASE_OFFSET, data);
+        }
+    }
+
+    pub fn sync_to_disk_with_csum(&mut self, block_device: Arc<dyn BlockDevice>) {
+        self.set_checksum();
+        block_device.write_offset(BASE_OFFSET, self.as_bytes());
     }
 
     // pub fn sync_super_block_to_disk(&self, block_device: Arc<dyn BlockDevice>){
@@ -0,0 +1,737 @@
+//! jbd2日志. 事务中修改的元数据块先缓存在内存中, 事务结束时写入日志inode,
+//! 提交块落盘后再写回原位置, 然后清空日志. 打开文件系统时重放日志中
+//! 已经提交但还没有写回原位置的事务
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::consts::*;
+use crate::prelude::*;
+use crate::utils::*;
+use crate::{return_errno_with_message, BlockDevice, Ext4Superblock, Jbd2};
+use spin::{Mutex, Once};
+
+fn be16(data: &[u8], off: usize) -> u16 {
+    u16::from_be_bytes([data[off], data[off + 1]])
+}
+
+fn be32(data: &[u8], off: usize) -> u32 {
+    u32::from_be_bytes([data[off], data[off + 1], data[off + 2], data[off + 3]])
+}
+
+fn put_be16(data: &mut [u8], off: usize, value: u16) {
+    data[off..off + 2].copy_from_slice(&value.to_be_bytes());
+}
+
+fn put_be32(data: &mut [u8], off: usize, value: u32) {
+    data[off..off + 4].copy_from_slice(&value.to_be_bytes());
+}
+
+// 日志超级块中字段的偏移
+const JSB_BLOCKSIZE: usize = 0x0C;
+const JSB_MAXLEN: usize = 0x10;
+const JSB_FIRST: usize = 0x14;
+const JSB_SEQUENCE: usize = 0x18;
+const JSB_START: usize = 0x1C;
+const JSB_FEATURE_INCOMPAT: usize = 0x28;
+const JSB_UUID: usize = 0x30;
+const JSB_CHECKSUM: usize = 0xFC;
+const JSB_SIZE: usize = 1024;
+
+/// 块头: 魔数, 块类型, 事务编号
+const JBD2_HEADER_SIZE: usize = 12;
+/// 提交块中第一个校验和的偏移
+const JBD2_COMMIT_CHKSUM: usize = 16;
+/// 撤销块中记录已用字节数的字段
+const JBD2_REVOKE_COUNT: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecoveryPass {
+    /// 找到最后一个完整提交的事务
+    Scan,
+    /// 收集撤销记录
+    Revoke,
+    /// 把日志中的块写回原位置
+    Replay,
+}
+
+pub struct Jbd2Journal {
+    /// 下层块设备, 日志本身的读写不经过事务
+    device: Arc<dyn BlockDevice>,
+    /// 日志inode的逻辑块到物理块的映射
+    blocks: Vec<u64>,
+    /// 日志超级块(日志inode的第0块)
+    jsb: Vec<u8>,
+    /// 下一个提交的事务的编号
+    sequence: u32,
+    /// 嵌套的事务层数, 最外层的事务结束时才提交
+    depth: usize,
+    /// 事务被放弃, 最外层结束时丢掉所有修改
+    aborted: bool,
+    /// 当前事务预留的块数
+    credits: usize,
+    /// 当前事务修改过的块, 块号到块的新内容
+    dirty: BTreeMap<u64, Vec<u8>>,
+}
+
+impl Debug for Jbd2Journal {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Jbd2Journal")
+            .field("blocks", &self.blocks.len())
+            .field("sequence", &self.sequence)
+            .field("depth", &self.depth)
+            .field("credits", &self.credits)
+            .field("dirty", &self.dirty.len())
+            .finish()
+    }
+}
+
+impl Jbd2 for Jbd2Journal {
+    fn load_journal(&mut self) {
+        self.jsb = self.read_jblock(0);
+        self.sequence = be32(&self.jsb, JSB_SEQUENCE);
+    }
+
+    fn journal_start(&mut self) {
+        self.sequence = be32(&self.jsb, JSB_SEQUENCE);
+        self.depth = 0;
+        self.aborted = false;
+        self.credits = 0;
+        self.dirty.clear();
+    }
+
+    fn transaction_start(&mut self) {
+        self.depth += 1;
+    }
+
+    fn write_transaction(&mut self, block_id: usize, block_data: Vec<u8>) {
+        // 超出日志容量的事务不能拆开提交, 最外层结束时整个放弃
+        let block_id = block_id as u64;
+        if self.dirty.insert(block_id, block_data).is_none()
+            && self.dirty.len() == self.max_transaction_blocks() + 1
+        {
+            log::warn!("[Jbd2Journal] transaction exceeds the journal, will abort");
+        }
+    }
+
+    fn transaction_stop(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+        if self.depth > 0 {
+            return;
+        }
+        if self.aborted || self.overflowed() {
+            self.aborted = false;
+            self.dirty.clear();
+        } else {
+            self.commit();
+        }
+        self.credits = 0;
+    }
+
+    fn journal_stop(&mut self) {
+        self.commit();
+        self.depth = 0;
+    }
+
+    fn recover(&mut self) {
+        if be32(&self.jsb, JSB_START) != 0 {
+            let mut revoked = BTreeMap::new();
+            let end_seq = self.do_one_pass(RecoveryPass::Scan, 0, &mut revoked);
+            self.do_one_pass(RecoveryPass::Revoke, end_seq, &mut revoked);
+            self.do_one_pass(RecoveryPass::Replay, end_seq, &mut revoked);
+            self.device.flush();
+            log::info!(
+                "[Jbd2Journal] recovered transactions {} to {}",
+                be32(&self.jsb, JSB_SEQUENCE),
+                end_seq
+            );
+            self.sequence = end_seq;
+            self.set_log_start(0, end_seq);
+        }
+        self.set_needs_recovery(false);
+        self.device.flush();
+    }
+}
+
+impl Jbd2Journal {
+    /// `blocks`是日志inode的块映射
+    pub fn new(device: Arc<dyn BlockDevice>, blocks: Vec<u64>) -> Self {
+        let mut journal = Self {
+            device,
+            blocks,
+            jsb: Vec::new(),
+            sequence: 0,
+            depth: 0,
+            aborted: false,
+            credits: 0,
+            dirty: BTreeMap::new(),
+        };
+        journal.load_journal();
+        journal
+    }
+
+    /// 日志超级块是否有效, 只支持和文件系统相同的块大小
+    pub fn is_valid(&self) -> bool {
+        let blocktype = be32(&self.jsb, 4);
+        be32(&self.jsb, 0) == JBD2_MAGIC_NUMBER
+            && (blocktype == JBD2_SUPERBLOCK_V1 || blocktype == JBD2_SUPERBLOCK_V2)
+            && be32(&self.jsb, JSB_BLOCKSIZE) as usize == BLOCK_SIZE
+            && self.first() > 0
+            && self.first() < self.maxlen()
+    }
+
+    pub fn in_transaction(&self) -> bool {
+        self.depth > 0
+    }
+
+    /// 当前事务中块`block_id`的内容
+    pub fn get_dirty(&self, block_id: u64) -> Option<&Vec<u8>> {
+        self.dirty.get(&block_id)
+    }
+
+    /// 块`block_id`不再属于事务, 它的内容由调用者直接写入磁盘
+    pub fn forget(&mut self, block_id: u64) {
+        self.dirty.remove(&block_id);
+    }
+
+    pub fn transaction_abort(&mut self) {
+        self.aborted = true;
+        self.transaction_stop();
+    }
+
+    /// 为当前事务预留`credits`个块, 嵌套的事务累加. 日志放不下时返回false
+    pub fn reserve_credits(&mut self, credits: usize) -> bool {
+        if self.credits + credits > self.max_transaction_blocks() {
+            return false;
+        }
+        self.credits += credits;
+        true
+    }
+
+    /// 当前事务修改的块已经超过日志的容量
+    pub fn overflowed(&self) -> bool {
+        self.dirty.len() > self.max_transaction_blocks()
+    }
+
+    fn maxlen(&self) -> u32 {
+        core::cmp::min(be32(&self.jsb, JSB_MAXLEN), self.blocks.len() as u32)
+    }
+
+    fn first(&self) -> u32 {
+        be32(&self.jsb, JSB_FIRST)
+    }
+
+    fn has_feature(&self, feature: u32) -> bool {
+        be32(&self.jsb, 4) == JBD2_SUPERBLOCK_V2
+            && be32(&self.jsb, JSB_FEATURE_INCOMPAT) & feature != 0
+    }
+
+    fn has_csum(&self) -> bool {
+        self.has_feature(JBD2_FEATURE_INCOMPAT_CSUM_V2 | JBD2_FEATURE_INCOMPAT_CSUM_V3)
+    }
+
+    fn csum_seed(&self) -> u32 {
+        ext4_crc32c(EXT4_CRC32_INIT, &self.jsb[JSB_UUID..JSB_UUID + 16], 16)
+    }
+
+    /// 描述符块中一个tag的长度
+    fn tag_bytes(&self) -> usize {
+        if self.has_feature(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            return 16;
+        }
+        let mut size = 12;
+        if self.has_feature(JBD2_FEATURE_INCOMPAT_CSUM_V2) {
+            size += 2;
+        }
+        if self.has_feature(JBD2_FEATURE_INCOMPAT_64BIT) {
+            size
+        } else {
+            size - 4
+        }
+    }
+
+    /// 每个描述符块能描述的块数, 第一个tag后面跟着16字节的uuid
+    fn tags_per_descriptor(&self) -> usize {
+        let tail = if self.has_csum() { 4 } else { 0 };
+        (BLOCK_SIZE - JBD2_HEADER_SIZE - tail - 16) / self.tag_bytes()
+    }
+
+    /// 一个事务最多能包含的块数, 还要留出描述符块和提交块的位置
+    fn max_transaction_blocks(&self) -> usize {
+        let space = (self.maxlen() - self.first()) as usize - 1;
+        let per_desc = self.tags_per_descriptor();
+        space * per_desc / (per_desc + 1)
+    }
+
+    fn next_pos(&self, pos: u32) -> u32 {
+        if pos + 1 >= self.maxlen() {
+            self.first()
+        } else {
+            pos + 1
+        }
+    }
+
+    fn read_jblock(&self, pos: u32) -> Vec<u8> {
+        self.device
+            .read_offset(self.blocks[pos as usize] as usize * BLOCK_SIZE)
+    }
+
+    fn write_jblock(&self, pos: u32, data: &[u8]) {
+        self.device
+            .write_offset(self.blocks[pos as usize] as usize * BLOCK_SIZE, data);
+    }
+
+    fn block_csum(&self, data: &[u8]) -> u32 {
+        ext4_crc32c(self.csum_seed(), data, data.len() as u32)
+    }
+
+    /// 描述符块末尾的校验和, 计算时这4字节为0
+    fn descriptor_csum_ok(&self, block: &[u8]) -> bool {
+        let mut data = block.to_vec();
+        put_be32(&mut data, BLOCK_SIZE - 4, 0);
+        be32(block, BLOCK_SIZE - 4) == self.block_csum(&data)
+    }
+
+    fn commit_csum_ok(&self, block: &[u8]) -> bool {
+        let mut data = block.to_vec();
+        put_be32(&mut data, JBD2_COMMIT_CHKSUM, 0);
+        be32(block, JBD2_COMMIT_CHKSUM) == self.block_csum(&data)
+    }
+
+    fn put_header(&self, block: &mut [u8], blocktype: u32, seq: u32) {
+        put_be32(block, 0, JBD2_MAGIC_NUMBER);
+        put_be32(block, 4, blocktype);
+        put_be32(block, 8, seq);
+    }
+
+    /// 描述符块中的(块号, 标志)
+    fn parse_tags(&self, block: &[u8]) -> Vec<(u64, u16)> {
+        let tag_bytes = self.tag_bytes();
+        let end = BLOCK_SIZE - if self.has_csum() { 4 } else { 0 };
+        let mut tags = Vec::new();
+        let mut off = JBD2_HEADER_SIZE;
+        while off + tag_bytes <= end {
+            let mut blocknr = be32(block, off) as u64;
+            if self.has_feature(JBD2_FEATURE_INCOMPAT_64BIT) {
+                blocknr |= (be32(block, off + 8) as u64) << 32;
+            }
+            // tag3的标志是32位的, 低16位和普通tag的标志在同一位置
+            let flags = be16(block, off + 6);
+            tags.push((blocknr, flags));
+
+            off += tag_bytes;
+            if flags & JBD2_FLAG_SAME_UUID == 0 {
+                off += 16;
+            }
+            if flags & JBD2_FLAG_LAST_TAG != 0 {
+                break;
+            }
+        }
+        tags
+    }
+
+    fn put_tag(&self, block: &mut [u8], off: usize, blocknr: u64, flags: u16, csum: u32) {
+        put_be32(block, off, blocknr as u32);
+        if self.has_feature(JBD2_FEATURE_INCOMPAT_CSUM_V3) {
+            put_be32(block, off + 4, flags as u32);
+            put_be32(block, off + 8, (blocknr >> 32) as u32);
+            put_be32(block, off + 12, csum);
+            return;
+        }
+        put_be16(block, off + 4, csum as u16);
+        put_be16(block, off + 6, flags);
+        if self.has_feature(JBD2_FEATURE_INCOMPAT_64BIT) {
+            put_be32(block, off + 8, (blocknr >> 32) as u32);
+        }
+    }
+
+    /// 修改日志超级块中日志的起始位置和第一个事务的编号, `start`为0表示日志为空
+    fn set_log_start(&mut self, start: u32, seq: u32) {
+        put_be32(&mut self.jsb, JSB_START, start);
+        put_be32(&mut self.jsb, JSB_SEQUENCE, seq);
+        if self.has_csum() {
+            put_be32(&mut self.jsb, JSB_CHECKSUM, 0);
+            let csum = ext4_crc32c(EXT4_CRC32_INIT, &self.jsb[..JSB_SIZE], JSB_SIZE as u32);
+            put_be32(&mut self.jsb, JSB_CHECKSUM, csum);
+        }
+        let jsb = core::mem::take(&mut self.jsb);
+        self.write_jblock(0, &jsb);
+        self.jsb = jsb;
+    }
+
+    /// 设置或清除文件系统超级块中的RECOVER标志
+    fn set_needs_recovery(&self, recover: bool) {
+        let raw_data = self.device.read_offset(BASE_OFFSET);
+        let mut super_block = Ext4Superblock::try_from(raw_data).unwrap();
+        if super_block.needs_recovery() != recover {
+            super_block.set_needs_recovery(recover);
+            super_block.sync_to_disk_with_csum(self.device.clone());
+        }
+    }
+
+    /// 写回原位置的超级块保留RECOVER标志, 日志清空后才清除
+    fn keep_recover_flag(block: &mut [u8]) {
+        let sb_size = core::mem::size_of::<Ext4Superblock>();
+        let sb_data = block[BASE_OFFSET..BASE_OFFSET + sb_size].to_vec();
+        let mut super_block = Ext4Superblock::try_from(sb_data).unwrap();
+        super_block.set_needs_recovery(true);
+        super_block.set_checksum();
+        block[BASE_OFFSET..BASE_OFFSET + sb_size].copy_from_slice(super_block.as_bytes());
+    }
+
+    /// 把当前事务写入日志, 提交后写回原位置并清空日志
+    fn commit(&mut self) {
+        if self.dirty.is_empty() {
+            return;
+        }
+        let blocks: Vec<(u64, Vec<u8>)> = core::mem::take(&mut self.dirty).into_iter().collect();
+        let seq = self.sequence;
+        let first = self.first();
+        let tag_bytes = self.tag_bytes();
+        let v3 = self.has_feature(JBD2_FEATURE_INCOMPAT_CSUM_V3);
+
+        // 描述符块后面跟着它描述的块的副本
+        let mut pos = first;
+        for chunk in blocks.chunks(self.tags_per_descriptor()) {
+            let desc_pos = pos;
+            pos = self.next_pos(pos);
+
+            let mut desc = vec![0u8; BLOCK_SIZE];
+            self.put_header(&mut desc, JBD2_DESCRIPTOR_BLOCK, seq);
+            let mut off = JBD2_HEADER_SIZE;
+            for (i, (blocknr, data)) in chunk.iter().enumerate() {
+                let mut flags = 0;
+                if i > 0 {
+                    flags |= JBD2_FLAG_SAME_UUID;
+                }
+                if i == chunk.len() - 1 {
+                    flags |= JBD2_FLAG_LAST_TAG;
+                }
+                // 以魔数开头的块在日志中会被当成日志块, 写入时清掉魔数, 重放时恢复
+                let mut copy = data.clone();
+                if be32(&copy, 0) == JBD2_MAGIC_NUMBER {
+                    flags |= JBD2_FLAG_ESCAPE;
+                    put_be32(&mut copy, 0, 0);
+                }
+
+                let csum = if self.has_csum() {
+                    let csum = ext4_crc32c(self.csum_seed(), &seq.to_be_bytes(), 4);
+                    let csum = ext4_crc32c(csum, &copy, BLOCK_SIZE as u32);
+                    if v3 {
+                        csum
+                    } else {
+                        csum & 0xFFFF
+                    }
+                } else {
+                    0
+                };
+                self.put_tag(&mut desc, off, *blocknr, flags, csum);
+                off += tag_bytes;
+                if i == 0 {
+                    desc[off..off + 16].copy_from_slice(&self.jsb[JSB_UUID..JSB_UUID + 16]);
+                    off += 16;
+                }
+
+                self.write_jblock(pos, &copy);
+                pos = self.next_pos(pos);
+            }
+            if self.has_csum() {
+                let csum = self.block_csum(&desc);
+                put_be32(&mut desc, BLOCK_SIZE - 4, csum);
+            }
+            self.write_jblock(desc_pos, &desc);
+        }
+        self.device.flush();
+
+        // 日志超级块指向这个事务后再写提交块, 提交块落盘时事务才算完成
+        self.set_log_start(first, seq);
+        self.set_needs_recovery(true);
+        self.device.flush();
+
+        let mut commit = vec![0u8; BLOCK_SIZE];
+        self.put_header(&mut commit, JBD2_COMMIT_BLOCK, seq);
+        if self.has_csum() {
+            let csum = self.block_csum(&commit);
+            put_be32(&mut commit, JBD2_COMMIT_CHKSUM, csum);
+        }
+        self.write_jblock(pos, &commit);
+        self.device.flush();
+
+        // 写回原位置, 之后日志中的内容就不再需要了
+        let sb_block = (BASE_OFFSET / BLOCK_SIZE) as u64;
+        for (blocknr, mut data) in blocks {
+            if blocknr == sb_block {
+                Self::keep_recover_flag(&mut data);
+            }
+            self.device
+                .write_offset(blocknr as usize * BLOCK_SIZE, &data);
+        }
+        self.device.flush();
+
+        self.sequence = seq.wrapping_add(1);
+        self.set_log_start(0, self.sequence);
+        self.set_needs_recovery(false);
+        self.device.flush();
+    }
+
+    /// 从日志起始位置扫描事务. `Scan`返回最后一个完整提交的事务的下一个编号,
+    /// 其余两遍只处理编号小于`end_seq`的事务
+    fn do_one_pass(
+        &self,
+        pass: RecoveryPass,
+        end_seq: u32,
+        revoked: &mut BTreeMap<u64, u32>,
+    ) -> u32 {
+        let mut seq = be32(&self.jsb, JSB_SEQUENCE);
+        let mut pos = be32(&self.jsb, JSB_START);
+        let revoke_record_size = if self.has_feature(JBD2_FEATURE_INCOMPAT_64BIT) {
+            8
+        } else {
+            4
+        };
+
+        loop {
+            if pass != RecoveryPass::Scan && seq == end_seq {
+                break;
+            }
+            let block = self.read_jblock(pos);
+            if be32(&block, 0) != JBD2_MAGIC_NUMBER || be32(&block, 8) != seq {
+                break;
+            }
+
+            match be32(&block, 4) {
+                JBD2_DESCRIPTOR_BLOCK => {
+                    if self.has_csum() && !self.descriptor_csum_ok(&block) {
+                        break;
+                    }
+                    for (blocknr, flags) in self.parse_tags(&block) {
+                        pos = self.next_pos(pos);
+                        if pass != RecoveryPass::Replay {
+                            continue;
+                        }
+                        // 在这个事务或之后的事务中被撤销的块不再重放
+                        if revoked.get(&blocknr).is_some_and(|&rseq| rseq >= seq) {
+                            continue;
+                        }
+                        let mut data = self.read_jblock(pos);
+                        if flags & JBD2_FLAG_ESCAPE != 0 {
+                            put_be32(&mut data, 0, JBD2_MAGIC_NUMBER);
+                        }
+                        self.device
+                            .write_offset(blocknr as usize * BLOCK_SIZE, &data);
+                    }
+                    pos = self.next_pos(pos);
+                }
+                JBD2_COMMIT_BLOCK => {
+                    if self.has_csum() && !self.commit_csum_ok(&block) {
+                        break;
+                    }
+                    seq = seq.wrapping_add(1);
+                    pos = self.next_pos(pos);
+                }
+                JBD2_REVOKE_BLOCK => {
+                    if pass == RecoveryPass::Revoke {
+                        let count =
+                            core::cmp::min(be32(&block, JBD2_REVOKE_COUNT) as usize, BLOCK_SIZE);
+                        let mut off = JBD2_REVOKE_COUNT + 4;
+                        while off + revoke_record_size <= count {
+                            let blocknr = if revoke_record_size == 8 {
+                                (be32(&block, off) as u64) << 32 | be32(&block, off + 4) as u64
+                            } else {
+                                be32(&block, off) as u64
+                            };
+                            let rseq = revoked.entry(blocknr).or_insert(seq);
+                            *rseq = core::cmp::max(*rseq, seq);
+                            off += revoke_record_size;
+                        }
+                    }
+                    pos = self.next_pos(pos);
+                }
+                _ => break,
+            }
+        }
+        seq
+    }
+}
+
+/// 返回当前执行流的编号, 持有事务锁的执行流可以重入. 没有设置时只有一个执行流
+static CURRENT_ID: Once<fn() -> usize> = Once::new();
+
+/// 设置获取当前执行流编号的函数, 多核时传入返回hart编号的函数.
+/// 事务中不能切换到别的执行流
+pub fn set_current_id_fn(f: fn() -> usize) {
+    CURRENT_ID.call_once(|| f);
+}
+
+fn current_id() -> usize {
+    CURRENT_ID.get().map_or(0, |f| f())
+}
+
+/// 事务锁没有持有者
+const NO_OWNER: usize = usize::MAX;
+
+/// 交给文件系统其余部分使用的块设备. 同一时刻只有一个执行流在事务中,
+/// 它写入的块记在事务中, 读取时优先返回事务中的内容;
+/// 其他执行流, 没有事务或者没有日志时直接读写下层设备
+pub struct JournalDevice {
+    device: Arc<dyn BlockDevice>,
+    journal: Mutex<Option<Jbd2Journal>>,
+    /// 持有事务锁的执行流, 从最外层事务开始持有到它结束
+    owner: AtomicUsize,
+    /// 事务锁的重入层数, 只有持有者修改
+    depth: AtomicUsize,
+}
+
+impl JournalDevice {
+    pub fn new(device: Arc<dyn BlockDevice>) -> Self {
+        Self {
+            device,
+            journal: Mutex::new(None),
+            owner: AtomicUsize::new(NO_OWNER),
+            depth: AtomicUsize::new(0),
+        }
+    }
+
+    /// 下层块设备
+    pub fn device(&self) -> Arc<dyn BlockDevice> {
+        self.device.clone()
+    }
+
+    pub fn set_journal(&self, journal: Jbd2Journal) {
+        *self.journal.lock() = Some(journal);
+    }
+
+    /// 当前执行流是否持有事务锁
+    fn is_owner(&self) -> bool {
+        self.owner.load(Ordering::Acquire) == current_id()
+    }
+
+    /// 开始事务并预留`credits`个块, 日志放不下时返回ENOSPC
+    pub fn trans_start(&self, credits: usize) -> Result<usize> {
+        if !self.is_owner() {
+            let id = current_id();
+            while self
+                .owner
+                .compare_exchange_weak(NO_OWNER, id, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                core::hint::spin_loop();
+            }
+        }
+        self.depth.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(journal) = self.journal.lock().as_mut() {
+            if !journal.reserve_credits(credits) {
+                self.unlock();
+                return_errno_with_message!(Errnum::ENOSPC, "journal too small for transaction");
+            }
+            journal.transaction_start();
+        }
+        Ok(EOK)
+    }
+
+    /// 结束事务, 最外层事务超出日志容量时整个放弃并返回ENOSPC
+    pub fn trans_stop(&self) -> Result<usize> {
+        let mut overflowed = false;
+        if let Some(journal) = self.journal.lock().as_mut() {
+            overflowed = journal.overflowed() && self.depth.load(Ordering::Relaxed) == 1;
+            journal.transaction_stop();
+        }
+        self.unlock();
+        if overflowed {
+            return_errno_with_message!(Errnum::ENOSPC, "transaction exceeds the journal");
+        }
+        Ok(EOK)
+    }
+
+    pub fn trans_abort(&self) {
+        if let Some(journal) = self.journal.lock().as_mut() {
+            journal.transaction_abort();
+        }
+        self.unlock();
+    }
+
+    /// 退出一层事务, 最外层事务结束时释放事务锁
+    fn unlock(&self) {
+        if self.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.owner.store(NO_OWNER, Ordering::Release);
+        }
+    }
+
+    /// 文件数据不经过日志直接写入磁盘, 事务中这些块的旧内容作废
+    pub fn write_data(&self, offset: usize, data: &[u8]) {
+        if !self.is_owner() {
+            return self.device.write_offset(offset, data);
+        }
+        if let Some(journal) = self.journal.lock().as_mut() {
+            let first = offset / BLOCK_SIZE;
+            let last = (offset + data.len()).div_ceil(BLOCK_SIZE);
+            for block_id in first..last {
+                journal.forget(block_id as u64);
+            }
+        }
+        self.device.write_offset(offset, data);
+    }
+
+    fn read_block(&self, journal: &Jbd2Journal, block_id: usize) -> Vec<u8> {
+        match journal.get_dirty(block_id as u64) {
+            Some(data) => data.clone(),
+            None => self.device.read_offset(block_id * BLOCK_SIZE),
+        }
+    }
+}
+
+impl BlockDevice for JournalDevice {
+    fn read_offset(&self, offset: usize) -> Vec<u8> {
+        if !self.is_owner() {
+            return self.device.read_offset(offset);
+        }
+        let journal = self.journal.lock();
+        let journal = match journal.as_ref() {
+            Some(journal) if journal.in_transaction() => journal,
+            _ => return self.device.read_offset(offset),
+        };
+
+        let block_id = offset / BLOCK_SIZE;
+        let in_block = offset % BLOCK_SIZE;
+        let next_dirty = in_block != 0 && journal.get_dirty(block_id as u64 + 1).is_some();
+        if journal.get_dirty(block_id as u64).is_none() && !next_dirty {
+            return self.device.read_offset(offset);
+        }
+
+        // 读取的范围可能跨两个块
+        let mut data = self.read_block(journal, block_id)[in_block..].to_vec();
+        if in_block != 0 {
+            data.extend_from_slice(&self.read_block(journal, block_id + 1)[..in_block]);
+        }
+        data
+    }
+
+    fn write_offset(&self, offset: usize, data: &[u8]) {
+        if !self.is_owner() {
+            return self.device.write_offset(offset, data);
+        }
+        let mut journal = self.journal.lock();
+        let journal = match journal.as_mut() {
+            Some(journal) if journal.in_transaction() => journal,
+            _ => return self.device.write_offset(offset, data),
+        };
+
+        // 按块修改后记入事务
+        let mut written = 0;
+        while written < data.len() {
+            let pos = offset + written;
+            let block_id = pos / BLOCK_SIZE;
+            let in_block = pos % BLOCK_SIZE;
+            let len = core::cmp::min(BLOCK_SIZE - in_block, data.len() - written);
+
+            let mut block = self.read_block(journal, block_id);
+            block[in_block..in_block + len].copy_from_slice(&data[written..written + len]);
+            journal.write_transaction(block_id, block);
+            written += len;
+        }
+    }
+
+    fn flush(&self) {
+        self.device.flush();
+    }
+}
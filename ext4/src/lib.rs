@@ -8,6 +8,7 @@ pub mod ext4_structs;
 pub mod utils;
 pub mod ext4_impl;
 pub mod ext4_interface;
+pub mod jbd2;
 
 pub use consts::*;
 pub use ext4_error::*;
@@ -15,6 +16,7 @@ pub use ext4_error::*;
 pub use ext4_structs::*;
 pub use utils::*;
 pub use ext4_interface::*;
+pub use jbd2::*;
 #[allow(unused)]
 pub use ext4_impl::*;
 
@@ -22,10 +24,4 @@ pub use ext4_impl::*;
 extern crate alloc;
 
 #[cfg(test)]
-mod tests {
-    mod write_test {
-
-        #[test]
-        fn test_write() {}
-    }
-}
+mod tests;
@@ -9,6 +9,7 @@ mod ext4_error;
 mod ext4_impl;
 mod ext4_interface;
 mod ext4_structs;
+mod jbd2;
 mod prelude;
 mod utils;
 
@@ -16,6 +17,7 @@ pub use consts::*;
 pub use ext4_error::*;
 pub use ext4_interface::*;
 pub use ext4_structs::*;
+pub use jbd2::*;
 use prelude::*;
 pub use utils::*;
 
@@ -153,7 +155,8 @@ pub fn main() {
         assert!(r.is_ok(), "open file error {:?}", r.err());
 
         let write_data = vec![0x41 + i as u8; WRITE_SIZE];
-        ext4.ext4_file_write(&mut ext4_file, &write_data, WRITE_SIZE);
+        let r = ext4.ext4_file_write(&mut ext4_file, &write_data, WRITE_SIZE);
+        assert!(r.is_ok(), "write file error {:?}", r.err());
 
         // test
         let r = ext4.ext4_open(&mut ext4_file, path, "r+", false);
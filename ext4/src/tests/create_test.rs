@@ -0,0 +1,77 @@
+use super::*;
+
+#[test]
+fn test_create_persists() {
+    let disk = MemDisk::mkfs(32);
+    let fs = Ext4::open(disk.clone());
+
+    let file = fs
+        .ext4_create(ROOT_INO, "file", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+    assert_eq!(write_at(&fs, file, 0, b"hello ext4"), 10);
+    fs.ext4_set_attr(file, 70000, 1000, 0o600).unwrap();
+
+    let short = fs.ext4_symlink(ROOT_INO, "short", "file").unwrap();
+    let long_target = "d/".repeat(100) + "file";
+    let long = fs.ext4_symlink(ROOT_INO, "long", &long_target).unwrap();
+
+    let chr_rdev = (4 << 8) | 64;
+    let blk_rdev = (259 << 8) | 3;
+    let chr = fs
+        .ext4_mknod(
+            ROOT_INO,
+            "tty",
+            EXT4_INODE_MODE_CHARDEV as u16 | 0o620,
+            chr_rdev,
+        )
+        .unwrap();
+    let blk = fs
+        .ext4_mknod(
+            ROOT_INO,
+            "nvme",
+            EXT4_INODE_MODE_BLOCKDEV as u16 | 0o660,
+            blk_rdev,
+        )
+        .unwrap();
+
+    // 删除后inode和数据块都要释放
+    let gone = fs
+        .ext4_create(ROOT_INO, "gone", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+    write_at(&fs, gone, 0, &vec![1u8; 3 * BLOCK_SIZE]);
+    let (ino, links) = fs.ext4_unlink_entry(ROOT_INO, "gone").unwrap();
+    assert_eq!((ino, links), (gone, 0));
+    fs.ext4_free_inode(gone).unwrap();
+    drop(fs);
+
+    // 重新挂载后从磁盘读回
+    let fs = Ext4::open(disk.clone());
+    assert_eq!(lookup(&fs, ROOT_INO, "file"), Some(file));
+    assert_eq!(lookup(&fs, ROOT_INO, "gone"), None);
+    assert_eq!(read_all(&fs, file), b"hello ext4");
+
+    let inode = Ext4InodeRef::get_inode_ref(fs.self_ref.clone(), file)
+        .inner
+        .inode;
+    assert_eq!(inode.mode, EXT4_INODE_MODE_FILE as u16 | 0o600);
+    assert_eq!(
+        inode.uid as u32 | (inode.osd2.l_i_uid_high as u32) << 16,
+        70000
+    );
+    assert_eq!(inode.gid, 1000);
+    assert_eq!(inode.ext4_inode_get_links_cnt(), 1);
+
+    for (ino, target) in [(short, "file"), (long, long_target.as_str())] {
+        let mut link = Ext4File::new();
+        link.inode = ino;
+        assert_eq!(fs.ext4_follow_symlink(&link), target);
+    }
+
+    for (name, ino, rdev) in [("tty", chr, chr_rdev), ("nvme", blk, blk_rdev)] {
+        assert_eq!(lookup(&fs, ROOT_INO, name), Some(ino));
+        let inode_ref = Ext4InodeRef::get_inode_ref(fs.self_ref.clone(), ino);
+        assert_eq!(inode_ref.inner.inode.ext4_inode_get_rdev(), rdev);
+    }
+
+    disk.fsck();
+}
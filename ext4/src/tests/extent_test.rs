@@ -0,0 +1,44 @@
+use super::*;
+
+fn depth(fs: &Ext4, ino: u32) -> u16 {
+    let inode_ref = Ext4InodeRef::get_inode_ref(fs.self_ref.clone(), ino);
+    unsafe { inode_ref.inner.inode.extent_depth() }
+}
+
+#[test]
+fn test_extent_split_and_shrink() {
+    let disk = MemDisk::mkfs(32);
+    let fs = Ext4::open(disk.clone());
+    let ino = fs
+        .ext4_create(ROOT_INO, "fragmented", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+
+    // 隔一个块写一个块, 每个块都是一个单独的extent. 一个叶子块最多放340个extent,
+    // 1500个extent要分裂叶子和索引节点, 树长到两层以上
+    const EXTENTS: usize = 1500;
+    for i in 0..EXTENTS {
+        let data = [(i % 251) as u8 + 1; 16];
+        write_at(&fs, ino, 2 * i * BLOCK_SIZE, &data);
+    }
+    assert!(depth(&fs, ino) >= 2);
+    drop(fs);
+
+    let fs = Ext4::open(disk.clone());
+    let data = read_all(&fs, ino);
+    for i in 0..EXTENTS {
+        let block = &data[2 * i * BLOCK_SIZE..];
+        assert!(block[..16].iter().all(|&b| b == (i % 251) as u8 + 1));
+        if i + 1 < EXTENTS {
+            assert!(block[16..2 * BLOCK_SIZE].iter().all(|&b| b == 0));
+        }
+    }
+    disk.fsck();
+
+    // 截断后空的叶子和索引块都要释放, 剩下的extent放得进inode时树回到一层
+    fs.ext4_truncate(ino, 3 * BLOCK_SIZE as u64).unwrap();
+    assert_eq!(depth(&fs, ino), 0);
+    let data = read_all(&fs, ino);
+    assert_eq!(data.len(), 3 * BLOCK_SIZE);
+    assert_eq!(data[2 * BLOCK_SIZE], 2);
+    disk.fsck();
+}
@@ -0,0 +1,122 @@
+use super::*;
+
+use std::thread;
+
+fn needs_recovery(image: &[u8]) -> bool {
+    let raw_data = image[BASE_OFFSET..BASE_OFFSET + BLOCK_SIZE].to_vec();
+    Ext4Superblock::try_from(raw_data).unwrap().needs_recovery()
+}
+
+#[test]
+fn test_journal_replay() {
+    let disk = MemDisk::mkfs(32);
+    let fs = Ext4::open(disk.clone());
+    fs.ext4_create(ROOT_INO, "before", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+
+    // 提交时依次flush描述符和数据块, 日志超级块, 提交块. 提交块落盘后掉电,
+    // 事务还没有写回原位置
+    disk.crash_after(3);
+    let ino = fs
+        .ext4_create(ROOT_INO, "committed", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+    drop(fs);
+    let image = disk.image();
+    assert!(needs_recovery(&image));
+
+    let disk = MemDisk::from_image(image);
+    let fs = Ext4::open(disk.clone());
+    assert!(!needs_recovery(&disk.image()));
+    assert!(lookup(&fs, ROOT_INO, "before").is_some());
+    assert_eq!(lookup(&fs, ROOT_INO, "committed"), Some(ino));
+    disk.fsck();
+
+    // 提交块落盘前掉电, 重放时丢掉不完整的事务
+    disk.crash_after(2);
+    fs.ext4_create(ROOT_INO, "torn", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+    drop(fs);
+
+    let disk = MemDisk::from_image(disk.image());
+    let fs = Ext4::open(disk.clone());
+    assert_eq!(lookup(&fs, ROOT_INO, "committed"), Some(ino));
+    assert_eq!(lookup(&fs, ROOT_INO, "torn"), None);
+    disk.fsck();
+}
+
+#[test]
+fn test_journal_credits() {
+    let disk = MemDisk::mkfs(32);
+    let fs = Ext4::open(disk.clone());
+
+    // 嵌套事务的预留累加, 超出日志容量时在开始时就失败, 已经开始的事务不受影响
+    let mut depth = 0;
+    let err = loop {
+        match fs.ext4_trans_start() {
+            Ok(_) => depth += 1,
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(err.error(), Errnum::ENOSPC);
+    assert!(depth > 0);
+    fs.ext4_create(ROOT_INO, "nested", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap_err();
+    for _ in 0..depth {
+        fs.ext4_trans_stop().unwrap();
+    }
+
+    fs.ext4_create(ROOT_INO, "file", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+    disk.fsck();
+}
+
+fn thread_id() -> usize {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    std::thread_local! {
+        static ID: usize = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    }
+    ID.with(|id| *id)
+}
+
+#[test]
+fn test_journal_concurrent() {
+    set_current_id_fn(thread_id);
+    let disk = MemDisk::mkfs(32);
+    let fs = Ext4::open(disk.clone());
+
+    // 每个线程在自己的目录中建文件, 分配inode和块时共享位图和块组描述符
+    let handles: Vec<_> = (0..4)
+        .map(|t| {
+            let fs = fs.clone();
+            thread::spawn(move || {
+                let dir = fs
+                    .ext4_create(
+                        ROOT_INO,
+                        &format!("dir{}", t),
+                        EXT4_INODE_MODE_DIRECTORY as u16 | 0o755,
+                    )
+                    .unwrap();
+                for i in 0..50 {
+                    let ino = fs
+                        .ext4_create(
+                            dir,
+                            &format!("file{}", i),
+                            EXT4_INODE_MODE_FILE as u16 | 0o644,
+                        )
+                        .unwrap();
+                    write_at(&fs, ino, 0, &[t as u8 + 1; 100]);
+                }
+                dir
+            })
+        })
+        .collect();
+    let dirs: Vec<u32> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+    for (t, dir) in dirs.into_iter().enumerate() {
+        for i in 0..50 {
+            let ino = lookup(&fs, dir, &format!("file{}", i)).unwrap();
+            assert_eq!(read_all(&fs, ino), [t as u8 + 1; 100]);
+        }
+    }
+    disk.fsck();
+}
@@ -0,0 +1,148 @@
+//! 在内存中的ext4镜像上测试文件系统接口. 镜像和gen_img.sh一样用mkfs.ext4生成,
+//! 修改之后用e2fsck检查一致性
+
+extern crate std;
+
+use std::format;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::prelude::*;
+use crate::*;
+
+mod create_test;
+mod extent_test;
+mod journal_test;
+mod rename_test;
+mod write_test;
+
+/// 根目录的inode编号
+pub const ROOT_INO: u32 = 2;
+
+/// 整个镜像放在内存中的块设备
+pub struct MemDisk {
+    data: Mutex<Vec<u8>>,
+    /// 已经调用`flush`的次数
+    flushes: AtomicUsize,
+    /// 第几次`flush`之后模拟掉电, 0表示不掉电
+    crash_at: AtomicUsize,
+    /// 已经掉电, 之后的写入都被丢弃
+    crashed: AtomicBool,
+}
+
+impl BlockDevice for MemDisk {
+    fn read_offset(&self, offset: usize) -> Vec<u8> {
+        let data = self.data.lock().unwrap();
+        data[offset..offset + BLOCK_SIZE].to_vec()
+    }
+
+    fn write_offset(&self, offset: usize, data: &[u8]) {
+        if self.crashed.load(Ordering::SeqCst) {
+            return;
+        }
+        self.data.lock().unwrap()[offset..offset + data.len()].copy_from_slice(data);
+    }
+
+    fn flush(&self) {
+        let n = self.flushes.fetch_add(1, Ordering::SeqCst) + 1;
+        if n == self.crash_at.load(Ordering::SeqCst) {
+            self.crashed.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+impl MemDisk {
+    pub fn from_image(image: Vec<u8>) -> Arc<Self> {
+        Arc::new(Self {
+            data: Mutex::new(image),
+            flushes: AtomicUsize::new(0),
+            crash_at: AtomicUsize::new(0),
+            crashed: AtomicBool::new(false),
+        })
+    }
+
+    /// 用mkfs.ext4生成`size_mb`MB的带日志的镜像
+    pub fn mkfs(size_mb: usize) -> Arc<Self> {
+        let path = temp_path();
+        let output = Command::new("mkfs.ext4")
+            .args(["-q", "-F", "-b", "4096", "-I", "256"])
+            .arg(&path)
+            .arg(format!("{}M", size_mb))
+            .output()
+            .expect("mkfs.ext4 not found");
+        assert!(
+            output.status.success(),
+            "mkfs.ext4 failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let image = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        Self::from_image(image)
+    }
+
+    /// 再调用`flushes`次`flush`之后丢弃所有写入, 模拟写到一半时掉电
+    pub fn crash_after(&self, flushes: usize) {
+        let n = self.flushes.load(Ordering::SeqCst);
+        self.crash_at.store(n + flushes, Ordering::SeqCst);
+    }
+
+    pub fn image(&self) -> Vec<u8> {
+        self.data.lock().unwrap().clone()
+    }
+
+    /// 用`e2fsck -fn`检查镜像, 发现错误时panic并输出e2fsck的报告
+    pub fn fsck(&self) {
+        let path = temp_path();
+        std::fs::write(&path, self.image()).unwrap();
+        let output = Command::new("e2fsck")
+            .args(["-f", "-n"])
+            .arg(&path)
+            .output()
+            .expect("e2fsck not found");
+        std::fs::remove_file(&path).unwrap();
+        assert!(
+            output.status.success(),
+            "e2fsck found errors:\n{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+}
+
+/// 测试并行执行, 每个镜像用不同的临时文件
+fn temp_path() -> std::path::PathBuf {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    std::env::temp_dir().join(format!("ext4_rs_test_{}_{}.img", std::process::id(), id))
+}
+
+/// 在目录`parent`中查找`name`, 返回inode编号
+pub fn lookup(fs: &Ext4, parent: u32, name: &str) -> Option<u32> {
+    let mut parent_ref = Ext4InodeRef::get_inode_ref(fs.self_ref.clone(), parent);
+    fs.ext4_dir_find_entry_new(&mut parent_ref, name)
+        .ok()
+        .map(|de| de.inode)
+}
+
+/// 从`offset`开始写入`data`, 返回写入的字节数
+pub fn write_at(fs: &Ext4, ino: u32, offset: usize, data: &[u8]) -> usize {
+    let mut file = Ext4File::new();
+    file.inode = ino;
+    file.fpos = offset;
+    fs.ext4_file_write(&mut file, data, data.len()).unwrap()
+}
+
+/// 读出文件的全部内容
+pub fn read_all(fs: &Ext4, ino: u32) -> Vec<u8> {
+    let inode_ref = Ext4InodeRef::get_inode_ref(fs.self_ref.clone(), ino);
+    let size = inode_ref.inner.inode.inode_get_size() as usize;
+    let mut file = Ext4File::new();
+    file.inode = ino;
+    file.fsize = size as u64;
+    let mut data = vec![0xffu8; size];
+    let mut read_cnt = 0;
+    fs.ext4_file_read(&mut file, &mut data, size, &mut read_cnt)
+        .unwrap();
+    assert_eq!(read_cnt, size);
+    data
+}
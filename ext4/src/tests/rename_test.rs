@@ -0,0 +1,67 @@
+use super::*;
+
+fn links(fs: &Ext4, ino: u32) -> u16 {
+    Ext4InodeRef::get_inode_ref(fs.self_ref.clone(), ino)
+        .inner
+        .inode
+        .ext4_inode_get_links_cnt()
+}
+
+#[test]
+fn test_rename_and_exchange() {
+    let disk = MemDisk::mkfs(32);
+    let fs = Ext4::open(disk.clone());
+    let dir_mode = EXT4_INODE_MODE_DIRECTORY as u16 | 0o755;
+    let file_mode = EXT4_INODE_MODE_FILE as u16 | 0o644;
+
+    let d1 = fs.ext4_create(ROOT_INO, "d1", dir_mode).unwrap();
+    let d2 = fs.ext4_create(ROOT_INO, "d2", dir_mode).unwrap();
+    let sub = fs.ext4_create(d1, "sub", dir_mode).unwrap();
+    let file = fs.ext4_create(d1, "file", file_mode).unwrap();
+    write_at(&fs, file, 0, b"moved around");
+    let other = fs.ext4_create(d2, "other", file_mode).unwrap();
+    assert_eq!((links(&fs, d1), links(&fs, d2)), (3, 2));
+
+    // 跨目录移动目录, `..`和父目录的链接数跟着改变
+    fs.ext4_rename(d1, "sub", d2, "sub moved", false).unwrap();
+    assert_eq!(lookup(&fs, d1, "sub"), None);
+    assert_eq!(lookup(&fs, d2, "sub moved"), Some(sub));
+    assert_eq!(lookup(&fs, sub, ".."), Some(d2));
+    assert_eq!((links(&fs, d1), links(&fs, d2)), (2, 3));
+
+    // 目标已经存在时不能直接移动
+    assert!(fs.ext4_rename(d1, "file", d2, "other", false).is_err());
+    assert_eq!(lookup(&fs, d1, "file"), Some(file));
+    assert_eq!(lookup(&fs, d2, "other"), Some(other));
+
+    // 同一目录中改名, 链接数不变
+    fs.ext4_rename(d1, "file", d1, "renamed", false).unwrap();
+    assert_eq!(lookup(&fs, d1, "file"), None);
+    assert_eq!(lookup(&fs, d1, "renamed"), Some(file));
+    assert_eq!((links(&fs, d1), links(&fs, d2)), (2, 3));
+
+    // 跨目录交换目录和文件
+    fs.ext4_rename(d2, "sub moved", d1, "renamed", true)
+        .unwrap();
+    assert_eq!(lookup(&fs, d2, "sub moved"), Some(file));
+    assert_eq!(lookup(&fs, d1, "renamed"), Some(sub));
+    assert_eq!(lookup(&fs, sub, ".."), Some(d1));
+    assert_eq!((links(&fs, d1), links(&fs, d2)), (3, 2));
+    assert_eq!(links(&fs, sub), 2);
+    assert_eq!(links(&fs, file), 1);
+
+    // 交换的目标必须存在
+    assert!(fs.ext4_rename(d1, "renamed", d2, "missing", true).is_err());
+    drop(fs);
+
+    // 重新挂载后从磁盘读回
+    let fs = Ext4::open(disk.clone());
+    assert_eq!(lookup(&fs, d1, "renamed"), Some(sub));
+    assert_eq!(lookup(&fs, d2, "sub moved"), Some(file));
+    assert_eq!(lookup(&fs, d2, "other"), Some(other));
+    assert_eq!(lookup(&fs, sub, ".."), Some(d1));
+    assert_eq!((links(&fs, d1), links(&fs, d2)), (3, 2));
+    assert_eq!(read_all(&fs, file), b"moved around");
+
+    disk.fsck();
+}
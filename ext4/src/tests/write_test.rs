@@ -0,0 +1,64 @@
+use super::*;
+
+#[test]
+fn test_write_sparse_and_partial() {
+    let disk = MemDisk::mkfs(32);
+    let fs = Ext4::open(disk.clone());
+    let ino = fs
+        .ext4_create(ROOT_INO, "sparse", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+
+    // 跳过前5个块, 写入跨两个块的未对齐数据
+    let tail = vec![0x5au8; BLOCK_SIZE];
+    let tail_off = 5 * BLOCK_SIZE + 100;
+    assert_eq!(write_at(&fs, ino, tail_off, &tail), BLOCK_SIZE);
+
+    // 在已有块中间改写, 块中其余内容要保留
+    assert_eq!(write_at(&fs, ino, tail_off + 10, b"middle"), 6);
+    // 在空洞中写入一小段, 只分配这一个块
+    assert_eq!(write_at(&fs, ino, 2 * BLOCK_SIZE + 1, b"hole"), 4);
+    drop(fs);
+
+    let fs = Ext4::open(disk.clone());
+    let mut expected = vec![0u8; tail_off + BLOCK_SIZE];
+    expected[tail_off..].copy_from_slice(&tail);
+    expected[tail_off + 10..tail_off + 16].copy_from_slice(b"middle");
+    expected[2 * BLOCK_SIZE + 1..2 * BLOCK_SIZE + 5].copy_from_slice(b"hole");
+    assert_eq!(read_all(&fs, ino), expected);
+
+    let mut inode_ref = Ext4InodeRef::get_inode_ref(fs.self_ref.clone(), ino);
+    let mapped: Vec<u32> = (0..7)
+        .filter(|&lblk| inode_ref.get_pblock(&mut { lblk }) != 0)
+        .collect();
+    assert_eq!(mapped, [2, 5, 6]);
+
+    disk.fsck();
+}
+
+#[test]
+fn test_truncate_partial_block() {
+    let disk = MemDisk::mkfs(32);
+    let fs = Ext4::open(disk.clone());
+    let ino = fs
+        .ext4_create(ROOT_INO, "shrink", EXT4_INODE_MODE_FILE as u16 | 0o644)
+        .unwrap();
+    assert_eq!(
+        write_at(&fs, ino, 0, &vec![0xaau8; 3 * BLOCK_SIZE]),
+        3 * BLOCK_SIZE
+    );
+
+    // 截断到第二个块中间: 第三个块释放, 第二个块的后半部分清零
+    let keep = BLOCK_SIZE + 100;
+    fs.ext4_truncate(ino, keep as u64).unwrap();
+    assert_eq!(read_all(&fs, ino), vec![0xaau8; keep]);
+    let mut inode_ref = Ext4InodeRef::get_inode_ref(fs.self_ref.clone(), ino);
+    assert_eq!(inode_ref.get_pblock(&mut 2), 0);
+
+    // 再变大时, 原来块中的旧数据不能重新出现
+    fs.ext4_truncate(ino, 3 * BLOCK_SIZE as u64).unwrap();
+    let mut expected = vec![0u8; 3 * BLOCK_SIZE];
+    expected[..keep].fill(0xaa);
+    assert_eq!(read_all(&fs, ino), expected);
+
+    disk.fsck();
+}
@@ -3,6 +3,10 @@
 
 // current qemu memsize: 128MB
 // pub const USER_STACK_SIZE: usize = 4096 * 2;    // 8KB
+/// exec时立即分配的栈顶部分, 放得下参数和环境变量
+pub const MIN_USER_STACK_SIZE: usize = 4096 * 16; // 64KB
+/// default RLIMIT_STACK, the stack grows down on demand up to the limit
+pub const USER_STACK_LIMIT: usize = 4096 * 2048; // 8MB
 pub const USER_STACK_SIZE: usize = 4096 * 256; // 1MB
                                                //pub const USER_HEAP_SIZE: usize = 4096 * 2;
                                                // pub const KERNEL_HEAP_SIZE: usize = 0x2_00000;   // 2MB
@@ -17,9 +21,14 @@ pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const USER_MAX_VA: usize = 0x0000_003f_ffff_ffff; // 256GB
                                                       // pub const MMAP_MIN_ADDR: usize = 65536;
 pub const MMAP_MIN_ADDR: usize = 0x30_0000_0000; // mmap area: 128GB
+/// the user stack grows down from here, below the mmap area
+pub const USER_STACK_TOP: usize = MMAP_MIN_ADDR;
 
 pub const SIG_NUM: usize = 33;
 
+/// boot stacks are reserved for this many harts in entry.asm
+pub const MAX_HART_NUM: usize = 8;
+
 // used in OSInode::read_all(), can be optimized when app data size is known
 pub const LOAD_APP_SLICE_SIZE: usize = 0x1_0000; // 64KB
 
@@ -1,6 +1,7 @@
 //! ffi
 
 use crate::mm::MapPermission;
+use core::time::Duration;
 ///
 pub const NSEC_PER_SEC: usize = 10_0000_0000;
 
@@ -73,6 +74,39 @@ pub struct TimeVal {
     pub usec: usize,
 }
 
+impl From<Duration> for TimeVal {
+    fn from(duration: Duration) -> Self {
+        Self {
+            sec: duration.as_secs() as usize,
+            usec: duration.subsec_micros() as usize,
+        }
+    }
+}
+
+/// `struct rusage`, 目前只统计CPU时间
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Rusage {
+    /// user CPU time used
+    pub ru_utime: TimeVal,
+    /// system CPU time used
+    pub ru_stime: TimeVal,
+    /// maxrss, ixrss, idrss, isrss, minflt, majflt, nswap, inblock,
+    /// oublock, msgsnd, msgrcv, nsignals, nvcsw, nivcsw
+    pub ru_others: [usize; 14],
+}
+
+impl Rusage {
+    /// 不区分用户态和内核态, 运行时间都算作用户态的
+    pub fn from_cpu_time(cpu_time: Duration) -> Self {
+        Self {
+            ru_utime: cpu_time.into(),
+            ru_stime: TimeVal { sec: 0, usec: 0 },
+            ru_others: [0; 14],
+        }
+    }
+}
+
 // / sys_nanosleep
 // #[repr(C)]
 // #[derive(Clone, Copy, Debug, Default)]
@@ -126,5 +160,23 @@ bitflags! {
         const MAP_FIXED = 1 << 4;
         /// MAP_ANONYMOUS, 需要fd为-1, offset为0
         const MAP_ANONYMOUS = 1 << 5;
+        /// MAP_NORESERVE, no swap space to reserve, ignored
+        const MAP_NORESERVE = 1 << 14;
+        /// MAP_POPULATE, prefault pages, ignored
+        const MAP_POPULATE = 1 << 15;
+        /// MAP_STACK, ignored
+        const MAP_STACK = 1 << 17;
+        /// MAP_FIXED_NOREPLACE, like MAP_FIXED but fails with EEXIST instead of unmapping
+        const MAP_FIXED_NOREPLACE = 1 << 20;
+    }
+}
+
+bitflags! {
+    /// flags of `sys_mremap`
+    pub struct MremapFlags: u32 {
+        /// the mapping may be moved if it can't grow in place
+        const MREMAP_MAYMOVE = 1 << 0;
+        /// move the mapping to new_addr, requires MREMAP_MAYMOVE
+        const MREMAP_FIXED = 1 << 1;
     }
 }
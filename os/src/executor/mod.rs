@@ -1,13 +1,21 @@
 use core::future::Future;
 
-use alloc::collections::VecDeque;
-use async_task::{Runnable, ScheduleInfo, Task, WithInfo};
+use alloc::sync::Arc;
+use async_task::{Builder, Runnable, ScheduleInfo, Task, WithInfo};
 use log::trace;
 
+use crate::config::MAX_HART_NUM;
 use crate::mutex::SpinNoIrqLock;
+use crate::task::processor::get_local_hart;
+use crate::timer::{check_timers, handle_pending_timer_tick};
+
+pub mod sched;
+
+use sched::{RunQueue, TaskMeta};
+pub use sched::{SchedEntity, SchedPolicy};
 
 struct TaskQueue {
-    queue: SpinNoIrqLock<Option<VecDeque<Runnable>>>,
+    queue: SpinNoIrqLock<Option<RunQueue>>,
 }
 
 impl TaskQueue {
@@ -17,38 +25,82 @@ impl TaskQueue {
         }
     }
     pub fn init(&self) {
-        *self.queue.lock() = Some(VecDeque::new());
+        *self.queue.lock() = Some(RunQueue::new());
     }
-    pub fn push(&self, runnable: Runnable) {
+    pub fn push(&self, runnable: Runnable<TaskMeta>) {
         let mut lock = self.queue.lock();
-        lock.as_mut().unwrap().push_back(runnable);
+        lock.as_mut().unwrap().push(runnable);
+    }
+    pub fn fetch(&self) -> Option<Runnable<TaskMeta>> {
+        self.queue.lock().as_mut().unwrap().pick_next()
     }
-    #[allow(unused)]
-    pub fn push_preempt(&self, runnable: Runnable) {
-        self.queue.lock().as_mut().unwrap().push_front(runnable);
+    /// 被`hart_id`偷取一个允许在其上运行的任务
+    pub fn steal(&self, hart_id: usize) -> Option<Runnable<TaskMeta>> {
+        self.queue.lock().as_mut().unwrap().steal(hart_id)
     }
-    pub fn fetch(&self) -> Option<Runnable> {
-        self.queue.lock().as_mut().unwrap().pop_front()
+    pub fn highest_rt_prio(&self) -> Option<usize> {
+        self.queue.lock().as_ref().unwrap().highest_rt_prio()
     }
 }
 
-static TASK_QUEUE: TaskQueue = TaskQueue::new();
+/// 每个hart一个任务队列
+static TASK_QUEUES: [TaskQueue; MAX_HART_NUM] = {
+    const EMPTY: TaskQueue = TaskQueue::new();
+    [EMPTY; MAX_HART_NUM]
+};
 
+/// 由第一个启动的hart调用, 初始化所有hart的任务队列
 pub fn init() {
-    TASK_QUEUE.init();
+    for queue in TASK_QUEUES.iter() {
+        queue.init();
+    }
+}
+
+/// 先取本hart的任务, 没有的话从其他hart偷一个
+fn fetch() -> Option<Runnable<TaskMeta>> {
+    let hart_id = get_local_hart().hart_id;
+    if let Some(runnable) = TASK_QUEUES[hart_id].fetch() {
+        return Some(runnable);
+    }
+    (1..MAX_HART_NUM)
+        .map(|i| (hart_id + i) % MAX_HART_NUM)
+        .find_map(|victim| TASK_QUEUES[victim].steal(hart_id))
 }
 
-/// Add a task into task queue
-pub fn spawn<F>(future: F) -> (Runnable, Task<F::Output>)
+/// Add a task into task queue, `sched` decides its class and priority
+pub fn spawn<F>(
+    future: F,
+    sched: Arc<SchedEntity>,
+) -> (Runnable<TaskMeta>, Task<F::Output, TaskMeta>)
 where
     F: Future + Send + 'static,
     F::Output: Send + 'static,
 {
     #[allow(unused_variables)]
-    let schedule = move |runnable: Runnable, info: ScheduleInfo| {
-        TASK_QUEUE.push(runnable);
+    let schedule = move |runnable: Runnable<TaskMeta>, info: ScheduleInfo| {
+        let se = runnable.metadata();
+        // 运行中让出的任务在这里结算运行时间, 之后才能按新的vruntime入队
+        se.stop_exec();
+        // 被唤醒的任务放回唤醒者所在hart的队列, 除非亲和性不允许
+        let hart_id = se.select_hart(get_local_hart().hart_id);
+        TASK_QUEUES[hart_id].push(runnable);
     };
-    async_task::spawn(future, WithInfo(schedule))
+    Builder::new()
+        .metadata(sched)
+        .spawn(move |_| future, WithInfo(schedule))
+}
+
+/// 时钟中断时判断当前任务是否应该让出本hart
+pub fn need_resched(sched: &SchedEntity) -> bool {
+    let hart_id = get_local_hart().hart_id;
+    sched.need_resched(TASK_QUEUES[hart_id].highest_rt_prio())
+}
+
+fn run_task(task: Runnable<TaskMeta>) {
+    let se = task.metadata().clone();
+    se.start_exec();
+    task.run();
+    se.stop_exec();
 }
 
 #[allow(unused)]
@@ -56,9 +108,9 @@ where
 pub fn run_until_idle() -> usize {
     let mut n = 0;
     loop {
-        if let Some(task) = TASK_QUEUE.fetch() {
+        if let Some(task) = fetch() {
             // info!("fetch a task");
-            task.run();
+            run_task(task);
             n += 1;
         } else {
             break;
@@ -71,9 +123,16 @@ pub fn run_until_idle() -> usize {
 pub fn run_forever() -> ! {
     trace!("[run_forever] enter");
     loop {
-        if let Some(task) = TASK_QUEUE.fetch() {
+        check_timers();
+        if let Some(task) = fetch() {
             //debug!(run_forever(): fetch a task");
-            task.run();
+            run_task(task);
+        } else {
+            // 没有任务可运行, 等待下一次中断(至少是下一个时钟周期)
+            unsafe {
+                riscv::asm::wfi();
+            }
+            handle_pending_timer_tick();
         }
     }
 }
@@ -0,0 +1,413 @@
+//! 调度策略
+//!
+//! 每个hart的运行队列由若干调度类组成, 按优先级依次挑选:
+//! - [`RtClass`]: `SCHED_FIFO`/`SCHED_RR`, 按实时优先级调度
+//! - [`FairClass`]: `SCHED_NORMAL`/`SCHED_BATCH`/`SCHED_IDLE`, 按CFS式的虚拟运行时间调度
+//!
+//! 调度参数保存在[`SchedEntity`]中, 作为`Runnable`的metadata随任务一起入队.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use async_task::Runnable;
+use core::time::Duration;
+
+use crate::config::CLOCK_FREQ;
+use crate::ctypes::NSEC_PER_SEC;
+use crate::mutex::SpinNoIrqLock;
+use crate::task::processor::online_hart_mask;
+use crate::timer::get_time;
+
+/// `Runnable`携带的调度信息
+pub type TaskMeta = Arc<SchedEntity>;
+
+/// 实时优先级范围
+pub const MIN_RT_PRIO: usize = 1;
+pub const MAX_RT_PRIO: usize = 99;
+/// nice值范围
+pub const MIN_NICE: isize = -20;
+pub const MAX_NICE: isize = 19;
+/// `SCHED_RR`的时间片
+pub const RR_TIMESLICE_NS: u64 = 100_000_000;
+/// 唤醒的任务最多领先`min_vruntime`半个调度周期
+const SCHED_LATENCY_NS: u64 = 6_000_000;
+/// nice为0时的权重
+const NICE_0_WEIGHT: u64 = 1024;
+/// `SCHED_IDLE`的权重
+const IDLE_WEIGHT: u64 = 3;
+/// nice -20..=19 对应的权重, 与Linux的`sched_prio_to_weight`相同
+const PRIO_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+/// 调度策略, 取值与Linux相同
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchedPolicy {
+    Normal = 0,
+    Fifo = 1,
+    RR = 2,
+    Batch = 3,
+    Idle = 5,
+}
+
+impl SchedPolicy {
+    pub fn from_raw(policy: usize) -> Option<Self> {
+        match policy {
+            0 => Some(Self::Normal),
+            1 => Some(Self::Fifo),
+            2 => Some(Self::RR),
+            3 => Some(Self::Batch),
+            5 => Some(Self::Idle),
+            _ => None,
+        }
+    }
+    pub fn is_rt(&self) -> bool {
+        matches!(self, Self::Fifo | Self::RR)
+    }
+    /// 该策略允许的实时优先级范围
+    pub fn priority_range(&self) -> (usize, usize) {
+        if self.is_rt() {
+            (MIN_RT_PRIO, MAX_RT_PRIO)
+        } else {
+            (0, 0)
+        }
+    }
+}
+
+struct SchedInner {
+    policy: SchedPolicy,
+    rt_priority: usize,
+    nice: isize,
+    /// 允许运行的hart, 第i位代表hart i
+    affinity: usize,
+    reset_on_fork: bool,
+    /// 加权后的运行时间, 单位ns
+    vruntime: u64,
+    /// 上次被选中时所在队列的`min_vruntime`, 再次入队时据此换算vruntime
+    base_vruntime: u64,
+    /// 本次开始运行的时刻, 单位为时钟周期
+    exec_start: Option<usize>,
+    /// `SCHED_RR`剩余的时间片
+    slice_left: u64,
+    /// 累计运行时间, 单位ns
+    sum_exec_runtime: u64,
+}
+
+/// 一个可调度任务(线程)的调度参数和统计
+pub struct SchedEntity {
+    inner: SpinNoIrqLock<SchedInner>,
+}
+
+impl Default for SchedEntity {
+    fn default() -> Self {
+        Self {
+            inner: SpinNoIrqLock::new(SchedInner {
+                policy: SchedPolicy::Normal,
+                rt_priority: 0,
+                nice: 0,
+                affinity: usize::MAX,
+                reset_on_fork: false,
+                vruntime: 0,
+                base_vruntime: 0,
+                exec_start: None,
+                slice_left: RR_TIMESLICE_NS,
+                sum_exec_runtime: 0,
+            }),
+        }
+    }
+}
+
+impl SchedEntity {
+    /// fork/clone时子线程继承调度参数, `SCHED_RESET_ON_FORK`时退回普通策略
+    pub fn fork(&self) -> Self {
+        let inner = self.inner.lock();
+        let (policy, rt_priority, nice) = if inner.reset_on_fork {
+            let policy = if inner.policy.is_rt() {
+                SchedPolicy::Normal
+            } else {
+                inner.policy
+            };
+            (policy, 0, inner.nice.max(0))
+        } else {
+            (inner.policy, inner.rt_priority, inner.nice)
+        };
+        Self {
+            inner: SpinNoIrqLock::new(SchedInner {
+                policy,
+                rt_priority,
+                nice,
+                affinity: inner.affinity,
+                reset_on_fork: false,
+                vruntime: inner.vruntime,
+                base_vruntime: inner.base_vruntime,
+                exec_start: None,
+                slice_left: RR_TIMESLICE_NS,
+                sum_exec_runtime: 0,
+            }),
+        }
+    }
+
+    pub fn policy(&self) -> SchedPolicy {
+        self.inner.lock().policy
+    }
+    pub fn rt_priority(&self) -> usize {
+        self.inner.lock().rt_priority
+    }
+    pub fn reset_on_fork(&self) -> bool {
+        self.inner.lock().reset_on_fork
+    }
+    /// 调用者负责检查优先级是否在策略允许的范围内
+    pub fn set_policy(&self, policy: SchedPolicy, rt_priority: usize, reset_on_fork: bool) {
+        let mut inner = self.inner.lock();
+        inner.policy = policy;
+        inner.rt_priority = rt_priority;
+        inner.reset_on_fork = reset_on_fork;
+        inner.slice_left = RR_TIMESLICE_NS;
+    }
+    pub fn nice(&self) -> isize {
+        self.inner.lock().nice
+    }
+    pub fn set_nice(&self, nice: isize) {
+        self.inner.lock().nice = nice.clamp(MIN_NICE, MAX_NICE);
+    }
+    pub fn affinity(&self) -> usize {
+        self.inner.lock().affinity
+    }
+    pub fn set_affinity(&self, mask: usize) {
+        self.inner.lock().affinity = mask;
+    }
+    pub fn allowed_on(&self, hart_id: usize) -> bool {
+        self.inner.lock().affinity & (1 << hart_id) != 0
+    }
+
+    /// 选择入队的hart: 本hart允许时留在本hart, 否则选亲和性掩码中编号最小的在线hart
+    pub fn select_hart(&self, local: usize) -> usize {
+        let allowed = self.affinity() & online_hart_mask();
+        if allowed & (1 << local) != 0 || allowed == 0 {
+            local
+        } else {
+            allowed.trailing_zeros() as usize
+        }
+    }
+
+    /// 开始运行
+    pub fn start_exec(&self) {
+        self.inner.lock().exec_start = Some(get_time());
+    }
+
+    /// 停止运行, 把这段运行时间计入vruntime和RR时间片
+    pub fn stop_exec(&self) {
+        let mut inner = self.inner.lock();
+        if let Some(start) = inner.exec_start.take() {
+            let delta = ticks_to_ns(get_time() - start);
+            let weight = inner.weight();
+            inner.vruntime += delta * NICE_0_WEIGHT / weight;
+            inner.slice_left = inner.slice_left.saturating_sub(delta);
+            inner.sum_exec_runtime += delta;
+        }
+    }
+
+    /// 累计运行时间, 包括在内核中运行的时间
+    pub fn exec_runtime(&self) -> Duration {
+        Duration::from_nanos(self.inner.lock().sum_exec_runtime)
+    }
+
+    /// 时钟中断时判断正在运行的任务是否需要让出hart
+    /// - `SCHED_FIFO`: 只让给更高优先级的实时任务
+    /// - `SCHED_RR`: 时间片用完或有更高优先级的实时任务
+    /// - 其他: 每个时钟周期都让出, 由vruntime决定下一个运行的任务
+    pub fn need_resched(&self, waiting_rt_prio: Option<usize>) -> bool {
+        let inner = self.inner.lock();
+        let preempted = waiting_rt_prio.map_or(false, |prio| prio > inner.rt_priority);
+        match inner.policy {
+            SchedPolicy::Fifo => preempted,
+            SchedPolicy::RR => {
+                let running = inner
+                    .exec_start
+                    .map_or(0, |start| ticks_to_ns(get_time() - start));
+                preempted || running >= inner.slice_left
+            }
+            _ => true,
+        }
+    }
+}
+
+impl SchedInner {
+    fn weight(&self) -> u64 {
+        if self.policy == SchedPolicy::Idle {
+            IDLE_WEIGHT
+        } else {
+            PRIO_TO_WEIGHT[(self.nice - MIN_NICE) as usize]
+        }
+    }
+}
+
+fn ticks_to_ns(ticks: usize) -> u64 {
+    ticks as u64 * NSEC_PER_SEC as u64 / CLOCK_FREQ as u64
+}
+
+/// 调度类, 每个hart的运行队列按顺序由多个调度类组成
+pub trait SchedClass {
+    /// 加入一个就绪任务
+    fn enqueue(&mut self, runnable: Runnable<TaskMeta>);
+    /// 取出下一个要运行的任务
+    fn pick_next(&mut self) -> Option<Runnable<TaskMeta>>;
+    /// 其他hart来偷任务, 只能偷走允许在`hart_id`上运行的任务
+    fn steal(&mut self, hart_id: usize) -> Option<Runnable<TaskMeta>>;
+}
+
+/// `SCHED_FIFO`和`SCHED_RR`, 同优先级内先进先出
+pub struct RtClass {
+    /// 实时优先级 -> 就绪队列
+    queues: BTreeMap<usize, VecDeque<Runnable<TaskMeta>>>,
+}
+
+impl RtClass {
+    pub const fn new() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+    /// 就绪的最高实时优先级
+    pub fn highest_prio(&self) -> Option<usize> {
+        self.queues.keys().next_back().copied()
+    }
+}
+
+impl SchedClass for RtClass {
+    fn enqueue(&mut self, runnable: Runnable<TaskMeta>) {
+        let se = runnable.metadata();
+        let prio = {
+            let mut inner = se.inner.lock();
+            // 用完时间片的RR任务重新获得时间片, 排到队尾
+            if inner.slice_left == 0 {
+                inner.slice_left = RR_TIMESLICE_NS;
+            }
+            inner.rt_priority
+        };
+        self.queues.entry(prio).or_default().push_back(runnable);
+    }
+
+    fn pick_next(&mut self) -> Option<Runnable<TaskMeta>> {
+        let mut entry = self.queues.last_entry()?;
+        let runnable = entry.get_mut().pop_front();
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        runnable
+    }
+
+    fn steal(&mut self, hart_id: usize) -> Option<Runnable<TaskMeta>> {
+        let (prio, idx) = self.queues.iter().rev().find_map(|(prio, queue)| {
+            queue
+                .iter()
+                .position(|r| r.metadata().allowed_on(hart_id))
+                .map(|idx| (*prio, idx))
+        })?;
+        let queue = self.queues.get_mut(&prio).unwrap();
+        let runnable = queue.remove(idx);
+        if queue.is_empty() {
+            self.queues.remove(&prio);
+        }
+        runnable
+    }
+}
+
+/// `SCHED_NORMAL`/`SCHED_BATCH`/`SCHED_IDLE`, 总是选vruntime最小的任务
+pub struct FairClass {
+    /// (vruntime, 入队序号) -> 任务
+    timeline: BTreeMap<(u64, usize), Runnable<TaskMeta>>,
+    /// 单调递增, 新入队的任务据此确定vruntime
+    min_vruntime: u64,
+    seq: usize,
+}
+
+impl FairClass {
+    pub const fn new() -> Self {
+        Self {
+            timeline: BTreeMap::new(),
+            min_vruntime: 0,
+            seq: 0,
+        }
+    }
+}
+
+impl SchedClass for FairClass {
+    fn enqueue(&mut self, runnable: Runnable<TaskMeta>) {
+        let vruntime = {
+            let mut inner = runnable.metadata().inner.lock();
+            // 换算到本队列: 保持相对于上次所在队列min_vruntime的差值,
+            // 但睡眠很久的任务最多只领先半个调度周期
+            let lag = inner.vruntime as i64 - inner.base_vruntime as i64;
+            let floor = self.min_vruntime.saturating_sub(SCHED_LATENCY_NS / 2);
+            inner.vruntime = ((self.min_vruntime as i64 + lag).max(0) as u64).max(floor);
+            inner.vruntime
+        };
+        self.seq += 1;
+        self.timeline.insert((vruntime, self.seq), runnable);
+    }
+
+    fn pick_next(&mut self) -> Option<Runnable<TaskMeta>> {
+        let ((vruntime, _), runnable) = self.timeline.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        runnable.metadata().inner.lock().base_vruntime = self.min_vruntime;
+        Some(runnable)
+    }
+
+    fn steal(&mut self, hart_id: usize) -> Option<Runnable<TaskMeta>> {
+        // 从vruntime大的一端偷, 与本hart的pick_next错开
+        let key = *self
+            .timeline
+            .iter()
+            .rev()
+            .find(|(_, r)| r.metadata().allowed_on(hart_id))?
+            .0;
+        let runnable = self.timeline.remove(&key)?;
+        runnable.metadata().inner.lock().base_vruntime = self.min_vruntime;
+        Some(runnable)
+    }
+}
+
+/// 每个hart的运行队列, 实时任务总是优先于普通任务
+pub struct RunQueue {
+    rt: RtClass,
+    fair: FairClass,
+}
+
+impl RunQueue {
+    pub const fn new() -> Self {
+        Self {
+            rt: RtClass::new(),
+            fair: FairClass::new(),
+        }
+    }
+
+    fn classes(&mut self) -> [&mut dyn SchedClass; 2] {
+        [&mut self.rt, &mut self.fair]
+    }
+
+    pub fn push(&mut self, runnable: Runnable<TaskMeta>) {
+        if runnable.metadata().policy().is_rt() {
+            self.rt.enqueue(runnable);
+        } else {
+            self.fair.enqueue(runnable);
+        }
+    }
+
+    pub fn pick_next(&mut self) -> Option<Runnable<TaskMeta>> {
+        self.classes()
+            .into_iter()
+            .find_map(|class| class.pick_next())
+    }
+
+    pub fn steal(&mut self, hart_id: usize) -> Option<Runnable<TaskMeta>> {
+        self.classes()
+            .into_iter()
+            .find_map(|class| class.steal(hart_id))
+    }
+
+    pub fn highest_rt_prio(&self) -> Option<usize> {
+        self.rt.highest_prio()
+    }
+}
@@ -14,9 +14,10 @@ pub struct DevInode {
 }
 
 impl DevInode {
-    pub fn new(parent: Arc<dyn Inode>) -> Self {
+    /// root of the filesystem, reached from its parent through the mount table
+    pub fn new() -> Self {
         let meta = Arc::new(InodeMeta::new(
-            Some(parent.clone()),
+            None,
             "/dev".into(),
             InodeMode::FileDIR,
             0,
@@ -0,0 +1,342 @@
+//! epoll: 每个关心的fd在文件上登记一个回调waker, 文件可能就绪时把对应项放入就绪队列,
+//! `epoll_wait`只检查就绪队列中的项而不扫描整个兴趣列表
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Weak},
+    task::Wake,
+    vec::Vec,
+};
+use core::task::Waker;
+use log::error;
+
+use crate::{mutex::SpinNoIrqLock, utils::SyscallErr, AsyncResult, SyscallRet};
+
+use super::{poll::WaitQueue, File, FileMeta, OSFileType, PollEvents};
+
+pub const EPOLL_CTL_ADD: usize = 1;
+pub const EPOLL_CTL_DEL: usize = 2;
+pub const EPOLL_CTL_MOD: usize = 3;
+
+/// 嵌套epoll的最大层数, 同Linux的`EP_MAX_NESTS`
+const EP_MAX_NESTS: usize = 4;
+
+/// 串行化嵌套epoll的加入, 两个epoll同时互相加入时各自的检查都看不到对方
+static EPOLL_NEST_LOCK: SpinNoIrqLock<()> = SpinNoIrqLock::new(());
+
+bitflags! {
+    /// `events` of `struct epoll_event`, the low bits are the same as `PollEvents`
+    pub struct EpollEvents: u32 {
+        const EPOLLIN = 1 << 0;
+        const EPOLLPRI = 1 << 1;
+        const EPOLLOUT = 1 << 2;
+        const EPOLLERR = 1 << 3;
+        const EPOLLHUP = 1 << 4;
+        const EPOLLRDNORM = 1 << 6;
+        const EPOLLRDBAND = 1 << 7;
+        const EPOLLWRNORM = 1 << 8;
+        const EPOLLWRBAND = 1 << 9;
+        const EPOLLMSG = 1 << 10;
+        const EPOLLRDHUP = 1 << 13;
+        const EPOLLEXCLUSIVE = 1 << 28;
+        const EPOLLWAKEUP = 1 << 29;
+        /// Disable the fd after one event, until rearmed by `EPOLL_CTL_MOD`
+        const EPOLLONESHOT = 1 << 30;
+        /// Edge-triggered
+        const EPOLLET = 1 << 31;
+    }
+}
+
+impl EpollEvents {
+    fn poll_events(&self) -> PollEvents {
+        PollEvents::from_bits_truncate(self.bits() as i16)
+    }
+}
+
+/// `struct epoll_event`, riscv64上不是packed的
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+struct EpollInner {
+    interests: BTreeMap<usize, Arc<EpollItem>>,
+    /// 可能就绪的项, 由回调放入, `epoll_wait`时再确认
+    ready: VecDeque<Arc<EpollItem>>,
+    /// 等待本epoll的任务
+    wait: WaitQueue,
+}
+
+struct EpollItemInner {
+    event: EpollEvent,
+    /// 已经在就绪队列中
+    queued: bool,
+    /// `EPOLLONESHOT`触发过或者已被删除
+    disarmed: bool,
+}
+
+struct EpollItem {
+    file: Weak<dyn File>,
+    epoll: Weak<SpinNoIrqLock<EpollInner>>,
+    /// 登记到文件上的回调, 同一项总用同一个waker, 文件那边可以去重
+    waker: Waker,
+    inner: SpinNoIrqLock<EpollItemInner>,
+}
+
+/// 文件状态变化时的回调
+struct EpollCallback {
+    item: Weak<EpollItem>,
+}
+
+impl Wake for EpollCallback {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+    fn wake_by_ref(self: &Arc<Self>) {
+        if let Some(item) = self.item.upgrade() {
+            item.enqueue();
+        }
+    }
+}
+
+impl EpollItem {
+    fn new(
+        file: &Arc<dyn File>,
+        epoll: &Arc<SpinNoIrqLock<EpollInner>>,
+        event: EpollEvent,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|item| Self {
+            file: Arc::downgrade(file),
+            epoll: Arc::downgrade(epoll),
+            waker: Arc::new(EpollCallback { item: item.clone() }).into(),
+            inner: SpinNoIrqLock::new(EpollItemInner {
+                event,
+                queued: false,
+                disarmed: false,
+            }),
+        })
+    }
+
+    /// 放入就绪队列并唤醒等待者
+    fn enqueue(self: &Arc<Self>) {
+        let Some(epoll) = self.epoll.upgrade() else {
+            return;
+        };
+        let mut epoll = epoll.lock();
+        {
+            let mut inner = self.inner.lock();
+            if inner.queued || inner.disarmed {
+                return;
+            }
+            inner.queued = true;
+        }
+        epoll.ready.push_back(self.clone());
+        let wakers = epoll.wait.take();
+        drop(epoll);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// 检查文件是否真的就绪, 并重新登记回调
+    fn check(&self) -> Option<EpollEvent> {
+        let event = self.inner.lock().event;
+        let file = self.file.upgrade()?;
+        let events = EpollEvents::from_bits_truncate(event.events);
+        let wanted = events.poll_events() | PollEvents::POLLERR | PollEvents::POLLHUP;
+        let revents = file.poll(events.poll_events(), Some(&self.waker)) & wanted;
+        if revents.is_empty() {
+            None
+        } else {
+            Some(EpollEvent {
+                events: revents.bits() as u32,
+                data: event.data,
+            })
+        }
+    }
+}
+
+pub struct EpollFile {
+    meta: FileMeta,
+    inner: Arc<SpinNoIrqLock<EpollInner>>,
+}
+
+impl EpollFile {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            meta: FileMeta::new_bare(true, false, OSFileType::Epoll),
+            inner: Arc::new(SpinNoIrqLock::new(EpollInner {
+                interests: BTreeMap::new(),
+                ready: VecDeque::new(),
+                wait: WaitQueue::new(),
+            })),
+        })
+    }
+
+    /// `file`是epoll时转换成`EpollFile`
+    pub fn from_file(file: Arc<dyn File>) -> Option<Arc<Self>> {
+        if file.get_meta().filetype != OSFileType::Epoll {
+            return None;
+        }
+        // 只有EpollFile的类型是OSFileType::Epoll
+        Some(unsafe { Arc::from_raw(Arc::into_raw(file) as *const Self) })
+    }
+
+    /// 把`epoll`加入本epoll前检查: 从`epoll`往下能回到本epoll, 或者嵌套超过
+    /// `EP_MAX_NESTS`层时返回ELOOP, 否则poll嵌套的epoll时会无限递归
+    fn loop_check(&self, epoll: &EpollFile, depth: usize) -> Result<(), SyscallErr> {
+        if Arc::ptr_eq(&self.inner, &epoll.inner) || depth > EP_MAX_NESTS {
+            return Err(SyscallErr::ELOOP);
+        }
+        let items: Vec<_> = epoll.inner.lock().interests.values().cloned().collect();
+        for item in items {
+            if let Some(nested) = item.file.upgrade().and_then(EpollFile::from_file) {
+                self.loop_check(&nested, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `EPOLL_CTL_ADD`/`EPOLL_CTL_MOD`/`EPOLL_CTL_DEL`
+    pub fn ctl(&self, op: usize, fd: usize, file: Arc<dyn File>, event: EpollEvent) -> SyscallRet {
+        let _nest_guard = match EpollFile::from_file(file.clone()) {
+            Some(epoll) if op == EPOLL_CTL_ADD => {
+                let guard = EPOLL_NEST_LOCK.lock();
+                self.loop_check(&epoll, 0)?;
+                Some(guard)
+            }
+            _ => None,
+        };
+        let mut inner = self.inner.lock();
+        let item = match op {
+            EPOLL_CTL_ADD => {
+                if inner.interests.contains_key(&fd) {
+                    return Err(SyscallErr::EEXIST.into());
+                }
+                let item = EpollItem::new(&file, &self.inner, event);
+                inner.interests.insert(fd, item.clone());
+                item
+            }
+            EPOLL_CTL_MOD => {
+                let item = inner.interests.get(&fd).ok_or(SyscallErr::ENOENT)?.clone();
+                let mut item_inner = item.inner.lock();
+                item_inner.event = event;
+                item_inner.disarmed = false;
+                drop(item_inner);
+                item
+            }
+            EPOLL_CTL_DEL => {
+                let item = inner.interests.remove(&fd).ok_or(SyscallErr::ENOENT)?;
+                item.inner.lock().disarmed = true;
+                return Ok(0);
+            }
+            _ => return Err(SyscallErr::EINVAL.into()),
+        };
+        drop(inner);
+        // 由下一次epoll_wait检查并在文件上登记回调
+        item.enqueue();
+        Ok(0)
+    }
+
+    /// 确认就绪队列中的项, 最多返回`max_events`个事件
+    pub fn collect_events(&self, max_events: usize) -> Vec<EpollEvent> {
+        let candidates = core::mem::take(&mut self.inner.lock().ready);
+        let mut events = Vec::new();
+        let mut unchecked = VecDeque::new();
+        let mut level_triggered = Vec::new();
+        let mut closed = Vec::new();
+        for item in candidates {
+            if events.len() >= max_events {
+                unchecked.push_back(item);
+                continue;
+            }
+            let flags = {
+                let mut item_inner = item.inner.lock();
+                item_inner.queued = false;
+                if item_inner.disarmed {
+                    continue;
+                }
+                EpollEvents::from_bits_truncate(item_inner.event.events)
+            };
+            if item.file.strong_count() == 0 {
+                closed.push(item);
+                continue;
+            }
+            let Some(event) = item.check() else {
+                continue;
+            };
+            events.push(event);
+            if flags.contains(EpollEvents::EPOLLONESHOT) {
+                item.inner.lock().disarmed = true;
+            } else if !flags.contains(EpollEvents::EPOLLET) {
+                // 水平触发: 下次还要检查, 直到不再就绪
+                level_triggered.push(item);
+            }
+        }
+        let mut inner = self.inner.lock();
+        // 没来得及检查的放回队首, 保持顺序
+        while let Some(item) = unchecked.pop_back() {
+            inner.ready.push_front(item);
+        }
+        for item in closed {
+            inner
+                .interests
+                .retain(|_, interest| !Arc::ptr_eq(interest, &item));
+        }
+        drop(inner);
+        for item in level_triggered {
+            item.enqueue();
+        }
+        events
+    }
+
+    /// 就绪队列为空时登记等待者, 返回是否登记成功
+    pub fn register_waiter(&self, waker: &Waker) -> bool {
+        let mut inner = self.inner.lock();
+        if !inner.ready.is_empty() {
+            return false;
+        }
+        inner.wait.register(waker);
+        true
+    }
+}
+
+impl File for EpollFile {
+    fn read<'a>(&'a self, _buf: &'a mut [u8]) -> AsyncResult<usize> {
+        Box::pin(async move { Err(SyscallErr::EINVAL.into()) })
+    }
+
+    fn write<'a>(&'a self, _buf: &'a [u8]) -> AsyncResult<usize> {
+        Box::pin(async move { Err(SyscallErr::EINVAL.into()) })
+    }
+
+    fn get_meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    fn seek(&self, _offset: usize) -> Option<usize> {
+        None
+    }
+
+    fn ioctl(&self, _request: usize, _argp: usize) -> SyscallRet {
+        error!("[EpollFile] ioctl not implemented");
+        Err(SyscallErr::ENOTTY as usize)
+    }
+
+    /// 有任一项就绪时可读, 用于嵌套epoll和对epoll fd的poll
+    fn poll(&self, events: PollEvents, waker: Option<&Waker>) -> PollEvents {
+        let candidates: Vec<_> = self.inner.lock().ready.iter().cloned().collect();
+        let readable = candidates
+            .iter()
+            .any(|item| !item.inner.lock().disarmed && item.check().is_some());
+        if let Some(waker) = waker {
+            self.inner.lock().wait.register(waker);
+        }
+        if readable && events.contains(PollEvents::POLLIN) {
+            PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+}
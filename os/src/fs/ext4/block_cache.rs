@@ -137,6 +137,14 @@ impl BlockCacheManager {
             block_cache
         }
     }
+
+    /// Sync all block cache to block device
+    pub fn sync_all(&self) {
+        let inner = self.inner.lock();
+        for (_, cache) in inner.queue.iter() {
+            cache.lock().sync();
+        }
+    }
 }
 
 impl ext4_rs::BlockDevice for BlockCacheManager {
@@ -188,6 +196,11 @@ impl ext4_rs::BlockDevice for BlockCacheManager {
             block_id += 1;
         }
     }
+
+    // 日志需要在提交块之前把日志块写到磁盘上
+    fn flush(&self) {
+        self.sync_all();
+    }
 }
 
 lazy_static! {
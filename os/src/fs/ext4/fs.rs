@@ -9,10 +9,15 @@ use crate::{
         path::Path,
     },
     mutex::SpinNoIrqLock,
+    task::processor::get_local_hart,
 };
 
 use super::inode::Ext4Inode;
 
+/// offset of `s_magic` in the superblock
+const EXT4_MAGIC_OFFSET: usize = 0x38;
+const EXT4_SUPER_MAGIC: u16 = 0xEF53;
+
 pub struct Ext4FileSystem {
     pub block_device: Arc<dyn ext4_rs::BlockDevice>,
     pub root_inode: Arc<dyn Inode>,
@@ -21,6 +26,8 @@ pub struct Ext4FileSystem {
 impl Ext4FileSystem {
     pub fn open(block_device: Arc<dyn ext4_rs::BlockDevice>) -> Arc<SpinNoIrqLock<Self>> {
         // log::debug!("[Ext4FileSystem::open] enter");
+        // 事务锁按hart区分持有者, 事务中不会切换任务
+        ext4_rs::set_current_id_fn(|| get_local_hart().hart_id);
         let ext4 = Ext4::open(block_device.clone());
         let root_inode = Arc::new(Ext4Inode::new(
             ext4,
@@ -32,12 +39,20 @@ impl Ext4FileSystem {
                 EXT4_ROOT_INO,
             )),
         ));
+        root_inode.load_owner();
         Arc::new(SpinNoIrqLock::new(Ext4FileSystem {
             block_device,
             root_inode,
         }))
     }
 
+    /// check the superblock magic before `open`, which panics on a bad image
+    pub fn probe(block_device: Arc<dyn ext4_rs::BlockDevice>) -> bool {
+        let raw_data = block_device.read_offset(ext4_rs::BASE_OFFSET);
+        u16::from_le_bytes([raw_data[EXT4_MAGIC_OFFSET], raw_data[EXT4_MAGIC_OFFSET + 1]])
+            == EXT4_SUPER_MAGIC
+    }
+
     pub fn root_inode(&self) -> Arc<dyn Inode> {
         self.root_inode.clone()
     }
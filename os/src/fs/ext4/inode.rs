@@ -1,8 +1,9 @@
-use core::borrow::BorrowMut;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::{
     fs::{
         inode::{Inode, InodeMeta, InodeMode},
+        page_cache::PageCache,
         path::Path,
     },
     utils::SyscallErr,
@@ -14,23 +15,27 @@ use alloc::{
     string::{String, ToString},
     sync::Arc,
 };
-use ext4_rs::{Ext4, Ext4File, Ext4InodeRef, Ext4MountPoint, OpenFlag};
+use ext4_rs::{BlockDevice, Ext4, Ext4Error, Ext4File, Ext4InodeRef, Ext4MountPoint, OpenFlag};
 use log::{debug, error, warn};
 
-use super::ram_inode::Ext4RamInode;
-
 pub struct Ext4Inode {
     fs: Arc<Ext4>,
     meta: Arc<InodeMeta>,
+    /// the last link on disk has been removed, free the inode when dropped
+    unlinked: AtomicBool,
 }
 
 impl Ext4Inode {
     pub fn new(fs: Arc<Ext4>, meta: Arc<InodeMeta>) -> Self {
-        Self { fs, meta }
+        Self {
+            fs,
+            meta,
+            unlinked: AtomicBool::new(false),
+        }
     }
 
-    /// update the `data_size` of the inode from disk
-    fn update_size(&self, size_delta: usize) {
+    /// update the `data_size` of the inode after writing up to `end`
+    fn update_size(&self, end: usize) {
         // let size = self.get_size();
         // self.meta.inner.lock().data_size = size;
 
@@ -40,7 +45,8 @@ impl Ext4Inode {
         //     size
         // );
         // let old_size = self.meta.inner.lock().data_size;
-        self.meta.inner.lock().data_size += size_delta;
+        let mut meta_inner = self.meta.inner.lock();
+        meta_inner.data_size = core::cmp::max(meta_inner.data_size, end);
     }
 
     fn get_size(&self) -> usize {
@@ -52,6 +58,29 @@ impl Ext4Inode {
         inode_ref.inner.inode.inode_get_size() as usize
     }
 
+    /// size, owner, group and permission bits of the inode on disk
+    fn get_attr_from_ino(fs: &Arc<Ext4>, ino: u64) -> (usize, u32, u32, u16) {
+        let inode_ref = Ext4InodeRef::get_inode_ref(Arc::downgrade(fs), ino as u32);
+        let inode = &inode_ref.inner.inode;
+        let uid = inode.uid as u32 | (inode.osd2.l_i_uid_high as u32) << 16;
+        let gid = inode.gid as u32 | (inode.osd2.l_i_gid_high as u32) << 16;
+        (
+            inode.inode_get_size() as usize,
+            uid,
+            gid,
+            inode.mode & 0o7777,
+        )
+    }
+
+    /// load owner, group and permission bits from disk
+    pub fn load_owner(&self) {
+        let (_, uid, gid, perm) = Self::get_attr_from_ino(&self.fs, self.meta.ino as u64);
+        let mut meta_inner = self.meta.inner.lock();
+        meta_inner.uid = uid;
+        meta_inner.gid = gid;
+        meta_inner.perm = perm;
+    }
+
     fn create_ext4_file(&self, offset: usize) -> Ext4File {
         Ext4File {
             mp: Ext4MountPoint::new("/"),
@@ -62,6 +91,33 @@ impl Ext4Inode {
         }
     }
 
+    /// create a child inode on disk and wrap it
+    fn create_child(
+        &self,
+        this: Arc<dyn Inode>,
+        name: &str,
+        mode: InodeMode,
+        rdev: u64,
+    ) -> SysResult<Arc<dyn Inode>> {
+        if self.meta.mode != InodeMode::FileDIR {
+            return Err(SyscallErr::ENOTDIR.into());
+        }
+        let disk_mode = mode as u16 | mode.default_perm();
+        let new_ino = self
+            .fs
+            .ext4_mknod(self.meta.ino as u32, name, disk_mode, rdev as u32)
+            .map_err(ext4_err_to_sys)?;
+        let meta = InodeMeta::new(
+            Some(this),
+            self.meta.path().append_name(name),
+            mode,
+            0,
+            new_ino as usize,
+        );
+        meta.inner.lock().rdev = rdev;
+        Ok(Arc::new(Ext4Inode::new(self.fs.clone(), Arc::new(meta))))
+    }
+
     fn follow_symlink(&self, ino: u64) -> Path {
         let ext4_file = Ext4File {
             mp: Ext4MountPoint::new("/"),
@@ -99,62 +155,63 @@ impl Inode for Ext4Inode {
 
     fn write<'a>(&'a self, offset: usize, buf: &'a [u8]) -> AsyncResult<usize> {
         Box::pin(async move {
-            let _ = self
+            let write_cnt = self
                 .fs
-                .ext4_file_write(&mut self.create_ext4_file(offset), buf, buf.len());
-            self.update_size(buf.len());
-            log::debug!("[Ext4Inode::write] write {} bytes", buf.len());
-            Ok(buf.len())
+                .ext4_file_write(&mut self.create_ext4_file(offset), buf, buf.len())
+                .map_err(ext4_err_to_sys)?;
+            self.update_size(offset + write_cnt);
+            log::debug!("[Ext4Inode::write] write {} bytes", write_cnt);
+            Ok(write_cnt)
         })
     }
 
+    fn sync_attr(&self) -> SysResult<()> {
+        let (uid, gid, perm) = {
+            let inner = self.meta.inner.lock();
+            (inner.uid, inner.gid, inner.perm)
+        };
+        self.fs
+            .ext4_set_attr(self.meta.ino as u32, uid, gid, perm)
+            .map_err(ext4_err_to_sys)?;
+        Ok(())
+    }
+
     fn mknod(
         &self,
         this: Arc<dyn Inode>,
         name: &str,
         mode: InodeMode,
     ) -> SysResult<Arc<dyn Inode>> {
+        self.create_child(this, name, mode, 0)
+    }
+
+    fn mknod_dev(
+        &self,
+        this: Arc<dyn Inode>,
+        name: &str,
+        mode: InodeMode,
+        rdev: u64,
+    ) -> SysResult<Arc<dyn Inode>> {
+        self.create_child(this, name, mode, rdev)
+    }
+
+    fn symlink(&self, this: Arc<dyn Inode>, name: &str, target: &str) -> SysResult<Arc<dyn Inode>> {
         if self.meta.mode != InodeMode::FileDIR {
-            return Err(1);
-        }
-        if mode == InodeMode::FileDIR {
-            let new_path = self.meta.path.append_name(name);
-            // let new_path = self.get_meta().path.to_string() + "/" + name;
-            let mut file_out = Ext4File::new();
-            let _ = self
-                .fs
-                .ext4_open(&mut file_out, &new_path.to_string(), "a+", false);
-            let new_ino = file_out.inode;
-            // let new_ino = if mode == InodeMode::FileDIR {
-            //     let mut file_out = Ext4File::new();
-            //     let _ = self.fs.ext4_open(&mut file_out, &new_path, "a+", false);
-            //     file_out.inode
-            // } else if mode == InodeMode::FileREG {
-            //     let mut file_out = Ext4File::new();
-            //     let _ = self.fs.ext4_open(&mut file_out, &new_path, "a+", true);
-            //     file_out.inode
-            // } else {
-            //     return Err(1);
-            // };
-            // let path = self.meta.path.append_name(name);
-            let meta = Arc::new(InodeMeta::new(
-                Some(this.clone()),
-                new_path,
-                mode,
-                0,
-                new_ino as usize,
-            ));
-            Ok(Arc::new(Ext4Inode {
-                fs: self.fs.clone(),
-                meta,
-            }))
-        } else if mode == InodeMode::FileREG {
-            let new_path = self.meta.path.append_name(name);
-            let meta = Arc::new(InodeMeta::new(Some(this.clone()), new_path, mode, 0, 0));
-            Ok(Arc::new(Ext4RamInode::new(meta)))
-        } else {
-            Err(1)
+            return Err(SyscallErr::ENOTDIR.into());
         }
+        let new_ino = self
+            .fs
+            .ext4_symlink(self.meta.ino as u32, name, target)
+            .map_err(ext4_err_to_sys)?;
+        let meta = InodeMeta::new_symlink(
+            Some(this),
+            self.meta.path().append_name(name),
+            InodeMode::FileLNK,
+            Some(target.into()),
+            target.len(),
+            new_ino as usize,
+        );
+        Ok(Arc::new(Ext4Inode::new(self.fs.clone(), Arc::new(meta))))
     }
 
     fn get_meta(&self) -> Arc<InodeMeta> {
@@ -167,6 +224,7 @@ impl Inode for Ext4Inode {
         //     self.meta.path
         // );
         assert_eq!(self.meta.mode, InodeMode::FileDIR);
+        let dir_path = self.meta.path();
         let mut meta_inner = self.meta.inner.lock();
         let dir_entries = self.fs.read_dir_entry(self.meta.ino as u64);
 
@@ -176,9 +234,17 @@ impl Inode for Ext4Inode {
             if name == "." || name == ".." {
                 return;
             }
-            let path = self.meta.path.append_name(&name);
+            let path = dir_path.append_name(&name);
             let mode = dirent_inodetype_2_inodemode(unsafe { entry.inner.inode_type });
-            let data_size = Self::get_size_from_ino(&self.fs, ino as u64);
+            let (data_size, uid, gid, perm) = Self::get_attr_from_ino(&self.fs, ino as u64);
+            let rdev = if mode == InodeMode::FileCHR || mode == InodeMode::FileBLK {
+                Ext4InodeRef::get_inode_ref(Arc::downgrade(&self.fs), ino as u32)
+                    .inner
+                    .inode
+                    .ext4_inode_get_rdev() as u64
+            } else {
+                0
+            };
 
             // handle symlink
             let link_target = if mode == InodeMode::FileLNK {
@@ -193,16 +259,118 @@ impl Inode for Ext4Inode {
 
             let inode_meta =
                 InodeMeta::new_symlink(Some(this.clone()), path, mode, link_target, data_size, ino);
-            let inode = Arc::new(Ext4Inode {
-                fs: self.fs.clone(),
-                meta: Arc::new(inode_meta),
-            });
+            {
+                let mut inner = inode_meta.inner.lock();
+                inner.uid = uid;
+                inner.gid = gid;
+                inner.perm = perm;
+                inner.rdev = rdev;
+            }
+            let inode = Arc::new(Ext4Inode::new(self.fs.clone(), Arc::new(inode_meta)));
             // debug!("[Ext4Inode::load_children_from_disk] insert: {}", name);
             meta_inner.children.insert(name, inode);
         });
     }
 
-    fn clear(&self) {}
+    fn clear(&self) {
+        if self.meta.mode == InodeMode::FileREG {
+            if let Err(e) = self.fs.ext4_truncate(self.meta.ino as u32, 0) {
+                error!("[Ext4Inode::clear] {:?}", e);
+            }
+        }
+    }
+
+    fn set_size(&self, size: usize) -> SysResult<()> {
+        self.fs
+            .ext4_truncate(self.meta.ino as u32, size as u64)
+            .map_err(ext4_err_to_sys)?;
+        Ok(())
+    }
+
+    fn unlink(&self) -> SysResult<()> {
+        let parent = self
+            .meta
+            .inner
+            .lock()
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .ok_or(SyscallErr::EBUSY)?;
+        let (_, links) = self
+            .fs
+            .ext4_unlink_entry(parent.get_meta().ino as u32, &self.meta.name())
+            .map_err(ext4_err_to_sys)?;
+        if links == 0 {
+            self.unlinked.store(true, Ordering::Release);
+        }
+        Ok(())
+    }
+
+    fn rename(
+        &self,
+        new_dir: Arc<dyn Inode>,
+        new_name: &str,
+        target: Option<Arc<dyn Inode>>,
+        exchange: bool,
+    ) -> SysResult<()> {
+        let old_dir = self
+            .meta
+            .inner
+            .lock()
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .ok_or(SyscallErr::EBUSY)?;
+        // 删除被替换的目标和移动目录项在同一个事务中
+        self.fs.ext4_trans_start().map_err(ext4_err_to_sys)?;
+        let ret = (|| {
+            if let Some(target) = target.filter(|_| !exchange) {
+                target.unlink()?;
+            }
+            self.fs
+                .ext4_rename(
+                    old_dir.get_meta().ino as u32,
+                    &self.meta.name(),
+                    new_dir.get_meta().ino as u32,
+                    new_name,
+                    exchange,
+                )
+                .map_err(ext4_err_to_sys)
+        })();
+        match ret {
+            Ok(_) => self.fs.ext4_trans_stop().map_err(ext4_err_to_sys)?,
+            Err(e) => {
+                self.fs.ext4_trans_abort();
+                return Err(e);
+            }
+        };
+        Ok(())
+    }
+
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        self.meta.page_cache.clone()
+    }
+
+    fn flush(&self) {
+        self.fs.block_device.flush();
+    }
+}
+
+impl Drop for Ext4Inode {
+    /// 已经没有目录项指向的inode在最后一个引用消失时才释放,
+    /// 这样被删除但仍打开着的文件还能继续读写
+    fn drop(&mut self) {
+        if self.unlinked.load(Ordering::Acquire) {
+            if let Err(e) = self.fs.ext4_free_inode(self.meta.ino as u32) {
+                error!("[Ext4Inode::drop] free inode {}: {:?}", self.meta.ino, e);
+            }
+        }
+    }
+}
+
+fn ext4_err_to_sys(ext4_err: Ext4Error) -> usize {
+    error!("[Ext4Inode] {:?}", ext4_err);
+    ext4_err.error() as usize
 }
 
 fn dirent_inodetype_2_inodemode(inode_type: u8) -> InodeMode {
@@ -3,6 +3,5 @@
 pub mod block_cache;
 pub mod fs;
 pub mod inode;
-pub mod ram_inode;
 
 const EXT4_ROOT_INO: usize = 2;
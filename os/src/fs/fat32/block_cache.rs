@@ -79,7 +79,9 @@ impl Drop for BlockCache {
 }
 
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<SpinNoIrqLock<BlockCache>>)>,
+    /// (block id, address of the block device, cache), several fat32
+    /// filesystems may be mounted at the same time
+    queue: VecDeque<(usize, usize, Arc<SpinNoIrqLock<BlockCache>>)>,
 }
 
 impl BlockCacheManager {
@@ -94,8 +96,13 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<SpinNoIrqLock<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
+        let device_addr = Arc::as_ptr(&block_device) as *const () as usize;
+        if let Some(pair) = self
+            .queue
+            .iter()
+            .find(|pair| pair.0 == block_id && pair.1 == device_addr)
+        {
+            Arc::clone(&pair.2)
         } else {
             // substitute
             if self.queue.len() == BLOCK_CACHE_SIZE {
@@ -104,7 +111,7 @@ impl BlockCacheManager {
                     .queue
                     .iter()
                     .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
+                    .find(|(_, pair)| Arc::strong_count(&pair.2) == 1)
                 {
                     self.queue.drain(idx..=idx);
                 } else {
@@ -116,7 +123,8 @@ impl BlockCacheManager {
                 block_id,
                 Arc::clone(&block_device),
             )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
+            self.queue
+                .push_back((block_id, device_addr, Arc::clone(&block_cache)));
             block_cache
         }
     }
@@ -137,10 +145,9 @@ pub fn get_block_cache(
         .get_block_cache(block_id, block_device)
 }
 /// Sync all block cache to block device
-#[allow(unused)]
 pub fn block_cache_sync_all() {
     let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
+    for (_, _, cache) in manager.queue.iter() {
         cache.lock().sync();
     }
 }
@@ -1,10 +1,14 @@
 use alloc::{
     format,
     string::{String, ToString},
+    vec,
+    vec::Vec,
 };
 use log::info;
 
-use super::{file::FAT32File, time::FAT32Timestamp, LNAME_MAXLEN, SNAME_LEN};
+use crate::{config::SysResult, utils::SyscallErr};
+
+use super::{file::FAT32File, time::FAT32Timestamp, LNAME_MAXLEN, SECTOR_SIZE, SNAME_LEN};
 
 // const ATTR_READ_ONLY: u8 = 0x01;
 // const ATTR_HIDDEN: u8 = 0x02;
@@ -14,6 +18,8 @@ pub const ATTR_DIRECTORY: u8 = 0x10;
 // const ATTR_ARCHIVE: u8 = 0x20;
 const ATTR_LONG_NAME: u8 = 0x0F;
 const DENTRY_SIZE: usize = 0x20;
+/// 一个目录最多有65536个目录项
+const DIR_MAX_SIZE: usize = 65536 * DENTRY_SIZE;
 
 pub struct FAT32DentryContent<'a> {
     file: &'a mut FAT32File,
@@ -40,9 +46,176 @@ impl<'a> FAT32DentryContent<'a> {
         let ret = self.file.write(data, self.offset);
         self.offset += ret;
     }
+
+    /// 查找文件名为`name`的目录项, 同时返回它所在的目录项组的结束位置
+    pub fn find(&mut self, name: &str) -> Option<(FAT32DirEntry, usize)> {
+        self.seek(0);
+        while let Some(dentry) = FAT32DirEntry::read_dentry(self) {
+            if dentry.fname() == name {
+                return Some((dentry, self.offset));
+            }
+        }
+        None
+    }
+
+    /// 把`[start, end)`中的目录项标记为已删除
+    pub fn remove(&mut self, start: usize, end: usize) {
+        for offset in (start..end).step_by(DENTRY_SIZE) {
+            self.file.write(&[0xE5], offset);
+        }
+    }
+
+    /// 以长文件名`name`写入一组目录项, 属性/时间/首簇/大小来自`dentry`,
+    /// 返回写入的范围. 目录已满或者没有空闲簇时返回ENOSPC
+    pub fn insert(&mut self, name: &str, dentry: &FAT32DirEntry) -> SysResult<(usize, usize)> {
+        let mut lname = [0u16; LNAME_MAXLEN];
+        for (i, c) in name.encode_utf16().enumerate() {
+            lname[i] = c;
+        }
+        let entry = FAT32DirEntry {
+            lname,
+            sname: self.unique_sname(name),
+            offset: 0,
+            ..*dentry
+        };
+        let count = (name.encode_utf16().count() + 12) / 13 + 1;
+        let start = self.alloc_slots(count)?;
+        self.seek(start);
+        entry.write_dentry(self);
+        Ok((start, start + count * DENTRY_SIZE))
+    }
+
+    /// 给还没有簇的目录分配第一个簇并写入`.`和`..`, 返回分配的簇
+    pub fn init_dir(&mut self, parent_cluster: u32) -> SysResult<u32> {
+        if self.file.fat.info.lock().free_cluster_count == 0 {
+            return Err(SyscallErr::ENOSPC.into());
+        }
+        let cluster_size = SECTOR_SIZE * self.file.fat.meta.sector_per_cluster;
+        self.file.write(&vec![0u8; cluster_size], 0);
+        let cluster = self.file.first_cluster();
+        self.seek(0);
+        FAT32DirEntry {
+            sname: *b".          ",
+            ..FAT32DirEntry::new(ATTR_DIRECTORY, cluster)
+        }
+        .write_dentry(self);
+        FAT32DirEntry {
+            sname: *b"..         ",
+            ..FAT32DirEntry::new(ATTR_DIRECTORY, parent_cluster)
+        }
+        .write_dentry(self);
+        Ok(cluster)
+    }
+
+    /// 目录的`..`改为指向首簇为`cluster`的目录, 根目录记为0
+    pub fn set_dotdot(&mut self, cluster: u32) {
+        let mut buf = [0u8; DENTRY_SIZE];
+        if self.file.read(&mut buf, DENTRY_SIZE) != DENTRY_SIZE || &buf[0..11] != b"..         " {
+            info!("[Dentry] No dotdot dentry!");
+            return;
+        }
+        buf[20..22].copy_from_slice(&((cluster >> 16) as u16).to_le_bytes());
+        buf[26..28].copy_from_slice(&(cluster as u16).to_le_bytes());
+        self.file.write(&buf, DENTRY_SIZE);
+    }
+
+    /// 长文件名对应的短文件名"BASIS~N.EXT", N使它在目录中唯一
+    fn unique_sname(&mut self, name: &str) -> [u8; SNAME_LEN] {
+        let (base, ext) = match name.rfind('.') {
+            Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+            _ => (name, ""),
+        };
+        let legal = |s: &str, len: usize| -> Vec<u8> {
+            s.chars()
+                .map(|c| c.to_ascii_uppercase())
+                .filter(|&c| c.is_ascii_alphanumeric() || "$%'-_@~`!(){}^#&".contains(c))
+                .map(|c| c as u8)
+                .take(len)
+                .collect()
+        };
+        let mut sname = [b' '; SNAME_LEN];
+        // 本身就是合法的8.3文件名时直接使用
+        let (full_base, full_ext) = (legal(base, 8), legal(ext, 3));
+        if !full_base.is_empty() && full_base == base.as_bytes() && full_ext == ext.as_bytes() {
+            sname[..full_base.len()].copy_from_slice(&full_base);
+            sname[8..8 + full_ext.len()].copy_from_slice(&full_ext);
+            if !self.sname_exists(&sname) {
+                return sname;
+            }
+        }
+
+        let mut base = legal(base, 6);
+        if base.is_empty() {
+            base.push(b'_');
+        }
+        let ext = legal(ext, 3);
+        sname = [b' '; SNAME_LEN];
+        sname[8..8 + ext.len()].copy_from_slice(&ext);
+        for n in 1.. {
+            let tail = format!("~{}", n);
+            let base_len = core::cmp::min(base.len(), 8 - tail.len());
+            sname[..8].fill(b' ');
+            sname[..base_len].copy_from_slice(&base[..base_len]);
+            sname[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+            if !self.sname_exists(&sname) {
+                break;
+            }
+        }
+        sname
+    }
+
+    fn sname_exists(&mut self, sname: &[u8; SNAME_LEN]) -> bool {
+        let mut buf = [0u8; DENTRY_SIZE];
+        let mut offset = 0;
+        while self.file.read(&mut buf, offset) == DENTRY_SIZE && buf[0] != 0x00 {
+            if buf[0] != 0xE5 && buf[11] != ATTR_LONG_NAME && &buf[..SNAME_LEN] == sname {
+                return true;
+            }
+            offset += DENTRY_SIZE;
+        }
+        false
+    }
+
+    /// 找到`count`个连续的空闲目录项, 不够时在目录末尾扩展清零的簇
+    fn alloc_slots(&mut self, count: usize) -> SysResult<usize> {
+        let mut buf = [0u8; DENTRY_SIZE];
+        let mut offset = 0;
+        let mut free = 0;
+        while self.file.read(&mut buf, offset) == DENTRY_SIZE {
+            offset += DENTRY_SIZE;
+            if buf[0] == 0x00 || buf[0] == 0xE5 {
+                free += 1;
+                if free == count {
+                    return Ok(offset - count * DENTRY_SIZE);
+                }
+            } else {
+                free = 0;
+            }
+        }
+        let cluster_size = SECTOR_SIZE * self.file.fat.meta.sector_per_cluster;
+        let len = ((count - free) * DENTRY_SIZE + cluster_size - 1) / cluster_size * cluster_size;
+        if offset + (count - free) * DENTRY_SIZE > DIR_MAX_SIZE
+            || len / cluster_size > self.file.fat.info.lock().free_cluster_count
+        {
+            return Err(SyscallErr::ENOSPC.into());
+        }
+        self.file.write(&vec![0u8; len], offset);
+        Ok(offset - free * DENTRY_SIZE)
+    }
+}
+
+/// FAT32长文件名中不能出现的字符
+pub fn valid_lname(name: &str) -> bool {
+    name.encode_utf16().count() <= LNAME_MAXLEN - 1
+        && !name.ends_with('.')
+        && !name
+            .chars()
+            .any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c))
 }
 
 pub struct FAT32DirEntry {
+    /// 目录项组(长文件名项和短文件名项)在目录中的起始位置
+    pub offset: usize,
     pub lname: [u16; LNAME_MAXLEN],
     pub sname: [u8; SNAME_LEN],
     pub attr: u8,
@@ -54,6 +227,21 @@ pub struct FAT32DirEntry {
 }
 
 impl FAT32DirEntry {
+    /// 没有名字的目录项, 名字由`insert`写入. 内核没有实时时钟, 时间都为0
+    pub fn new(attr: u8, fstcluster: u32) -> Self {
+        Self {
+            offset: 0,
+            lname: [0; LNAME_MAXLEN],
+            sname: [b' '; SNAME_LEN],
+            attr,
+            crt_time: FAT32Timestamp::default(),
+            wrt_time: FAT32Timestamp::default(),
+            acc_time: FAT32Timestamp::default(),
+            fstcluster,
+            filesize: 0,
+        }
+    }
+
     pub fn fname(&self) -> String {
         let mut lname_len = 0;
         while lname_len < LNAME_MAXLEN && self.lname[lname_len] != 0 {
@@ -106,7 +294,9 @@ impl FAT32DirEntry {
             };
         }
 
+        let mut start: Option<usize> = None;
         loop {
+            let entry_offset = reader.offset;
             let ret = reader.read_dentry(&mut read_buf[..]);
             if ret != DENTRY_SIZE {
                 return None;
@@ -139,6 +329,7 @@ impl FAT32DirEntry {
                         info!("[Dentry] Not first dentry!");
                         return None;
                     }
+                    start = Some(entry_offset);
                 }
 
                 next_id = match real_ord {
@@ -190,6 +381,7 @@ impl FAT32DirEntry {
                 }
 
                 return Some(Self {
+                    offset: start.unwrap_or(entry_offset),
                     lname,
                     sname,
                     attr,
@@ -215,7 +407,6 @@ impl FAT32DirEntry {
         }
     }
 
-    #[allow(unused)]
     fn write_dentry(&self, writer: &mut FAT32DentryContent) {
         let mut lname_len = 0;
         while lname_len < LNAME_MAXLEN && self.lname[lname_len] != 0 {
@@ -263,7 +454,7 @@ impl FAT32DirEntry {
             s_lname!(30, lname_offset + 12);
 
             write_buf[0] = (ldir_id as u8) | {
-                if ldir_id == lname_len {
+                if ldir_id == ldir_count {
                     0x40
                 } else {
                     0
@@ -282,7 +473,8 @@ impl FAT32DirEntry {
         write_buf[11] = self.attr;
         write_buf[12] = 0;
         write_buf[13] = self.crt_time.tenms;
-        lsb16!(16, self.crt_time.time);
+        lsb16!(14, self.crt_time.time);
+        lsb16!(16, self.crt_time.date);
         lsb16!(18, self.acc_time.date);
         lsb16!(20, (((self.fstcluster >> 16) & 0xFFFF) as u16));
         lsb16!(22, self.wrt_time.time);
@@ -1,6 +1,9 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-use alloc::sync::Arc;
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+};
 use log::info;
 
 use crate::drivers::block::block_dev::BlockDevice;
@@ -8,6 +11,7 @@ use crate::drivers::block::block_dev::BlockDevice;
 use super::{
     block_cache::get_block_cache,
     // block_dev::BlockDevice,
+    file::FAT32File,
     fs::{FAT32Info, FAT32Meta},
     SpinNoIrqLock,
     FATENTRY_EOC,
@@ -45,6 +49,9 @@ pub struct FAT32FileAllocTable {
     pub info: Arc<SpinNoIrqLock<FAT32Info>>,
     pub meta: Arc<FAT32Meta>,
     pub ino_counter: AtomicUsize,
+    /// content of the directories alive in memory, by ino, so that rename can
+    /// reach the directory entries of another directory
+    dirs: SpinNoIrqLock<BTreeMap<usize, Weak<SpinNoIrqLock<FAT32File>>>>,
 }
 
 impl FAT32FileAllocTable {
@@ -58,6 +65,7 @@ impl FAT32FileAllocTable {
             info,
             meta,
             ino_counter: AtomicUsize::new(0),
+            dirs: SpinNoIrqLock::new(BTreeMap::new()),
         };
         ret.stat_free();
         ret
@@ -160,11 +168,28 @@ impl FAT32FileAllocTable {
             self.write_fat(pre, 0x0FFFFFFF);
         }
         self.write_fat(cluster_id, 0);
-        self.info.lock().free_cluster_count += 1;
+        let mut info = self.info.lock();
+        info.free_cluster_count += 1;
+        // 刚分配的簇马上释放时退回去, 下次分配直接复用它
+        if cluster_id == info.next_free_cluster {
+            info.next_free_cluster -= 1;
+        }
         Some(())
     }
 
     pub fn alloc_ino(&self) -> usize {
         self.ino_counter.fetch_add(1, Ordering::Relaxed)
     }
+
+    pub fn register_dir(&self, ino: usize, file: &Arc<SpinNoIrqLock<FAT32File>>) {
+        self.dirs.lock().insert(ino, Arc::downgrade(file));
+    }
+
+    pub fn unregister_dir(&self, ino: usize) {
+        self.dirs.lock().remove(&ino);
+    }
+
+    pub fn dir_file(&self, ino: usize) -> Option<Arc<SpinNoIrqLock<FAT32File>>> {
+        self.dirs.lock().get(&ino).and_then(|file| file.upgrade())
+    }
 }
@@ -167,7 +167,9 @@ impl FAT32File {
         ret
     }
 
+    /// 释放整条簇链, 只在内存中分配过的簇也都还给FAT
     pub fn clear(&mut self) {
+        self.get_clusters();
         self.clusters.iter().for_each(|&cluster_id| {
             self.fat.free_cluster(cluster_id, None);
         });
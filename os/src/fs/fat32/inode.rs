@@ -6,12 +6,15 @@ use crate::{
     config::{AsyncResult, SysResult},
     fs::{
         inode::{Inode, InodeMeta, InodeMode},
+        page_cache::PageCache,
         path::Path,
     },
+    utils::SyscallErr,
 };
 
 use super::{
-    dentry::{FAT32DentryContent, FAT32DirEntry, ATTR_DIRECTORY},
+    block_cache::block_cache_sync_all,
+    dentry::{valid_lname, FAT32DentryContent, FAT32DirEntry, ATTR_DIRECTORY},
     fat::FAT32FileAllocTable,
     file::FAT32File,
     SpinNoIrqLock,
@@ -31,26 +34,24 @@ impl FAT32Inode {
         first_cluster: usize,
     ) -> Self {
         let file = FAT32File::new(Arc::clone(&fat), first_cluster, None);
-        Self {
-            fat: Arc::clone(&fat),
-            file: Arc::new(SpinNoIrqLock::new(file)),
-            meta: Arc::new(InodeMeta::new(
-                fa_inode,
-                path.clone(),
-                InodeMode::FileDIR,
-                0,
-                fat.alloc_ino(),
-            )),
-        }
+        let meta = InodeMeta::new(
+            fa_inode,
+            path.clone(),
+            InodeMode::FileDIR,
+            0,
+            fat.alloc_ino(),
+        );
+        Self::from_parts(fat, file, meta)
     }
 
+    /// `parent_path` is passed in as the parent's meta is locked while loading its children
     pub fn from_dentry(
         fat: Arc<FAT32FileAllocTable>,
         // fa_inode: Option<Arc<dyn Inode>>,
         fa_inode: Arc<dyn Inode>,
+        parent_path: &Path,
         dentry: &FAT32DirEntry,
     ) -> Self {
-        let parent_path = &fa_inode.get_meta().path;
         let path = parent_path.append_name(&dentry.fname());
         let mode = if (dentry.attr & ATTR_DIRECTORY) == ATTR_DIRECTORY {
             InodeMode::FileDIR
@@ -65,17 +66,14 @@ impl FAT32Inode {
                 _ => None,
             },
         );
-        Self {
-            fat: Arc::clone(&fat),
-            file: Arc::new(SpinNoIrqLock::new(file)),
-            meta: Arc::new(InodeMeta::new(
-                Some(fa_inode),
-                path,
-                mode,
-                dentry.filesize as usize,
-                fat.alloc_ino(),
-            )),
-        }
+        let meta = InodeMeta::new(
+            Some(fa_inode),
+            path,
+            mode,
+            dentry.filesize as usize,
+            fat.alloc_ino(),
+        );
+        Self::from_parts(fat, file, meta)
     }
 
     pub fn new(
@@ -84,7 +82,7 @@ impl FAT32Inode {
         name: &str,
         mode: InodeMode,
     ) -> Self {
-        let parent_path = &fa_inode.get_meta().path;
+        let parent_path = fa_inode.get_meta().path();
         let path = parent_path.append_name(name);
         // log::debug!(
         //     "[FAT32Inode::new] parent_path: {}, path: {}",
@@ -99,19 +97,68 @@ impl FAT32Inode {
                 _ => None,
             },
         );
+        let meta = InodeMeta::new(
+            Some(fa_inode),
+            Path::from(path.clone()),
+            mode,
+            0,
+            fat.alloc_ino(),
+        );
+        Self::from_parts(fat, file, meta)
+    }
+
+    /// directories are registered in the FAT so that rename can reach their entries
+    fn from_parts(fat: Arc<FAT32FileAllocTable>, file: FAT32File, meta: InodeMeta) -> Self {
+        let file = Arc::new(SpinNoIrqLock::new(file));
+        if meta.mode == InodeMode::FileDIR {
+            fat.register_dir(meta.ino, &file);
+        }
         Self {
-            fat: Arc::clone(&fat),
-            file: Arc::new(SpinNoIrqLock::new(file)),
-            meta: Arc::new(InodeMeta::new(
-                Some(fa_inode),
-                Path::from(path.clone()),
-                mode,
-                0,
-                fat.alloc_ino(),
-            )),
+            fat,
+            file,
+            meta: Arc::new(meta),
         }
     }
 
+    /// first cluster of a directory as recorded in `..`, 0 for the root
+    fn dir_cluster(&self, file: &FAT32File) -> u32 {
+        match file.first_cluster() {
+            cluster if cluster as usize == self.fat.meta.root_cluster_id => 0,
+            cluster => cluster,
+        }
+    }
+
+    /// 只在内存中新建的目录还没有簇, 往里面写目录项前先分配簇, 并把它自己的
+    /// 目录项写入父目录. 父目录同样只在内存中时先处理父目录
+    fn dir_on_disk(&self, dir: &Arc<dyn Inode>) -> SysResult<Arc<SpinNoIrqLock<FAT32File>>> {
+        let file = self
+            .fat
+            .dir_file(dir.get_meta().ino)
+            .ok_or(SyscallErr::EIO)?;
+        if file.lock().first_cluster() != 0 {
+            return Ok(file);
+        }
+        let parent = dir
+            .get_meta()
+            .inner
+            .lock()
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .ok_or(SyscallErr::EBUSY)?;
+        let parent_file = self.dir_on_disk(&parent)?;
+        let parent_cluster = self.dir_cluster(&parent_file.lock());
+        let cluster = FAT32DentryContent::new(&mut file.lock()).init_dir(parent_cluster)?;
+        let dentry = FAT32DirEntry::new(ATTR_DIRECTORY, cluster);
+        let name = dir.get_meta().name();
+        if let Err(err) = FAT32DentryContent::new(&mut parent_file.lock()).insert(&name, &dentry) {
+            // 还没有目录项指向新分配的簇, 把它还回去
+            file.lock().clear();
+            return Err(err);
+        }
+        Ok(file)
+    }
+
     fn update_size(&self) {
         self.meta.inner.lock().data_size = self.file.lock().size.unwrap_or(0);
     }
@@ -165,6 +212,7 @@ impl Inode for FAT32Inode {
     fn load_children_from_disk(&self, this: Arc<dyn Inode>) {
         assert_eq!(self.meta.mode, InodeMode::FileDIR);
         let meta = self.meta.clone();
+        let dir_path = meta.path();
         let mut meta_inner = meta.inner.lock();
         let mut content = self.file.lock();
         let fat = Arc::clone(&content.fat);
@@ -174,7 +222,8 @@ impl Inode for FAT32Inode {
             if fname == "." || fname == ".." {
                 continue;
             }
-            let inode = FAT32Inode::from_dentry(Arc::clone(&fat), Arc::clone(&this), &dentry);
+            let inode =
+                FAT32Inode::from_dentry(Arc::clone(&fat), Arc::clone(&this), &dir_path, &dentry);
             let inode_rc: Arc<dyn Inode> = Arc::new(inode);
             meta_inner
                 .children
@@ -186,4 +235,122 @@ impl Inode for FAT32Inode {
         self.file.lock().clear();
         self.update_size();
     }
+
+    /// 变大时和`write`一样写入0, 变小时释放多出的簇
+    fn set_size(&self, size: usize) -> SysResult<()> {
+        let mut file = self.file.lock();
+        let data_size = file.size.unwrap_or(0);
+        if size > data_size {
+            file.write(&vec![0u8; size - data_size], data_size);
+        } else {
+            file.modify_size(size as isize - data_size as isize);
+        }
+        drop(file);
+        self.update_size();
+        Ok(())
+    }
+
+    /// 以新的长文件名在新目录中写入目录项组, 成功后再删除旧的目录项组.
+    /// 只在内存中新建的文件和目录没有目录项, 移动它们不用改动磁盘
+    fn rename(
+        &self,
+        new_dir: Arc<dyn Inode>,
+        new_name: &str,
+        target: Option<Arc<dyn Inode>>,
+        exchange: bool,
+    ) -> SysResult<()> {
+        if !valid_lname(new_name) {
+            return Err(SyscallErr::EINVAL.into());
+        }
+        let old_dir = self
+            .meta
+            .inner
+            .lock()
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .ok_or(SyscallErr::EBUSY)?;
+        let old_name = self.meta.name();
+        let (Some(old_file), Some(new_file)) = (
+            self.fat.dir_file(old_dir.get_meta().ino),
+            self.fat.dir_file(new_dir.get_meta().ino),
+        ) else {
+            return Err(SyscallErr::EIO.into());
+        };
+
+        let find = |file: &SpinNoIrqLock<FAT32File>, name: &str| {
+            FAT32DentryContent::new(&mut file.lock()).find(name)
+        };
+        let insert = |file: &SpinNoIrqLock<FAT32File>, name: &str, dentry: &FAT32DirEntry| {
+            FAT32DentryContent::new(&mut file.lock()).insert(name, dentry)
+        };
+        let remove = |file: &SpinNoIrqLock<FAT32File>, (start, end): (usize, usize)| {
+            FAT32DentryContent::new(&mut file.lock()).remove(start, end)
+        };
+        let set_dotdot = |file: &SpinNoIrqLock<FAT32File>, parent: &SpinNoIrqLock<FAT32File>| {
+            let cluster = self.dir_cluster(&parent.lock());
+            let mut file = file.lock();
+            if file.first_cluster() != 0 {
+                FAT32DentryContent::new(&mut file).set_dotdot(cluster);
+            }
+        };
+
+        let src = find(&old_file, &old_name);
+        let dst = find(&new_file, new_name);
+        // 先写入新的目录项, 失败时旧的目录项保持不变
+        let moved = match &src {
+            Some((src, _)) => {
+                self.dir_on_disk(&new_dir)?;
+                Some(insert(&new_file, new_name, src)?)
+            }
+            None => None,
+        };
+        if exchange {
+            if let Some((dst, _)) = &dst {
+                let ret = self
+                    .dir_on_disk(&old_dir)
+                    .and_then(|_| insert(&old_file, &old_name, dst));
+                if let Err(err) = ret {
+                    if let Some(range) = moved {
+                        remove(&new_file, range);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        // 被替换的目标只删除目录项, 和unlink一样不释放它的簇
+        if let Some((src, end)) = src {
+            remove(&old_file, (src.offset, end));
+        }
+        if let Some((dst, end)) = dst {
+            remove(&new_file, (dst.offset, end));
+        }
+        if !Arc::ptr_eq(&old_file, &new_file) {
+            if self.meta.mode == InodeMode::FileDIR {
+                set_dotdot(&self.file, &new_file);
+            }
+            if let Some(target) = target.filter(|_| exchange) {
+                if let Some(target_file) = self.fat.dir_file(target.get_meta().ino) {
+                    set_dotdot(&target_file, &old_file);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        self.meta.page_cache.clone()
+    }
+
+    fn flush(&self) {
+        block_cache_sync_all();
+    }
+}
+
+impl Drop for FAT32Inode {
+    fn drop(&mut self) {
+        if self.meta.mode == InodeMode::FileDIR {
+            self.fat.unregister_dir(self.meta.ino);
+        }
+    }
 }
@@ -42,17 +42,23 @@ impl FdTable {
 
 impl FdTable {
     /// alloc lowest-numbered available fd greater than or equal to least_fd
-    /// return allocated fd number
+    /// return allocated fd number, `EMFILE` if it would exceed `RLIMIT_NOFILE`
     fn allocate(&mut self, least_fd: usize) -> SysResult<usize> {
         if least_fd < self.table.len() {
-            if let Some(fd) = (0..self.table.len()).find(|fd| self.table[*fd].is_none()) {
+            if let Some(fd) = (least_fd..self.table.len()).find(|fd| self.table[*fd].is_none()) {
                 Ok(fd)
             } else {
-                // self.table.push(None);
-                self.reserve(self.table.len() + 1)?;
-                Ok(self.table.len() - 1)
+                let fd = self.table.len();
+                if fd >= self.rlimit.rlim_cur {
+                    return Err(SyscallErr::EMFILE.into());
+                }
+                self.reserve(fd)?;
+                Ok(fd)
             }
         } else {
+            if least_fd >= self.rlimit.rlim_cur {
+                return Err(SyscallErr::EMFILE.into());
+            }
             self.reserve(least_fd)?;
             self.table[least_fd] = None;
             Ok(least_fd)
@@ -61,7 +67,7 @@ impl FdTable {
 
     /// resize fdtable to reserve fd
     fn reserve(&mut self, fd: usize) -> SysResult<()> {
-        if fd > self.rlimit.rlim_cur - 1 {
+        if fd >= self.rlimit.rlim_cur {
             return Err(SyscallErr::EBADF as usize);
         }
         // len is at least (fd + 1)
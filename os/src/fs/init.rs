@@ -1,36 +1,40 @@
-use alloc::sync::Arc;
-
 use super::{
-    create_dir,
-    devfs::dev::DevInode,
-    inode::Inode,
+    inode::InodeMode,
+    mount::{do_mount, FsType, MountFlags, MOUNT_TABLE},
     os_inode::{list_apps, ROOT_INODE},
-    procfs::proc::ProcInode,
-    AT_FDCWD,
+    page_cache::writeback_loop,
 };
+use crate::task::schedule::spawn_kernel_thread;
 
 /// used on start of os
 pub fn init() {
     mount_fs();
     list_apps();
+    spawn_kernel_thread(writeback_loop());
 }
 
-fn mount_fs() {
-    let root_meta = ROOT_INODE.get_meta();
-    let mut root_inner = root_meta.inner.lock();
-
-    // 'mount' inner fs es
-    let dev: Arc<dyn Inode> = Arc::new(DevInode::new(ROOT_INODE.clone()));
-    let proc: Arc<dyn Inode> = Arc::new(ProcInode::new(ROOT_INODE.clone()));
-    root_inner.children.insert(dev.get_name(), dev.clone());
-    root_inner.children.insert(proc.get_name(), proc.clone());
+#[cfg(feature = "fat32")]
+const ROOT_FS_TYPE: FsType = FsType::Vfat;
+#[cfg(not(feature = "fat32"))]
+const ROOT_FS_TYPE: FsType = FsType::Ext4;
 
-    drop(root_inner);
+fn mount_fs() {
+    MOUNT_TABLE
+        .lock()
+        .mount_root("/dev/root", ROOT_FS_TYPE, ROOT_INODE.clone());
 
-    log::debug!("[mount_fs] Mounted dev and proc fs");
-    // create new '/tmp' directory
-    match create_dir(AT_FDCWD, &"/tmp".into()) {
-        Ok(_) => {}
-        Err(e) => log::info!("[mount_fs] Fail to create '/tmp' directory: {}", e),
+    // mount inner fs es, creating the mount points if missing
+    for (target, fstype) in [("/dev", "devtmpfs"), ("/proc", "proc"), ("/tmp", "tmpfs")] {
+        let name = &target[1..];
+        if ROOT_INODE.find(name).is_err() {
+            if let Err(e) = ROOT_INODE.mknod_v(name, InodeMode::FileDIR) {
+                log::info!("[mount_fs] Fail to create '{}' directory: {}", target, e);
+                continue;
+            }
+        }
+        match do_mount("none", &target.into(), fstype, MountFlags::empty()) {
+            Ok(_) => log::debug!("[mount_fs] Mounted {} on {}", fstype, target),
+            Err(e) => log::info!("[mount_fs] Fail to mount {} on {}: {}", fstype, target, e),
+        }
     }
 }
@@ -9,10 +9,12 @@ use log::debug;
 use crate::{
     config::{AsyncResult, SysResult},
     mutex::SpinNoIrqLock,
-    timer::TimeSpec,
+    syscall::cred::{Credentials, S_ISGID},
+    timer::{current_time_spec, TimeSpec},
+    utils::SyscallErr,
 };
 
-use super::path::Path;
+use super::{mount::MOUNT_TABLE, page_cache::PageCache, path::Path};
 
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub enum InodeMode {
@@ -25,6 +27,17 @@ pub enum InodeMode {
     FileFIFO = 0x1000, /* FIFO */
 }
 
+impl InodeMode {
+    /// 不记录权限的文件系统(fat32, 内存中的文件)使用的默认权限
+    pub fn default_perm(&self) -> u16 {
+        match self {
+            InodeMode::FileDIR | InodeMode::FileREG => 0o755,
+            InodeMode::FileLNK => 0o777,
+            _ => 0o666,
+        }
+    }
+}
+
 // impl From<u32> for InodeMode {
 //     fn from(value: u32) -> Self {
 //         match value {
@@ -55,10 +68,66 @@ pub trait Inode: Send + Sync {
     fn write<'a>(&'a self, offset: usize, buf: &'a [u8]) -> AsyncResult<usize>;
     fn mknod(&self, this: Arc<dyn Inode>, name: &str, mode: InodeMode)
         -> SysResult<Arc<dyn Inode>>;
+    /// create a device file, `rdev` is only meaningful for FileCHR and FileBLK
+    fn mknod_dev(
+        &self,
+        this: Arc<dyn Inode>,
+        name: &str,
+        mode: InodeMode,
+        rdev: u64,
+    ) -> SysResult<Arc<dyn Inode>> {
+        let child = self.mknod(this, name, mode)?;
+        child.get_meta().inner.lock().rdev = rdev;
+        Ok(child)
+    }
+    /// create a symlink pointing to `target`
+    fn symlink(
+        &self,
+        _this: Arc<dyn Inode>,
+        _name: &str,
+        _target: &str,
+    ) -> SysResult<Arc<dyn Inode>> {
+        Err(SyscallErr::EPERM.into())
+    }
     fn get_meta(&self) -> Arc<InodeMeta>;
     fn load_children_from_disk(&self, this: Arc<dyn Inode>);
     /// clear the file content, inode still exists
     fn clear(&self);
+    /// change the size of the file content on disk to `size`, dropping what is
+    /// beyond it or extending it with zeros
+    fn set_size(&self, _size: usize) -> SysResult<()> {
+        Err(SyscallErr::EINVAL.into())
+    }
+    /// remove the directory entry of this inode on disk, called before
+    /// it is removed from its parent's children
+    fn unlink(&self) -> SysResult<()> {
+        Ok(())
+    }
+    /// move the directory entry of this inode on disk to `new_name` in `new_dir`.
+    /// `target` is the inode currently named `new_name`, swapped with this one
+    /// if `exchange`, otherwise replaced. Called before the in-memory tree is updated
+    fn rename(
+        &self,
+        _new_dir: Arc<dyn Inode>,
+        _new_name: &str,
+        _target: Option<Arc<dyn Inode>>,
+        _exchange: bool,
+    ) -> SysResult<()> {
+        Ok(())
+    }
+    /// write the owner, group and permission bits of the meta back to disk,
+    /// called after chmod, chown and creating the inode
+    fn sync_attr(&self) -> SysResult<()> {
+        Ok(())
+    }
+    /// page cache of the file content, `None` if the content must not be cached
+    /// (e.g. device files whose content changes on every read)
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        None
+    }
+    /// push what `write` left in the caches of the filesystem to the device,
+    /// called after the dirty pages of the inode are written back
+    fn flush(&self) {}
 }
 
 impl dyn Inode {
@@ -83,7 +152,7 @@ impl dyn Inode {
     }
 
     pub fn get_name(&self) -> String {
-        self.get_meta().name.clone()
+        self.get_meta().name()
     }
 
     pub fn mknod_v(self: &Arc<Self>, name: &str, mode: InodeMode) -> SysResult<Arc<dyn Inode>> {
@@ -94,6 +163,27 @@ impl dyn Inode {
         Ok(child)
     }
 
+    pub fn mknod_dev_v(
+        self: &Arc<Self>,
+        name: &str,
+        mode: InodeMode,
+        rdev: u64,
+    ) -> SysResult<Arc<dyn Inode>> {
+        let child = self.mknod_dev(self.clone(), name, mode, rdev)?;
+        self.get_meta().children_handler(self.clone(), |chidren| {
+            chidren.insert(name.to_string(), child.clone());
+        });
+        Ok(child)
+    }
+
+    pub fn symlink_v(self: &Arc<Self>, name: &str, target: &str) -> SysResult<Arc<dyn Inode>> {
+        let child = self.symlink(self.clone(), name, target)?;
+        self.get_meta().children_handler(self.clone(), |chidren| {
+            chidren.insert(name.to_string(), child.clone());
+        });
+        Ok(child)
+    }
+
     pub fn open_path(
         self: &Arc<Self>,
         path: &Path,
@@ -105,6 +195,8 @@ impl dyn Inode {
             if name == "." {
                 continue;
             } else if name == ".." {
+                // `..` of a mount root is the parent of the covered directory
+                current_node = MOUNT_TABLE.lock().leave(current_node);
                 if let Some(new_dir) = current_node.get_meta().inner.lock().parent.clone() {
                     current_node = new_dir.upgrade().unwrap();
                 } else {
@@ -113,13 +205,13 @@ impl dyn Inode {
             } else {
                 // name is a String
                 if let Ok(new_node) = current_node.find(name) {
-                    current_node = new_node.clone();
+                    current_node = MOUNT_TABLE.lock().enter(new_node);
                 } else if i == path.len() - 1 && create_file {
                     debug!("[open_path] file {} created", name);
-                    current_node = current_node.mknod_v(name, InodeMode::FileREG).unwrap();
+                    current_node = current_node.mknod_v(name, InodeMode::FileREG)?;
                 } else if i == path.len() - 1 && create_dir {
                     debug!("[open_path] dir {} created", name);
-                    current_node = current_node.mknod_v(name, InodeMode::FileDIR).unwrap();
+                    current_node = current_node.mknod_v(name, InodeMode::FileDIR)?;
                 } else {
                     debug!("[open_path] file {} not found", name);
                     return Err(1);
@@ -129,7 +221,125 @@ impl dyn Inode {
         Ok(current_node)
     }
 
-    pub fn delete(&self) {
+    /// read file content through the page cache if there is one
+    pub async fn read_cached(self: &Arc<Self>, offset: usize, buf: &mut [u8]) -> SysResult<usize> {
+        match self.page_cache() {
+            Some(page_cache) => page_cache.read(self, offset, buf).await,
+            None => self.read(offset, buf).await,
+        }
+    }
+
+    /// write file content through the page cache if there is one
+    pub async fn write_cached(self: &Arc<Self>, offset: usize, buf: &[u8]) -> SysResult<usize> {
+        match self.page_cache() {
+            Some(page_cache) => page_cache.write(self, offset, buf).await,
+            None => self.write(offset, buf).await,
+        }
+    }
+
+    /// change the file size to `size`, what is cached beyond it is dropped
+    pub fn truncate(&self, size: usize) -> SysResult<()> {
+        if let Some(page_cache) = self.page_cache() {
+            page_cache.truncate(size);
+        }
+        self.set_size(size)?;
+        let meta = self.get_meta();
+        let mut inner = meta.inner.lock();
+        inner.data_size = size;
+        inner.st_mtim = current_time_spec();
+        inner.st_ctim = inner.st_mtim;
+        Ok(())
+    }
+
+    /// write back dirty cached pages
+    pub async fn sync(self: &Arc<Self>) -> SysResult<()> {
+        if let Some(page_cache) = self.page_cache() {
+            page_cache.sync(self).await?;
+            self.flush();
+        }
+        Ok(())
+    }
+
+    /// rename this inode to `new_name` in `new_dir`. `target` is the inode
+    /// currently named `new_name`, swapped with this one if `exchange`,
+    /// otherwise replaced
+    pub fn rename_v(
+        self: &Arc<Self>,
+        new_dir: &Arc<dyn Inode>,
+        new_name: &str,
+        target: Option<Arc<dyn Inode>>,
+        exchange: bool,
+    ) -> SysResult<()> {
+        let old_dir = self
+            .get_meta()
+            .inner
+            .lock()
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .ok_or(SyscallErr::EBUSY)?;
+        let old_name = self.get_name();
+        self.rename(new_dir.clone(), new_name, target.clone(), exchange)?;
+
+        old_dir
+            .get_meta()
+            .children_handler(old_dir.clone(), |children| children.remove(&old_name));
+        new_dir
+            .get_meta()
+            .children_handler(new_dir.clone(), |children| {
+                children.insert(new_name.to_string(), self.clone())
+            });
+        match target {
+            Some(target) if exchange => {
+                old_dir
+                    .get_meta()
+                    .children_handler(old_dir.clone(), |children| {
+                        children.insert(old_name.clone(), target.clone())
+                    });
+                target.relocate(&old_dir, &old_name);
+            }
+            _ => {}
+        }
+        self.relocate(new_dir, new_name);
+        Ok(())
+    }
+
+    /// 移动到`parent`下的`name`之后, 更新自己和已经加载的子孙的路径
+    fn relocate(self: &Arc<Self>, parent: &Arc<dyn Inode>, name: &str) {
+        let path = parent.get_meta().path().append_name(name);
+        let meta = self.get_meta();
+        let children: Vec<Arc<dyn Inode>> = {
+            let mut inner = meta.inner.lock();
+            inner.parent = Some(Arc::downgrade(parent));
+            inner.name = name.to_string();
+            inner.path = path;
+            inner.children.values().cloned().collect()
+        };
+        for child in children {
+            child.relocate(self, &child.get_name());
+        }
+    }
+
+    /// whether `self` is `inode` or one of its ancestors
+    pub fn is_ancestor_of(self: &Arc<Self>, inode: &Arc<dyn Inode>) -> bool {
+        let mut current = Some(inode.clone());
+        while let Some(node) = current {
+            if Arc::ptr_eq(&node.get_meta(), &self.get_meta()) {
+                return true;
+            }
+            current = node
+                .get_meta()
+                .inner
+                .lock()
+                .parent
+                .as_ref()
+                .and_then(|parent| parent.upgrade());
+        }
+        false
+    }
+
+    pub fn delete(&self) -> SysResult<()> {
+        self.unlink()?;
         let parent = self.get_meta().inner.lock().parent.clone();
         if let Some(parent) = parent {
             let parent = parent.upgrade().unwrap();
@@ -140,6 +350,7 @@ impl dyn Inode {
                     children.remove(&name);
                 });
         }
+        Ok(())
     }
 }
 
@@ -148,13 +359,11 @@ pub struct InodeMeta {
     pub ino: usize,
     /// type of inode
     pub mode: InodeMode,
-    /// name which doesn't have slash
-    pub name: String,
-    /// path
-    pub path: Path,
     /// if mode == FileLNK, then link_target is none-empty.
     /// link_target is the RELATIVE path that the symlink pargets to.
     pub link_target: Option<Path>,
+    /// only allocated for regular files, used by inodes that return it from `Inode::page_cache`
+    pub page_cache: Option<Arc<PageCache>>,
     pub inner: SpinNoIrqLock<InodeMetaInner>,
 }
 
@@ -185,21 +394,65 @@ impl InodeMeta {
         Self {
             ino,
             mode,
-            name: path.get_name(),
-            path,
             link_target,
+            page_cache: (mode == InodeMode::FileREG).then(PageCache::new),
             inner: SpinNoIrqLock::new(InodeMetaInner {
                 st_atim: TimeSpec::new(),
                 st_mtim: TimeSpec::new(),
                 st_ctim: TimeSpec::new(),
                 parent,
+                name: path.get_name(),
+                path,
                 children: BTreeMap::new(),
                 data_size,
                 state: InodeState::Init,
+                uid: 0,
+                gid: 0,
+                perm: mode.default_perm(),
+                rdev: 0,
             }),
         }
     }
 
+    pub fn name(&self) -> String {
+        self.inner.lock().name.clone()
+    }
+
+    pub fn path(&self) -> Path {
+        self.inner.lock().path.clone()
+    }
+
+    /// 以`cred`的文件系统身份访问, `access`为`MAY_*`的组合, 没有权限时返回`EACCES`
+    pub fn check_access(&self, cred: &Credentials, access: u16) -> SysResult<()> {
+        let inner = self.inner.lock();
+        let is_dir = self.mode == InodeMode::FileDIR;
+        if cred.may_access(inner.uid, inner.gid, inner.perm, is_dir, access) {
+            Ok(())
+        } else {
+            Err(SyscallErr::EACCES.into())
+        }
+    }
+
+    /// 新建的文件属于创建者, 父目录有setgid位时继承父目录的组.
+    /// `perm`由调用者去掉`umask`中的位
+    pub fn init_owner(&self, cred: &Credentials, parent: &InodeMeta, perm: u16) {
+        let (parent_gid, parent_perm) = {
+            let parent_inner = parent.inner.lock();
+            (parent_inner.gid, parent_inner.perm)
+        };
+        let mut inner = self.inner.lock();
+        inner.uid = cred.user.fs;
+        inner.perm = perm & 0o7777;
+        if parent_perm & S_ISGID != 0 {
+            inner.gid = parent_gid;
+            if self.mode == InodeMode::FileDIR {
+                inner.perm |= S_ISGID;
+            }
+        } else {
+            inner.gid = cred.group.fs;
+        }
+    }
+
     /// use this method **instead of** access inode.meta.inner.children directly
     /// to ensure children are loaded from disk before use  
     /// We can do whatever we want to do on children by providing a handler
@@ -240,6 +493,10 @@ pub struct InodeMetaInner {
     pub st_ctim: TimeSpec,
     /// parent
     pub parent: Option<Weak<dyn Inode>>,
+    /// name which doesn't have slash, changed by rename
+    pub name: String,
+    /// path, changed when the inode or one of its ancestors is renamed
+    pub path: Path,
     /// children list (name, inode)
     /// USE INODEMETA::GET_CHILDREN() TO ENSURE CHILDREN ARE LOADED FROM DISK BEFORE USE
     pub children: BTreeMap<String, Arc<dyn Inode>>,
@@ -247,4 +504,12 @@ pub struct InodeMetaInner {
     pub data_size: usize,
     // inode state, mainly for Dir inode
     pub state: InodeState,
+    /// owner
+    pub uid: u32,
+    /// group
+    pub gid: u32,
+    /// permission bits, including setuid/setgid/sticky (the low 12 bits of `st_mode`)
+    pub perm: u16,
+    /// device number of FileCHR and FileBLK
+    pub rdev: u64,
 }
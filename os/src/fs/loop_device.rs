@@ -0,0 +1,54 @@
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use crate::{drivers::block::block_dev::BlockDevice, utils::block_on::block_on};
+
+use super::inode::Inode;
+
+/// Expose a regular file as a block device, so that an image file
+/// can be mounted as ext4 or fat32.
+pub struct LoopDevice {
+    inode: Arc<dyn Inode>,
+}
+
+impl LoopDevice {
+    pub fn new(inode: Arc<dyn Inode>) -> Self {
+        Self { inode }
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) {
+        // bytes beyond the end of the image read as 0
+        buf.fill(0);
+        let size = self.inode.get_meta().inner.lock().data_size;
+        if offset >= size {
+            return;
+        }
+        let len = core::cmp::min(buf.len(), size - offset);
+        let _ = block_on(self.inode.read_cached(offset, &mut buf[..len]));
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) {
+        let _ = block_on(self.inode.write_cached(offset, buf));
+    }
+}
+
+impl BlockDevice for LoopDevice {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.read_at(block_id * buf.len(), buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        self.write_at(block_id * buf.len(), buf);
+    }
+}
+
+impl ext4_rs::BlockDevice for LoopDevice {
+    fn read_offset(&self, offset: usize) -> Vec<u8> {
+        let mut buf = vec![0u8; ext4_rs::BLOCK_SIZE];
+        self.read_at(offset, &mut buf);
+        buf
+    }
+
+    fn write_offset(&self, offset: usize, data: &[u8]) {
+        self.write_at(offset, data);
+    }
+}
@@ -1,15 +1,22 @@
 //! File system in os
 // pub mod ctypes;
 mod devfs;
+pub mod epoll;
 mod ext4;
 mod fat32;
 pub mod fd_table;
 pub mod init;
 pub mod inode;
+mod loop_device;
+pub mod mount;
 mod os_inode;
+pub mod page_cache;
 pub mod path;
 pub mod pipe;
+pub mod poll;
 mod procfs;
+pub mod signalfd;
+mod tmpfs;
 // pub mod socketpair;
 // mod stdio;
 
@@ -17,6 +24,8 @@ mod procfs;
 // mod stdio;
 pub mod tty;
 
+use core::task::Waker;
+
 use crate::{
     config::AsyncResult,
     mutex::SpinNoIrqLock,
@@ -79,6 +88,11 @@ pub trait File: Send + Sync {
     fn seek(&self, offset: usize) -> Option<usize>;
 
     fn ioctl(&self, _request: usize, _argp: usize) -> SyscallRet;
+
+    /// Return the ready ones of `events`, `POLLERR` and `POLLHUP` are reported even if not requested.
+    /// `waker` (if any) will be woken on the next change that may make `events` ready,
+    /// even if some are ready now, so that edge-triggered epoll sees new data
+    fn poll(&self, events: PollEvents, waker: Option<&Waker>) -> PollEvents;
     // fn ioctl(&self, _request: usize, _argp: usize) -> SyscallRet {
     //     error!("ioctl not implemented");
     //     Err(SyscallErr::ENOTTY as usize)
@@ -91,6 +105,8 @@ pub enum OSFileType {
     Pipe,
     SocketPair,
     TTY,
+    Epoll,
+    SignalFd,
 }
 
 #[derive(Debug)]
@@ -123,11 +139,11 @@ impl Fstat {
         Self {
             st_dev: 0,
             st_ino: metadata.ino as u64,
-            st_mode: metadata.mode as u32,
+            st_mode: metadata.mode as u32 | data_lock.perm as u32,
             st_nlink: 1,
-            st_uid: 0,
-            st_gid: 0,
-            st_rdev: 0,
+            st_uid: data_lock.uid,
+            st_gid: data_lock.gid,
+            st_rdev: data_lock.rdev,
             __pad1: 0,
             st_size: data_size as u64,
             st_blksize: BLOCK_SIZE as u32,
@@ -142,6 +158,8 @@ impl Fstat {
 
 pub const AT_FDCWD: isize = -100;
 pub const AT_REMOVEDIR: u32 = 0x200;
+pub const RENAME_NOREPLACE: u32 = 1 << 0;
+pub const RENAME_EXCHANGE: u32 = 1 << 1;
 
 use alloc::sync::Arc;
 use fat32::BLOCK_SIZE;
@@ -150,4 +168,5 @@ use inode::Inode;
 // use alloc::sync::Arc;
 // pub use devfs::tty::TtyFile;
 pub use os_inode::{create_dir, open_fd, open_inode, open_osinode, OSInode, OpenFlags};
+pub use poll::PollEvents;
 // pub use stdio::{Stdin, Stdout};
@@ -0,0 +1,381 @@
+//! VFS mount table
+//!
+//! Every mounted filesystem is recorded as a `MountPoint`, which pairs the
+//! directory inode it covers with the root inode of the mounted filesystem.
+//! Inodes are identified by their address, so the covered inode must stay
+//! alive while mounted (the table holds an `Arc` to it).
+//!
+//! `Inode::open_path` consults the table on every step: entering a covered
+//! directory switches to the root of the topmost filesystem mounted there,
+//! and `..` at a mount root climbs back to the covered directory first.
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
+use lazy_static::lazy_static;
+use log::{debug, info, warn};
+
+use crate::{
+    mutex::SpinNoIrqLock,
+    task::all_processes,
+    utils::{block_on::block_on, SyscallErr},
+    SysResult, SyscallRet,
+};
+
+use super::{
+    devfs::dev::DevInode,
+    ext4::fs::Ext4FileSystem,
+    fat32::fs::FAT32FileSystem,
+    inode::{Inode, InodeMode},
+    loop_device::LoopDevice,
+    open_inode, page_cache,
+    path::Path,
+    procfs::proc::ProcInode,
+    tmpfs::TmpInode,
+    OpenFlags, AT_FDCWD,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsType {
+    Ext4,
+    Vfat,
+    Devfs,
+    Procfs,
+    Tmpfs,
+}
+
+impl FsType {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ext4" => Some(Self::Ext4),
+            "vfat" | "fat32" => Some(Self::Vfat),
+            "devtmpfs" | "devfs" => Some(Self::Devfs),
+            "proc" | "procfs" => Some(Self::Procfs),
+            "tmpfs" => Some(Self::Tmpfs),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ext4 => "ext4",
+            Self::Vfat => "vfat",
+            Self::Devfs => "devtmpfs",
+            Self::Procfs => "proc",
+            Self::Tmpfs => "tmpfs",
+        }
+    }
+}
+
+bitflags! {
+    /// flags of `sys_mount`
+    pub struct MountFlags: u32 {
+        const MS_RDONLY = 1 << 0;
+        const MS_NOSUID = 1 << 1;
+        const MS_NODEV = 1 << 2;
+        const MS_NOEXEC = 1 << 3;
+        const MS_SYNCHRONOUS = 1 << 4;
+        const MS_REMOUNT = 1 << 5;
+        const MS_NOATIME = 1 << 10;
+        const MS_BIND = 1 << 12;
+        /// 以下几位只影响内核日志和 atime 策略，接受但忽略
+        const MS_SILENT = 1 << 15;
+        const MS_RELATIME = 1 << 21;
+        const MS_STRICTATIME = 1 << 24;
+        const MS_LAZYTIME = 1 << 25;
+    }
+}
+
+bitflags! {
+    /// flags of `sys_umount2`
+    pub struct UmountFlags: u32 {
+        const MNT_FORCE = 1 << 0;
+        const MNT_DETACH = 1 << 1;
+        const MNT_EXPIRE = 1 << 2;
+        const UMOUNT_NOFOLLOW = 1 << 3;
+    }
+}
+
+pub struct MountPoint {
+    /// device or image the filesystem comes from, only used for display
+    pub source: String,
+    /// absolute path of the mount point
+    pub target: Path,
+    pub fstype: FsType,
+    pub flags: MountFlags,
+    /// directory hidden by this mount, `None` for the root filesystem
+    covered: Option<Arc<dyn Inode>>,
+    /// root inode of the mounted filesystem
+    root: Arc<dyn Inode>,
+}
+
+pub struct MountTable {
+    /// in mount order, so later entries stack over earlier ones
+    mounts: Vec<MountPoint>,
+}
+
+lazy_static! {
+    pub static ref MOUNT_TABLE: SpinNoIrqLock<MountTable> = SpinNoIrqLock::new(MountTable::new());
+}
+
+pub fn inode_addr(inode: &Arc<dyn Inode>) -> usize {
+    Arc::as_ptr(inode) as *const () as usize
+}
+
+impl MountTable {
+    pub const fn new() -> Self {
+        Self { mounts: Vec::new() }
+    }
+
+    /// the mount whose root is `root`
+    fn find_by_root(&self, root: &Arc<dyn Inode>) -> Option<&MountPoint> {
+        let addr = inode_addr(root);
+        self.mounts
+            .iter()
+            .rev()
+            .find(|mnt| inode_addr(&mnt.root) == addr)
+    }
+
+    /// the topmost mount covering `dir`
+    fn find_by_covered(&self, dir: &Arc<dyn Inode>) -> Option<&MountPoint> {
+        let addr = inode_addr(dir);
+        self.mounts.iter().rev().find(|mnt| {
+            mnt.covered
+                .as_ref()
+                .is_some_and(|covered| inode_addr(covered) == addr)
+        })
+    }
+
+    /// whether `inode` is the root of a mount or covered by one
+    pub fn is_mount_point(&self, inode: &Arc<dyn Inode>) -> bool {
+        self.find_by_root(inode).is_some() || self.find_by_covered(inode).is_some()
+    }
+
+    /// follow mounts stacked on `dir` down to the visible root
+    pub fn enter(&self, dir: Arc<dyn Inode>) -> Arc<dyn Inode> {
+        let mut current = dir;
+        while let Some(mnt) = self.find_by_covered(&current) {
+            current = mnt.root.clone();
+        }
+        current
+    }
+
+    /// if `dir` is a mount root, climb back to the directory it covers
+    pub fn leave(&self, dir: Arc<dyn Inode>) -> Arc<dyn Inode> {
+        let mut current = dir;
+        while let Some(covered) = self
+            .find_by_root(&current)
+            .and_then(|mnt| mnt.covered.clone())
+        {
+            current = covered;
+        }
+        current
+    }
+
+    pub fn mount_root(&mut self, source: &str, fstype: FsType, root: Arc<dyn Inode>) {
+        assert!(
+            self.mounts.is_empty(),
+            "[MountTable::mount_root] already mounted"
+        );
+        self.mounts.push(MountPoint {
+            source: source.to_string(),
+            target: Path::root(),
+            fstype,
+            flags: MountFlags::empty(),
+            covered: None,
+            root,
+        });
+    }
+
+    /// content of `/proc/mounts`
+    pub fn to_proc_mounts(&self) -> String {
+        self.mounts
+            .iter()
+            .map(|mnt| {
+                let rw = if mnt.flags.contains(MountFlags::MS_RDONLY) {
+                    "ro"
+                } else {
+                    "rw"
+                };
+                format!(
+                    "{} {} {} {} 0 0\n",
+                    mnt.source,
+                    mnt.target,
+                    mnt.fstype.name(),
+                    rw
+                )
+            })
+            .collect()
+    }
+}
+
+/// root inode of the filesystem `inode` lives in
+pub fn fs_root_of(inode: &Arc<dyn Inode>) -> Arc<dyn Inode> {
+    let mut fs_root = inode.clone();
+    loop {
+        let parent = fs_root.get_meta().inner.lock().parent.clone();
+        match parent.and_then(|parent| parent.upgrade()) {
+            Some(parent) => fs_root = parent,
+            None => break,
+        }
+    }
+    fs_root
+}
+
+/// absolute path of `inode` in the global tree, taking the mount point of
+/// the filesystem it lives in into account.
+/// `InodeMeta::path` is only relative to the root of its own filesystem.
+pub fn absolute_path(inode: &Arc<dyn Inode>) -> Path {
+    let fs_root = fs_root_of(inode);
+    let path = inode.get_meta().path();
+    let table = MOUNT_TABLE.lock();
+    match table.find_by_root(&fs_root) {
+        Some(mnt) => {
+            let root_len = fs_root.get_meta().path().len();
+            path.get_inner()[root_len..]
+                .iter()
+                .fold(mnt.target.clone(), |acc, name| acc.append_name(name))
+        }
+        None => path,
+    }
+}
+
+/// whether some process still has a file open in the filesystem rooted at
+/// `root`, or its cwd under `target`
+fn fs_in_use(root: &Arc<dyn Inode>, target: &Path) -> bool {
+    let root_addr = inode_addr(root);
+    all_processes().iter().any(|process| {
+        process.inner_handler(|inner| {
+            inner.cwd.get_inner().starts_with(target.get_inner())
+                || inner.fd_table.table.iter().flatten().any(|fd| {
+                    fd.file
+                        .get_meta()
+                        .inner
+                        .lock()
+                        .inode
+                        .as_ref()
+                        .is_some_and(|inode| inode_addr(&fs_root_of(inode)) == root_addr)
+                })
+        })
+    })
+}
+
+/// build the root inode of a new filesystem of type `fstype` from `source`
+fn open_fs(source: &str, fstype: FsType) -> SysResult<Arc<dyn Inode>> {
+    let root: Arc<dyn Inode> = match fstype {
+        FsType::Devfs => Arc::new(DevInode::new()),
+        FsType::Procfs => Arc::new(ProcInode::new()),
+        FsType::Tmpfs => Arc::new(TmpInode::new_root()),
+        FsType::Ext4 | FsType::Vfat => {
+            // only image files are supported as block devices for now
+            let image = open_inode(AT_FDCWD, &source.into(), OpenFlags::empty())
+                .map_err(|_| SyscallErr::ENOENT as usize)?;
+            if image.get_meta().mode != InodeMode::FileREG {
+                return Err(SyscallErr::ENOTBLK.into());
+            }
+            let device = Arc::new(LoopDevice::new(image));
+            if fstype == FsType::Ext4 {
+                if !Ext4FileSystem::probe(device.clone()) {
+                    return Err(SyscallErr::EINVAL.into());
+                }
+                Ext4FileSystem::open(device).lock().root_inode()
+            } else {
+                FAT32FileSystem::open(device)
+                    .map_err(|_| SyscallErr::EINVAL as usize)?
+                    .lock()
+                    .root_inode()
+            }
+        }
+    };
+    Ok(root)
+}
+
+/// attach filesystem `fstype` from `source` on directory `target`
+pub fn do_mount(source: &str, target: &Path, fstype: &str, flags: MountFlags) -> SyscallRet {
+    let dir = open_inode(AT_FDCWD, target, OpenFlags::empty())?;
+    if dir.get_meta().mode != InodeMode::FileDIR {
+        return Err(SyscallErr::ENOTDIR.into());
+    }
+    if flags.contains(MountFlags::MS_REMOUNT) {
+        // only the flags of an existing mount can be changed
+        let mut table = MOUNT_TABLE.lock();
+        let addr = inode_addr(&dir);
+        let mnt = table
+            .mounts
+            .iter_mut()
+            .rev()
+            .find(|mnt| inode_addr(&mnt.root) == addr)
+            .ok_or(SyscallErr::EINVAL as usize)?;
+        mnt.flags = flags - MountFlags::MS_REMOUNT;
+        return Ok(0);
+    }
+    if flags.contains(MountFlags::MS_BIND) {
+        warn!("[do_mount] bind mount not supported");
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let fstype = FsType::from_name(fstype).ok_or(SyscallErr::ENODEV as usize)?;
+    let root = open_fs(source, fstype)?;
+    let target = absolute_path(&dir);
+    info!(
+        "[do_mount] mount {} ({}) on {}",
+        source,
+        fstype.name(),
+        target
+    );
+    MOUNT_TABLE.lock().mounts.push(MountPoint {
+        source: source.to_string(),
+        target,
+        fstype,
+        flags,
+        covered: Some(dir),
+        root,
+    });
+    Ok(0)
+}
+
+/// detach the topmost filesystem mounted on `target`.
+/// Without `MNT_DETACH` no open file or cwd may be left in it
+pub fn do_umount(target: &Path, flags: UmountFlags) -> SyscallRet {
+    let dir = open_inode(AT_FDCWD, target, OpenFlags::empty())?;
+    let addr = inode_addr(&dir);
+    let position = |table: &MountTable| {
+        table
+            .mounts
+            .iter()
+            .rposition(|mnt| inode_addr(&mnt.root) == addr)
+            .ok_or(SyscallErr::EINVAL as usize)
+    };
+    let detach = flags.contains(UmountFlags::MNT_DETACH);
+    let target = {
+        let table = MOUNT_TABLE.lock();
+        let mnt = &table.mounts[position(&table)?];
+        if mnt.covered.is_none() {
+            return Err(SyscallErr::EBUSY.into());
+        }
+        // a filesystem with something mounted inside it can't go away first
+        let busy = table.mounts.iter().any(|other| {
+            other
+                .covered
+                .as_ref()
+                .is_some_and(|covered| inode_addr(&fs_root_of(covered)) == addr)
+        });
+        if busy && !detach {
+            return Err(SyscallErr::EBUSY.into());
+        }
+        mnt.target.clone()
+    };
+    // walking the processes takes their locks, so don't hold the table meanwhile
+    if !detach && fs_in_use(&dir, &target) {
+        return Err(SyscallErr::EBUSY.into());
+    }
+    // dirty pages of the filesystem can't be written back once it is gone
+    block_on(page_cache::sync_fs(&dir))?;
+    let mut table = MOUNT_TABLE.lock();
+    let idx = position(&table)?;
+    let mnt = table.mounts.remove(idx);
+    debug!("[do_umount] umount {} from {}", mnt.source, mnt.target);
+    Ok(0)
+}
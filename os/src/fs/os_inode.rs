@@ -1,6 +1,7 @@
 use super::inode::{Inode, InodeMode};
+use super::mount::absolute_path;
 use super::path::Path;
-use super::{File, FileMeta, FileMetaInner};
+use super::{File, FileMeta, FileMetaInner, PollEvents};
 use crate::config::{AsyncResult, SysResult};
 #[allow(unused)]
 use crate::drivers::ramfs::virtio_ramfs::VIRTIO_RAMFS;
@@ -13,7 +14,10 @@ use crate::fs::ext4::fs::Ext4FileSystem;
 #[allow(unused)]
 use crate::fs::fat32::fs::FAT32FileSystem;
 use crate::fs::AT_FDCWD;
-use crate::task::processor::current_process;
+use crate::signal::SIGXFSZ;
+use crate::syscall::resource::RLIMIT_FSIZE;
+use crate::task::processor::{current_process, current_thread};
+use crate::task::schedule::spawn_kernel_thread;
 use crate::utils::SyscallErr;
 use crate::SyscallRet;
 use alloc::boxed::Box;
@@ -21,6 +25,7 @@ use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::task::Waker;
 use lazy_static::*;
 use log::{error, info};
 
@@ -44,7 +49,8 @@ impl OSInode {
     }
 
     pub fn get_path(&self) -> Path {
-        self.inner_handler(|inner| inner.inode.as_ref().unwrap().get_meta().path.clone())
+        let inode = self.inner_handler(|inner| inner.inode.clone()).unwrap();
+        absolute_path(&inode)
     }
 }
 
@@ -55,7 +61,7 @@ impl OSInode {
         let inode_meta = inode.get_meta();
         if inode_meta.mode == InodeMode::FileLNK {
             let rel_target_path = inode_meta.link_target.as_ref().unwrap();
-            let abs_target_path = inode_meta.path.append_to_dir(rel_target_path);
+            let abs_target_path = absolute_path(&inode).append_to_dir(rel_target_path);
             log::debug!(
                 "[OSInode::new] symlink: {} -> {}",
                 inode.get_name(),
@@ -79,11 +85,34 @@ impl OSInode {
         let inode = self.inner_handler(|inner| inner.inode.clone()).unwrap();
         let size = inode.get_meta().inner.lock().data_size;
         let mut v = vec![0u8; size];
-        assert!(inode.read(0, v.as_mut_slice()).await.unwrap() == size);
+        assert!(inode.read_cached(0, v.as_mut_slice()).await.unwrap() == size);
         v
     }
 }
 
+impl Drop for OSInode {
+    /// 最后一次close时把写入的脏页写回磁盘
+    fn drop(&mut self) {
+        if !self.meta.writable {
+            return;
+        }
+        let inode = match self.meta.inner.get_mut().inode.take() {
+            Some(inode) => inode,
+            None => return,
+        };
+        if inode
+            .page_cache()
+            .is_some_and(|page_cache| page_cache.has_dirty())
+        {
+            spawn_kernel_thread(async move {
+                if let Err(err) = inode.sync().await {
+                    error!("[OSInode::drop] sync failed: {}", err);
+                }
+            });
+        }
+    }
+}
+
 impl File for OSInode {
     fn read<'a>(&'a self, buf: &'a mut [u8]) -> AsyncResult<usize> {
         Box::pin(async move {
@@ -93,7 +122,7 @@ impl File for OSInode {
             let inode = self.inner_handler(|inner| inner.inode.clone()).unwrap();
             let offset = self.get_offset();
             log::debug!("[OSInode::read] offset = {}", offset);
-            let read_size = inode.read(offset, buf).await?;
+            let read_size = inode.read_cached(offset, buf).await?;
             log::debug!("[OSInode::read] read_size = {}", read_size);
             self.set_offset(offset + read_size);
             Ok(read_size)
@@ -106,7 +135,18 @@ impl File for OSInode {
             }
             let inode = self.inner_handler(|inner| inner.inode.clone()).unwrap();
             let offset = self.get_offset();
-            let write_size = inode.write(offset, buf).await?;
+            // RLIMIT_FSIZE: 超出的部分不写, 一个字节也写不了时发送SIGXFSZ
+            let buf = if inode.get_meta().mode == InodeMode::FileREG {
+                let limit = current_process().rlimit(RLIMIT_FSIZE).rlim_cur;
+                if offset >= limit && !buf.is_empty() {
+                    current_thread().unwrap().send_signal(SIGXFSZ);
+                    return Err(SyscallErr::EFBIG.into());
+                }
+                &buf[..buf.len().min(limit.saturating_sub(offset))]
+            } else {
+                buf
+            };
+            let write_size = inode.write_cached(offset, buf).await?;
             self.set_offset(offset + write_size);
             Ok(write_size)
         })
@@ -127,6 +167,10 @@ impl File for OSInode {
         error!("[OSInode] ioctl not implemented");
         Err(SyscallErr::ENOTTY as usize)
     }
+    /// 普通文件的读写不会阻塞, 总是就绪
+    fn poll(&self, events: PollEvents, _waker: Option<&Waker>) -> PollEvents {
+        events & (PollEvents::POLLIN | PollEvents::POLLOUT)
+    }
 }
 
 // #[cfg(not(feature = "ext4"))]
@@ -253,8 +297,8 @@ pub fn open_inode(dirfd: isize, path: &Path, flags: OpenFlags) -> SysResult<Arc<
     open_cwd(dirfd, path).and_then(|cwd| {
         cwd.open_path(path, flags.contains(OpenFlags::CREATE), false)
             .and_then(|inode| {
-                if flags.contains(OpenFlags::TRUNC) {
-                    inode.clear();
+                if flags.contains(OpenFlags::TRUNC) && inode.get_meta().mode == InodeMode::FileREG {
+                    inode.truncate(0)?;
                 }
                 Ok(inode)
             })
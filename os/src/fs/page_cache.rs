@@ -0,0 +1,339 @@
+//! Page cache of file content
+//!
+//! Every regular file owns one `PageCache`, so a cached page is identified
+//! by `(ino, page_index)`. `read()`, `write()` and file-backed mmap all use
+//! the same frames. Dirty pages are written back by `sync()`, and the inodes
+//! owning dirty pages are recorded in `DIRTY_INODES` for `sys_sync`, the
+//! periodic writeback and `umount`.
+//!
+//! All caches are registered in `PAGE_CACHES`, so that clean pages nobody
+//! maps can be dropped when the frame allocator runs dry.
+
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use alloc::{
+    collections::BTreeMap,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use lazy_static::lazy_static;
+use log::warn;
+
+use crate::{
+    config::PAGE_SIZE,
+    mm::{frame_alloc, FrameTracker},
+    mutex::SpinNoIrqLock,
+    timer::TimeoutFuture,
+    utils::SyscallErr,
+    SysResult,
+};
+
+use super::{
+    inode::Inode,
+    mount::{fs_root_of, inode_addr},
+};
+
+/// dirty pages are written back at least this often
+const WRITEBACK_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct Page {
+    /// shared with `MapArea::data_frames` when mapped into user space
+    pub frame: Arc<FrameTracker>,
+    dirty: AtomicBool,
+}
+
+impl Page {
+    fn new(frame: FrameTracker) -> Self {
+        Self {
+            frame: Arc::new(frame),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn bytes(&self) -> &'static mut [u8] {
+        self.frame.ppn.get_bytes_array()
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Acquire)
+    }
+
+    pub fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Release);
+    }
+}
+
+pub struct PageCache {
+    /// page index in file -> page
+    pages: SpinNoIrqLock<BTreeMap<usize, Arc<Page>>>,
+}
+
+lazy_static! {
+    /// inodes with dirty pages, keyed by the address of the inode
+    static ref DIRTY_INODES: SpinNoIrqLock<BTreeMap<usize, Arc<dyn Inode>>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+    /// every live page cache, keyed by its address
+    static ref PAGE_CACHES: SpinNoIrqLock<BTreeMap<usize, Weak<PageCache>>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
+fn mark_inode_dirty(inode: &Arc<dyn Inode>) {
+    DIRTY_INODES
+        .lock()
+        .entry(Arc::as_ptr(inode) as *const () as usize)
+        .or_insert_with(|| inode.clone());
+}
+
+impl PageCache {
+    pub fn new() -> Arc<Self> {
+        let page_cache = Arc::new(Self {
+            pages: SpinNoIrqLock::new(BTreeMap::new()),
+        });
+        PAGE_CACHES.lock().insert(
+            Arc::as_ptr(&page_cache) as usize,
+            Arc::downgrade(&page_cache),
+        );
+        page_cache
+    }
+
+    /// get page `page_index` of `inode`, load it from disk on miss.
+    /// bytes beyond the end of file are zero.
+    pub async fn get_page(
+        &self,
+        inode: &Arc<dyn Inode>,
+        page_index: usize,
+    ) -> SysResult<Arc<Page>> {
+        if let Some(page) = self.pages.lock().get(&page_index) {
+            return Ok(page.clone());
+        }
+        let frame = match frame_alloc() {
+            Some(frame) => frame,
+            None => {
+                // clean pages are already gone, write back the dirty ones so they can go too
+                sync_all().await?;
+                reclaim(RECLAIM_BATCH);
+                frame_alloc().ok_or(SyscallErr::ENOMEM)?
+            }
+        };
+        let page = Page::new(frame);
+        let offset = page_index * PAGE_SIZE;
+        let data_size = inode.get_meta().inner.lock().data_size;
+        if offset < data_size {
+            let len = core::cmp::min(PAGE_SIZE, data_size - offset);
+            inode.read(offset, &mut page.bytes()[..len]).await?;
+        }
+        // someone else may have loaded the page while we were reading
+        Ok(self
+            .pages
+            .lock()
+            .entry(page_index)
+            .or_insert(Arc::new(page))
+            .clone())
+    }
+
+    /// read through the cache, never beyond the end of file
+    pub async fn read(
+        &self,
+        inode: &Arc<dyn Inode>,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> SysResult<usize> {
+        let data_size = inode.get_meta().inner.lock().data_size;
+        if offset >= data_size {
+            return Ok(0);
+        }
+        let end = core::cmp::min(offset + buf.len(), data_size);
+        let mut pos = offset;
+        while pos < end {
+            let page = self.get_page(inode, pos / PAGE_SIZE).await?;
+            let page_offset = pos % PAGE_SIZE;
+            let len = core::cmp::min(PAGE_SIZE - page_offset, end - pos);
+            buf[pos - offset..pos - offset + len]
+                .copy_from_slice(&page.bytes()[page_offset..page_offset + len]);
+            pos += len;
+        }
+        Ok(end - offset)
+    }
+
+    /// write into the cache, extending the file if needed.
+    /// data reaches the disk on `sync()`.
+    pub async fn write(
+        &self,
+        inode: &Arc<dyn Inode>,
+        offset: usize,
+        buf: &[u8],
+    ) -> SysResult<usize> {
+        let end = offset + buf.len();
+        let mut pos = offset;
+        while pos < end {
+            let page = self.get_page(inode, pos / PAGE_SIZE).await?;
+            let page_offset = pos % PAGE_SIZE;
+            let len = core::cmp::min(PAGE_SIZE - page_offset, end - pos);
+            page.bytes()[page_offset..page_offset + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            page.mark_dirty();
+            pos += len;
+        }
+        let meta = inode.get_meta();
+        let mut meta_inner = meta.inner.lock();
+        if end > meta_inner.data_size {
+            meta_inner.data_size = end;
+        }
+        drop(meta_inner);
+        mark_inode_dirty(inode);
+        Ok(buf.len())
+    }
+
+    /// pages mapped shared and writable may be modified behind our back
+    pub fn mark_page_dirty(&self, inode: &Arc<dyn Inode>, page_index: usize) {
+        if let Some(page) = self.pages.lock().get(&page_index) {
+            page.mark_dirty();
+            mark_inode_dirty(inode);
+        }
+    }
+
+    /// drop cached content beyond `size`, used when the file is truncated
+    pub fn truncate(&self, size: usize) {
+        let mut pages = self.pages.lock();
+        let first_dropped = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        pages.split_off(&first_dropped);
+        if size % PAGE_SIZE != 0 {
+            if let Some(page) = pages.get(&(size / PAGE_SIZE)) {
+                page.bytes()[size % PAGE_SIZE..].fill(0);
+            }
+        }
+    }
+
+    /// whether `frame` is the cached frame of page `page_index`
+    pub fn holds(&self, page_index: usize, frame: &Arc<FrameTracker>) -> bool {
+        self.pages
+            .lock()
+            .get(&page_index)
+            .is_some_and(|page| Arc::ptr_eq(&page.frame, frame))
+    }
+
+    pub fn has_dirty(&self) -> bool {
+        self.pages.lock().values().any(|page| page.is_dirty())
+    }
+
+    /// drop up to `count` clean pages that are not mapped into user space,
+    /// return how many were dropped
+    fn shrink(&self, count: usize) -> usize {
+        let mut pages = self.pages.lock();
+        let victims: Vec<usize> = pages
+            .iter()
+            .filter(|(_, page)| {
+                // nobody reads, writes or maps the page but the cache itself
+                !page.is_dirty()
+                    && Arc::strong_count(page) == 1
+                    && Arc::strong_count(&page.frame) == 1
+            })
+            .map(|(index, _)| *index)
+            .take(count)
+            .collect();
+        for index in victims.iter() {
+            pages.remove(index);
+        }
+        victims.len()
+    }
+
+    /// write back all dirty pages of `inode`
+    pub async fn sync(&self, inode: &Arc<dyn Inode>) -> SysResult<()> {
+        let dirty_pages: Vec<(usize, Arc<Page>)> = self
+            .pages
+            .lock()
+            .iter()
+            .filter(|(_, page)| page.is_dirty())
+            .map(|(index, page)| (*index, page.clone()))
+            .collect();
+        // the inode may recompute its size from what has reached the disk so far
+        let data_size = inode.get_meta().inner.lock().data_size;
+        for (page_index, page) in dirty_pages {
+            let offset = page_index * PAGE_SIZE;
+            if offset >= data_size {
+                continue;
+            }
+            let len = core::cmp::min(PAGE_SIZE, data_size - offset);
+            page.dirty.store(false, Ordering::Release);
+            inode.write(offset, &page.bytes()[..len]).await?;
+        }
+        let meta = inode.get_meta();
+        let mut meta_inner = meta.inner.lock();
+        meta_inner.data_size = core::cmp::max(meta_inner.data_size, data_size);
+        Ok(())
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        PAGE_CACHES.lock().remove(&(self as *const Self as usize));
+    }
+}
+
+/// pages dropped at a time when the frame allocator runs dry
+pub const RECLAIM_BATCH: usize = 64;
+
+/// drop up to `count` clean, unmapped pages from all caches, return how many were dropped.
+/// called by the frame allocator, so it must not allocate frames itself
+pub fn reclaim(count: usize) -> usize {
+    // a cache may be dropped with the last `Arc`, which takes `PAGE_CACHES` again
+    let page_caches: Vec<Arc<PageCache>> = PAGE_CACHES
+        .lock()
+        .values()
+        .filter_map(|page_cache| page_cache.upgrade())
+        .collect();
+    let mut reclaimed = 0;
+    for page_cache in page_caches.iter() {
+        if reclaimed >= count {
+            break;
+        }
+        reclaimed += page_cache.shrink(count - reclaimed);
+    }
+    reclaimed
+}
+
+/// write back every dirty inode, used by `sys_sync`
+pub async fn sync_all() -> SysResult<()> {
+    let inodes: Vec<Arc<dyn Inode>> = core::mem::take(&mut *DIRTY_INODES.lock())
+        .into_values()
+        .collect();
+    for inode in inodes {
+        inode.sync().await?;
+    }
+    Ok(())
+}
+
+/// write back the dirty inodes of the filesystem rooted at `fs_root`, used by `umount`
+pub async fn sync_fs(fs_root: &Arc<dyn Inode>) -> SysResult<()> {
+    let inodes: Vec<Arc<dyn Inode>> = {
+        let mut dirty_inodes = DIRTY_INODES.lock();
+        let root_addr = inode_addr(fs_root);
+        let keys: Vec<usize> = dirty_inodes
+            .iter()
+            .filter(|(_, inode)| inode_addr(&fs_root_of(inode)) == root_addr)
+            .map(|(key, _)| *key)
+            .collect();
+        keys.iter()
+            .filter_map(|key| dirty_inodes.remove(key))
+            .collect()
+    };
+    for inode in inodes {
+        inode.sync().await?;
+    }
+    fs_root.flush();
+    Ok(())
+}
+
+/// kernel thread writing dirty pages back every `WRITEBACK_INTERVAL`,
+/// so data reaches the disk even if nobody calls sync
+pub async fn writeback_loop() {
+    loop {
+        let _ = TimeoutFuture::new(WRITEBACK_INTERVAL).await;
+        if let Err(err) = sync_all().await {
+            warn!("[writeback_loop] sync failed: {}", err);
+        }
+    }
+}
@@ -56,11 +56,17 @@ impl Path {
         self.inner.push(s.to_string());
     }
 
-    #[allow(unused)]
     fn pop(&mut self) -> Option<String> {
         self.inner.pop() /* .unwrap_or(String::new()) */
     }
 
+    /// the directory containing the last component
+    pub fn parent(&self) -> Self {
+        let mut parent = self.clone();
+        parent.pop();
+        parent
+    }
+
     pub fn root() -> Self {
         Self {
             inner: Vec::new(),
@@ -1,9 +1,13 @@
 use alloc::{boxed::Box, sync::Arc};
+use core::task::Waker;
 use log::error;
 
-use crate::{mutex::SpinNoIrqLock, task::yield_task, utils::SyscallErr, AsyncResult, SyscallRet};
+use crate::{mutex::SpinNoIrqLock, utils::SyscallErr, AsyncResult, SyscallRet};
 
-use super::{File, FileMeta};
+use super::{
+    poll::{PollWaitFuture, WaitQueue},
+    File, FileMeta, PollEvents,
+};
 
 // pub const PIPE_BUFFER_SIZE: usize = 101000;
 // pub const PIPE_BUFFER_SIZE: usize = 1024 * 16;
@@ -23,6 +27,8 @@ impl Pipe {
             write_pos: 0,
             // eof: false,
             writer_count: 1,
+            read_wait: WaitQueue::new(),
+            write_wait: WaitQueue::new(),
         }));
         (
             Arc::new(Self {
@@ -44,6 +50,8 @@ impl Pipe {
             write_pos: 0,
             // eof: false,
             writer_count: 2,
+            read_wait: WaitQueue::new(),
+            write_wait: WaitQueue::new(),
         }));
         (
             Arc::new(Self {
@@ -69,6 +77,14 @@ impl Pipe {
             }
         }
         log::debug!("[Pipe::read_inner] read_size = {}", read_size);
+        // 腾出了空间, 唤醒等待写的任务
+        let wakers = if read_size > 0 {
+            buffer.write_wait.take()
+        } else {
+            Default::default()
+        };
+        drop(buffer);
+        wakers.into_iter().for_each(Waker::wake);
         read_size
     }
 
@@ -82,6 +98,13 @@ impl Pipe {
             write_size += 1;
         }
         log::debug!("[Pipe::write_inner] write_size = {}", write_size);
+        let wakers = if write_size > 0 {
+            buffer.read_wait.take()
+        } else {
+            Default::default()
+        };
+        drop(buffer);
+        wakers.into_iter().for_each(Waker::wake);
         write_size
     }
 }
@@ -102,7 +125,7 @@ impl File for Pipe {
                     return Ok(0);
                 } else {
                     // empty buffer but writer exists, wait
-                    yield_task().await;
+                    PollWaitFuture::new(self, PollEvents::POLLIN).await;
                     continue;
                 }
             }
@@ -116,7 +139,7 @@ impl File for Pipe {
             }
             // block on full buffer by default
             while self.buffer.lock().full() {
-                yield_task().await;
+                PollWaitFuture::new(self, PollEvents::POLLOUT).await;
             }
             Ok(self.write_inner(buf))
         })
@@ -134,6 +157,32 @@ impl File for Pipe {
         error!("[Pipe] ioctl not implemented");
         Err(SyscallErr::ENOTTY as usize)
     }
+
+    fn poll(&self, events: PollEvents, waker: Option<&Waker>) -> PollEvents {
+        let mut buffer = self.buffer.lock();
+        let mut ready = PollEvents::empty();
+        if self.meta.readable {
+            if events.contains(PollEvents::POLLIN) && !buffer.empty() {
+                ready |= PollEvents::POLLIN;
+            }
+            // 没有写者时读端总是就绪
+            if buffer.eof() {
+                ready |= PollEvents::POLLHUP;
+            }
+        }
+        if self.meta.writable && events.contains(PollEvents::POLLOUT) && !buffer.full() {
+            ready |= PollEvents::POLLOUT;
+        }
+        if let Some(waker) = waker {
+            if self.meta.readable && events.contains(PollEvents::POLLIN) {
+                buffer.read_wait.register(waker);
+            }
+            if self.meta.writable && events.contains(PollEvents::POLLOUT) {
+                buffer.write_wait.register(waker);
+            }
+        }
+        ready
+    }
 }
 
 impl Drop for Pipe {
@@ -142,6 +191,12 @@ impl Drop for Pipe {
             let mut buffer = self.buffer.lock();
             // buffer.eof = true;
             buffer.writer_count -= 1;
+            // 最后一个写者关闭, 读者会读到EOF
+            if buffer.writer_count == 0 {
+                let wakers = buffer.read_wait.take();
+                drop(buffer);
+                wakers.into_iter().for_each(Waker::wake);
+            }
         }
     }
 }
@@ -154,6 +209,10 @@ struct PipeRingBuffer {
     // eof: bool,
     // number of writers, if writer_count == 0, then eof
     writer_count: usize,
+    /// tasks waiting for data or EOF
+    read_wait: WaitQueue,
+    /// tasks waiting for free space
+    write_wait: WaitQueue,
 }
 
 impl PipeRingBuffer {
@@ -179,6 +238,9 @@ impl PipeRingBuffer {
         self.writer_count == 0
         // self.eof
     }
+    fn empty(&self) -> bool {
+        self.read_pos == self.write_pos
+    }
     fn full(&self) -> bool {
         (self.write_pos + 1) % PIPE_BUFFER_SIZE == self.read_pos
     }
@@ -0,0 +1,71 @@
+//! 文件就绪状态, 供`ppoll`/`pselect6`以及阻塞读写使用
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use alloc::vec::Vec;
+
+use super::File;
+
+bitflags! {
+    /// Poll events
+    pub struct PollEvents: i16 {
+        /// There is data to read
+        const POLLIN = 1 << 0;
+        /// Execption about fd
+        const POLLPRI = 1 << 1;
+        /// There is data to write
+        const POLLOUT = 1 << 2;
+        /// Error condition
+        const POLLERR = 1 << 3;
+        /// Hang up
+        const POLLHUP = 1 << 4;
+        /// Invalid request: fd not open
+        const POLLNVAL = 1 << 5;
+    }
+}
+
+/// 等待某个文件上的事件就绪, 返回就绪的事件
+pub struct PollWaitFuture<'a> {
+    file: &'a dyn File,
+    events: PollEvents,
+}
+
+impl<'a> PollWaitFuture<'a> {
+    pub fn new(file: &'a dyn File, events: PollEvents) -> Self {
+        Self { file, events }
+    }
+}
+
+impl Future for PollWaitFuture<'_> {
+    type Output = PollEvents;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let ready = self.file.poll(self.events, Some(cx.waker()));
+        if ready.is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(ready)
+        }
+    }
+}
+
+/// 等待者列表, 同一个任务只登记一次
+pub struct WaitQueue {
+    wakers: Vec<Waker>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self { wakers: Vec::new() }
+    }
+    pub fn register(&mut self, waker: &Waker) {
+        if !self.wakers.iter().any(|w| w.will_wake(waker)) {
+            self.wakers.push(waker.clone());
+        }
+    }
+    /// 取出所有等待者, 应在释放锁之后再唤醒
+    pub fn take(&mut self) -> Vec<Waker> {
+        core::mem::take(&mut self.wakers)
+    }
+}
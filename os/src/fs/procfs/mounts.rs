@@ -1,11 +1,14 @@
 use alloc::{boxed::Box, sync::Arc};
 
 use crate::{
-    fs::inode::{Inode, InodeMeta, InodeMode},
+    fs::{
+        inode::{Inode, InodeMeta, InodeMode},
+        mount::MOUNT_TABLE,
+    },
     AsyncResult, SysResult,
 };
 
-/// faked /proc/mounts, nothing to read.
+/// /proc/mounts, generated from the mount table on every read
 pub struct MountsInode {
     meta: Arc<InodeMeta>,
     // content: String,
@@ -28,8 +31,17 @@ impl MountsInode {
 }
 
 impl Inode for MountsInode {
-    fn read<'a>(&'a self, _offset: usize, _buf: &'a mut [u8]) -> AsyncResult<usize> {
-        Box::pin(async move { Ok(0) })
+    fn read<'a>(&'a self, offset: usize, buf: &'a mut [u8]) -> AsyncResult<usize> {
+        Box::pin(async move {
+            let content = MOUNT_TABLE.lock().to_proc_mounts();
+            let content = content.as_bytes();
+            if offset >= content.len() {
+                return Ok(0);
+            }
+            let read_size = core::cmp::min(buf.len(), content.len() - offset);
+            buf[..read_size].copy_from_slice(&content[offset..offset + read_size]);
+            Ok(read_size)
+        })
     }
     fn write<'a>(&'a self, _offset: usize, _buf: &'a [u8]) -> AsyncResult<usize> {
         panic!("[MountsInode::write] invalid")
@@ -0,0 +1,173 @@
+//! signalfd: 通过`read`同步接收信号, 读到的信号不再走处理函数
+
+use alloc::{boxed::Box, sync::Arc};
+use core::future::Future;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use log::error;
+
+use crate::{
+    mutex::SpinNoIrqLock,
+    signal::{SigBitmap, SigInfo},
+    task::processor::current_thread,
+    task::task::Thread,
+    utils::SyscallErr,
+    AsyncResult, SyscallRet,
+};
+
+use super::{File, FileMeta, OSFileType, PollEvents};
+
+/// `struct signalfd_siginfo`, 每次读出一个或多个
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SignalfdSiginfo {
+    pub ssi_signo: u32,
+    pub ssi_errno: i32,
+    pub ssi_code: i32,
+    pub ssi_pid: u32,
+    pub ssi_uid: u32,
+    _pad: [u8; 108],
+}
+
+impl SignalfdSiginfo {
+    fn new(info: &SigInfo) -> Self {
+        let (pid, uid) = info.sender().unwrap_or((0, 0));
+        Self {
+            ssi_signo: info.si_signo as u32,
+            ssi_errno: info.si_errno,
+            ssi_code: info.si_code,
+            ssi_pid: pid,
+            ssi_uid: uid,
+            _pad: [0; 108],
+        }
+    }
+}
+
+pub struct SignalFd {
+    meta: FileMeta,
+    /// 关心的信号, 可由`signalfd4`修改
+    mask: SpinNoIrqLock<SigBitmap>,
+    nonblock: bool,
+}
+
+impl SignalFd {
+    pub fn new(mask: SigBitmap, nonblock: bool) -> Arc<Self> {
+        Arc::new(Self {
+            meta: FileMeta::new_bare(true, false, OSFileType::SignalFd),
+            mask: SpinNoIrqLock::new(mask - SigBitmap::unblockable()),
+            nonblock,
+        })
+    }
+
+    /// `file`是signalfd时转换成`SignalFd`
+    pub fn from_file(file: Arc<dyn File>) -> Option<Arc<Self>> {
+        if file.get_meta().filetype != OSFileType::SignalFd {
+            return None;
+        }
+        // 只有SignalFd的类型是OSFileType::SignalFd
+        Some(unsafe { Arc::from_raw(Arc::into_raw(file) as *const Self) })
+    }
+
+    pub fn set_mask(&self, mask: SigBitmap) {
+        *self.mask.lock() = mask - SigBitmap::unblockable();
+    }
+
+    fn mask(&self) -> SigBitmap {
+        *self.mask.lock()
+    }
+}
+
+/// 等到有关心的信号, 或者被其他未屏蔽的信号打断
+struct SignalFdWaitFuture<'a> {
+    signalfd: &'a SignalFd,
+    thread: Arc<Thread>,
+}
+
+impl Future for SignalFdWaitFuture<'_> {
+    type Output = SyscallRet;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.thread.set_interruptible_waker(cx.waker().clone());
+        if !(self.thread.pending_signals() & self.signalfd.mask()).is_empty() {
+            Poll::Ready(Ok(0))
+        } else if self.thread.have_unmasked_signals() {
+            Poll::Ready(Err(SyscallErr::EINTR.into()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl File for SignalFd {
+    fn read<'a>(&'a self, buf: &'a mut [u8]) -> AsyncResult<usize> {
+        Box::pin(async move {
+            let record_size = size_of::<SignalfdSiginfo>();
+            if buf.len() < record_size {
+                return Err(SyscallErr::EINVAL.into());
+            }
+            let thread = current_thread().unwrap();
+            let mut n = 0;
+            loop {
+                for record in buf.chunks_exact_mut(record_size) {
+                    let Some(sig_info) = thread.dequeue_signal(self.mask()) else {
+                        break;
+                    };
+                    let info = SignalfdSiginfo::new(&sig_info);
+                    unsafe {
+                        core::ptr::copy_nonoverlapping(
+                            &info as *const SignalfdSiginfo as *const u8,
+                            record.as_mut_ptr(),
+                            record_size,
+                        );
+                    }
+                    n += record_size;
+                }
+                if n > 0 {
+                    return Ok(n);
+                }
+                if self.nonblock {
+                    return Err(SyscallErr::EAGAIN.into());
+                }
+                let ret = SignalFdWaitFuture {
+                    signalfd: self,
+                    thread: thread.clone(),
+                }
+                .await;
+                thread.clear_interruptible_waker();
+                ret?;
+            }
+        })
+    }
+
+    fn write<'a>(&'a self, _buf: &'a [u8]) -> AsyncResult<usize> {
+        Box::pin(async move { Err(SyscallErr::EINVAL.into()) })
+    }
+
+    fn get_meta(&self) -> &FileMeta {
+        &self.meta
+    }
+
+    fn seek(&self, _offset: usize) -> Option<usize> {
+        None
+    }
+
+    fn ioctl(&self, _request: usize, _argp: usize) -> SyscallRet {
+        error!("[SignalFd] ioctl not implemented");
+        Err(SyscallErr::ENOTTY as usize)
+    }
+
+    /// 调用者线程有关心的待处理信号时可读
+    fn poll(&self, events: PollEvents, waker: Option<&Waker>) -> PollEvents {
+        let thread = current_thread().unwrap();
+        if let Some(waker) = waker {
+            thread.register_signal_waiter(waker);
+        }
+        if !(thread.pending_signals() & self.mask()).is_empty()
+            && events.contains(PollEvents::POLLIN)
+        {
+            PollEvents::POLLIN
+        } else {
+            PollEvents::empty()
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! tmpfs, a filesystem living entirely in memory
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+
+use crate::{mutex::SpinNoIrqLock, utils::SyscallErr, AsyncResult, SysResult};
+
+use super::{
+    inode::{Inode, InodeMeta, InodeMode},
+    page_cache::PageCache,
+    path::Path,
+};
+
+/// 0 is reserved for inodes that don't care about their number
+static TMPFS_INO: AtomicUsize = AtomicUsize::new(1);
+
+pub struct TmpInode {
+    meta: Arc<InodeMeta>,
+    data: SpinNoIrqLock<Vec<u8>>,
+}
+
+impl TmpInode {
+    pub fn new_root() -> Self {
+        Self::new(None, Path::root(), InodeMode::FileDIR)
+    }
+
+    fn new(parent: Option<Arc<dyn Inode>>, path: Path, mode: InodeMode) -> Self {
+        let ino = TMPFS_INO.fetch_add(1, Ordering::Relaxed);
+        Self {
+            meta: Arc::new(InodeMeta::new(parent, path, mode, 0, ino)),
+            data: SpinNoIrqLock::new(Vec::new()),
+        }
+    }
+}
+
+impl Inode for TmpInode {
+    fn read<'a>(&'a self, offset: usize, buf: &'a mut [u8]) -> AsyncResult<usize> {
+        Box::pin(async move {
+            if self.meta.mode == InodeMode::FileDIR {
+                return Err(SyscallErr::EISDIR.into());
+            }
+            let data = self.data.lock();
+            if offset >= data.len() {
+                return Ok(0);
+            }
+            let read_size = core::cmp::min(buf.len(), data.len() - offset);
+            buf[..read_size].copy_from_slice(&data[offset..offset + read_size]);
+            Ok(read_size)
+        })
+    }
+
+    fn write<'a>(&'a self, offset: usize, buf: &'a [u8]) -> AsyncResult<usize> {
+        Box::pin(async move {
+            if self.meta.mode == InodeMode::FileDIR {
+                return Err(SyscallErr::EISDIR.into());
+            }
+            let mut data = self.data.lock();
+            if offset + buf.len() > data.len() {
+                data.resize(offset + buf.len(), 0);
+            }
+            data[offset..offset + buf.len()].copy_from_slice(buf);
+            self.meta.inner.lock().data_size = data.len();
+            Ok(buf.len())
+        })
+    }
+
+    fn mknod(
+        &self,
+        this: Arc<dyn Inode>,
+        name: &str,
+        mode: InodeMode,
+    ) -> SysResult<Arc<dyn Inode>> {
+        if self.meta.mode != InodeMode::FileDIR {
+            return Err(SyscallErr::ENOTDIR.into());
+        }
+        let path = self.meta.path().append_name(name);
+        Ok(Arc::new(Self::new(Some(this), path, mode)))
+    }
+
+    fn get_meta(&self) -> Arc<InodeMeta> {
+        self.meta.clone()
+    }
+
+    /// nothing on disk, children only live in `InodeMeta`
+    fn load_children_from_disk(&self, _this: Arc<dyn Inode>) {}
+
+    fn clear(&self) {
+        self.data.lock().clear();
+        self.meta.inner.lock().data_size = 0;
+    }
+
+    fn set_size(&self, size: usize) -> SysResult<()> {
+        self.data.lock().resize(size, 0);
+        Ok(())
+    }
+
+    fn page_cache(&self) -> Option<Arc<PageCache>> {
+        self.meta.page_cache.clone()
+    }
+}
@@ -1,44 +1,158 @@
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
+use core::{mem::size_of, task::Waker, time::Duration};
 // use core::str::Utf8Error;
 use lazy_static::lazy_static;
 // use log::trace;
 // use log::debug;
 
 use crate::{
-    mutex::SpinNoIrqLock, sbi::console_getchar, task::yield_task, utils::SyscallErr, AsyncResult,
-    SyscallRet,
+    mm::user_check::UserCheck,
+    mutex::SpinNoIrqLock,
+    sbi::console_getchar,
+    signal::{SigBitmap, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU},
+    task::{
+        pgroup::ProcessGroup,
+        processor::{current_process, current_thread},
+    },
+    timer::{add_timer, current_time_duration},
+    utils::SyscallErr,
+    AsyncResult, SysResult, SyscallRet,
 };
 
-use super::{File, FileMeta};
+use super::{poll::PollWaitFuture, File, FileMeta, PollEvents};
 
 lazy_static! {
     // pub static ref TTY: Arc<SpinNoIrqLock<TtyFile>> = Arc::new(SpinNoIrqLock::new(TtyFile::new()));
     pub static ref TTY: Arc<TtyFile> = Arc::new(TtyFile::new(true, true));
+    /// 串口终端的状态, 所有`TtyFile`都是同一个终端
+    static ref TERMINAL: SpinNoIrqLock<TtyInner> = SpinNoIrqLock::new(TtyInner {
+        session: None,
+        fg_pgid: 0,
+        win_size: WinSize::new(),
+        termios: Termios::new(),
+    });
+}
+
+/// 串口没有输入中断, 等待输入的任务每隔这么久检查一次
+const CONSOLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// 已经从串口读出但还没有被进程读走的字符
+static CONSOLE_INPUT: SpinNoIrqLock<VecDeque<u8>> = SpinNoIrqLock::new(VecDeque::new());
+
+/// 把串口的输入读进缓冲区, 遇到`ISIG`的控制字符时向前台进程组发送信号.
+/// 串口没有输入中断, 时钟中断时也会调用, 这样前台进程不读终端时也能被Ctrl-C打断
+pub fn check_console_input() {
+    let mut signals = Vec::new();
+    {
+        let mut input = CONSOLE_INPUT.lock();
+        loop {
+            let c = console_getchar();
+            // opensbi returns usize::MAX if no char available
+            if c == usize::MAX {
+                break;
+            }
+            let ch = c as u8;
+            match TERMINAL.lock().signal_of(ch) {
+                Some(signo) => signals.push(signo),
+                None => input.push_back(ch),
+            }
+        }
+    }
+    if signals.is_empty() {
+        return;
+    }
+    let fg_pgid = TERMINAL.lock().fg_pgid;
+    if let Some(pgroup) = ProcessGroup::find(fg_pgid) {
+        for signo in signals {
+            pgroup.send_signal(signo);
+        }
+    }
+}
+
+/// 取出一个输入字符, 没有输入时返回`None`
+fn console_take_char() -> Option<u8> {
+    check_console_input();
+    CONSOLE_INPUT.lock().pop_front()
+}
+
+/// 是否有输入可读
+fn console_has_input() -> bool {
+    check_console_input();
+    !CONSOLE_INPUT.lock().is_empty()
+}
+
+/// 串口成为会话`sid`的控制终端, `pgid`为前台进程组
+pub fn set_console_session(sid: usize, pgid: usize) {
+    let mut terminal = TERMINAL.lock();
+    terminal.session = Some(sid);
+    terminal.fg_pgid = pgid;
+}
+
+/// 后台进程组访问控制终端时向整个组发送`signo`(SIGTTIN或SIGTTOU),
+/// 返回`EINTR`, 继续运行后重新执行系统调用.
+/// 信号被忽略或阻塞时, 读返回`EIO`, 其他操作照常进行
+fn check_foreground(signo: usize) -> SysResult<()> {
+    let process = current_process();
+    let (session, fg_pgid) = {
+        let terminal = TERMINAL.lock();
+        (terminal.session, terminal.fg_pgid)
+    };
+    let pgroup = process.pgroup();
+    if session != Some(pgroup.sid()) || pgroup.pgid() == fg_pgid {
+        return Ok(());
+    }
+    let thread = current_thread().unwrap();
+    if thread.sig_mask().contains(SigBitmap::from_signo(signo))
+        || process.sig_action(signo).is_ignored(signo)
+    {
+        return if signo == SIGTTIN {
+            Err(SyscallErr::EIO.into())
+        } else {
+            Ok(())
+        };
+    }
+    pgroup.send_signal(signo);
+    Err(SyscallErr::EINTR.into())
 }
 
 pub struct TtyFile {
     // tty_inode: Arc<dyn Inode>,
     meta: FileMeta,
-    inner: SpinNoIrqLock<TtyInner>,
 }
 
 struct TtyInner {
-    fg_pgid: u32,
+    /// 以此为控制终端的会话
+    session: Option<usize>,
+    fg_pgid: usize,
     win_size: WinSize,
     termios: Termios,
 }
 
+impl TtyInner {
+    /// 开启`ISIG`时, 产生信号的控制字符
+    fn signal_of(&self, ch: u8) -> Option<usize> {
+        if self.termios.lflag & ISIG == 0 {
+            return None;
+        }
+        let cc = &self.termios.cc;
+        if ch == cc[VINTR] {
+            Some(SIGINT)
+        } else if ch == cc[VQUIT] {
+            Some(SIGQUIT)
+        } else if ch == cc[VSUSP] {
+            Some(SIGTSTP)
+        } else {
+            None
+        }
+    }
+}
+
 impl TtyFile {
     // pub fn new(tty_inode: Arc<dyn Inode>) -> Self {
     pub fn new(readable: bool, writable: bool) -> Self {
         Self {
             // tty_inode,
             meta: FileMeta::new_bare(readable, writable, super::OSFileType::TTY),
-            inner: SpinNoIrqLock::new(TtyInner {
-                fg_pgid: 0,
-                win_size: WinSize::new(),
-                termios: Termios::new(),
-            }),
         }
     }
 }
@@ -50,20 +164,14 @@ impl File for TtyFile {
             if !self.meta.readable {
                 return Err(SyscallErr::EBADF.into());
             }
+            check_foreground(SIGTTIN)?;
             // assert_eq!(buf.len(), 1);
-            // busy loop
-            let mut c: usize;
-            loop {
-                c = console_getchar();
-                // opensbi returns usize::MAX if no char available
-                if c == usize::MAX {
-                    yield_task().await;
-                    continue;
-                } else {
-                    break;
+            let ch = loop {
+                if let Some(ch) = console_take_char() {
+                    break ch;
                 }
-            }
-            let ch = c as u8;
+                PollWaitFuture::new(self, PollEvents::POLLIN).await;
+            };
             unsafe {
                 // buf.buffers[0].as_mut_ptr().write_volatile(ch);
                 buf.as_mut_ptr().write_volatile(ch);
@@ -77,6 +185,9 @@ impl File for TtyFile {
             if !self.meta.writable {
                 return Err(SyscallErr::EBADF.into());
             }
+            if TERMINAL.lock().termios.lflag & TOSTOP != 0 {
+                check_foreground(SIGTTOU)?;
+            }
             print!("{}", core::str::from_utf8(buf).unwrap_or("<invalid utf8>"));
             Ok(buf.len())
         })
@@ -100,8 +211,8 @@ impl File for TtyFile {
                 // UserCheck::new()
                 //     .check_writable_slice(value as *mut u8, core::mem::size_of::<Termios>())?;
                 unsafe {
-                    // (value as *mut Termios).copy_from(&self.inner.lock().termios as *const Termios, 1);
-                    *(argp as *mut Termios) = self.inner.lock().termios;
+                    // (value as *mut Termios).copy_from(&TERMINAL.lock().termios as *const Termios, 1);
+                    *(argp as *mut Termios) = TERMINAL.lock().termios;
                 }
                 Ok(0)
             }
@@ -110,7 +221,7 @@ impl File for TtyFile {
                 // UserCheck::new()
                 //     .check_readable_slice(value as *const u8, core::mem::size_of::<Termios>())?;
                 unsafe {
-                    self.inner.lock().termios = *(argp as *const Termios);
+                    TERMINAL.lock().termios = *(argp as *const Termios);
                 }
                 Ok(0)
             }
@@ -119,19 +230,64 @@ impl File for TtyFile {
                 // UserCheck::new()
                 //     .check_writable_slice(value as *mut u8, core::mem::size_of::<Pid>())?;
                 unsafe {
-                    *(argp as *mut u32) = self.inner.lock().fg_pgid;
+                    *(argp as *mut u32) = TERMINAL.lock().fg_pgid as u32;
                     log::info!("[TtyFile::ioctl] get fg pgid {}", *(argp as *const u32));
                 }
                 Ok(0)
             }
             TIOCSPGRP => {
-                // let _sum_guard = SumGuard::new();
-                // UserCheck::new()
-                //     .check_readable_slice(value as *const u8, core::mem::size_of::<Pid>())?;
+                UserCheck::new().check_readable_pages(argp as *const u8, size_of::<i32>())?;
+                let pgid = unsafe { *(argp as *const i32) };
+                log::info!("[TtyFile::ioctl] set fg pgid {}", pgid);
+                let sid = current_process().get_sid();
+                if TERMINAL.lock().session != Some(sid) {
+                    return Err(SyscallErr::ENOTTY.into());
+                }
+                if pgid < 0 {
+                    return Err(SyscallErr::EINVAL.into());
+                }
+                let pgroup = ProcessGroup::find(pgid as usize).ok_or(SyscallErr::ESRCH)?;
+                if pgroup.sid() != sid {
+                    return Err(SyscallErr::EPERM.into());
+                }
+                // 后台进程组设置前台进程组时也会收到SIGTTOU
+                check_foreground(SIGTTOU)?;
+                TERMINAL.lock().fg_pgid = pgid as usize;
+                Ok(0)
+            }
+            TIOCGSID => {
+                let session = TERMINAL.lock().session;
+                if session != Some(current_process().get_sid()) {
+                    return Err(SyscallErr::ENOTTY.into());
+                }
+                UserCheck::new().check_writable_pages(argp as *mut u8, size_of::<u32>())?;
                 unsafe {
-                    log::info!("[TtyFile::ioctl] set fg pgid {}", *(argp as *const u32));
-                    self.inner.lock().fg_pgid = *(argp as *const u32);
+                    *(argp as *mut u32) = session.unwrap() as u32;
+                }
+                Ok(0)
+            }
+            TIOCSCTTY => {
+                // 只有没有控制终端的会话首进程可以获取控制终端
+                let process = current_process();
+                let pgroup = process.pgroup();
+                if pgroup.sid() != process.getpid() {
+                    return Err(SyscallErr::EPERM.into());
                 }
+                let mut terminal = TERMINAL.lock();
+                if terminal.session.is_some() && terminal.session != Some(pgroup.sid()) && argp != 1
+                {
+                    return Err(SyscallErr::EPERM.into());
+                }
+                terminal.session = Some(pgroup.sid());
+                terminal.fg_pgid = pgroup.pgid();
+                Ok(0)
+            }
+            TIOCNOTTY => {
+                let mut terminal = TERMINAL.lock();
+                if terminal.session != Some(current_process().get_sid()) {
+                    return Err(SyscallErr::ENOTTY.into());
+                }
+                terminal.session = None;
                 Ok(0)
             }
             TIOCGWINSZ => {
@@ -139,7 +295,7 @@ impl File for TtyFile {
                 // UserCheck::new()
                 //     .check_writable_slice(value as *mut u8, core::mem::size_of::<WinSize>())?;
                 unsafe {
-                    *(argp as *mut WinSize) = self.inner.lock().win_size;
+                    *(argp as *mut WinSize) = TERMINAL.lock().win_size;
                 }
                 Ok(0)
             }
@@ -148,7 +304,7 @@ impl File for TtyFile {
                 // UserCheck::new()
                 //     .check_readable_slice(value as *const u8, core::mem::size_of::<WinSize>())?;
                 unsafe {
-                    self.inner.lock().win_size = *(argp as *const WinSize);
+                    TERMINAL.lock().win_size = *(argp as *const WinSize);
                 }
                 Ok(0)
             }
@@ -156,6 +312,25 @@ impl File for TtyFile {
             _ => unimplemented!(),
         }
     }
+
+    fn poll(&self, events: PollEvents, waker: Option<&Waker>) -> PollEvents {
+        let mut ready = PollEvents::empty();
+        if self.meta.readable && events.contains(PollEvents::POLLIN) && console_has_input() {
+            ready |= PollEvents::POLLIN;
+        }
+        if self.meta.writable && events.contains(PollEvents::POLLOUT) {
+            ready |= PollEvents::POLLOUT;
+        }
+        if self.meta.readable && events.contains(PollEvents::POLLIN) {
+            if let Some(waker) = waker {
+                add_timer(
+                    current_time_duration() + CONSOLE_POLL_INTERVAL,
+                    waker.clone(),
+                );
+            }
+        }
+        ready
+    }
 }
 
 /// Gets the current serial port settings.
@@ -185,6 +360,12 @@ const TCSBRK: usize = 0x5409;
 const TIOCGPGRP: usize = 0x540F;
 /// Set the foreground process group ID of this terminal.
 const TIOCSPGRP: usize = 0x5410;
+/// Make the given terminal the controlling terminal of the calling process.
+const TIOCSCTTY: usize = 0x540E;
+/// Give up this controlling terminal.
+const TIOCNOTTY: usize = 0x5422;
+/// Get the session ID of this terminal.
+const TIOCGSID: usize = 0x5429;
 /// Get window size.
 const TIOCGWINSZ: usize = 0x5413;
 /// Set window size.
@@ -213,6 +394,15 @@ impl WinSize {
     }
 }
 
+/// `lflag`: Generate signals for INTR, QUIT and SUSP characters
+const ISIG: u32 = 0o1;
+/// `lflag`: Send SIGTTOU to background processes that write to the terminal
+const TOSTOP: u32 = 0o400;
+/// Indexes of `cc`
+const VINTR: usize = 0;
+const VQUIT: usize = 1;
+const VSUSP: usize = 10;
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct Termios {
@@ -13,16 +13,26 @@ use core::time::Duration;
 
 use super::{flags::*, queue::FUTEXQUEUES};
 use crate::{
+    config::SysResult,
     futex::queue::futex_hash,
+    mm::user_check::UserCheck,
+    mutex::SpinNoIrqLock,
     task::{
         processor::{current_process, current_thread_uncheck},
-        task::{current_have_signals, TaskRef},
-        yield_task,
+        task::{current_have_signals, TaskRef, Thread, PROCESS_MANAGER},
     },
-    timer::current_time_duration,
+    timer::TimeLimitedFuture,
     utils::SyscallErr,
     SyscallRet, PAGE_SIZE_BITS,
 };
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use core::task::{Context, Poll, Waker};
 use log::info;
 
 use crate::mm::VirtAddr;
@@ -35,17 +45,104 @@ pub struct FutexQ {
     pub task: TaskRef,
     /// the bitset of the futex
     pub bitset: u32,
+    /// shared with the waiting `FutexWaitFuture`
+    pub waiter: Arc<FutexWaiter>,
 }
 
 impl FutexQ {
     /// Create a new futex queue
     pub fn new(key: FutexKey, task: TaskRef, bitset: u32) -> Self {
-        Self { key, task, bitset }
+        Self {
+            key,
+            task,
+            bitset,
+            waiter: Arc::new(FutexWaiter::new(key)),
+        }
     }
     /// check if the futex queues matches the key
     pub fn match_key(&self, key: &FutexKey) -> bool {
         self.key == *key
     }
+    /// 出队并唤醒等待者
+    pub fn wake(self) {
+        self.waiter.wake();
+    }
+}
+
+/// 等待者的状态, 由等待队列和等待的任务共享
+pub struct FutexWaiter {
+    woken: AtomicBool,
+    /// 当前所在的队列, requeue之后会改变
+    key: SpinNoIrqLock<FutexKey>,
+    waker: SpinNoIrqLock<Option<Waker>>,
+}
+
+impl FutexWaiter {
+    fn new(key: FutexKey) -> Self {
+        Self {
+            woken: AtomicBool::new(false),
+            key: SpinNoIrqLock::new(key),
+            waker: SpinNoIrqLock::new(None),
+        }
+    }
+    fn wake(&self) {
+        self.woken.store(true, Ordering::Release);
+        if let Some(waker) = self.waker.lock().take() {
+            waker.wake();
+        }
+    }
+    fn is_woken(&self) -> bool {
+        self.woken.load(Ordering::Acquire)
+    }
+    fn key(&self) -> FutexKey {
+        *self.key.lock()
+    }
+}
+
+/// 等待被`futex_wake`唤醒, 或者被信号打断
+struct FutexWaitFuture {
+    waiter: Arc<FutexWaiter>,
+}
+
+impl Future for FutexWaitFuture {
+    type Output = SyscallRet;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.waiter.is_woken() {
+            return Poll::Ready(Ok(0));
+        }
+        if current_have_signals() {
+            return Poll::Ready(Err(SyscallErr::EINTR.into()));
+        }
+        *self.waiter.waker.lock() = Some(cx.waker().clone());
+        current_thread_uncheck().set_interruptible_waker(cx.waker().clone());
+        // 设置waker之前可能已经被唤醒
+        if self.waiter.is_woken() {
+            return Poll::Ready(Ok(0));
+        }
+        Poll::Pending
+    }
+}
+
+/// 把等待者从队列中移除, 返回false说明已经被唤醒出队
+fn futex_unqueue(waiter: &Arc<FutexWaiter>) -> bool {
+    loop {
+        let key = waiter.key();
+        let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
+        // requeue时持有桶的锁修改key, 在锁内再检查一次
+        if waiter.key() != key {
+            continue;
+        }
+        return match hash_bucket
+            .iter()
+            .position(|futex_q| Arc::ptr_eq(&futex_q.waiter, waiter))
+        {
+            Some(idx) => {
+                hash_bucket.remove(idx);
+                true
+            }
+            None => false,
+        };
+    }
 }
 
 /// Futexes are matched on equal values of this key.
@@ -74,7 +171,7 @@ impl FutexKey {
     }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone, Copy)]
 /// 用于存储 robust list 的结构
 pub struct FutexRobustList {
     /// The location of the head of the robust list in user space
@@ -145,52 +242,35 @@ pub async fn futex_wait(
         expected_val,
         deadline
     );
-    let mut is_timeout = false;
-
-    // we may be victim of spurious wakeups, so we need to loop
-    loop {
-        let key = get_futex_key(vaddr, flags);
+    let key = get_futex_key(vaddr, flags);
+    let waiter = {
+        let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
+        // 持有桶的锁比较, 比较之后的futex_wake一定能看到我们
         let real_futex_val = futex_get_value_locked(vaddr)?;
         if expected_val != real_futex_val as u32 {
             return Err(SyscallErr::EAGAIN.into());
         }
-        // 比较后相等，放入等待队列
-        {
-            let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
-            let cur_futexq = FutexQ::new(key, current_thread_uncheck().clone(), bitset);
-            hash_bucket.push_back(cur_futexq);
-
-            // drop lock to avoid deadlock
-            drop(hash_bucket);
-        }
-        if let Some(deadline) = deadline {
-            let now = current_time_duration();
-            is_timeout = deadline < now;
-        }
-        if deadline.is_none() || !is_timeout {
-            yield_task().await;
-        }
+        let cur_futexq = FutexQ::new(key, current_thread_uncheck().clone(), bitset);
+        let waiter = cur_futexq.waiter.clone();
+        hash_bucket.push_back(cur_futexq);
+        waiter
+    };
 
+    let ret = TimeLimitedFuture::new(
+        FutexWaitFuture {
+            waiter: waiter.clone(),
+        },
+        deadline,
+    )
+    .await;
+    current_thread_uncheck().clear_interruptible_waker();
+    match ret {
+        Some(Ok(_)) => Ok(0),
         // If we were woken (and unqueued), we succeeded, whatever.
         // We doesn't care about the reason of wakeup if we were unqueued.
-        let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
-        let cur_id = current_thread_uncheck().get_tid();
-        //if let Some(idx) = hash_bucket.iter().position(|futex_q| futex_q.task.id().as_u64() == cur_id) {
-        if let Some(idx) = hash_bucket
-            .iter()
-            .position(|futex_q| futex_q.task.get_tid() == cur_id)
-        {
-            hash_bucket.remove(idx);
-            if is_timeout {
-                return Err(SyscallErr::ETIMEDOUT.into());
-            }
-            if current_have_signals() {
-                return Err(SyscallErr::EINTR.into());
-            }
-        } else {
-            // the task is woken up anyway
-            return Ok(0);
-        }
+        _ if !futex_unqueue(&waiter) => Ok(0),
+        Some(Err(err)) => Err(err),
+        None => Err(SyscallErr::ETIMEDOUT.into()),
     }
 }
 
@@ -200,28 +280,8 @@ pub async fn futex_wake(vaddr: VirtAddr, flags: i32, nr_waken: u32) -> SyscallRe
         "[futex_wake] vaddr: {:?}, flags: {:?}, nr_waken: {:?}",
         vaddr, flags, nr_waken
     );
-    let mut ret = 0;
     let key = get_futex_key(vaddr, flags);
-    {
-        let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
-
-        if hash_bucket.is_empty() {
-            info!("hash_bucket is empty");
-            return Ok(0);
-        } else {
-            hash_bucket.retain(|futex_q| {
-                if ret < nr_waken && futex_q.key == key {
-                    //let wake_up = WAIT_FOR_FUTEX.notify_task(&futex_q.task);
-                    info!("wake up task {:?}", futex_q.task.get_tid());
-                    ret += 1;
-                    return false;
-                }
-                true
-            })
-        }
-        // drop hash_bucket to avoid deadlock
-    }
-    yield_task().await;
+    let ret = futex_wake_matching(&key, nr_waken, |_| true);
     info!("[futex_wake] wake up {:?} tasks", ret);
     Ok(ret as usize)
 }
@@ -239,75 +299,409 @@ pub async fn futex_wake_bitset(
     if bitset == 0 {
         return Err(SyscallErr::EINVAL.into());
     }
-    let mut ret = 0;
     let key = get_futex_key(vaddr, flags);
-    {
-        let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
-        if hash_bucket.is_empty() {
-            return Ok(0);
+    let ret = futex_wake_matching(&key, nr_waken, |futex_q| futex_q.bitset & bitset != 0);
+    Ok(ret as usize)
+}
+
+/// 从桶中取出`key`上最多`nr`个满足`filter`的等待者
+fn dequeue_matching(
+    hash_bucket: &mut VecDeque<FutexQ>,
+    key: &FutexKey,
+    nr: u32,
+    filter: impl Fn(&FutexQ) -> bool,
+) -> Vec<FutexQ> {
+    let mut dequeued = Vec::new();
+    let mut idx = 0;
+    while idx < hash_bucket.len() && (dequeued.len() as u32) < nr {
+        if hash_bucket[idx].key == *key && filter(&hash_bucket[idx]) {
+            dequeued.push(hash_bucket.remove(idx).unwrap());
         } else {
-            hash_bucket.retain(|futex_q| {
-                if ret == nr_waken {
-                    return true;
-                }
-                if (futex_q.bitset & bitset) != 0 && futex_q.key == key {
-                    //WAIT_FOR_FUTEX.notify_task(&futex_q.task);
-                    ret += 1;
-                    return false;
-                }
-                return true;
-            })
+            idx += 1;
         }
-        // drop hash_bucket to avoid deadlock
     }
-    yield_task().await;
-    Ok(ret as usize)
+    dequeued
+}
+
+/// 唤醒`key`上最多`nr_waken`个满足`filter`的等待者
+fn futex_wake_matching(key: &FutexKey, nr_waken: u32, filter: impl Fn(&FutexQ) -> bool) -> u32 {
+    // drop hash_bucket before waking to avoid deadlock
+    let woken = dequeue_matching(
+        &mut FUTEXQUEUES.buckets[futex_hash(key)].lock(),
+        key,
+        nr_waken,
+        filter,
+    );
+    let ret = woken.len() as u32;
+    for futex_q in woken {
+        info!("wake up task {:?}", futex_q.task.get_tid());
+        futex_q.wake();
+    }
+    ret
 }
 
+/// 同时锁住两个key所在的桶, 按下标顺序加锁以免死锁, 两个桶相同时只锁一次.
+/// `f`的参数依次是第一个key和第二个key所在的桶
+fn with_bucket_pair<T>(
+    key1: &FutexKey,
+    key2: &FutexKey,
+    f: impl FnOnce(&mut VecDeque<FutexQ>, Option<&mut VecDeque<FutexQ>>) -> T,
+) -> T {
+    let (idx1, idx2) = (futex_hash(key1), futex_hash(key2));
+    if idx1 == idx2 {
+        return f(&mut FUTEXQUEUES.buckets[idx1].lock(), None);
+    }
+    let (lo, hi) = (idx1.min(idx2), idx1.max(idx2));
+    let mut lo_bucket = FUTEXQUEUES.buckets[lo].lock();
+    let mut hi_bucket = FUTEXQUEUES.buckets[hi].lock();
+    if idx1 == lo {
+        f(&mut lo_bucket, Some(&mut hi_bucket))
+    } else {
+        f(&mut hi_bucket, Some(&mut lo_bucket))
+    }
+}
+
+/// 唤醒`uaddr`上最多`nr_waken`个等待者, 再把最多`nr_requeue`个剩下的等待者移到`uaddr2`上.
+/// `cmpval`不为`None`时(FUTEX_CMP_REQUEUE), 持有桶的锁检查`uaddr`的值, 不相等时返回`EAGAIN`.
+/// 返回唤醒和移动的等待者总数
 pub async fn futex_requeue(
     uaddr: VirtAddr,
     flags: i32,
     nr_waken: u32,
     uaddr2: VirtAddr,
     nr_requeue: u32,
+    cmpval: Option<u32>,
 ) -> SyscallRet {
-    let mut ret = 0;
-    let mut requeued = 0;
     let key = get_futex_key(uaddr, flags);
     let req_key = get_futex_key(uaddr2, flags);
 
-    if key == req_key {
-        return futex_wake(uaddr, flags, nr_waken).await;
+    let woken = with_bucket_pair(&key, &req_key, |bucket, req_bucket| -> SysResult<_> {
+        if let Some(cmpval) = cmpval {
+            if futex_get_value_locked(uaddr)? as u32 != cmpval {
+                return Err(SyscallErr::EAGAIN.into());
+            }
+        }
+        let woken = dequeue_matching(bucket, &key, nr_waken, |_| true);
+        if key == req_key {
+            return Ok((woken, 0));
+        }
+        let moved = dequeue_matching(bucket, &key, nr_requeue, |_| true);
+        let nr_moved = moved.len();
+        // 两个key可能在同一个桶里
+        let req_bucket = req_bucket.unwrap_or(bucket);
+        for mut futex_q in moved {
+            futex_q.key = req_key;
+            *futex_q.waiter.key.lock() = req_key;
+            req_bucket.push_back(futex_q);
+        }
+        Ok((woken, nr_moved))
+    });
+    let (woken, nr_moved) = woken?;
+    let ret = woken.len() + nr_moved;
+    for futex_q in woken {
+        futex_q.wake();
     }
+    Ok(ret)
+}
 
-    {
+/// FUTEX_WAKE_OP: 原子地修改`uaddr2`, 唤醒`uaddr`上最多`nr_waken`个等待者,
+/// 如果`uaddr2`原来的值满足比较条件, 再唤醒`uaddr2`上最多`nr_waken2`个
+pub async fn futex_wake_op(
+    uaddr: VirtAddr,
+    flags: i32,
+    nr_waken: u32,
+    uaddr2: VirtAddr,
+    nr_waken2: u32,
+    val3: u32,
+) -> SyscallRet {
+    let mut op = (val3 >> 28) & 0xf;
+    let cmp = (val3 >> 24) & 0xf;
+    // 12位的有符号数
+    let mut oparg = ((val3 << 8) as i32 >> 20) as u32;
+    let cmparg = ((val3 << 20) as i32 >> 20) as u32;
+    if op & FUTEX_OP_OPARG_SHIFT != 0 {
+        if oparg > 31 {
+            return Err(SyscallErr::EINVAL.into());
+        }
+        oparg = 1 << oparg;
+        op &= !FUTEX_OP_OPARG_SHIFT;
+    }
+    if op > FUTEX_OP_XOR || cmp > FUTEX_OP_CMP_GE {
+        return Err(SyscallErr::ENOSYS.into());
+    }
+    check_futex_word(uaddr2)?;
+    let key = get_futex_key(uaddr, flags);
+    let key2 = get_futex_key(uaddr2, flags);
+
+    let woken = with_bucket_pair(&key, &key2, |bucket, bucket2| {
+        let word = futex_word(uaddr2);
+        let oldval = match op {
+            FUTEX_OP_SET => word.swap(oparg, Ordering::SeqCst),
+            FUTEX_OP_ADD => word.fetch_add(oparg, Ordering::SeqCst),
+            FUTEX_OP_OR => word.fetch_or(oparg, Ordering::SeqCst),
+            FUTEX_OP_ANDN => word.fetch_and(!oparg, Ordering::SeqCst),
+            _ => word.fetch_xor(oparg, Ordering::SeqCst),
+        };
+        let (oldval, cmparg) = (oldval as i32, cmparg as i32);
+        let cond = match cmp {
+            FUTEX_OP_CMP_EQ => oldval == cmparg,
+            FUTEX_OP_CMP_NE => oldval != cmparg,
+            FUTEX_OP_CMP_LT => oldval < cmparg,
+            FUTEX_OP_CMP_LE => oldval <= cmparg,
+            FUTEX_OP_CMP_GT => oldval > cmparg,
+            _ => oldval >= cmparg,
+        };
+        let mut woken = dequeue_matching(bucket, &key, nr_waken, |_| true);
+        if cond {
+            let bucket2 = bucket2.unwrap_or(bucket);
+            woken.extend(dequeue_matching(bucket2, &key2, nr_waken2, |_| true));
+        }
+        woken
+    });
+    let ret = woken.len();
+    for futex_q in woken {
+        futex_q.wake();
+    }
+    Ok(ret)
+}
+
+/// PI futex和robust futex的值由内核修改, 先检查`vaddr`对齐且可写
+fn check_futex_word(vaddr: VirtAddr) -> SysResult<()> {
+    let uaddr: usize = vaddr.into();
+    if uaddr % size_of::<u32>() != 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    UserCheck::new().check_writable_pages(uaddr as *const u8, size_of::<u32>())
+}
+
+/// 用户态的futex字, 调用者保证已经用[`check_futex_word`]检查过
+fn futex_word(vaddr: VirtAddr) -> &'static AtomicU32 {
+    let uaddr: usize = vaddr.into();
+    unsafe { &*(uaddr as *const AtomicU32) }
+}
+
+/// `tid`对应的线程还没有退出
+fn thread_alive(tid: u32) -> bool {
+    PROCESS_MANAGER
+        .lock()
+        .get(&(tid as usize))
+        .map_or(false, |process| process.strong_count() > 0)
+}
+
+/// FUTEX_LOCK_PI/FUTEX_TRYLOCK_PI: futex的值是持有者的tid, 有等待者时还有`FUTEX_WAITERS`.
+/// 解锁时内核把锁直接交给第一个等待者, 所以被唤醒时已经持有锁.
+/// 没有实现优先级继承, 等待者不会提升持有者的优先级
+pub async fn futex_lock_pi(
+    vaddr: VirtAddr,
+    flags: i32,
+    deadline: Option<Duration>,
+    trylock: bool,
+) -> SyscallRet {
+    check_futex_word(vaddr)?;
+    let key = get_futex_key(vaddr, flags);
+    let thread = current_thread_uncheck();
+    let tid = thread.get_tid() as u32;
+    let word = futex_word(vaddr);
+    let waiter = {
         let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
-        if hash_bucket.is_empty() {
-            return Ok(0);
-        } else {
-            while let Some(futex_q) = hash_bucket.pop_front() {
-                if futex_q.key == key {
-                    //WAIT_FOR_FUTEX.notify_task(&futex_q.task);
-                    ret += 1;
-                    if ret == nr_waken {
-                        break;
-                    }
+        loop {
+            let val = word.load(Ordering::SeqCst);
+            let owner = val & FUTEX_TID_MASK;
+            if owner == 0 {
+                // 锁空闲, 或者持有者死亡后被释放; 还有等待者时保留FUTEX_WAITERS
+                let waiters = if hash_bucket.iter().any(|futex_q| futex_q.key == key) {
+                    FUTEX_WAITERS
+                } else {
+                    0
+                };
+                let new = tid | waiters | (val & FUTEX_OWNER_DIED);
+                if word
+                    .compare_exchange(val, new, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+                {
+                    return Ok(0);
                 }
+                continue;
             }
-            if hash_bucket.is_empty() {
-                return Ok(ret as usize);
+            if owner == tid {
+                return Err(SyscallErr::EDEADLK.into());
             }
-            // requeue the rest of the waiters
-            let mut req_bucket = FUTEXQUEUES.buckets[futex_hash(&req_key)].lock();
-            while let Some(futex_q) = hash_bucket.pop_front() {
-                req_bucket.push_back(futex_q);
-                requeued += 1;
-                if requeued == nr_requeue {
-                    break;
-                }
+            if trylock {
+                return Err(SyscallErr::EAGAIN.into());
+            }
+            if !thread_alive(owner) {
+                return Err(SyscallErr::ESRCH.into());
+            }
+            if val & FUTEX_WAITERS != 0
+                || word
+                    .compare_exchange(val, val | FUTEX_WAITERS, Ordering::SeqCst, Ordering::SeqCst)
+                    .is_ok()
+            {
+                break;
             }
         }
+        let futex_q = FutexQ::new(key, thread.clone(), FUTEX_BITSET_MATCH_ANY);
+        let waiter = futex_q.waiter.clone();
+        hash_bucket.push_back(futex_q);
+        waiter
+    };
+
+    let ret = TimeLimitedFuture::new(
+        FutexWaitFuture {
+            waiter: waiter.clone(),
+        },
+        deadline,
+    )
+    .await;
+    thread.clear_interruptible_waker();
+    match ret {
+        Some(Ok(_)) => Ok(0),
+        // 已经出队说明锁交给了我们
+        _ if !futex_unqueue(&waiter) => Ok(0),
+        Some(Err(err)) => Err(err),
+        None => Err(SyscallErr::ETIMEDOUT.into()),
+    }
+}
+
+/// 把PI futex交给`key`上的第一个等待者, 新的值是它的tid和`FUTEX_WAITERS`以及`extra`;
+/// 没有等待者时设为`extra`. 调用者持有桶的锁
+fn futex_handoff_pi(
+    hash_bucket: &mut VecDeque<FutexQ>,
+    key: &FutexKey,
+    word: &AtomicU32,
+    extra: u32,
+) -> Option<FutexQ> {
+    match dequeue_matching(hash_bucket, key, 1, |_| true).pop() {
+        Some(next) => {
+            word.store(
+                next.task.get_tid() as u32 | FUTEX_WAITERS | extra,
+                Ordering::SeqCst,
+            );
+            Some(next)
+        }
+        None => {
+            word.store(extra, Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+/// FUTEX_UNLOCK_PI: 只有持有者能解锁
+pub async fn futex_unlock_pi(vaddr: VirtAddr, flags: i32) -> SyscallRet {
+    check_futex_word(vaddr)?;
+    let key = get_futex_key(vaddr, flags);
+    let tid = current_thread_uncheck().get_tid() as u32;
+    let word = futex_word(vaddr);
+    let next = {
+        let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
+        if word.load(Ordering::SeqCst) & FUTEX_TID_MASK != tid {
+            return Err(SyscallErr::EPERM.into());
+        }
+        futex_handoff_pi(&mut hash_bucket, &key, word, 0)
+    };
+    if let Some(next) = next {
+        next.wake();
+    }
+    Ok(0)
+}
+
+/// 用户态`struct robust_list_head`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RobustListHead {
+    /// 链表的第一项, 链表以指向`RobustListHead`自身结束
+    pub list_next: usize,
+    /// 从链表项到futex字的偏移
+    pub futex_offset: isize,
+    /// 正在加锁或解锁, 可能还没有加入链表的项
+    pub list_op_pending: usize,
+}
+
+/// 最多处理这么多项, 防止用户构造的环形链表
+const ROBUST_LIST_LIMIT: usize = 2048;
+
+/// 持有者`tid`退出时释放它持有的robust futex: 值改为`FUTEX_OWNER_DIED`,
+/// 有等待者时唤醒一个. PI futex直接交给第一个等待者
+fn handle_futex_death(uaddr: usize, tid: u32, pi: bool) {
+    let vaddr = VirtAddr::from(uaddr);
+    if check_futex_word(vaddr).is_err() {
+        return;
+    }
+    let key = get_futex_key(vaddr, 0);
+    let word = futex_word(vaddr);
+    let woken = {
+        let mut hash_bucket = FUTEXQUEUES.buckets[futex_hash(&key)].lock();
+        let val = word.load(Ordering::SeqCst);
+        if val & FUTEX_TID_MASK != tid {
+            return;
+        }
+        if pi {
+            futex_handoff_pi(&mut hash_bucket, &key, word, FUTEX_OWNER_DIED)
+        } else {
+            word.store((val & FUTEX_WAITERS) | FUTEX_OWNER_DIED, Ordering::SeqCst);
+            if val & FUTEX_WAITERS != 0 {
+                dequeue_matching(&mut hash_bucket, &key, 1, |_| true).pop()
+            } else {
+                None
+            }
+        }
+    };
+    if let Some(futex_q) = woken {
+        futex_q.wake();
+    }
+}
+
+/// 读取用户态的一个指针
+fn read_user_ptr(uaddr: usize) -> Option<usize> {
+    UserCheck::new()
+        .check_readable_pages(uaddr as *const u8, size_of::<usize>())
+        .ok()?;
+    Some(unsafe { *(uaddr as *const usize) })
+}
+
+/// 线程退出时遍历它的robust list, 链表项指针的最低位表示PI futex
+fn exit_robust_list(tid: u32, robust_list: &FutexRobustList) {
+    let head_addr = robust_list.head;
+    if UserCheck::new()
+        .check_readable_pages(head_addr as *const u8, size_of::<RobustListHead>())
+        .is_err()
+    {
+        return;
+    }
+    let head = unsafe { *(head_addr as *const RobustListHead) };
+    let futex_addr = |entry: usize| (entry & !1).wrapping_add_signed(head.futex_offset);
+    let mut entry = head.list_next;
+    let mut limit = ROBUST_LIST_LIMIT;
+    while entry != head_addr && limit > 0 {
+        // 先取下一项, 处理之后链表可能被其他线程修改
+        let Some(next) = read_user_ptr(entry & !1) else {
+            break;
+        };
+        if entry != head.list_op_pending {
+            handle_futex_death(futex_addr(entry), tid, entry & 1 != 0);
+        }
+        entry = next;
+        limit -= 1;
+    }
+    if head.list_op_pending != 0 {
+        let pending = head.list_op_pending;
+        handle_futex_death(futex_addr(pending), tid, pending & 1 != 0);
+    }
+}
+
+/// 线程退出时释放它持有的robust futex, 然后清零`clear_child_tid`并唤醒在上面等待的线程,
+/// `pthread_join`等依赖于此
+pub fn futex_exit(thread: &Thread) {
+    let tid = thread.get_tid();
+    let inner = thread.get_inner_mut();
+    if let Some(robust_list) = inner.robust_list.take() {
+        exit_robust_list(tid as u32, &robust_list);
+    }
+    if let Some(ctid) = inner.tid_addr.clear_tid_address.take() {
+        let vaddr = VirtAddr::from(ctid);
+        if check_futex_word(vaddr).is_ok() {
+            futex_word(vaddr).store(0, Ordering::SeqCst);
+            futex_wake_matching(&get_futex_key(vaddr, 0), 1, |_| true);
+        }
     }
-    yield_task().await;
-    Ok(ret as usize)
 }
@@ -1,24 +1,25 @@
 extern crate alloc;
 
 use crate::{
-    timer::{current_time_duration, TimeSpec},
+    mm::user_check::UserCheck,
+    task::{
+        find_thread,
+        processor::{current_process, current_thread_uncheck},
+    },
+    timer::{current_time_duration, read_timeout},
     utils::SyscallErr,
     SyscallRet,
 };
+use core::mem::size_of;
 use core::time::Duration;
 use log::error;
 
 use flags::*;
-use futex::{futex_requeue, futex_wait, futex_wake, futex_wake_bitset};
-
-/*
-use axprocess::{
-    current_process, current_task, futex::FutexRobustList
-}
-;
-
-use crate::{RobustList, SyscallError, SyscallResult, TimeSecs};
-*/
+pub use futex::futex_exit;
+use futex::{
+    futex_lock_pi, futex_requeue, futex_unlock_pi, futex_wait, futex_wake, futex_wake_bitset,
+    futex_wake_op, FutexRobustList, RobustListHead,
+};
 
 pub mod flags;
 pub mod futex;
@@ -33,61 +34,61 @@ pub async fn sys_futex(
     uaddr2: usize,
     val3: u32,
 ) -> SyscallRet {
-    // convert `TimeSecs` struct to `timeout` nanoseconds
-    let timeout = if val2 != 0 {
-        let time_sepc: TimeSpec = unsafe { *(val2 as *const TimeSpec) };
-        time_sepc.turn_to_nanos()
-    } else {
-        // usize::MAX
-        0
-    };
-
     let flags: i32 = futex_op_to_flag(futex_op);
     // cmd determines the operation of futex
     let cmd: i32 = futex_op & FUTEX_CMD_MASK;
-    // TODO: shared futex and real time clock
+    // TODO: shared futex
     // It's Ok for ananonymous mmap to use private futex
     if (flags & FLAGS_SHARED) != 0 {
         log::info!(
             "[sys_futex] shared futex is not supported, but it's ok for anonymous mmap to use private futex"
         );
     }
-    if (flags & FLAGS_CLOCKRT) != 0 {
-        panic!("FUTEX_CLOCK_REALTIME is not supported");
+    // 只有绝对超时的操作可以选择时钟; 内核的CLOCK_REALTIME和CLOCK_MONOTONIC相同, 不需要换算
+    if (flags & FLAGS_CLOCKRT) != 0 && cmd != FUTEX_WAIT_BITSET && cmd != FUTEX_LOCK_PI2 {
+        return Err(SyscallErr::ENOSYS.into());
     }
 
     match cmd {
         FUTEX_WAIT => {
-            let val3 = FUTEX_BITSET_MATCH_ANY;
             // convert relative timeout to absolute timeout
-            let deadline: Option<Duration> = if timeout != 0 {
-                Some(Duration::from_nanos(timeout as u64) + current_time_duration())
-            } else {
-                None
-            };
-            futex_wait(uaddr.into(), flags, val, deadline, val3).await
+            let deadline: Option<Duration> =
+                read_timeout(val2)?.map(|timeout| timeout + current_time_duration());
+            futex_wait(uaddr.into(), flags, val, deadline, FUTEX_BITSET_MATCH_ANY).await
         }
         FUTEX_WAIT_BITSET => {
-            let deadline: Option<Duration> = if timeout != 0 {
-                Some(Duration::from_nanos(timeout as u64))
-            } else {
-                None
-            };
+            if val3 == 0 {
+                return Err(SyscallErr::EINVAL.into());
+            }
+            let deadline = read_timeout(val2)?;
             futex_wait(uaddr.into(), flags, val, deadline, val3).await
         }
         FUTEX_WAKE => futex_wake(uaddr.into(), flags, val).await,
         FUTEX_WAKE_BITSET => futex_wake_bitset(uaddr.into(), flags, val, val3).await,
-        FUTEX_REQUEUE => futex_requeue(uaddr.into(), flags, val, uaddr2.into(), val2 as u32).await,
+        // `val2`是要移动的等待者数量, 不是超时
+        FUTEX_REQUEUE => {
+            futex_requeue(uaddr.into(), flags, val, uaddr2.into(), val2 as u32, None).await
+        }
         FUTEX_CMP_REQUEUE => {
-            error!("[linux_syscall_api] futex: unsupported futex operation: FUTEX_CMP_REQUEUE");
-            return Err(SyscallErr::ENOSYS.into());
+            futex_requeue(
+                uaddr.into(),
+                flags,
+                val,
+                uaddr2.into(),
+                val2 as u32,
+                Some(val3),
+            )
+            .await
         }
         FUTEX_WAKE_OP => {
-            // futex_wake(uaddr, flags, uaddr2, val, val2, val3)
-            error!("[linux_syscall_api] futex: unsupported futex operation: FUTEX_WAKE_OP");
-            return Err(SyscallErr::ENOSYS.into());
+            futex_wake_op(uaddr.into(), flags, val, uaddr2.into(), val2 as u32, val3).await
+        }
+        // 超时是绝对时间
+        FUTEX_LOCK_PI | FUTEX_LOCK_PI2 => {
+            futex_lock_pi(uaddr.into(), flags, read_timeout(val2)?, false).await
         }
-        // TODO: priority-inheritance futex
+        FUTEX_TRYLOCK_PI => futex_lock_pi(uaddr.into(), flags, None, true).await,
+        FUTEX_UNLOCK_PI => futex_unlock_pi(uaddr.into(), flags).await,
         _ => {
             error!(
                 "[linux_syscall_api] futex: unsupported futex operation: {}",
@@ -99,60 +100,34 @@ pub async fn sys_futex(
     // success anyway and reach here
 }
 
-/*
-/// 内核只发挥存储的作用
-/// 但要保证head对应的地址已经被分配
-/// # Arguments
-/// * head: usize
-/// * len: usize
-pub fn syscall_set_robust_list(args: [usize; 6]) -> SyscallResult {
-    let head = args[0];
-    let len = args[1];
-    let process = current_process();
-    if len != core::mem::size_of::<RobustList>() {
-        return Err(SyscallError::EINVAL);
-    }
-    let curr_id = current_task().id().as_u64();
-    if process.manual_alloc_for_lazy(head.into()).is_ok() {
-        let mut robust_list = process.robust_list.lock();
-        robust_list.insert(curr_id, FutexRobustList::new(head, len));
-        Ok(0)
-    } else {
-        Err(SyscallError::EINVAL)
+/// 内核只记录robust list的位置, 线程退出时才读取它
+pub fn sys_set_robust_list(head: usize, len: usize) -> SyscallRet {
+    if len != size_of::<RobustListHead>() {
+        return Err(SyscallErr::EINVAL.into());
     }
+    current_thread_uncheck().get_inner_mut().robust_list = Some(FutexRobustList::new(head, len));
+    Ok(0)
 }
 
-/// 取出对应线程的robust list
-/// # Arguments
-/// * pid: i32
-/// * head: *mut usize
-/// * len: *mut usize
-pub fn syscall_get_robust_list(args: [usize; 6]) -> SyscallResult {
-    let pid = args[0] as i32;
-    let head = args[1] as *mut usize;
-    let len = args[2] as *mut usize;
-
-    if pid == 0 {
-        let process = current_process();
-        let curr_id = current_task().id().as_u64();
-        if process
-            .manual_alloc_for_lazy((head as usize).into())
-            .is_ok()
-        {
-            let robust_list = process.robust_list.lock();
-            if robust_list.contains_key(&curr_id) {
-                let list = robust_list.get(&curr_id).unwrap();
-                unsafe {
-                    *head = list.head;
-                    *len = list.len;
-                }
-            } else {
-                return Err(SyscallError::EPERM);
-            }
-            return Ok(0);
+/// 取出线程`tid`(0表示当前线程)的robust list,
+/// 没有特权时只能读取真实用户id相同的进程中的线程
+pub fn sys_get_robust_list(tid: usize, head: *mut usize, len: *mut usize) -> SyscallRet {
+    let thread = if tid == 0 {
+        current_thread_uncheck()
+    } else {
+        let thread = find_thread(tid).ok_or(SyscallErr::ESRCH)?;
+        let cred = current_process().cred();
+        if !cred.is_privileged() && thread.process.cred().user.real != cred.user.real {
+            return Err(SyscallErr::EPERM.into());
         }
-        return Err(SyscallError::EPERM);
+        thread
+    };
+    let robust_list = thread.get_inner_mut().robust_list.unwrap_or_default();
+    UserCheck::new().check_writable_pages(head as *const u8, size_of::<usize>())?;
+    UserCheck::new().check_writable_pages(len as *const u8, size_of::<usize>())?;
+    unsafe {
+        *head = robust_list.head;
+        *len = size_of::<RobustListHead>();
     }
-    Err(SyscallError::EPERM)
+    Ok(0)
 }
-    */
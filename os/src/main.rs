@@ -51,14 +51,14 @@ pub mod trap;
 pub mod utils;
 
 use core::arch::{asm, global_asm};
-// use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::{AtomicBool, Ordering};
 
+use log::info;
 use riscv::register::sstatus;
-// use sbi::hart_start;
 
 use crate::config::*;
-// use crate::mm::KERNEL_SPACE;
-// use crate::sbi::hart_start;
+use crate::mm::KERNEL_SPACE;
+use crate::sbi::hart_start;
 use crate::task::processor::new_local_hart;
 
 global_asm!(include_str!("entry.asm"));
@@ -92,84 +92,76 @@ pub fn fake_main(hart_id: usize) {
 #[rustfmt::skip]
 /// the rust entry-point of os
 pub fn rust_main(hart_id: usize) -> ! {
-    new_local_hart(hart_id);
-
-    // if FIRST_HART
-    //     .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
-    //     .is_ok()
-    // {
-    print!("\u{1B}[38;5;14m");
-    println!("");
-    println!("    :::     :::::::::   ::::::::  ::::    :::     :::            ::::::::   ::::::::  ");
-    println!("  :+: :+:   :+:    :+: :+:    :+: :+:+:   :+:   :+: :+:         :+:    :+: :+:    :+: ");
-    println!(" +:+   +:+  +:+    +:+ +:+    +:+ :+:+:+  +:+  +:+   +:+        +:+    +:+ +:+        ");
-    println!("+#++:++#++: +#++:++#:  +#+    +:+ +#+ +:+ +#+ +#++:++#++:       +#+    +:+ +#++:++#++ ");
-    println!("+#+     +#+ +#+    +#+ +#+    +#+ +#+  +#+#+# +#+     +#+       +#+    +#+        +#+ ");
-    println!("#+#     #+# #+#    #+# #+#    #+# #+#   #+#+# #+#     #+#       #+#    #+# #+#    #+# ");
-    println!("###     ### ###    ###  ########  ###    #### ###     ###        ########   ########  ");
-    println!("");
-    // println!("~*^*~ Bug bug flying away! ~*^*~");
-    print!("\u{1B}[0m");
-
-    // 允许S mode访问U mode的页面, 需要localctx的env_context进行管理, 目前就保持全局开启
-    unsafe {
-        sstatus::set_sum();
-    }
-    clear_bss();
-    logging::init();
-    mm::init();
-    trap::init();
-    executor::init();
-    trap::enable_timer_interrupt();
-    timer::set_next_trigger();
-    fs::init::init();
-    // 允许S mode访问U mode的页面, 需要localctx的env_context进行管理, 目前就保持全局开启
-    unsafe {
-        sstatus::set_sum();
+    if FIRST_HART
+        .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst)
+        .is_ok()
+    {
+        // 先清空bss, 否则会把new_local_hart记录的在线hart清掉
+        clear_bss();
+        new_local_hart(hart_id);
+
+        print!("\u{1B}[38;5;14m");
+        println!("");
+        println!("    :::     :::::::::   ::::::::  ::::    :::     :::            ::::::::   ::::::::  ");
+        println!("  :+: :+:   :+:    :+: :+:    :+: :+:+:   :+:   :+: :+:         :+:    :+: :+:    :+: ");
+        println!(" +:+   +:+  +:+    +:+ +:+    +:+ :+:+:+  +:+  +:+   +:+        +:+    +:+ +:+        ");
+        println!("+#++:++#++: +#++:++#:  +#+    +:+ +#+ +:+ +#+ +#++:++#++:       +#+    +:+ +#++:++#++ ");
+        println!("+#+     +#+ +#+    +#+ +#+    +#+ +#+  +#+#+# +#+     +#+       +#+    +#+        +#+ ");
+        println!("#+#     #+# #+#    #+# #+#    #+# #+#   #+#+# #+#     #+#       #+#    #+# #+#    #+# ");
+        println!("###     ### ###    ###  ########  ###    #### ###     ###        ########   ########  ");
+        println!("");
+        // println!("~*^*~ Bug bug flying away! ~*^*~");
+        print!("\u{1B}[0m");
+
+        logging::init();
+        mm::init();
+        trap::init();
+        executor::init();
+        trap::enable_timer_interrupt();
+        timer::set_next_trigger();
+        fs::init::init();
+        // 允许S mode访问U mode的页面, 需要localctx的env_context进行管理, 目前就保持全局开启
+        unsafe {
+            sstatus::set_sum();
+        }
+        loader::list_apps();
+        task::add_initproc();
+
+        INIT_FINISHED.store(true, Ordering::SeqCst);
+        start_all_cpu(hart_id);
+    } else {
+        new_local_hart(hart_id);
+        // 其他hart由第一个hart通过hart_start唤醒, 此时初始化应该已经完成
+        while !INIT_FINISHED.load(Ordering::SeqCst) {}
+
+        // 允许S mode访问U mode的页面, 需要localctx的env_context进行管理, 目前就保持全局开启
+        unsafe {
+            sstatus::set_sum();
+        }
+        trap::init();
+        trap::enable_timer_interrupt();
+        timer::set_next_trigger();
+
+        KERNEL_SPACE.lock().activate();
+        info!("hart {} start!", hart_id);
     }
-    loader::list_apps();
-    task::add_initproc();
-
-    // INIT_FINISHED.store(true, Ordering::SeqCst);
-    // start_all_cpu(hart_id);
-    // }
-    // else {
-    //     while !INIT_FINISHED.load(Ordering::SeqCst) {} // todo:实际上这里似乎并不需要这条语句，不过还是先留着。
-
-    //     // 允许S mode访问U mode的页面, 需要localctx的env_context进行管理, 目前就保持全局开启
-    //     unsafe {
-    //         sstatus::set_sum();
-    //     }
-    //     trap::init();
-    //     trap::enable_timer_interrupt();
-    //     timer::set_next_trigger();
-
-    //     KERNEL_SPACE.lock().activate();
-    //     // info!("cpu: {} start!", hart_id);
-    // }
 
     executor::run_forever();
-    // if hart_id == 0 {
-    //     executor::run_forever();
-    // } else {
-    //     loop {}
-    // }
 }
 
-// #[allow(unused)]
-// fn start_all_cpu(hart_id: usize) {
-//     // info!("cpu:{} Hello, world!", hart_id);
-//     for i in 0..4 {
-//         if i == hart_id {
-//             continue;
-//         }
-//         let status = hart_start(i, 0x80200000);
-//         // info!(
-//         //     "hart {} start to wake up hart {}... status {}",
-//         //     hart_id, i, status
-//         // );
-//     }
-// }
-
-// static FIRST_HART: AtomicBool = AtomicBool::new(true);
-// static INIT_FINISHED: AtomicBool = AtomicBool::new(false);
+/// 唤醒除自己以外的所有hart, 不存在的hart会让hart_start返回错误, 直接忽略
+fn start_all_cpu(hart_id: usize) {
+    for i in 0..MAX_HART_NUM {
+        if i == hart_id {
+            continue;
+        }
+        let status = hart_start(i, 0x80200000);
+        info!(
+            "hart {} start to wake up hart {}... status {}",
+            hart_id, i, status as isize
+        );
+    }
+}
+
+static FIRST_HART: AtomicBool = AtomicBool::new(true);
+static INIT_FINISHED: AtomicBool = AtomicBool::new(false);
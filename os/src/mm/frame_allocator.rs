@@ -3,6 +3,7 @@
 
 use super::{KernelAddr, PhysAddr, PhysPageNum};
 use crate::config::MEMORY_END;
+use crate::fs::page_cache;
 use crate::mutex::SpinNoIrqLock;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
@@ -106,7 +107,15 @@ pub fn init_frame_allocator() {
 
 /// allocate a frame
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR.lock().alloc().map(FrameTracker::new)
+    let ppn = FRAME_ALLOCATOR.lock().alloc();
+    match ppn {
+        Some(ppn) => Some(FrameTracker::new(ppn)),
+        None => {
+            // 内存不足时丢弃一批干净的文件页再试
+            page_cache::reclaim(page_cache::RECLAIM_BATCH);
+            FRAME_ALLOCATOR.lock().alloc().map(FrameTracker::new)
+        }
+    }
 }
 
 /// deallocate a frame
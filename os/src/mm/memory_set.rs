@@ -1,14 +1,20 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
+use super::shm::SharedMemory;
 use super::{frame_alloc, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
 #[allow(unused)]
 use crate::boards::vf2::{VF2_RAMFS_BASE, VF2_RAMFS_SIZE};
-use crate::config::{SysResult, KERNEL_BASE, MEMORY_END, MMIO, PAGE_SIZE, USER_STACK_SIZE};
+use crate::config::{
+    SysResult, KERNEL_BASE, MEMORY_END, MIN_USER_STACK_SIZE, MMIO, PAGE_SIZE, USER_STACK_SIZE,
+    USER_STACK_TOP,
+};
 use crate::mutex::SpinNoIrqLock;
+use crate::sbi::remote_sfence_vma;
 use crate::signal::sigreturn_trampoline;
 use crate::task::aux::*;
+use crate::task::processor::{get_local_hart, online_hart_mask};
 use crate::utils::SyscallErr;
 use crate::SyscallRet;
 use crate::{MMAP_MIN_ADDR, USER_MAX_VA};
@@ -23,11 +29,10 @@ use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::arch::asm;
-use core::fmt::Debug;
 use lazy_static::lazy_static;
 use log::{debug, info, trace, warn};
 use riscv::register::satp;
-use xmas_elf::program::Type;
+use xmas_elf::program::{ProgramHeader, Type};
 use xmas_elf::ElfFile;
 
 #[allow(unused)]
@@ -64,6 +69,18 @@ pub const DL_INTERP_OFFSET: usize = 0x20_0000_0000;
 pub fn kernel_token() -> usize {
     KERNEL_SPACE.lock().token()
 }
+
+/// Refresh TLB of all harts after mappings are changed or removed,
+/// threads of the same process may be running on other harts
+pub fn flush_tlb() {
+    unsafe {
+        asm!("sfence.vma");
+    }
+    let other_harts = online_hart_mask() & !(1 << get_local_hart().hart_id);
+    if other_harts != 0 {
+        remote_sfence_vma(other_harts, 0, usize::MAX);
+    }
+}
 /// memory set structure, controls virtual-memory space
 pub struct MemorySet {
     /// page table
@@ -117,6 +134,73 @@ impl MemorySet {
         );
     }
 
+    /// MAP_SHARED anonymous memory, frames are shared with children after fork
+    pub fn insert_shared_area(&mut self, vpn_range: VPNRange, permission: MapPermission) {
+        self.push(
+            MapArea::from_vpn_range(vpn_range, MapType::Shared, permission),
+            None,
+            0,
+        );
+    }
+
+    /// attach System V shared memory `shm` on `vpn_range`
+    pub fn insert_shm_area(
+        &mut self,
+        vpn_range: VPNRange,
+        permission: MapPermission,
+        shm: Arc<SharedMemory>,
+    ) {
+        self.push(
+            MapArea::from_vpn_range(vpn_range, MapType::Shm(shm), permission),
+            None,
+            0,
+        );
+    }
+
+    /// the address space goes away on exit or exec, detach all shared memory
+    pub fn detach_all_shm(&self) {
+        for area in self.areas.iter() {
+            if let MapType::Shm(shm) = &area.map_type {
+                shm.dec_nattch();
+            }
+        }
+    }
+
+    /// detach the shared memory attached at `start_vpn`, used for sys_shmdt.
+    /// the attachment may have been split by munmap/mprotect, all its pieces are removed.
+    /// return the segment and the number of areas removed
+    pub fn remove_shm_area(
+        &mut self,
+        start_vpn: VirtPageNum,
+    ) -> Option<(Arc<SharedMemory>, usize)> {
+        // a piece of the attachment maps page `vpn - start_vpn` of the segment at `vpn`
+        let attached_here = |area: &MapArea| match &area.map_type {
+            MapType::Shm(shm) => {
+                let start = area.vpn_range.get_start();
+                start >= start_vpn
+                    && shm
+                        .frames
+                        .get(start.0 - start_vpn.0)
+                        .zip(area.data_frames.get(&start))
+                        .is_some_and(|(segment_frame, frame)| Arc::ptr_eq(segment_frame, frame))
+            }
+            _ => false,
+        };
+        let (mut detached, kept): (Vec<MapArea>, Vec<MapArea>) = core::mem::take(&mut self.areas)
+            .into_iter()
+            .partition(attached_here);
+        self.areas = kept;
+        let shm = match &detached.first()?.map_type {
+            MapType::Shm(shm) => shm.clone(),
+            _ => unreachable!(),
+        };
+        for area in detached.iter_mut() {
+            area.unmap(&mut self.page_table);
+        }
+        flush_tlb();
+        Some((shm, detached.len()))
+    }
+
     /// used for lazy allocation
     pub fn insert_anonymous_area(&mut self, vpn_range: VPNRange, permission: MapPermission) {
         self.push(
@@ -126,18 +210,68 @@ impl MemorySet {
         )
     }
 
+    /// map `file` on `vpn_range`, pages come from the page cache of the file
+    pub fn insert_file_area(
+        &mut self,
+        vpn_range: VPNRange,
+        permission: MapPermission,
+        file: FileMapping,
+    ) {
+        self.push(
+            MapArea::from_vpn_range(vpn_range, MapType::File(file), permission),
+            None,
+            0,
+        )
+    }
+
+    /// mark pages of shared file mappings overlapping [start, start + len) dirty,
+    /// used by sys_msync and sys_sync
+    pub fn mark_shared_dirty(&self, start: usize, len: usize) {
+        for area in self.filter_overlap(start, len) {
+            area.mark_shared_dirty();
+        }
+    }
+
+    /// unmap up to `count` file pages still shared with the page cache,
+    /// see `MapArea::release_cached`. return how many were unmapped
+    pub fn release_cached_pages(&mut self, count: usize) -> usize {
+        let mut released = 0;
+        for area in self.areas.iter_mut() {
+            if released >= count {
+                break;
+            }
+            released += area.release_cached(&self.page_table, count - released);
+        }
+        if released > 0 {
+            flush_tlb();
+        }
+        released
+    }
+
+    /// files behind shared mappings overlapping [start, start + len)
+    pub fn shared_inodes(&self, start: usize, len: usize) -> Vec<Arc<dyn Inode>> {
+        self.filter_overlap(start, len)
+            .into_iter()
+            .filter_map(|area| match &area.map_type {
+                MapType::File(file) if file.shared => Some(file.inode.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
     /// allocate physical frame, update pagetable entry, insert frame into area.data_frames
-    /// only used for anonymous
+    /// used for anonymous and file areas
     pub fn manual_alloc_for_lazy(&mut self, vpn: VirtPageNum) -> Result<(), SyscallErr> {
         if let Some(pte) = self.page_table.find_pte(vpn) {
             if pte.ppn() == PhysPageNum::from(0) {
-                for area in self.areas.iter_mut().rev() {
+                for area in self.areas.iter_mut().rev().chain(self.heap.iter_mut()) {
                     if area.vpn_range.contains(vpn) {
-                        let frame = frame_alloc().unwrap();
-                        let ppn = frame.ppn;
-                        info!("[manual_alloc_for_lazy] vpn: {:?}, ppn: {:?}", vpn, ppn);
-                        *pte = PageTableEntry::new(ppn, pte.flags());
-                        area.data_frames.insert(vpn, Arc::new(frame));
+                        area.alloc_lazy(vpn, pte)?;
+                        info!(
+                            "[manual_alloc_for_lazy] vpn: {:?}, ppn: {:?}",
+                            vpn,
+                            pte.ppn()
+                        );
                         return Ok(());
                     }
                 }
@@ -170,17 +304,97 @@ impl MemorySet {
             area.unmap(&mut self.page_table);
         }
     }
-    /// especially used for sys_mmap
-    /// no conflict will happen, because mmap_start is monotonically increasing
-    pub fn get_unmapped_area(&self, start: usize, len: usize) -> Option<VPNRange> {
-        let end = start + len;
-        if end > USER_MAX_VA + 1 {
+    /// whether no area, including the heap, overlaps `range`
+    pub fn is_unmapped(&self, range: VPNRange) -> bool {
+        VirtAddr::from(range.get_end()).0 <= USER_MAX_VA + 1
+            && !self
+                .areas
+                .iter()
+                .chain(self.heap.iter())
+                .any(|area| area.vpn_range.is_overlap(range))
+    }
+    /// number of pages mapped in `range`, including the heap
+    pub fn mapped_pages_in(&self, range: VPNRange) -> usize {
+        self.areas
+            .iter()
+            .chain(self.heap.iter())
+            .filter(|area| area.vpn_range.is_overlap(range))
+            .map(|area| {
+                let start = area.vpn_range.get_start().max(range.get_start());
+                let end = area.vpn_range.get_end().min(range.get_end());
+                end.0 - start.0
+            })
+            .sum()
+    }
+    /// size of the whole user address space in bytes, checked against `RLIMIT_AS`
+    pub fn mapped_size(&self) -> usize {
+        self.areas
+            .iter()
+            .chain(self.heap.iter())
+            .map(|area| area.vpn_range.get_end().0 - area.vpn_range.get_start().0)
+            .sum::<usize>()
+            * PAGE_SIZE
+    }
+    /// especially used for sys_mmap and sys_mremap
+    /// find `len` bytes of free address space, `hint` is tried first if it lies in the mmap area,
+    /// otherwise the lowest gap above `mmap_start` is taken
+    pub fn get_unmapped_area(&self, hint: usize, len: usize) -> Option<VPNRange> {
+        let page_count = len.checked_add(PAGE_SIZE - 1)? / PAGE_SIZE;
+        // hints below the mmap area are ignored, like a NULL hint
+        if hint >= MMAP_MIN_ADDR {
+            let start = VirtAddr::from(hint).floor();
+            let range = VPNRange::new(start, VirtPageNum(start.0 + page_count));
+            if self.is_unmapped(range) {
+                return Some(range);
+            }
+        }
+        let mut used: Vec<VPNRange> = self
+            .areas
+            .iter()
+            .chain(self.heap.iter())
+            .map(|area| area.vpn_range)
+            .collect();
+        used.sort_by_key(|range| range.get_start());
+        let mut start = VirtAddr::from(self.mmap_start).floor();
+        for range in used {
+            if range.get_start().0 >= start.0 + page_count {
+                break;
+            }
+            if range.get_end() > start {
+                start = range.get_end();
+            }
+        }
+        let range = VPNRange::new(start, VirtPageNum(start.0 + page_count));
+        if VirtAddr::from(range.get_end()).0 > USER_MAX_VA + 1 {
             warn!("[sys_mmap] out of mmap virtual memory space");
             return None;
         }
-        let start_vpn = VirtAddr::from(start).floor();
-        let end_vpn = VirtAddr::from(end).ceil();
-        Some(VPNRange::new(start_vpn, end_vpn))
+        Some(range)
+    }
+    /// a fault at `vpn` below the user stack grows the stack down to it,
+    /// as long as the stack stays within `limit` bytes (`RLIMIT_STACK`)
+    /// and at least one unmapped guard page is left below it.
+    /// returns whether the stack has grown
+    pub fn grow_stack(&mut self, vpn: VirtPageNum, limit: usize) -> bool {
+        let top = VirtAddr::from(USER_STACK_TOP).floor();
+        let Some(idx) = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_end() == top)
+        else {
+            return false;
+        };
+        let bottom = self.areas[idx].vpn_range.get_start();
+        if vpn >= bottom || vpn.0 == 0 || (top.0 - vpn.0) * PAGE_SIZE > limit {
+            return false;
+        }
+        // 栈和下面的映射之间至少隔一个guard page
+        if !self.is_unmapped(VPNRange::new(VirtPageNum(vpn.0 - 1), bottom)) {
+            return false;
+        }
+        let area = &mut self.areas[idx];
+        area.expand_down(&mut self.page_table, vpn);
+        true
     }
     /// map_offset says data's offset in the first page
     fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>, map_offset: usize) {
@@ -203,35 +417,135 @@ impl MemorySet {
         });
         overlap_areas
     }
-    /// wipe out the area that is overlapped with [start, start + len)
-    /// remove the overlap areas from Memoryset, **页表映射的清除由调用者负责**
-    /// especially used for do_unmap
-    pub fn wipe_overlap(&mut self, start: usize, len: usize) -> Vec<MapArea> {
-        let end = start + len;
-        let rm_range = VPNRange::new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil());
+    /// especially used for sys_munmap and sys_mmap
+    /// 参数合法性由调用者保证
+    /// areas partially covered by [start, start + len) are split, only the covered part is removed
+    pub fn do_unmap(&mut self, start: usize, len: usize) {
+        let rm_range = VPNRange::new(
+            VirtAddr::from(start).floor(),
+            VirtAddr::from(start + len).ceil(),
+        );
         let mut overlap_areas = Vec::new();
         let mut prev_areas = Vec::new();
-        self.areas.drain(..).for_each(|area| {
-            if area.vpn_range.is_overlap(rm_range) {
-                overlap_areas.push(area);
-            } else {
+        for mut area in core::mem::take(&mut self.areas) {
+            if !area.vpn_range.is_overlap(rm_range) {
                 prev_areas.push(area);
+                continue;
             }
-        });
-        // 更新memory_set.areas
+            // the parts outside of rm_range stay mapped
+            if area.vpn_range.get_start() < rm_range.get_start() {
+                let rest = area.split_off(rm_range.get_start());
+                prev_areas.push(area);
+                area = rest;
+            }
+            if area.vpn_range.get_end() > rm_range.get_end() {
+                prev_areas.push(area.split_off(rm_range.get_end()));
+            }
+            overlap_areas.push(area);
+        }
         self.areas = prev_areas;
-        overlap_areas
-    }
-    /// especially used for sys_munmap and sys_mmap
-    /// 参数合法性由调用者保证
-    pub fn do_unmap(&mut self, start: usize, len: usize) {
-        //warn!("[MemroySet::do_unmap] not fully implemented!");
-        let overlap_areas = self.wipe_overlap(start, len);
+        // writes through shared mappings must not be lost
+        for area in overlap_areas.iter() {
+            area.mark_shared_dirty();
+            if let MapType::Shm(shm) = &area.map_type {
+                shm.dec_nattch();
+            }
+        }
         // 删除overlap_areas在页表中的映射和释放对应的物理页帧
         // Todo: 未检查用户是否有权限删除
         self.remove_areas(overlap_areas);
-        // 一定要刷表
-        self.activate();
+        // 一定要刷表, 其他hart上可能有同一进程的线程
+        flush_tlb();
+    }
+    /// split the area holding `range` so that `range` becomes an area of its own,
+    /// returns its index in `self.areas`
+    fn isolate_area(&mut self, range: VPNRange) -> Option<usize> {
+        let idx = self.areas.iter().position(|area| {
+            area.vpn_range.contains(range.get_start())
+                && range.get_end() <= area.vpn_range.get_end()
+        })?;
+        if self.areas[idx].vpn_range.get_end() > range.get_end() {
+            let upper = self.areas[idx].split_off(range.get_end());
+            self.areas.push(upper);
+        }
+        if self.areas[idx].vpn_range.get_start() < range.get_start() {
+            let upper = self.areas[idx].split_off(range.get_start());
+            self.areas.push(upper);
+            return Some(self.areas.len() - 1);
+        }
+        Some(idx)
+    }
+    /// especially used for sys_mremap
+    /// resize `old_range`, which must lie in a single area, to `new_pages` pages.
+    /// grow in place if possible, otherwise move it when `may_move`.
+    /// `fixed` forces the new start, whatever is mapped there is unmapped.
+    pub fn do_mremap(
+        &mut self,
+        old_range: VPNRange,
+        new_pages: usize,
+        may_move: bool,
+        fixed: Option<VirtPageNum>,
+    ) -> Result<VirtPageNum, SyscallErr> {
+        let old_start = old_range.get_start();
+        let old_pages = old_range.get_end().0 - old_start.0;
+        if let Some(new_start) = fixed {
+            let new_range = VPNRange::new(new_start, VirtPageNum(new_start.0 + new_pages));
+            if new_range.is_overlap(old_range) {
+                return Err(SyscallErr::EINVAL);
+            }
+            if VirtAddr::from(new_range.get_end()).0 > USER_MAX_VA + 1 {
+                return Err(SyscallErr::ENOMEM);
+            }
+            self.do_unmap(VirtAddr::from(new_start).0, new_pages * PAGE_SIZE);
+        }
+        let mut idx = self.isolate_area(old_range).ok_or(SyscallErr::EFAULT)?;
+        // a shm segment has a fixed size
+        if new_pages > old_pages && matches!(self.areas[idx].map_type, MapType::Shm(_)) {
+            return Err(SyscallErr::EINVAL);
+        }
+        if new_pages < old_pages {
+            let new_end = VirtPageNum(old_start.0 + new_pages);
+            self.do_unmap(
+                VirtAddr::from(new_end).0,
+                (old_pages - new_pages) * PAGE_SIZE,
+            );
+            if fixed.is_none() {
+                return Ok(old_start);
+            }
+            idx = self
+                .isolate_area(VPNRange::new(old_start, new_end))
+                .ok_or(SyscallErr::EFAULT)?;
+        }
+        let new_start = match fixed {
+            Some(new_start) => new_start,
+            None => {
+                let grow_range =
+                    VPNRange::new(old_range.get_end(), VirtPageNum(old_start.0 + new_pages));
+                if new_pages == old_pages || self.is_unmapped(grow_range) {
+                    // grow in place
+                    if new_pages > old_pages {
+                        self.areas[idx].expand(&mut self.page_table, grow_range.get_end());
+                        flush_tlb();
+                    }
+                    return Ok(old_start);
+                }
+                if !may_move {
+                    return Err(SyscallErr::ENOMEM);
+                }
+                self.get_unmapped_area(0, new_pages * PAGE_SIZE)
+                    .ok_or(SyscallErr::ENOMEM)?
+                    .get_start()
+            }
+        };
+        let mut area = self.areas.remove(idx);
+        area.relocate(&mut self.page_table, new_start);
+        let new_end = VirtPageNum(new_start.0 + new_pages);
+        if area.vpn_range.get_end() < new_end {
+            area.expand(&mut self.page_table, new_end);
+        }
+        self.areas.push(area);
+        flush_tlb();
+        Ok(new_start)
     }
     /// especially used for sys_mprotect
     /// change the protection on **pages**, 不修改`MapArea.map_perm`的权限
@@ -252,8 +566,21 @@ impl MemorySet {
                     //     return Err(SyscallErr::EACCES.into());
                     // }
                     // remap
-                    let pte_flags = PTEFlags::from_bits(perm.bits).unwrap();
-                    self.page_table.update_flags(vpn, pte_flags);
+                    let mut pte_flags = PTEFlags::from_bits(perm.bits).unwrap();
+                    // 私有页的物理页帧仍与page cache或fork出的进程共享时不能直接可写,
+                    // 只记下想要的写权限(COW), 写缺页时再复制出私有的页帧
+                    let frame_shared = area
+                        .data_frames
+                        .get(&vpn)
+                        .map_or(false, |frame| Arc::strong_count(frame) > 1);
+                    let pte = self.page_table.find_pte(vpn).unwrap();
+                    let mut flags = pte.flags();
+                    flags.remove(PTEFlags::R | PTEFlags::W | PTEFlags::X | PTEFlags::COW);
+                    if pte_flags.contains(PTEFlags::W) && !area.is_shared() && frame_shared {
+                        pte_flags.remove(PTEFlags::W);
+                        pte_flags.insert(PTEFlags::COW);
+                    }
+                    *pte = PageTableEntry::new(pte.ppn(), flags | pte_flags);
                     found = true;
                     break;
                 }
@@ -262,6 +589,7 @@ impl MemorySet {
                 return Err(SyscallErr::EFAULT.into());
             }
         }
+        flush_tlb();
         Ok(0)
     }
     /// map sigreturn trampoline
@@ -376,7 +704,13 @@ impl MemorySet {
     }
     /// Include sections in elf and user stack,
     /// returns (memory_set, user_sp, entry_point, aux_vec).
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize, Vec<AuxHeader>) {
+    /// Read-only segments are mapped from the page cache of `elf_inode` if given.
+    /// The user stack is at most `stack_limit` bytes (`RLIMIT_STACK`).
+    pub fn from_elf(
+        elf_data: &[u8],
+        elf_inode: Option<Arc<dyn Inode>>,
+        stack_limit: usize,
+    ) -> (Self, usize, usize, Vec<AuxHeader>) {
         let mut memory_set = Self::new_from_global();
 
         // map program headers of elf, with U flag
@@ -471,31 +805,7 @@ impl MemorySet {
                 if header_va.is_none() {
                     header_va = Some(start_va.into());
                 }
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
-
-                let mut map_perm = MapPermission::U;
-                let ph_flags = ph.flags();
-                if ph_flags.is_read() {
-                    map_perm |= MapPermission::R;
-                }
-                if ph_flags.is_write() {
-                    map_perm |= MapPermission::W;
-                }
-                if ph_flags.is_execute() {
-                    map_perm |= MapPermission::X;
-                }
-
-                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
-                max_end_vpn = map_area.vpn_range.get_end();
-
-                // BUGGY: map_offset is not considered. Elf section is NOT aligned to PAGE_SIZE
-                let map_offset = start_va.0 - start_va.floor().0 * PAGE_SIZE;
-
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                    map_offset,
-                );
+                max_end_vpn = memory_set.map_elf_segment(&elf, &ph, 0, elf_inode.as_ref());
             }
         }
 
@@ -511,11 +821,12 @@ impl MemorySet {
         });
 
         // map user stack with U flags
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_bottom: usize = max_end_va.into();
-        // guard page
-        user_stack_bottom += PAGE_SIZE;
-        let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
+        // map user stack with U flags
+        // it is mapped lazily and grows down on demand, see `grow_stack`
+        let user_stack_size =
+            (stack_limit & !(PAGE_SIZE - 1)).clamp(MIN_USER_STACK_SIZE, USER_STACK_SIZE);
+        let user_stack_top = USER_STACK_TOP;
+        let user_stack_bottom = user_stack_top - user_stack_size;
         info!(
             "[MemorySet::from_elf] user stack [{:#x}, {:#x})",
             user_stack_bottom, user_stack_top
@@ -524,22 +835,32 @@ impl MemorySet {
             MapArea::new(
                 user_stack_bottom.into(),
                 user_stack_top.into(),
-                MapType::Framed,
+                MapType::Anonymous,
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
             0,
         );
-        // map heap with U flags
+        // 参数和环境变量由内核直接写到栈顶, 这部分先分配好
+        let init_stack_range = VPNRange::new(
+            VirtAddr::from(user_stack_top - MIN_USER_STACK_SIZE).floor(),
+            VirtAddr::from(user_stack_top).floor(),
+        );
+        for vpn in init_stack_range {
+            assert!(memory_set.manual_alloc_for_lazy(vpn).is_ok());
+        }
+        // map heap with U flags, right after the elf
         // add guard page
-        let heap_bottom = user_stack_top + PAGE_SIZE;
+        let max_end_va: VirtAddr = max_end_vpn.into();
+        let heap_bottom = max_end_va.0 + PAGE_SIZE;
         let heap_top = heap_bottom;
         memory_set.brk = heap_top;
         // info!("user space heap_top: {:#x}", heap_top);
+        // brk only reserves the pages, frames are allocated on first touch
         let mut heap_area = MapArea::new(
             heap_bottom.into(),
             heap_top.into(),
-            MapType::Framed,
+            MapType::Anonymous,
             MapPermission::R | MapPermission::W | MapPermission::U,
         );
         info!(
@@ -557,6 +878,12 @@ impl MemorySet {
     pub fn from_existed_user_lazily(user_space: &MemorySet) -> MemorySet {
         let page_table = PageTable::from_existed_user(&user_space.page_table);
         let areas = user_space.areas.clone();
+        // 子进程继承父进程attach的共享内存
+        for area in areas.iter() {
+            if let MapType::Shm(shm) = &area.map_type {
+                shm.inc_nattch();
+            }
+        }
         let heap = user_space.heap.clone();
         let brk = user_space.brk;
         MemorySet {
@@ -625,6 +952,7 @@ impl MemorySet {
     ///Remove all `MapArea`, 注意还有heap
     pub fn recycle_data_pages(&mut self) {
         //*self = Self::new_bare();
+        self.detach_all_shm();
         self.areas.clear();
         self.heap = None;
     }
@@ -676,13 +1004,13 @@ impl MemorySet {
 
             // - 打开并读取动态链接器文件内容。
             let interp_osinode =
-                OSInode::new(true, false, interp_inode).expect("failed to open interp");
+                OSInode::new(true, false, interp_inode.clone()).expect("failed to open interp");
             let interp_elf_data = block_on(interp_osinode.read_all());
 
             // - 将动态链接器的ELF文件映射到内存中。
             let interp_elf = ElfFile::new(&interp_elf_data).unwrap();
 
-            self.map_elf(&interp_elf, DL_INTERP_OFFSET.into());
+            self.map_elf(&interp_elf, DL_INTERP_OFFSET.into(), Some(&interp_inode));
 
             // - 返回动态链接器的入口点地址。
             Some(interp_elf.header.pt2.entry_point() as usize + DL_INTERP_OFFSET)
@@ -692,7 +1020,12 @@ impl MemorySet {
         }
     }
 
-    fn map_elf(&mut self, elf: &ElfFile, offset: VirtAddr) -> (VirtPageNum, VirtAddr) {
+    fn map_elf(
+        &mut self,
+        elf: &ElfFile,
+        offset: VirtAddr,
+        elf_inode: Option<&Arc<dyn Inode>>,
+    ) -> (VirtPageNum, VirtAddr) {
         // 获取ELF文件的头部信息和程序头的数量。
         let elf_header = elf.header;
         let ph_count = elf_header.pt2.ph_count();
@@ -712,49 +1045,80 @@ impl MemorySet {
 
             if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
                 let start_va: VirtAddr = (ph.virtual_addr() as usize + offset.0).into();
-                let end_va: VirtAddr =
-                    ((ph.virtual_addr() + ph.mem_size()) as usize + offset.0).into();
 
                 if !has_found_header_va {
                     header_va = start_va.0;
                     has_found_header_va = true;
                 }
 
-                let ph_flags = ph.flags();
-                let mut map_perm = MapPermission::U;
-                if ph_flags.is_read() {
-                    map_perm |= MapPermission::R;
-                }
-                if ph_flags.is_write() {
-                    map_perm |= MapPermission::W;
-                }
-                if ph_flags.is_execute() {
-                    map_perm |= MapPermission::X;
-                }
+                max_end_vpn = self.map_elf_segment(elf, &ph, offset.0, elf_inode);
+            }
+        }
+        // 返回最大结束虚拟页号和头部虚拟地址。
+        (max_end_vpn, header_va.into())
+    }
 
-                let vm_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
-                max_end_vpn = vm_area.vpn_range.get_end();
+    /// map a PT_LOAD segment at `bias`, returns the end vpn of the segment.
+    /// Read-only segments whose file offset is aligned like their address are
+    /// mapped privately from the page cache of `elf_inode`, the rest are copied.
+    fn map_elf_segment(
+        &mut self,
+        elf: &ElfFile,
+        ph: &ProgramHeader,
+        bias: usize,
+        elf_inode: Option<&Arc<dyn Inode>>,
+    ) -> VirtPageNum {
+        let start_va: VirtAddr = (ph.virtual_addr() as usize + bias).into();
+        let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize + bias).into();
 
-                let map_offset = start_va.0 - start_va.floor().0 * PAGE_SIZE;
+        let mut map_perm = MapPermission::U;
+        let ph_flags = ph.flags();
+        if ph_flags.is_read() {
+            map_perm |= MapPermission::R;
+        }
+        if ph_flags.is_write() {
+            map_perm |= MapPermission::W;
+        }
+        if ph_flags.is_execute() {
+            map_perm |= MapPermission::X;
+        }
 
-                debug!(
-                    "[map_elf] ph offset {:#x}, file size {:#x}, mem size {:#x}",
-                    ph.offset(),
-                    ph.file_size(),
-                    ph.mem_size()
-                );
-                debug!("[dynamic_link_map_elf] map_offset is {:#x}", map_offset);
-                self.areas.push(vm_area.clone());
+        // data's offset in the first page
+        let map_offset = start_va.0 - start_va.floor().0 * PAGE_SIZE;
+        let ph_offset = ph.offset() as usize;
+        let file_size = ph.file_size() as usize;
+        debug!(
+            "[map_elf_segment] ph offset {:#x}, file size {:#x}, mem size {:#x}, map_offset {:#x}",
+            ph_offset,
+            file_size,
+            ph.mem_size(),
+            map_offset
+        );
 
+        match elf_inode {
+            Some(inode) if !ph_flags.is_write() && ph_offset % PAGE_SIZE == map_offset => {
+                let map_type = MapType::File(FileMapping {
+                    inode: inode.clone(),
+                    offset: ph_offset - map_offset,
+                    len: map_offset + file_size,
+                    shared: false,
+                });
+                let map_area = MapArea::new(start_va, end_va, map_type, map_perm);
+                let end_vpn = map_area.vpn_range.get_end();
+                self.push(map_area, None, 0);
+                end_vpn
+            }
+            _ => {
+                let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+                let end_vpn = map_area.vpn_range.get_end();
                 self.push(
-                    vm_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
+                    map_area,
+                    Some(&elf.input[ph_offset..ph_offset + file_size]),
                     map_offset,
                 );
+                end_vpn
             }
         }
-        // 返回最大结束虚拟页号和头部虚拟地址。
-        (max_end_vpn, header_va.into())
     }
 }
 
@@ -830,7 +1194,7 @@ impl MapArea {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
             data_frames: BTreeMap::new(),
-            map_type: another.map_type,
+            map_type: another.map_type.clone(),
             map_perm: another.map_perm,
         }
     }
@@ -852,14 +1216,62 @@ impl MapArea {
         }
         self.vpn_range.update_end(new_end);
     }
+    /// especially used for the user stack, which grows down
+    pub fn expand_down(&mut self, page_table: &mut PageTable, new_start: VirtPageNum) {
+        let old_start = self.vpn_range.get_start();
+        assert!(new_start < old_start);
+        for vpn in VPNRange::new(new_start, old_start) {
+            self.map_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(new_start, self.vpn_range.get_end());
+    }
+    /// split the area at `at`, self keeps [start, at) and [at, end) is returned.
+    /// the page table is untouched
+    pub fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        assert!(self.vpn_range.get_start() < at && at < self.vpn_range.get_end());
+        let offset = (at.0 - self.vpn_range.get_start().0) * PAGE_SIZE;
+        let mut map_type = self.map_type.clone();
+        match &mut map_type {
+            MapType::File(file) => {
+                file.offset += offset;
+                file.len = file.len.saturating_sub(offset);
+            }
+            MapType::Shm(shm) => shm.inc_nattch(),
+            _ => {}
+        }
+        let upper = Self {
+            vpn_range: VPNRange::new(at, self.vpn_range.get_end()),
+            data_frames: self.data_frames.split_off(&at),
+            map_type,
+            map_perm: self.map_perm,
+        };
+        self.vpn_range.update_end(at);
+        upper
+    }
+    /// move the area to `new_start`, keeping its frames and pte flags.
+    /// used for sys_mremap, the new range must be unmapped
+    pub fn relocate(&mut self, page_table: &mut PageTable, new_start: VirtPageNum) {
+        let old_start = self.vpn_range.get_start();
+        let to_new = |vpn: VirtPageNum| VirtPageNum(vpn.0 - old_start.0 + new_start.0);
+        for vpn in self.vpn_range {
+            let pte = page_table.translate(vpn).unwrap();
+            page_table.unmap(vpn);
+            page_table.map(to_new(vpn), pte.ppn(), pte.flags());
+        }
+        self.data_frames = core::mem::take(&mut self.data_frames)
+            .into_iter()
+            .map(|(vpn, frame)| (to_new(vpn), frame))
+            .collect();
+        self.vpn_range = VPNRange::new(new_start, to_new(self.vpn_range.get_end()));
+    }
     // 在页表中添加映射关系
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         let ppn: PhysPageNum;
-        match self.map_type {
+        match &self.map_type {
             MapType::Linear => {
                 ppn = PhysPageNum(vpn.0 - 0x4000000);
             }
-            MapType::Framed => {
+            MapType::Framed | MapType::Shared => {
                 let frame = frame_alloc().unwrap();
                 ppn = frame.ppn;
                 self.data_frames.insert(vpn, Arc::new(frame));
@@ -868,16 +1280,108 @@ impl MapArea {
                 // map Anonymous area to physical address 0
                 ppn = PhysPageNum::from(0)
             }
+            MapType::Shm(shm) => {
+                let frame = shm.frames[vpn.0 - self.vpn_range.get_start().0].clone();
+                ppn = frame.ppn;
+                self.data_frames.insert(vpn, frame);
+            }
+            MapType::File(_) => {
+                // filled from the page cache on first touch, see `alloc_lazy`
+                ppn = PhysPageNum::from(0)
+            }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         page_table.map(vpn, ppn, pte_flags);
     }
+    /// back the lazily mapped `vpn`, whose pte points to ppn 0, with a frame
+    pub fn alloc_lazy(
+        &mut self,
+        vpn: VirtPageNum,
+        pte: &mut PageTableEntry,
+    ) -> Result<(), SyscallErr> {
+        let (frame, flags) = match &self.map_type {
+            MapType::File(file) => {
+                // follow mprotect, and keep the COW of a page forked before its first touch
+                let mut perm = MapPermission::from_bits_truncate(pte.flags().bits());
+                if pte.is_cow() {
+                    perm |= MapPermission::W;
+                }
+                let page_index = vpn.0 - self.vpn_range.get_start().0;
+                let (frame, flags) = file.frame_for(page_index, perm)?;
+                (frame, flags | PTEFlags::V | PTEFlags::A | PTEFlags::D)
+            }
+            _ => (
+                Arc::new(frame_alloc().ok_or(SyscallErr::ENOMEM)?),
+                pte.flags(),
+            ),
+        };
+        *pte = PageTableEntry::new(frame.ppn, flags);
+        self.data_frames.insert(vpn, frame);
+        Ok(())
+    }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
-        }
+        // lazily allocated frames of anonymous areas live in data_frames as well
+        self.data_frames.remove(&vpn);
         page_table.unmap(vpn);
     }
+    /// MAP_SHARED area, whose writes must not be copied on write
+    pub fn is_shared(&self) -> bool {
+        match &self.map_type {
+            MapType::Shared | MapType::Shm(_) => true,
+            MapType::File(file) => file.shared,
+            _ => false,
+        }
+    }
+    /// unmap up to `count` pages whose frame still belongs to the page cache, so that
+    /// the cache can drop them. Their ptes go back to ppn 0 and are filled again on
+    /// the next touch. Pages of shared writable mappings may have been written through
+    /// the mapping, they are marked dirty. The caller flushes the TLB
+    fn release_cached(&mut self, page_table: &PageTable, count: usize) -> usize {
+        let file = match &self.map_type {
+            MapType::File(file) => file,
+            _ => return 0,
+        };
+        let page_cache = match file.inode.page_cache() {
+            Some(page_cache) => page_cache,
+            None => return 0,
+        };
+        let start = self.vpn_range.get_start();
+        let file_page = |vpn: VirtPageNum| file.offset / PAGE_SIZE + vpn.0 - start.0;
+        // private pages already written have a copy of their own
+        let victims: Vec<VirtPageNum> = self
+            .data_frames
+            .iter()
+            .filter(|(vpn, frame)| page_cache.holds(file_page(**vpn), frame))
+            .map(|(vpn, _)| *vpn)
+            .take(count)
+            .collect();
+        let dirty = file.shared && self.map_perm.contains(MapPermission::W);
+        for vpn in victims.iter() {
+            if dirty {
+                page_cache.mark_page_dirty(&file.inode, file_page(*vpn));
+            }
+            self.data_frames.remove(vpn);
+            if let Some(pte) = page_table.find_pte(*vpn) {
+                *pte = PageTableEntry::new(PhysPageNum::from(0), pte.flags());
+            }
+        }
+        victims.len()
+    }
+    /// shared writable file pages may have been modified through the mapping,
+    /// make sure they are written back on next sync
+    pub fn mark_shared_dirty(&self) {
+        if let MapType::File(file) = &self.map_type {
+            if !file.shared || !self.map_perm.contains(MapPermission::W) {
+                return;
+            }
+            if let Some(page_cache) = file.inode.page_cache() {
+                for vpn in self.vpn_range {
+                    let page_index = vpn.0 - self.vpn_range.get_start().0;
+                    page_cache.mark_page_dirty(&file.inode, file.offset / PAGE_SIZE + page_index);
+                }
+            }
+        }
+    }
     pub fn map(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
             // if vpn.0 & 0x4000000 == 0 {
@@ -894,7 +1398,7 @@ impl MapArea {
     /// data: with offset and maybe with shorter length, quite flexible
     /// assume that all frames were cleared before
     pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8], offset: usize) {
-        assert_eq!(self.map_type, MapType::Framed);
+        assert!(matches!(self.map_type, MapType::Framed));
         let mut start: usize = 0;
         let mut current_vpn = self.vpn_range.get_start();
         let len = data.len();
@@ -928,12 +1432,82 @@ impl MapArea {
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Clone)]
 /// map type for memory set: identical or framed
 pub enum MapType {
     Linear,
     Framed,
     Anonymous,
+    /// MAP_SHARED anonymous memory, allocated eagerly so that fork shares the frames
+    Shared,
+    /// System V shared memory, the frames belong to the segment
+    Shm(Arc<SharedMemory>),
+    /// backed by the page cache of a file
+    File(FileMapping),
+}
+
+/// file region behind a `MapType::File` area
+#[derive(Clone)]
+pub struct FileMapping {
+    pub inode: Arc<dyn Inode>,
+    /// file offset of the first page of the area, page aligned
+    pub offset: usize,
+    /// bytes of the file mapped from the start of the area, the rest reads as zero
+    pub len: usize,
+    /// MAP_SHARED: writes go to the page cache instead of a private copy
+    pub shared: bool,
+}
+
+impl FileMapping {
+    /// frame and pte flags to map the `page_index`th page of the area with
+    /// ENOMEM when out of frames, EIO when the page can't be read from the file
+    fn frame_for(
+        &self,
+        page_index: usize,
+        perm: MapPermission,
+    ) -> Result<(Arc<FrameTracker>, PTEFlags), SyscallErr> {
+        let mut flags = PTEFlags::from_bits(perm.bits).unwrap();
+        let file_page = self.offset / PAGE_SIZE + page_index;
+        let valid = core::cmp::min(self.len.saturating_sub(page_index * PAGE_SIZE), PAGE_SIZE);
+        let page_cache = match self.inode.page_cache() {
+            Some(page_cache) => page_cache,
+            None => {
+                // content can't be shared, read a private copy
+                let frame = frame_alloc().ok_or(SyscallErr::ENOMEM)?;
+                block_on(self.inode.read(
+                    file_page * PAGE_SIZE,
+                    &mut frame.ppn.get_bytes_array()[..valid],
+                ))
+                .map_err(|_| SyscallErr::EIO)?;
+                return Ok((Arc::new(frame), flags));
+            }
+        };
+        let page = block_on(page_cache.get_page(&self.inode, file_page)).map_err(|err| {
+            if err == SyscallErr::ENOMEM as usize {
+                SyscallErr::ENOMEM
+            } else {
+                SyscallErr::EIO
+            }
+        })?;
+        if self.shared {
+            if perm.contains(MapPermission::W) {
+                page_cache.mark_page_dirty(&self.inode, file_page);
+            }
+            Ok((page.frame.clone(), flags))
+        } else if valid == PAGE_SIZE {
+            // private: share the cached frame until the first write
+            if flags.contains(PTEFlags::W) {
+                flags.remove(PTEFlags::W);
+                flags.insert(PTEFlags::COW);
+            }
+            Ok((page.frame.clone(), flags))
+        } else {
+            // partial page, the part beyond the mapped file range must be zero
+            let frame = frame_alloc().ok_or(SyscallErr::ENOMEM)?;
+            frame.ppn.get_bytes_array()[..valid].copy_from_slice(&page.bytes()[..valid]);
+            Ok((Arc::new(frame), flags))
+        }
+    }
 }
 
 bitflags! {
@@ -14,11 +14,13 @@ mod page_table;
 pub mod shm;
 pub mod user_check;
 
-use address::VPNRange;
+pub use address::VPNRange;
 pub use address::{KernelAddr, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum};
 pub use frame_allocator::{frame_alloc, frame_dealloc, FrameTracker};
 pub use memory_set::{dump_test, from_global_test, remap_test};
-pub use memory_set::{kernel_token, MapPermission, MemorySet, KERNEL_SPACE};
+pub use memory_set::{
+    flush_tlb, kernel_token, FileMapping, MapPermission, MemorySet, KERNEL_SPACE,
+};
 pub use page_fault::handle_recoverable_page_fault;
 pub use page_table::PTEFlags;
 pub use page_table::{
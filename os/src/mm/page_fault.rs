@@ -1,36 +1,62 @@
+use crate::fs::page_cache::{self, RECLAIM_BATCH};
 use crate::mm::address::VirtPageNum;
 use crate::mm::frame_allocator::frame_alloc;
 use crate::mm::page_table::{PTEFlags, PageTable, PageTableEntry};
-use crate::mm::PhysPageNum;
+use crate::mm::{flush_tlb, MemorySet, PhysPageNum};
+use crate::syscall::resource::RLIMIT_STACK;
 // use crate::syscall::process;
 // use crate::syscall::process;
+use crate::task::all_processes;
 use crate::task::processor::current_process;
 // use crate::task::current_task;
 // use crate::task::processor::current_process;
-use crate::utils::SyscallErr;
+use crate::utils::{block_on::block_on, SyscallErr};
 use alloc::sync::Arc;
-use log::error;
+use log::{error, warn};
+use riscv::register::scause::{Exception, Trap};
 
 /// call this function only when scause.cause() == Exception::LoadPageFault || Exception::StorePageFault
+/// (or the access/instruction faults raised by lazily mapped pages)
 /// 1. fork COW area
-/// 2. lazy allocation
+/// 2. lazy allocation, including file backed areas
+/// 3. stale TLB entry: another hart already fixed the pte
+/// 4. user stack growth
 pub fn handle_recoverable_page_fault(
     page_table: &PageTable,
     vpn: VirtPageNum,
+    cause: Trap,
 ) -> Result<(), SyscallErr> {
+    if !page_table.find_pte(vpn).map_or(false, |pte| pte.is_valid()) {
+        // 栈下面的地址, 扩展栈后按lazy allocation处理
+        let process = current_process();
+        let mut inner = process.inner_lock();
+        let limit = inner.rlimits.get(RLIMIT_STACK).rlim_cur;
+        inner.memory_set.grow_stack(vpn, limit);
+    }
     if let Some(pte) = page_table.find_pte(vpn) {
-        if vpn == VirtPageNum::from(0) {
-            // alloc for thread local variable
-            // TODO: temp alloc physical page for vpn: ppn = 0: 0
-            let frame = frame_alloc().unwrap();
-            let ppn = frame.ppn;
-            // let flags = PTEFlags::V | PTEFlags::U | PTEFlags::W | PTEFlags::R;
-            let flags =
-                PTEFlags::V | PTEFlags::U | PTEFlags::W | PTEFlags::R | PTEFlags::A | PTEFlags::D;
-            *pte = PageTableEntry::new(ppn, flags);
-            return Ok(());
+        // 同一进程的其他hart已经处理过这个缺页, 本hart的TLB中还是旧的表项
+        if pte.ppn() != PhysPageNum::from(0) && !pte.is_cow() {
+            let permitted = match cause {
+                Trap::Exception(Exception::LoadPageFault)
+                | Trap::Exception(Exception::LoadFault) => pte.readable(),
+                Trap::Exception(Exception::StorePageFault)
+                | Trap::Exception(Exception::StoreFault) => pte.writable(),
+                Trap::Exception(Exception::InstructionPageFault)
+                | Trap::Exception(Exception::InstructionFault) => pte.executable(),
+                _ => false,
+            };
+            if permitted {
+                unsafe {
+                    core::arch::asm!(
+                        "sfence.vma x0, x0",
+                        options(nomem, nostack, preserves_flags)
+                    );
+                }
+                return Ok(());
+            }
         }
-        if pte.is_cow() {
+        // a COW page forked before its first touch is still lazy
+        if pte.is_cow() && pte.ppn() != PhysPageNum::from(0) {
             // fork COW area
             // 如果refcnt == 1, 则直接修改pte, 否则, 分配新的frame, 修改pte, 更新MemorySet
             // debug!("handle cow page fault(cow), vpn {:#x}", vpn.0);
@@ -43,7 +69,8 @@ pub fn handle_recoverable_page_fault(
                 if area.vpn_range.contains(vpn) {
                     // 根据VPN找到对应的data_frame, 并查看Arc的引用计数
                     let data_frame = area.data_frames.get(&vpn).unwrap();
-                    if Arc::strong_count(data_frame) == 1 {
+                    // MAP_SHARED pages are shared on purpose, never copy them
+                    if area.is_shared() || Arc::strong_count(data_frame) == 1 {
                         // 直接修改pte
                         // clear COW bit and set valid bit
                         // debug!("ref_cnt = 1");
@@ -54,7 +81,7 @@ pub fn handle_recoverable_page_fault(
                         // return Ok(());
                     } else {
                         // 分配新的frame, 修改pte, 更新MemorySet
-                        let frame = frame_alloc().unwrap();
+                        let frame = frame_alloc().ok_or(SyscallErr::ENOMEM)?;
                         let src_frame = pte.ppn().get_bytes_array();
                         let dst_frame = frame.ppn.get_bytes_array();
                         dst_frame.copy_from_slice(src_frame);
@@ -67,13 +94,8 @@ pub fn handle_recoverable_page_fault(
                         area.data_frames.insert(vpn, Arc::new(frame));
                         // return Ok(());
                     }
-                    // todo?: flush TLB
-                    unsafe {
-                        core::arch::asm!(
-                            "sfence.vma x0, x0",
-                            options(nomem, nostack, preserves_flags)
-                        );
-                    }
+                    // 其他hart可能缓存了旧的只读表项
+                    flush_tlb();
                     return Ok(());
                 }
             }
@@ -91,7 +113,7 @@ pub fn handle_recoverable_page_fault(
                     // return Ok(());
                 } else {
                     // 分配新的frame, 修改pte, 更新MemorySet
-                    let frame = frame_alloc().unwrap();
+                    let frame = frame_alloc().ok_or(SyscallErr::ENOMEM)?;
                     let src_frame = pte.ppn().get_bytes_array();
                     let dst_frame = frame.ppn.get_bytes_array();
                     dst_frame.copy_from_slice(src_frame);
@@ -104,12 +126,7 @@ pub fn handle_recoverable_page_fault(
                     heap.data_frames.insert(vpn, Arc::new(frame));
                     // return Ok(());
                 }
-                unsafe {
-                    core::arch::asm!(
-                        "sfence.vma x0, x0",
-                        options(nomem, nostack, preserves_flags)
-                    );
-                }
+                flush_tlb();
                 return Ok(());
             }
             log::info!("cow page fault recover failed");
@@ -117,7 +134,7 @@ pub fn handle_recoverable_page_fault(
         }
         // COW_handle_END
         else {
-            // lazy allocation: mmap region
+            // lazy allocation: mmap region and file backed area
             // log::debug!(
             //     "[handle_lazy_allocation_page_fault] lazy alloc, vpn: {:#x}",
             //     vpn.0
@@ -127,19 +144,36 @@ pub fn handle_recoverable_page_fault(
                 let process = current_process();
                 let memory_set = &mut process.inner_lock().memory_set;
                 // 分配物理页帧, 更新页表, 管理MapArea::data_frames
-                for area in memory_set.areas.iter_mut().rev() {
-                    if area.vpn_range.contains(vpn) {
-                        let frame = frame_alloc().unwrap();
-                        let ppn = frame.ppn;
-                        *pte = PageTableEntry::new(ppn, pte.flags());
-                        area.data_frames.insert(vpn, Arc::new(frame));
-                        unsafe {
-                            core::arch::asm!(
-                                "sfence.vma x0, x0",
-                                options(nomem, nostack, preserves_flags)
-                            );
+                let mut reclaimed = false;
+                loop {
+                    let area = match memory_set
+                        .areas
+                        .iter_mut()
+                        .rev()
+                        .chain(memory_set.heap.iter_mut())
+                        .find(|area| area.vpn_range.contains(vpn))
+                    {
+                        Some(area) => area,
+                        None => break,
+                    };
+                    match area.alloc_lazy(vpn, pte) {
+                        Ok(()) => {
+                            unsafe {
+                                core::arch::asm!(
+                                    "sfence.vma x0, x0",
+                                    options(nomem, nostack, preserves_flags)
+                                );
+                            }
+                            return Ok(());
+                        }
+                        // 内存不足时回收映射到用户空间的文件页, 再试一次
+                        Err(SyscallErr::ENOMEM) if !reclaimed => {
+                            reclaimed = true;
+                            if !reclaim_mapped_file_pages(memory_set) {
+                                return Err(SyscallErr::ENOMEM);
+                            }
                         }
-                        return Ok(());
+                        Err(err) => return Err(err),
                     }
                 }
             }
@@ -151,3 +185,27 @@ pub fn handle_recoverable_page_fault(
         return Err(SyscallErr::EFAULT);
     }
 }
+
+/// out of frames: take file pages back from the address spaces, write back
+/// the dirty ones and drop them from the page cache.
+/// `memory_set` belongs to the current process, whose lock the caller holds.
+/// return whether any frame was freed
+fn reclaim_mapped_file_pages(memory_set: &mut MemorySet) -> bool {
+    let mut released = memory_set.release_cached_pages(RECLAIM_BATCH);
+    for process in all_processes() {
+        if released >= RECLAIM_BATCH {
+            break;
+        }
+        // 当前进程已经加锁, 其他正被持有的进程也跳过
+        if let Some(mut inner) = process.inner.try_lock() {
+            released += inner
+                .memory_set
+                .release_cached_pages(RECLAIM_BATCH - released);
+        }
+    }
+    // pages of shared writable mappings were marked dirty
+    if let Err(err) = block_on(page_cache::sync_all()) {
+        warn!("[reclaim_mapped_file_pages] sync failed: {}", err);
+    }
+    page_cache::reclaim(RECLAIM_BATCH) > 0
+}
@@ -1,13 +1,127 @@
-use crate::config::{SyscallRet, PAGE_SIZE};
-use crate::mm::VirtAddr;
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, FrameTracker};
 use crate::mutex::SpinNoIrqLock;
-// use crate::task::processor::current_process;
 use crate::task::RecycleAllocator;
+use crate::timer::current_time_spec;
 use crate::utils::SyscallErr;
 use alloc::collections::BTreeMap;
-use alloc::sync::Weak;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
-use riscv::addr::Page;
+
+/// 新建私有共享内存的key
+pub const IPC_PRIVATE: usize = 0;
+
+bitflags! {
+    /// shmflg of `sys_shmget` and `sys_shmat`, the low 9 bits are the access mode
+    pub struct ShmFlags: u32 {
+        /// 不存在时创建
+        const IPC_CREAT = 0o1000;
+        /// 与IPC_CREAT一起使用, 已存在时失败
+        const IPC_EXCL = 0o2000;
+        /// 只读attach
+        const SHM_RDONLY = 0o10000;
+        /// attach地址向下对齐到SHMLBA
+        const SHM_RND = 0o20000;
+    }
+}
+
+/// `struct ipc64_perm`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct IpcPerm {
+    pub key: i32,
+    pub uid: u32,
+    pub gid: u32,
+    pub cuid: u32,
+    pub cgid: u32,
+    pub mode: u32,
+    pub seq: u16,
+    _pad: u16,
+    _unused: [usize; 2],
+}
+
+/// `struct shmid64_ds`, used by `IPC_STAT` and `IPC_SET`
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct ShmIdDs {
+    pub shm_perm: IpcPerm,
+    pub shm_segsz: usize,
+    pub shm_atime: isize,
+    pub shm_dtime: isize,
+    pub shm_ctime: isize,
+    pub shm_cpid: i32,
+    pub shm_lpid: i32,
+    pub shm_nattch: usize,
+    _unused: [usize; 2],
+}
+
+/// 共享内存段
+///
+/// 每个attach它的`MapArea`都持有一个`Arc<SharedMemory>`, 物理页帧在最后一个引用消失时释放.
+/// attach数和Linux一样按映射它的区域计, 由shmat/shmdt/fork/exit/exec/munmap显式维护,
+/// 不能用引用计数代替(查找时的临时引用也会算进去).
+pub struct SharedMemory {
+    pub id: usize,
+    key: usize,
+    size: usize,
+    /// 所有attach共享的物理页帧
+    pub frames: Vec<Arc<FrameTracker>>,
+    stat: SpinNoIrqLock<ShmIdDs>,
+}
+
+impl SharedMemory {
+    fn new(id: usize, key: usize, size: usize, mode: u32, cpid: usize) -> Option<Self> {
+        let page_cnt = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut frames = Vec::with_capacity(page_cnt);
+        for _ in 0..page_cnt {
+            frames.push(Arc::new(frame_alloc()?));
+        }
+        let stat = ShmIdDs {
+            shm_perm: IpcPerm {
+                key: key as i32,
+                mode: mode & 0o777,
+                ..Default::default()
+            },
+            shm_segsz: size,
+            shm_ctime: current_time_spec().sec as isize,
+            shm_cpid: cpid as i32,
+            ..Default::default()
+        };
+        Some(Self {
+            id,
+            key,
+            size,
+            frames,
+            stat: SpinNoIrqLock::new(stat),
+        })
+    }
+
+    /// 记录一次shmat或shmdt
+    pub fn touch(&self, pid: usize, attach: bool) {
+        let mut stat = self.stat.lock();
+        let now = current_time_spec().sec as isize;
+        if attach {
+            stat.shm_atime = now;
+            stat.shm_nattch += 1;
+        } else {
+            stat.shm_dtime = now;
+            stat.shm_nattch -= 1;
+        }
+        stat.shm_lpid = pid as i32;
+    }
+
+    /// 多了一个映射它的区域, 如fork或者区域被拆分
+    pub fn inc_nattch(&self) {
+        self.stat.lock().shm_nattch += 1;
+    }
+
+    /// 少了一个映射它的区域, 如exit/exec或munmap, 和Linux一样也算一次detach
+    pub fn dec_nattch(&self) {
+        let mut stat = self.stat.lock();
+        stat.shm_dtime = current_time_spec().sec as isize;
+        stat.shm_nattch -= 1;
+    }
+}
 
 // 共享内存管理器结构体
 pub struct SharedMemoryManager {
@@ -15,7 +129,7 @@ pub struct SharedMemoryManager {
     // key -> id 的映射
     key_map: BTreeMap<usize, usize>,
     // id -> 共享内存 的映射
-    shm_map: BTreeMap<usize, SharedMemory>,
+    shm_map: BTreeMap<usize, Arc<SharedMemory>>,
 }
 
 impl SharedMemoryManager {
@@ -28,102 +142,96 @@ impl SharedMemoryManager {
         }
     }
 
+    /// 查找或创建key对应的共享内存, 返回其ID
+    pub fn get(
+        &mut self,
+        key: usize,
+        size: usize,
+        flags: ShmFlags,
+        mode: u32,
+        pid: usize,
+    ) -> Result<usize, SyscallErr> {
+        if key != IPC_PRIVATE {
+            if let Some(&id) = self.key_map.get(&key) {
+                if flags.contains(ShmFlags::IPC_CREAT | ShmFlags::IPC_EXCL) {
+                    return Err(SyscallErr::EEXIST);
+                }
+                if size > self.shm_map[&id].size {
+                    return Err(SyscallErr::EINVAL);
+                }
+                return Ok(id);
+            }
+            if !flags.contains(ShmFlags::IPC_CREAT) {
+                return Err(SyscallErr::ENOENT);
+            }
+        }
+        if size == 0 {
+            return Err(SyscallErr::EINVAL);
+        }
+        self.alloc(key, size, mode, pid)
+    }
+
     // 分配一个新的共享内存区域
-    pub fn alloc(&mut self, key: usize, len: usize) -> usize {
+    fn alloc(
+        &mut self,
+        key: usize,
+        size: usize,
+        mode: u32,
+        pid: usize,
+    ) -> Result<usize, SyscallErr> {
         let id = self.id_allocator.id_alloc(); // 获取一个新的ID
-        self.key_map.insert(key, id); // 将key映射到这个ID
-        let page_cnt = len / PAGE_SIZE; // 计算需要的页数
-        let shm = SharedMemory::new(page_cnt); // 创建一个新的共享内存实例
-        self.shm_map.insert(id, shm); // 将ID映射到这个共享内存实例
-        id // 返回分配的ID
+        let shm = match SharedMemory::new(id, key, size, mode, pid) {
+            Some(shm) => shm,
+            None => {
+                self.dealloc_id(id);
+                return Err(SyscallErr::ENOMEM);
+            }
+        };
+        if key != IPC_PRIVATE {
+            self.key_map.insert(key, id); // 将key映射到这个ID
+        }
+        self.shm_map.insert(id, Arc::new(shm)); // 将ID映射到这个共享内存实例
+        Ok(id) // 返回分配的ID
     }
 
-    /// 将虚拟内存区域附加到共享内存
-    /// 返回该虚拟内存区域的起始地址
-    pub fn attach(&mut self, shm_id: usize, addr: Option<VirtAddr>) -> SyscallRet {
-        let shm = self.shm_map.get_mut(&shm_id).ok_or(SyscallErr::EINVAL)?; // 获取共享内存实例
-        shm.attach(addr) // 附加虚拟内存区域
+    fn dealloc_id(&mut self, id: usize) {
+        // RecycleAllocator不回收0
+        if id != 0 {
+            self.id_allocator.dealloc(id);
+        }
     }
 
-    // 分离共享内存（未实现）
-    #[allow(unused)]
-    pub fn detach() {
-        todo!()
+    /// 获取共享内存段, 用于attach
+    pub fn get_by_id(&self, id: usize) -> Result<Arc<SharedMemory>, SyscallErr> {
+        self.shm_map.get(&id).cloned().ok_or(SyscallErr::EINVAL)
     }
-}
 
-// 共享内存结构体
-pub struct SharedMemory {
-    _pages: Vec<Weak<Page>>, // 页面的弱引用
-    _page_cnt: usize,        // 页面数量
-}
+    /// IPC_STAT
+    pub fn stat(&self, id: usize) -> Result<ShmIdDs, SyscallErr> {
+        let shm = self.shm_map.get(&id).ok_or(SyscallErr::EINVAL)?;
+        let stat = *shm.stat.lock();
+        Ok(stat)
+    }
 
-impl SharedMemory {
-    // 创建一个新的共享内存实例
-    pub fn new(page_cnt: usize) -> Self {
-        Self {
-            _page_cnt: page_cnt,
-            _pages: Vec::new(),
-        }
+    /// IPC_SET, 只能修改权限位
+    pub fn set(&self, id: usize, ds: &ShmIdDs) -> Result<(), SyscallErr> {
+        let shm = self.shm_map.get(&id).ok_or(SyscallErr::EINVAL)?;
+        let mut stat = shm.stat.lock();
+        stat.shm_perm.uid = ds.shm_perm.uid;
+        stat.shm_perm.gid = ds.shm_perm.gid;
+        stat.shm_perm.mode = ds.shm_perm.mode & 0o777;
+        stat.shm_ctime = current_time_spec().sec as isize;
+        Ok(())
     }
 
-    /// 注意：此方法必须由当前线程调用
-    pub fn attach(&mut self, _addr: Option<VirtAddr>) -> SyscallRet {
-        // current_process().inner_handler(|proc| {
-        //     // 暂时给予用户所有权限
-        //     let permission =
-        //         MapPermission::R | MapPermission::X | MapPermission::W | MapPermission::U;
-        //     let mut vma = match addr {
-        //         Some(addr) => proc
-        //             .memory_set
-        //             .allocate_spec_area(
-        //                 self.page_cnt << PAGE_SIZE_BITS,
-        //                 permission,
-        //                 addr,
-        //                 VmAreaType::Shm,
-        //             )?
-        //             .ok_or(SyscallErr::ENOMEM)?,
-        //         None => proc
-        //             .memory_space
-        //             .allocate_area(self.page_cnt << PAGE_SIZE_BITS, permission, VmAreaType::Shm)
-        //             .ok_or(SyscallErr::ENOMEM)?,
-        //     };
-        //     debug_assert!(vma.end_vpn().0 - vma.start_vpn().0 == self.page_cnt);
-        //     for (idx, vpn) in vma.vpn_range.into_iter().enumerate() {
-        //         log::debug!(
-        //             "[SharedMemory::attach] attach vma, vpn {:#x}, pte flags {:?}",
-        //             vpn.0,
-        //             PTEFlags::from(permission)
-        //         );
-        //         let page = match self.pages.len() <= idx {
-        //             true => {
-        //                 let page = Arc::new(PageBuilder::new().permission(permission).build());
-        //                 self.pages.push(Arc::downgrade(&page));
-        //                 page
-        //             }
-        //             false => match self.pages[idx].upgrade().as_ref() {
-        //                 Some(page) => page.clone(),
-        //                 None => {
-        //                     let page = Arc::new(PageBuilder::new().permission(permission).build());
-        //                     self.pages[idx] = Arc::downgrade(&page);
-        //                     page
-        //                 }
-        //             },
-        //         };
-        //         vma.map_one(vpn, Some(page));
-        //     }
-        //     log::info!(
-        //         "[SharedMemory::attach] attach vma, start vpn {:#x}, pte flags {:?}",
-        //         vma.start_vpn().0,
-        //         PTEFlags::from(permission)
-        //     );
-        //     let start_vpn = vma.start_vpn();
-        //     proc.memory_space.insert_area(vma);
-        //     proc.memory_space.activate();
-        //
-        //     Ok(VirtAddr::from(start_vpn).0)
-        // })
-        Ok(0)
+    /// IPC_RMID, 已经attach的区域在detach前仍然可用
+    pub fn remove(&mut self, id: usize) -> Result<(), SyscallErr> {
+        let shm = self.shm_map.remove(&id).ok_or(SyscallErr::EINVAL)?;
+        if shm.key != IPC_PRIVATE {
+            self.key_map.remove(&shm.key);
+        }
+        self.dealloc_id(id);
+        Ok(())
     }
 }
 
@@ -22,7 +22,7 @@ extern "C" {
     #[allow(improper_ctypes)]
     fn __try_write_user_u8(user_addr: usize) -> TryOpRet;
     #[allow(improper_ctypes)]
-    fn __tray_read_user_u8(user_addr: usize) -> TryOpRet;
+    fn __try_read_user_u8(user_addr: usize) -> TryOpRet;
 }
 
 impl Drop for UserCheck {
@@ -49,11 +49,38 @@ impl UserCheck {
         let page_table = PageTable::from_token(satp);
 
         while vpn < buf_end {
-            if let Some(scause) = self.try_write_user(VirtAddr::from(vpn).0) {
+            // a lazily mapped private file page needs a second fault to be copied on write
+            while let Some(scause) = self.try_write_user(VirtAddr::from(vpn).0) {
                 match scause.cause() {
                     Trap::Exception(Exception::LoadPageFault)
-                    | Trap::Exception(Exception::StorePageFault) => {
-                        handle_recoverable_page_fault(&page_table, vpn)?
+                    | Trap::Exception(Exception::LoadFault)
+                    | Trap::Exception(Exception::StorePageFault)
+                    | Trap::Exception(Exception::StoreFault) => {
+                        handle_recoverable_page_fault(&page_table, vpn, scause.cause())?
+                    }
+                    _ => break,
+                }
+            }
+            vpn.0 += 1;
+        }
+        Ok(())
+    }
+    /// Check whether the pages are readable
+    /// 1. the kernel try to read a lazily mapped page in the user space
+    pub fn check_readable_pages(&self, buf: *const u8, len: usize) -> SysResult<()> {
+        trace!("[check_readable_pages] buf: {:p}, len: {:#x}", buf, len);
+        let buf_start = VirtAddr::from(buf as usize).floor();
+        let buf_end = VirtAddr::from(buf as usize + len).ceil();
+        let mut vpn = buf_start;
+        let satp = satp::read().bits();
+        let page_table = PageTable::from_token(satp);
+
+        while vpn < buf_end {
+            if let Some(scause) = self.try_read_user(VirtAddr::from(vpn).0) {
+                match scause.cause() {
+                    Trap::Exception(Exception::LoadPageFault)
+                    | Trap::Exception(Exception::LoadFault) => {
+                        handle_recoverable_page_fault(&page_table, vpn, scause.cause())?
                     }
                     _ => {}
                 }
@@ -69,9 +96,8 @@ impl UserCheck {
             _ => Some(ret.scause),
         }
     }
-    #[allow(unused)]
     fn try_read_user(&self, user_addr: usize) -> Option<Scause> {
-        let ret = unsafe { __tray_read_user_u8(user_addr) };
+        let ret = unsafe { __try_read_user_u8(user_addr) };
         match ret.is_err {
             0 => None,
             _ => Some(ret.scause),
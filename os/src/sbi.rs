@@ -61,3 +61,12 @@ pub fn shutdown(failure: bool) -> ! {
 pub fn hart_start(hart_id: usize, start_addr: usize) -> usize {
     sbi_call(SBI_HART_START, hart_id, start_addr, 0)
 }
+/// use sbi call to flush the TLB of harts in `hart_mask` for [start, start + size)
+pub fn remote_sfence_vma(hart_mask: usize, start: usize, size: usize) {
+    sbi_call(
+        SBI_REMOTE_SFENCE_VMA,
+        &hart_mask as *const usize as usize,
+        start,
+        size,
+    );
+}
@@ -1,7 +1,6 @@
 use super::signo::*;
 use super::SigBitmap;
-use crate::{task::exit_current, SIG_NUM};
-use log::debug;
+use crate::SIG_NUM;
 
 #[derive(Clone)]
 pub struct SigHandlers {
@@ -15,29 +14,79 @@ impl SigHandlers {
             sig_handlers: [SigAction::new(); SIG_NUM + 1],
         }
     }
+
+    /// execve之后用户的处理函数不复存在, 恢复默认, 被忽略的信号保持忽略
+    pub fn reset_for_exec(&mut self) {
+        for action in self.sig_handlers.iter_mut() {
+            if action.sa_handler != SIG_IGN {
+                *action = SigAction::new();
+            }
+        }
+    }
+}
+
+/// 默认处理
+pub const SIG_DFL: usize = 0;
+/// 忽略信号
+pub const SIG_IGN: usize = 1;
+
+bitflags! {
+    pub struct SigActionFlags: usize {
+        /// SIGCHLD is not generated when children stop or continue
+        const SA_NOCLDSTOP = 1;
+        const SA_NOCLDWAIT = 2;
+        /// The handler takes `siginfo_t` and `ucontext_t` as extra arguments
+        const SA_SIGINFO = 4;
+        /// Return to `sa_restorer` instead of the kernel trampoline
+        const SA_RESTORER = 0x04000000;
+        /// Run the handler on the alternate stack set by `sigaltstack`
+        const SA_ONSTACK = 0x08000000;
+        /// Restart the interrupted syscall after the handler returns
+        const SA_RESTART = 0x10000000;
+        /// Do not block the signal while its handler is running
+        const SA_NODEFER = 0x40000000;
+        /// Reset the handler to `SIG_DFL` once delivered
+        const SA_RESETHAND = 0x80000000;
+    }
 }
 
 /// User defined
+///
+/// riscv的`struct sigaction`是`{ handler, flags, mask }`, 没有`sa_restorer`,
+/// 设置了`SA_RESTORER`时它跟在`sa_mask`之后
 #[derive(Clone, Copy)]
+#[repr(C)]
 pub struct SigAction {
     pub sa_handler: usize,
-    // todo?: 使用bitmap!实现
-    // 信号处理的flags
-    // pub sa_flags: usize,
+    /// 信号处理的flags
+    pub sa_flags: SigActionFlags,
+    /// 执行用户例程期间的信号掩码(需要在信号处理结束后恢复)
+    pub sa_mask: SigBitmap,
     /// 存储了sig_return的函数处理地址
     /// 仅在SA_RESTORER标志被设置时有效
     pub sa_restorer: usize,
-    /// 执行用户例程期间的信号掩码(需要在信号处理结束后恢复)
-    pub sa_mask: SigBitmap,
 }
 
 impl SigAction {
     /// empty SigAction
     fn new() -> Self {
         Self {
-            sa_handler: 0,
-            sa_restorer: 0,
+            sa_handler: SIG_DFL,
+            sa_flags: SigActionFlags::empty(),
             sa_mask: SigBitmap::empty(),
+            sa_restorer: 0,
+        }
+    }
+
+    /// 信号是否会被直接丢弃
+    pub fn is_ignored(&self, signo: usize) -> bool {
+        match self.sa_handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(
+                SignalDefault::get_action(signo),
+                SignalDefault::Ignore | SignalDefault::Cont
+            ),
+            _ => false,
         }
     }
 }
@@ -78,6 +127,7 @@ impl SignalDefault {
             SIGTSTP => Self::Stop,
             SIGTTIN => Self::Stop,
             SIGTTOU => Self::Stop,
+            SIGURG => Self::Ignore,
             SIGUSR1 => Self::Terminate,
             SIGUSR2 => Self::Terminate,
             SIGXCPU => Self::Core,
@@ -92,12 +142,3 @@ impl SignalDefault {
         }
     }
 }
-
-pub fn ign_sig_handler() {
-    debug!("ignore this sig");
-}
-
-pub fn term_sig_handler() {
-    debug!("term sig handler");
-    exit_current(-1);
-}
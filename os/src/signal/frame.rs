@@ -0,0 +1,178 @@
+//! 用户栈上的信号栈帧, 布局与riscv64 Linux的`struct rt_sigframe`一致
+
+use super::{SigBitmap, SIGCHLD};
+
+/// sent by `kill`
+pub const SI_USER: i32 = 0;
+/// sent by the kernel itself
+pub const SI_KERNEL: i32 = 0x80;
+/// sent by `tkill` or `tgkill`
+pub const SI_TKILL: i32 = -6;
+
+/// `si_code` of SIGCHLD
+pub const CLD_EXITED: i32 = 1;
+pub const CLD_KILLED: i32 = 2;
+pub const CLD_DUMPED: i32 = 3;
+pub const CLD_STOPPED: i32 = 5;
+pub const CLD_CONTINUED: i32 = 6;
+
+/// `si_code` of SIGSEGV
+pub const SEGV_MAPERR: i32 = 1;
+pub const SEGV_ACCERR: i32 = 2;
+/// `si_code` of SIGBUS, misaligned address
+pub const BUS_ADRALN: i32 = 1;
+/// `si_code` of SIGBUS, the page behind the address can't be read
+pub const BUS_ADRERR: i32 = 2;
+/// `si_code` of SIGILL
+pub const ILL_ILLOPC: i32 = 1;
+pub const ILL_ILLTRP: i32 = 7;
+/// `si_code` of SIGTRAP
+pub const TRAP_BRKPT: i32 = 1;
+
+/// `ss_flags` of `stack_t`
+pub const SS_ONSTACK: i32 = 1;
+pub const SS_DISABLE: i32 = 2;
+/// Disable the alternate stack once a handler switches to it
+pub const SS_AUTODISARM: i32 = 1 << 31;
+/// 备用信号栈的最小大小
+pub const MINSIGSTKSZ: usize = 2048;
+
+/// `siginfo_t`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    _pad: i32,
+    /// 联合体部分, 例如发送者的pid/uid, 或者出错的地址
+    pub fields: [usize; 14],
+}
+
+impl SigInfo {
+    pub fn new(signo: usize, code: i32) -> Self {
+        Self {
+            si_signo: signo as i32,
+            si_errno: 0,
+            si_code: code,
+            _pad: 0,
+            fields: [0; 14],
+        }
+    }
+    /// `kill`之类的信息, 联合体部分是发送者的`si_pid`和`si_uid`
+    pub fn kill(signo: usize, code: i32, pid: usize, uid: u32) -> Self {
+        let mut info = Self::new(signo, code);
+        info.fields[0] = pid as u32 as usize | (uid as usize) << 32;
+        info
+    }
+    /// 发送者的pid和uid, 只有用户发送的信号和SIGCHLD才有
+    pub fn sender(&self) -> Option<(u32, u32)> {
+        (self.si_code <= SI_USER || self.si_signo as usize == SIGCHLD)
+            .then(|| (self.fields[0] as u32, (self.fields[0] >> 32) as u32))
+    }
+    /// 硬件异常的信息, 联合体部分是出错的地址`si_addr`
+    pub fn fault(signo: usize, code: i32, addr: usize) -> Self {
+        let mut info = Self::new(signo, code);
+        info.fields[0] = addr;
+        info
+    }
+    /// SIGCHLD的信息, 联合体部分是`si_pid`, `si_uid`和`si_status`
+    pub fn child(code: i32, pid: usize, status: i32) -> Self {
+        let mut info = Self::new(SIGCHLD, code);
+        info.fields[0] = pid as u32 as usize;
+        info.fields[1] = status as u32 as usize;
+        info
+    }
+}
+
+/// `stack_t`, 也用来保存线程的备用信号栈
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SignalStack {
+    pub ss_sp: usize,
+    pub ss_flags: i32,
+    pub ss_size: usize,
+}
+
+impl SignalStack {
+    pub fn disabled() -> Self {
+        Self {
+            ss_sp: 0,
+            ss_flags: SS_DISABLE,
+            ss_size: 0,
+        }
+    }
+    pub fn is_disabled(&self) -> bool {
+        self.ss_flags & SS_DISABLE != 0
+    }
+    /// `sp`是否在备用信号栈上
+    pub fn contains(&self, sp: usize) -> bool {
+        !self.is_disabled() && sp > self.ss_sp && sp - self.ss_sp <= self.ss_size
+    }
+    /// 报告给用户的状态, `sp`为当前的用户栈指针
+    pub fn user_view(&self, sp: usize) -> Self {
+        let ss_flags = if self.contains(sp) {
+            SS_ONSTACK
+        } else {
+            self.ss_flags
+        };
+        Self { ss_flags, ..*self }
+    }
+}
+
+/// 浮点寄存器, 内核不保存用户的浮点状态, 总是为0
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+pub struct FpState {
+    pub f: [u64; 64],
+    pub fcsr: u32,
+    _reserved: [u32; 3],
+}
+
+/// `mcontext_t`, `gregs[0]`是pc, 其余是x1~x31
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct MContext {
+    pub gregs: [usize; 32],
+    pub fpregs: FpState,
+}
+
+/// `ucontext_t`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct UContext {
+    pub uc_flags: usize,
+    pub uc_link: usize,
+    pub uc_stack: SignalStack,
+    pub uc_sigmask: SigBitmap,
+    /// 内核的sigset_t只有64位, 用户态的是1024位
+    _unused: [u8; 120],
+    pub uc_mcontext: MContext,
+}
+
+impl UContext {
+    pub fn new(stack: SignalStack, sigmask: SigBitmap, gregs: [usize; 32]) -> Self {
+        Self {
+            uc_flags: 0,
+            uc_link: 0,
+            uc_stack: stack,
+            uc_sigmask: sigmask,
+            _unused: [0; 120],
+            uc_mcontext: MContext {
+                gregs,
+                fpregs: FpState {
+                    f: [0; 64],
+                    fcsr: 0,
+                    _reserved: [0; 3],
+                },
+            },
+        }
+    }
+}
+
+/// 处理函数运行时用户栈顶的内容
+#[repr(C)]
+pub struct SigFrame {
+    pub info: SigInfo,
+    pub uc: UContext,
+}
@@ -1,17 +1,39 @@
 mod action;
+mod frame;
 mod signo;
 
-use action::{ign_sig_handler, term_sig_handler, SignalDefault};
+use core::future::Future;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use action::{SignalDefault, SIG_DFL, SIG_IGN};
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::{vec, vec::Vec};
+use frame::{SigFrame, UContext, MINSIGSTKSZ, SS_AUTODISARM, SS_DISABLE, SS_ONSTACK};
 use log::{debug, trace, warn};
 
-pub use action::SigHandlers;
+pub use action::{SigAction, SigActionFlags, SigHandlers};
+pub use frame::{
+    SigInfo, SignalStack, BUS_ADRALN, BUS_ADRERR, CLD_CONTINUED, CLD_DUMPED, CLD_EXITED,
+    CLD_KILLED, CLD_STOPPED, ILL_ILLOPC, ILL_ILLTRP, SEGV_ACCERR, SEGV_MAPERR, SI_KERNEL, SI_TKILL,
+    SI_USER, TRAP_BRKPT,
+};
 pub use signo::*;
 
+use crate::syscall::process::find_process;
+use crate::syscall::resource::RLIMIT_CORE;
+use crate::task::exit_group_current;
+use crate::task::pgroup::ProcessGroup;
 use crate::task::processor::current_process;
-use crate::task::task::PROCESS_MANAGER;
+use crate::task::task::{JobEvent, Process, Thread};
+use crate::task::{all_processes, find_thread, INITPROC};
+use crate::timer::{current_time_duration, read_timeout, TimeLimitedFuture};
 use crate::{
-    mm::user_check::UserCheck, task::processor::current_thread, utils::SyscallErr, SyscallRet,
-    SIG_NUM,
+    mm::user_check::UserCheck, task::processor::current_thread, utils::SyscallErr, SysResult,
+    SyscallRet, SIG_NUM,
 };
 
 bitflags! {
@@ -52,9 +74,70 @@ bitflags! {
     }
 }
 
+impl SigBitmap {
+    pub fn from_signo(signo: usize) -> Self {
+        Self::from_bits_truncate(1 << (signo - 1))
+    }
+    /// 不能被阻塞的信号
+    pub fn unblockable() -> Self {
+        Self::SIGKILL | Self::SIGSTOP
+    }
+    /// 取出`set`中编号最小的信号
+    pub fn take_lowest(&mut self, set: SigBitmap) -> Option<usize> {
+        let candidates = *self & set;
+        if candidates.is_empty() {
+            return None;
+        }
+        let signo = candidates.bits().trailing_zeros() as usize + 1;
+        self.remove(Self::from_signo(signo));
+        Some(signo)
+    }
+}
+
+/// 待处理的信号和它们的`siginfo`
+///
+/// 信号不排队: 已经待处理的信号再次发送时被丢弃, 保留第一次的信息
+#[derive(Clone)]
+pub struct SigPending {
+    pub set: SigBitmap,
+    infos: BTreeMap<usize, SigInfo>,
+}
+
+impl SigPending {
+    pub fn new() -> Self {
+        Self {
+            set: SigBitmap::empty(),
+            infos: BTreeMap::new(),
+        }
+    }
+    pub fn insert(&mut self, info: SigInfo) {
+        let signo = info.si_signo as usize;
+        let sig = SigBitmap::from_signo(signo);
+        if !self.set.contains(sig) {
+            self.set.insert(sig);
+            self.infos.insert(signo, info);
+        }
+    }
+    pub fn remove(&mut self, sigs: SigBitmap) {
+        self.set.remove(sigs);
+        let set = self.set;
+        self.infos
+            .retain(|signo, _| set.contains(SigBitmap::from_signo(*signo)));
+    }
+    /// 取出`set`中编号最小的信号
+    pub fn take_lowest(&mut self, set: SigBitmap) -> Option<SigInfo> {
+        let signo = self.set.take_lowest(set)?;
+        Some(
+            self.infos
+                .remove(&signo)
+                .unwrap_or_else(|| SigInfo::new(signo, SI_KERNEL)),
+        )
+    }
+}
+
 /// 每个线程一个
 ///
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct SigSet {
     /// thread signal mask
     ///  A child created via fork initially has a signal mask that is a copy of its parent's signal mask
@@ -63,7 +146,7 @@ pub struct SigSet {
     /// pending signals
     /// A child created via fork initially has an empty pending signal set
     /// the pending signal set is preserved across an execve
-    pub pending_sigs: SigBitmap,
+    pub pending_sigs: SigPending,
 }
 
 impl SigSet {
@@ -71,7 +154,7 @@ impl SigSet {
     pub fn new() -> Self {
         Self {
             thread_mask: SigBitmap::empty(),
-            pending_sigs: SigBitmap::empty(),
+            pending_sigs: SigPending::new(),
         }
     }
     /// especially used by fork
@@ -79,7 +162,7 @@ impl SigSet {
     pub fn from_existed_user(user_sigset: &SigSet) -> Self {
         Self {
             thread_mask: user_sigset.thread_mask,
-            pending_sigs: SigBitmap::empty(),
+            pending_sigs: SigPending::new(),
         }
     }
 }
@@ -88,101 +171,261 @@ extern "C" {
     pub fn sigreturn_trampoline();
 }
 
-pub fn handle_signals() {
-    // let process = current_process();
-    // let mut inner = process.inner_lock();
+/// 被信号打断的系统调用
+#[derive(Clone, Copy)]
+pub struct InterruptedSyscall {
+    /// 系统调用的第一个参数, a0已经被返回值覆盖
+    pub a0: usize,
+    /// 执行了`SA_RESTART`的处理函数后能否重新执行,
+    /// 例如`ppoll`这类调用总是返回`EINTR`
+    pub restart_after_handler: bool,
+}
+
+/// 返回用户态之前处理未被阻塞的待处理信号:
+/// 默认终止时结束整个线程组, 停止时等到SIGCONT, 有处理函数时在用户栈上搭建栈帧
+pub async fn handle_signals() {
     let thread = current_thread().unwrap();
-    let inner = thread.get_inner_mut();
-    if inner.sig_set.pending_sigs.is_empty() {
-        // no pending signals now
-        return;
-    }
-    // 遍历所有信号
-    // 编号小的信号优先处理
-    for signo in 1..SIG_NUM + 1 {
-        if inner
-            .sig_set
-            .pending_sigs
-            .contains(SigBitmap::from_bits(1 << (signo - 1)).unwrap())
-            && (!inner
-                .sig_set
-                .thread_mask
-                .contains(SigBitmap::from_bits(1 << (signo - 1)).unwrap()))
-        {
-            if inner.sig_handlers.sig_handlers[signo].sa_handler == 0 {
-                // 未注册 ,由内核处理
-                match SignalDefault::get_action(signo) {
-                    SignalDefault::Terminate => {
-                        // 目前会发生死锁, 应该不是在term_sig_handler中调用exit_current
-                        todo!();
-                        //term_sig_handler();
-                    }
-                    SignalDefault::Ignore => {
-                        ign_sig_handler();
-                    }
-                    SignalDefault::Stop => {
-                        unimplemented!();
-                    }
-                    SignalDefault::Cont => {
-                        unimplemented!();
-                    }
-                    SignalDefault::Core => {
-                        term_sig_handler();
+    let interrupted = thread.get_inner_mut().interrupted_syscall.take();
+    loop {
+        if thread.process.inner_lock().stopped {
+            WaitContinueFuture::new(thread.clone()).await;
+        }
+        // 编号小的信号优先处理
+        let Some(info) = thread.dequeue_signal(!thread.sig_mask()) else {
+            break;
+        };
+        let signo = info.si_signo as usize;
+        let action = thread.process.sig_action(signo);
+        match action.sa_handler {
+            SIG_IGN => continue,
+            SIG_DFL => match SignalDefault::get_action(signo) {
+                SignalDefault::Ignore | SignalDefault::Cont => continue,
+                SignalDefault::Terminate => {
+                    debug!("[handle_signals] terminated by signal {}", signo);
+                    exit_group_current(signo as i32);
+                    return;
+                }
+                SignalDefault::Core => {
+                    // 不转储core, RLIMIT_CORE不为0时只设置状态中的core标志
+                    debug!("[handle_signals] core dumped by signal {}", signo);
+                    let dumped = thread.process.rlimit(RLIMIT_CORE).rlim_cur > 0;
+                    exit_group_current(signo as i32 | if dumped { 0x80 } else { 0 });
+                    return;
+                }
+                SignalDefault::Stop => {
+                    do_stop(&thread, signo);
+                    continue;
+                }
+            },
+            _ => {
+                if let Some(syscall) = interrupted {
+                    if syscall.restart_after_handler
+                        && action.sa_flags.contains(SigActionFlags::SA_RESTART)
+                    {
+                        restart_syscall(&thread, syscall);
                     }
                 }
+                if setup_frame(&thread, &info, &action).is_err() {
+                    // 用户栈不可用, 无法运行处理函数
+                    warn!("[handle_signals] bad user stack for signal {}", signo);
+                    exit_group_current(SIGSEGV as i32);
+                }
+                // 一次只进入一个处理函数, 其余的等它返回后再处理
                 return;
             }
-            // user defined handler
-            assert!(
-                signo != SIGKILL && signo != SIGSTOP,
-                "SIGKILL and SIGSTOP cannot be caught or ignored"
-            );
-            let handler = inner.sig_handlers.sig_handlers[signo];
-            if inner.handling_signo == 0
-                || !handler
-                    .sa_mask
-                    .contains(SigBitmap::from_bits(1 << (signo - 1)).unwrap())
-            {
-                // 该信号未被阻塞, 将调用用户注册的信号处理函数
-                // handle flag
-                inner.handling_signo = signo;
-                inner
-                    .sig_set
-                    .pending_sigs
-                    .remove(SigBitmap::from_bits(1 << (signo - 1)).unwrap());
-
-                // backup the context
-                let trap_ctx = inner.get_trap_context();
-                inner.signal_context = Some(*trap_ctx);
-
-                // modify trapframe
-                trap_ctx.sepc = handler.sa_handler;
-                // a0
-                trap_ctx.x[10] = signo;
-                // ra
-                // 6.15debug
-                debug!(
-                    "sigreturn_trampoline addr: {:#x}",
-                    sigreturn_trampoline as usize
-                );
-                trap_ctx.x[1] = sigreturn_trampoline as usize;
+        }
+    }
+    // 没有运行处理函数, 被打断的系统调用对用户不可见
+    if let Some(mask) = thread.get_inner_mut().saved_sigmask.take() {
+        thread.set_sig_mask(mask);
+    }
+    if let Some(syscall) = interrupted {
+        restart_syscall(&thread, syscall);
+    }
+}
+
+/// 在临时的信号屏蔽字下等待`future`, 超时返回`None`
+///
+/// 被信号打断时原来的屏蔽字要等处理函数返回后才恢复, 否则打断等待的信号又会被屏蔽
+pub async fn wait_with_sigmask<T, F: Future<Output = SysResult<T>>>(
+    future: F,
+    timeout: Option<Duration>,
+    sigmask: Option<SigBitmap>,
+) -> Option<SysResult<T>> {
+    let thread = current_thread().unwrap();
+    let old_mask = sigmask.map(|mask| thread.set_sig_mask(mask - SigBitmap::unblockable()));
+    let deadline = timeout.map(|timeout| current_time_duration() + timeout);
+    let ret = TimeLimitedFuture::new(future, deadline).await;
+    thread.clear_interruptible_waker();
+    if let Some(mask) = old_mask {
+        match ret {
+            Some(Err(err)) if err == SyscallErr::EINTR as usize => {
+                thread.get_inner_mut().saved_sigmask = Some(mask);
+            }
+            _ => {
+                thread.set_sig_mask(mask);
             }
         }
     }
+    ret
+}
+
+/// 读取用户的信号集, 空指针表示不修改信号屏蔽字
+pub fn read_sigmask(sigmask_ptr: usize) -> SysResult<Option<SigBitmap>> {
+    if sigmask_ptr == 0 {
+        return Ok(None);
+    }
+    UserCheck::new().check_readable_pages(sigmask_ptr as *const u8, size_of::<SigBitmap>())?;
+    let mask = unsafe { *(sigmask_ptr as *const usize) };
+    Ok(Some(SigBitmap::from_bits_truncate(mask)))
+}
+
+/// 回到ecall指令重新执行系统调用
+fn restart_syscall(thread: &Thread, syscall: InterruptedSyscall) {
+    let trap_ctx = thread.get_inner_mut().get_trap_context();
+    trap_ctx.x[10] = syscall.a0;
+    trap_ctx.sepc -= 4;
+}
+
+/// 停止整个进程, 其他线程在下一次处理信号时也会停下
+fn do_stop(thread: &Thread, signo: usize) {
+    let process = &thread.process;
+    let newly_stopped = !core::mem::replace(&mut process.inner_lock().stopped, true);
+    if newly_stopped {
+        debug!("[do_stop] process {} stopped", process.getpid());
+        process.notify_parent_stop(JobEvent::Stopped(signo));
+    }
 }
 
-pub fn sys_rt_sigerturn() -> SyscallRet {
-    debug!("[sys_rt_sigerturn]");
-    if let Some(thread) = current_thread() {
-        let inner = thread.get_inner_mut();
-        inner.handling_signo = 0;
-        // restore the trap context
-        let trap_ctx = inner.get_trap_context();
-        *trap_ctx = inner.signal_context.unwrap();
-        Ok(trap_ctx.x[10])
+/// 向当前线程发送硬件异常引起的同步信号, `addr`是出错的地址
+///
+/// 信号被阻塞或忽略时恢复成默认处理, 否则返回用户态后会在同一条指令上反复出错
+pub fn force_sig_fault(signo: usize, code: i32, addr: usize) {
+    let thread = current_thread().unwrap();
+    let sig = SigBitmap::from_signo(signo);
+    let blocked = thread.sig_mask().contains(sig);
+    let mut process_inner = thread.process.inner_lock();
+    let action = &mut process_inner.sig_handlers.sig_handlers[signo];
+    if blocked || action.is_ignored(signo) {
+        action.sa_handler = SIG_DFL;
+        drop(process_inner);
+        thread.sig_set.lock().thread_mask.remove(sig);
     } else {
-        Err(SyscallErr::EUNDEF.into())
+        drop(process_inner);
     }
+    thread.send_signal_info(SigInfo::fault(signo, code, addr));
+}
+
+/// 等到进程被SIGCONT继续, 或者收到SIGKILL
+struct WaitContinueFuture {
+    thread: Arc<Thread>,
+}
+
+impl WaitContinueFuture {
+    fn new(thread: Arc<Thread>) -> Self {
+        Self { thread }
+    }
+}
+
+impl Future for WaitContinueFuture {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.thread.set_interruptible_waker(cx.waker().clone());
+        let killed = self.thread.pending_signals().contains(SigBitmap::SIGKILL);
+        if killed || !self.thread.process.inner_lock().stopped {
+            self.thread.clear_interruptible_waker();
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// 在用户栈(或者备用信号栈)上保存当前上下文, 并跳转到处理函数
+fn setup_frame(thread: &Thread, info: &SigInfo, action: &SigAction) -> SysResult<()> {
+    let signo = info.si_signo as usize;
+    let inner = thread.get_inner_mut();
+    let trap_ctx = inner.get_trap_context();
+    let sp = trap_ctx.x[2];
+    let altstack = inner.sig_altstack;
+    let mut frame_top = sp;
+    if action.sa_flags.contains(SigActionFlags::SA_ONSTACK)
+        && !altstack.is_disabled()
+        && !altstack.contains(sp)
+    {
+        frame_top = altstack.ss_sp + altstack.ss_size;
+        if altstack.ss_flags & SS_AUTODISARM != 0 {
+            inner.sig_altstack = SignalStack::disabled();
+        }
+    }
+    let frame_addr = (frame_top - size_of::<SigFrame>()) & !0xf;
+    UserCheck::new().check_writable_pages(frame_addr as *mut u8, size_of::<SigFrame>())?;
+
+    let mut gregs = trap_ctx.x;
+    gregs[0] = trap_ctx.sepc;
+    // 处理函数返回后恢复的是`sigsuspend`之前的屏蔽字
+    let sigmask = inner
+        .saved_sigmask
+        .take()
+        .unwrap_or_else(|| thread.sig_mask());
+    let frame = frame_addr as *mut SigFrame;
+    unsafe {
+        frame.write(SigFrame {
+            info: *info,
+            uc: UContext::new(altstack.user_view(sp), sigmask, gregs),
+        });
+        trap_ctx.x[11] = &(*frame).info as *const SigInfo as usize;
+        trap_ctx.x[12] = &(*frame).uc as *const UContext as usize;
+    }
+    trap_ctx.x[2] = frame_addr;
+    trap_ctx.sepc = action.sa_handler;
+    trap_ctx.x[10] = signo;
+    // 处理函数返回时执行rt_sigreturn
+    trap_ctx.x[1] = if action.sa_flags.contains(SigActionFlags::SA_RESTORER) {
+        action.sa_restorer
+    } else {
+        sigreturn_trampoline as usize
+    };
+
+    {
+        let mut sig_set = thread.sig_set.lock();
+        sig_set.thread_mask |= action.sa_mask;
+        if !action.sa_flags.contains(SigActionFlags::SA_NODEFER) {
+            sig_set.thread_mask |= SigBitmap::from_signo(signo);
+        }
+    }
+    if action.sa_flags.contains(SigActionFlags::SA_RESETHAND) {
+        thread.process.inner_lock().sig_handlers.sig_handlers[signo].sa_handler = SIG_DFL;
+    }
+    Ok(())
+}
+
+/// 从用户栈上的栈帧恢复处理信号之前的上下文
+pub fn sys_rt_sigreturn() -> SyscallRet {
+    debug!("[sys_rt_sigreturn]");
+    let thread = current_thread().unwrap();
+    let inner = thread.get_inner_mut();
+    let trap_ctx = inner.get_trap_context();
+    let frame_addr = trap_ctx.x[2];
+    if UserCheck::new()
+        .check_readable_pages(frame_addr as *const u8, size_of::<SigFrame>())
+        .is_err()
+    {
+        exit_group_current(SIGSEGV as i32);
+        return Ok(0);
+    }
+    let uc = unsafe { &(*(frame_addr as *const SigFrame)).uc };
+    let gregs = uc.uc_mcontext.gregs;
+    trap_ctx.sepc = gregs[0];
+    trap_ctx.x[1..].copy_from_slice(&gregs[1..]);
+    thread.set_sig_mask(uc.uc_sigmask - SigBitmap::unblockable());
+    // 处理函数可能修改了保存的备用信号栈
+    let stack = uc.uc_stack;
+    if stack.ss_flags & SS_ONSTACK == 0 {
+        inner.sig_altstack = stack;
+    }
+    // a0是被打断时的值, 不能被返回值覆盖
+    Ok(trap_ctx.x[10])
 }
 
 const SIGBLOCK: i32 = 0;
@@ -196,22 +439,114 @@ pub fn sys_rt_sigaction(signo: usize, act: usize, old_act: usize) -> SyscallRet
         act,
         old_act,
     );
-    if signo == 0 || signo >= SIG_NUM {
+    if signo == 0 || signo > SIG_NUM {
         return Err(SyscallErr::EINVAL.into());
     }
-    if signo == SIGKILL || signo == SIGSTOP {
-        return Err(SyscallErr::EPERM.into());
+    // 处理函数是整个进程共享的
+    let process = current_process();
+    let old = if act != 0 {
+        if signo == SIGKILL || signo == SIGSTOP {
+            return Err(SyscallErr::EINVAL.into());
+        }
+        let act = read_sigaction(act)?;
+        let mut process_inner = process.inner_lock();
+        let old = core::mem::replace(&mut process_inner.sig_handlers.sig_handlers[signo], act);
+        // 改为忽略时丢弃所有线程中已经待处理的信号
+        if act.is_ignored(signo) {
+            let sig = SigBitmap::from_signo(signo);
+            process_inner.pending_sigs.remove(sig);
+            drop(process_inner);
+            for thread in process.live_threads() {
+                thread.sig_set.lock().pending_sigs.remove(sig);
+            }
+        }
+        old
+    } else {
+        process.sig_action(signo)
+    };
+    if old_act != 0 {
+        // old_act非零说明要求写入旧的信号处理函数到这个地址
+        write_sigaction(old_act, &old)?;
+    }
+    Ok(0)
+}
+
+/// 用户的`struct sigaction`只有在设置了`SA_RESTORER`时才有`sa_restorer`
+fn sigaction_size(flags: SigActionFlags) -> usize {
+    if flags.contains(SigActionFlags::SA_RESTORER) {
+        size_of::<SigAction>()
+    } else {
+        size_of::<SigAction>() - size_of::<usize>()
+    }
+}
+
+fn read_sigaction(addr: usize) -> SysResult<SigAction> {
+    let fields = addr as *const usize;
+    UserCheck::new()
+        .check_readable_pages(addr as *const u8, sigaction_size(SigActionFlags::empty()))?;
+    let sa_flags = SigActionFlags::from_bits_truncate(unsafe { *fields.add(1) });
+    UserCheck::new().check_readable_pages(addr as *const u8, sigaction_size(sa_flags))?;
+    unsafe {
+        Ok(SigAction {
+            sa_handler: *fields,
+            sa_flags,
+            sa_mask: SigBitmap::from_bits_truncate(*fields.add(2)) - SigBitmap::unblockable(),
+            sa_restorer: if sa_flags.contains(SigActionFlags::SA_RESTORER) {
+                *fields.add(3)
+            } else {
+                0
+            },
+        })
     }
+}
+
+fn write_sigaction(addr: usize, action: &SigAction) -> SysResult<()> {
+    let size = sigaction_size(action.sa_flags);
+    UserCheck::new().check_writable_pages(addr as *mut u8, size)?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            action as *const SigAction as *const u8,
+            addr as *mut u8,
+            size,
+        );
+    }
+    Ok(())
+}
+
+/// 设置和查询备用信号栈
+pub fn sys_sigaltstack(ss: usize, old_ss: usize) -> SyscallRet {
+    trace!("[sys_sigaltstack]: ss {:#x}, old_ss {:#x}", ss, old_ss);
     let thread = current_thread().unwrap();
     let inner = thread.get_inner_mut();
-    if act != 0 {
-        let act = unsafe { &*(act as *const action::SigAction) };
-        inner.sig_handlers.sig_handlers[signo] = *act;
+    let sp = inner.trap_context.x[2];
+    let on_stack = inner.sig_altstack.contains(sp);
+    if old_ss != 0 {
+        UserCheck::new().check_writable_pages(old_ss as *mut u8, size_of::<SignalStack>())?;
+        unsafe {
+            *(old_ss as *mut SignalStack) = inner.sig_altstack.user_view(sp);
+        }
     }
-    if old_act != 0 {
-        // old_act非零说明要求写入旧的信号处理函数到这个地址
-        let old_act = unsafe { &mut *(old_act as *mut action::SigAction) };
-        *old_act = inner.sig_handlers.sig_handlers[signo];
+    if ss == 0 {
+        return Ok(0);
+    }
+    UserCheck::new().check_readable_pages(ss as *const u8, size_of::<SignalStack>())?;
+    let stack = unsafe { *(ss as *const SignalStack) };
+    // 正在备用信号栈上运行时不能修改
+    if on_stack {
+        return Err(SyscallErr::EPERM.into());
+    }
+    match stack.ss_flags & !SS_AUTODISARM {
+        SS_DISABLE => inner.sig_altstack = SignalStack::disabled(),
+        0 | SS_ONSTACK => {
+            if stack.ss_size < MINSIGSTKSZ {
+                return Err(SyscallErr::ENOMEM.into());
+            }
+            inner.sig_altstack = SignalStack {
+                ss_flags: stack.ss_flags & SS_AUTODISARM,
+                ..stack
+            };
+        }
+        _ => return Err(SyscallErr::EINVAL.into()),
     }
     Ok(0)
 }
@@ -226,20 +561,22 @@ pub fn sys_rt_sigprocmask(how: i32, set: usize, old_set: usize) -> SyscallRet {
         old_set,
     );
     let thread = current_thread().unwrap();
-    let mut sig_set = thread.get_inner_mut().sig_set;
     if old_set != 0 {
         UserCheck::new()
             .check_writable_pages(old_set as *mut u8, core::mem::size_of::<SigBitmap>())
             .map_err(|_| SyscallErr::EFAULT)?;
         let old_set = unsafe { &mut *(old_set as *mut SigBitmap) };
-        *old_set = sig_set.thread_mask;
+        *old_set = thread.sig_mask();
     }
     if set == 0 {
         debug!("set is null, Ok and do nothing");
         return Ok(0);
     }
 
-    let set = unsafe { SigBitmap::from_bits(*(set as *const usize)).ok_or(SyscallErr::EINVAL)? };
+    UserCheck::new().check_readable_pages(set as *const u8, size_of::<SigBitmap>())?;
+    let set =
+        unsafe { SigBitmap::from_bits_truncate(*(set as *const usize)) } - SigBitmap::unblockable();
+    let mut sig_set = thread.sig_set.lock();
     match how {
         SIGBLOCK => {
             sig_set.thread_mask |= set;
@@ -257,76 +594,187 @@ pub fn sys_rt_sigprocmask(how: i32, set: usize, old_set: usize) -> SyscallRet {
     Ok(0)
 }
 
-/// pid > 0 then sig is sent to the process with the ID specified by pid
+/// - pid > 0: 发给pid进程
+/// - pid == 0: 发给调用者所在的进程组
+/// - pid == -1: 发给除了init和调用者以外的所有进程
+/// - pid < -1: 发给进程组`-pid`
+///
+/// signo为0时只检查目标是否存在
 pub fn sys_kill(pid: isize, signo: usize) -> SyscallRet {
     trace!("sys_kill: pid {}, signo {}", pid, signo);
-    warn!("[sys_kill] not fully implemented");
-    match pid {
-        0 => {
-            for (_, proc) in PROCESS_MANAGER.lock().iter() {
-                if let Some(proc) = proc.upgrade() {
-                    debug!(
-                        "proc {} send signal {} to proc {}",
-                        current_process().getpid(),
-                        signo,
-                        proc.getpid()
-                    );
-                    proc.send_signal(signo);
-                } else {
-                    continue;
-                }
-            }
-        }
-        /*
-        1 => {
-            for (_, proc) in PROCESS_MANAGER.lock().iter() {
-                if let Some(proc) = proc.upgrade() {
-                    if proc.getpid() == 0 {
-                        // init proc
-                        continue;
-                    }
-                    debug!(
-                        "proc {} send signal {} to proc {}",
-                        current_process().getpid(),
-                        signo,
-                        proc.getpid()
-                    );
-                    proc.send_signal(signo);
-                } else {
-                    continue;
-                }
-            }
-        }
-        */
-        _ => {
-            let mut pid = pid;
-            if pid < 0 {
-                pid = -pid;
-            }
-            if let Some(proc) = PROCESS_MANAGER.lock().get(&(pid as usize)) {
-                if let Some(proc) = proc.upgrade() {
-                    debug!(
-                        "proc {} send signal {} to proc {}",
-                        current_process().getpid(),
-                        signo,
-                        proc.getpid()
-                    );
-                    proc.send_signal(signo);
-                } else {
-                    // No such proc
-                    return Err(SyscallErr::ESRCH.into());
-                }
-            } else {
-                // No such proc
-                return Err(SyscallErr::ESRCH.into());
-            }
+    if signo > SIG_NUM {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let current = current_process();
+    let targets: Vec<Arc<Process>> = match pid {
+        0 => current.pgroup().members(),
+        -1 => all_processes()
+            .into_iter()
+            .filter(|process| !Arc::ptr_eq(process, &INITPROC) && !Arc::ptr_eq(process, &current))
+            .collect(),
+        pid if pid < 0 => ProcessGroup::find(-pid as usize)
+            .ok_or(SyscallErr::ESRCH)?
+            .members(),
+        pid => vec![find_process(pid as usize)?],
+    };
+    if targets.is_empty() {
+        return Err(SyscallErr::ESRCH.into());
+    }
+    // 有权限的目标收到信号, 一个也没有时返回EPERM
+    let targets: Vec<Arc<Process>> = targets
+        .into_iter()
+        .filter(|process| may_kill(&current, process, signo))
+        .collect();
+    if targets.is_empty() {
+        return Err(SyscallErr::EPERM.into());
+    }
+    if signo != 0 {
+        let info = SigInfo::kill(signo, SI_USER, current.getpid(), current.cred().user.real);
+        for process in targets {
+            debug!(
+                "proc {} send signal {} to proc {}",
+                current.getpid(),
+                signo,
+                process.getpid()
+            );
+            process.send_signal_info(info);
         }
     }
     Ok(0)
 }
 
-pub fn sys_rt_sigtimedwait(_set: *const u32, _info: *const u8, _timeout: *const u8) -> SyscallRet {
-    trace!("[sys_rt_sigtimedwait] is enter");
-    warn!("[sys_rt_sigtimedwait] implemented");
+/// 是否允许`current`向`target`发送信号: 见`Credentials::may_signal`,
+/// 另外同一会话中的进程总可以发送SIGCONT
+fn may_kill(current: &Arc<Process>, target: &Arc<Process>, signo: usize) -> bool {
+    current.cred().may_signal(&target.cred())
+        || (signo == SIGCONT && current.pgroup().sid() == target.pgroup().sid())
+}
+
+/// 发给一个线程的信号, signo为0时只检查线程是否存在
+fn send_thread_signal(thread: &Thread, signo: usize) -> SyscallRet {
+    if signo > SIG_NUM {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let current = current_process();
+    if !may_kill(&current, &thread.process, signo) {
+        return Err(SyscallErr::EPERM.into());
+    }
+    if signo != 0 {
+        thread.send_signal_info(SigInfo::kill(
+            signo,
+            SI_TKILL,
+            current.getpid(),
+            current.cred().user.real,
+        ));
+    }
     Ok(0)
 }
+
+pub fn sys_tkill(tid: isize, signo: usize) -> SyscallRet {
+    trace!("[sys_tkill]: tid {}, signo {}", tid, signo);
+    if tid <= 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let thread = find_thread(tid as usize).ok_or(SyscallErr::ESRCH)?;
+    send_thread_signal(&thread, signo)
+}
+
+/// 与`tkill`相同, 但线程必须属于线程组`tgid`, 避免tid被复用后发错线程
+pub fn sys_tgkill(tgid: isize, tid: isize, signo: usize) -> SyscallRet {
+    trace!("[sys_tgkill]: tgid {}, tid {}, signo {}", tgid, tid, signo);
+    if tgid <= 0 || tid <= 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let thread = find_thread(tid as usize).ok_or(SyscallErr::ESRCH)?;
+    if thread.process.getpid() != tgid as usize {
+        return Err(SyscallErr::ESRCH.into());
+    }
+    send_thread_signal(&thread, signo)
+}
+
+/// 等到有未被屏蔽的信号
+struct SigSuspendFuture {
+    thread: Arc<Thread>,
+}
+
+impl Future for SigSuspendFuture {
+    type Output = SyscallRet;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.thread.set_interruptible_waker(cx.waker().clone());
+        if self.thread.have_unmasked_signals() {
+            Poll::Ready(Err(SyscallErr::EINTR.into()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// 临时替换信号屏蔽字并等待信号, 处理函数返回后才恢复原来的屏蔽字
+pub async fn sys_rt_sigsuspend(mask: usize) -> SyscallRet {
+    trace!("[sys_rt_sigsuspend]: mask {:#x}", mask);
+    let mask = read_sigmask(mask)?.ok_or(SyscallErr::EFAULT)?;
+    let future = SigSuspendFuture {
+        thread: current_thread().unwrap(),
+    };
+    wait_with_sigmask(future, None, Some(mask)).await.unwrap()
+}
+
+/// 被阻塞的待处理信号
+pub fn sys_rt_sigpending(set: usize) -> SyscallRet {
+    trace!("[sys_rt_sigpending]: set {:#x}", set);
+    UserCheck::new().check_writable_pages(set as *mut u8, size_of::<SigBitmap>())?;
+    let thread = current_thread().unwrap();
+    let blocked = thread.pending_signals() & thread.sig_mask();
+    unsafe {
+        *(set as *mut SigBitmap) = blocked;
+    }
+    Ok(0)
+}
+
+/// 从待处理信号中取出`set`中的一个, 被其他未屏蔽的信号打断时返回`EINTR`
+struct SigTimedWaitFuture {
+    thread: Arc<Thread>,
+    set: SigBitmap,
+}
+
+impl Future for SigTimedWaitFuture {
+    type Output = SysResult<SigInfo>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.thread.set_interruptible_waker(cx.waker().clone());
+        if let Some(info) = self.thread.dequeue_signal(self.set) {
+            Poll::Ready(Ok(info))
+        } else if self.thread.have_unmasked_signals() {
+            Poll::Ready(Err(SyscallErr::EINTR.into()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// 同步等待`set`中的信号, 返回信号编号, 超时返回`EAGAIN`
+pub async fn sys_rt_sigtimedwait(set: usize, info: usize, timeout: usize) -> SyscallRet {
+    trace!(
+        "[sys_rt_sigtimedwait]: set {:#x}, info {:#x}, timeout {:#x}",
+        set,
+        info,
+        timeout
+    );
+    let set = read_sigmask(set)?.ok_or(SyscallErr::EFAULT)? - SigBitmap::unblockable();
+    let timeout = read_timeout(timeout)?;
+    if info != 0 {
+        UserCheck::new().check_writable_pages(info as *mut u8, size_of::<SigInfo>())?;
+    }
+    let future = SigTimedWaitFuture {
+        thread: current_thread().unwrap(),
+        set,
+    };
+    let sig_info = wait_with_sigmask(future, timeout, None)
+        .await
+        .ok_or(SyscallErr::EAGAIN)??;
+    if info != 0 {
+        unsafe {
+            *(info as *mut SigInfo) = sig_info;
+        }
+    }
+    Ok(sig_info.si_signo as usize)
+}
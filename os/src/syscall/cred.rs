@@ -0,0 +1,382 @@
+//! 用户和组身份: `get*id`/`set*id`系列调用
+//!
+//! 实现参考：https://man7.org/linux/man-pages/man7/credentials.7.html
+//! 没有实现capabilities, 有效用户id为0的进程拥有全部特权
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+use log::trace;
+
+use crate::config::{SysResult, SyscallRet};
+use crate::mm::user_check::UserCheck;
+use crate::task::processor::current_process;
+use crate::utils::SyscallErr;
+
+/// 参数为-1表示不修改对应的id
+const ID_UNCHANGED: u32 = u32::MAX;
+/// 附加组的最大数量
+pub const NGROUPS_MAX: usize = 65536;
+
+/// 文件权限位, 与`st_mode`的低12位一致
+pub const S_ISUID: u16 = 0o4000;
+pub const S_ISGID: u16 = 0o2000;
+pub const S_ISVTX: u16 = 0o1000;
+/// init进程的`umask`
+pub const DEFAULT_UMASK: u16 = 0o022;
+
+/// 要检查的访问权限, 取值与`faccessat`的`R_OK`/`W_OK`/`X_OK`相同
+pub const MAY_EXEC: u16 = 1;
+pub const MAY_WRITE: u16 = 2;
+pub const MAY_READ: u16 = 4;
+
+/// 一组用户id或组id: 真实, 有效, 保存的和文件系统id
+#[derive(Clone, Copy)]
+pub struct IdSet {
+    pub real: u32,
+    pub effective: u32,
+    pub saved: u32,
+    /// 访问文件时使用, 除非由`setfs*id`修改, 总是跟随有效id
+    pub fs: u32,
+}
+
+impl IdSet {
+    pub const fn new(id: u32) -> Self {
+        Self {
+            real: id,
+            effective: id,
+            saved: id,
+            fs: id,
+        }
+    }
+
+    fn contains(&self, id: u32) -> bool {
+        id == self.real || id == self.effective || id == self.saved
+    }
+
+    /// `setuid`: 有特权时修改全部id, 否则只能把有效id改为真实或保存的id
+    fn set(&mut self, id: u32, privileged: bool) -> SysResult<()> {
+        if id == ID_UNCHANGED {
+            return Err(SyscallErr::EINVAL.into());
+        }
+        if privileged {
+            *self = Self::new(id);
+        } else if id == self.real || id == self.saved {
+            self.effective = id;
+            self.fs = id;
+        } else {
+            return Err(SyscallErr::EPERM.into());
+        }
+        Ok(())
+    }
+
+    /// `setreuid`: 修改了真实id, 或有效id被改成与原真实id不同时, 保存的id随有效id变化
+    fn set_re(&mut self, real: u32, effective: u32, privileged: bool) -> SysResult<()> {
+        if !privileged
+            && ((real != ID_UNCHANGED && real != self.real && real != self.effective)
+                || (effective != ID_UNCHANGED && !self.contains(effective)))
+        {
+            return Err(SyscallErr::EPERM.into());
+        }
+        let old_real = self.real;
+        if real != ID_UNCHANGED {
+            self.real = real;
+        }
+        if effective != ID_UNCHANGED {
+            self.effective = effective;
+        }
+        if real != ID_UNCHANGED || (effective != ID_UNCHANGED && effective != old_real) {
+            self.saved = self.effective;
+        }
+        self.fs = self.effective;
+        Ok(())
+    }
+
+    /// `setresuid`: 没有特权时每个新id都必须是当前的某个id
+    fn set_res(
+        &mut self,
+        real: u32,
+        effective: u32,
+        saved: u32,
+        privileged: bool,
+    ) -> SysResult<()> {
+        if !privileged
+            && [real, effective, saved]
+                .iter()
+                .any(|&id| id != ID_UNCHANGED && !self.contains(id))
+        {
+            return Err(SyscallErr::EPERM.into());
+        }
+        if real != ID_UNCHANGED {
+            self.real = real;
+        }
+        if effective != ID_UNCHANGED {
+            self.effective = effective;
+        }
+        if saved != ID_UNCHANGED {
+            self.saved = saved;
+        }
+        self.fs = self.effective;
+        Ok(())
+    }
+
+    /// `setfsuid`: 无论成功与否都返回原来的文件系统id
+    fn set_fs(&mut self, id: u32, privileged: bool) -> u32 {
+        let old = self.fs;
+        if id != ID_UNCHANGED && (privileged || self.contains(id) || id == self.fs) {
+            self.fs = id;
+        }
+        old
+    }
+}
+
+/// 进程的身份, fork和exec时继承
+#[derive(Clone)]
+pub struct Credentials {
+    pub user: IdSet,
+    pub group: IdSet,
+    /// 附加组
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// init进程以root身份运行
+    pub fn root() -> Self {
+        Self {
+            user: IdSet::new(0),
+            group: IdSet::new(0),
+            groups: Vec::new(),
+        }
+    }
+
+    /// 修改身份和资源限制等需要的特权
+    pub fn is_privileged(&self) -> bool {
+        self.user.effective == 0
+    }
+
+    /// 用真实id代替文件系统id, 用于`faccessat`
+    pub fn with_real_ids(&self) -> Self {
+        let mut cred = self.clone();
+        cred.user.fs = cred.user.real;
+        cred.group.fs = cred.group.real;
+        cred
+    }
+
+    /// 发送信号: 发送者的真实或有效用户id等于目标的真实或保存的用户id
+    pub fn may_signal(&self, target: &Credentials) -> bool {
+        self.is_privileged()
+            || [self.user.real, self.user.effective]
+                .iter()
+                .any(|&uid| uid == target.user.real || uid == target.user.saved)
+    }
+
+    pub fn in_group(&self, gid: u32) -> bool {
+        gid == self.group.fs || self.groups.contains(&gid)
+    }
+
+    /// 以文件系统身份访问属主为`uid:gid`, 权限为`perm`的文件, `access`为`MAY_*`的组合.
+    /// root可以读写任何文件, 但只能执行至少有一个执行位的文件
+    pub fn may_access(&self, uid: u32, gid: u32, perm: u16, is_dir: bool, access: u16) -> bool {
+        if self.user.fs == 0 {
+            return access & MAY_EXEC == 0 || is_dir || perm & 0o111 != 0;
+        }
+        let granted = if uid == self.user.fs {
+            perm >> 6
+        } else if self.in_group(gid) {
+            perm >> 3
+        } else {
+            perm
+        } & 0o7;
+        granted & access == access
+    }
+
+    /// exec时根据文件的setuid/setgid位修改有效id, 之后保存的id等于有效id.
+    /// 没有组执行位的setgid表示强制锁而不是setgid
+    pub fn exec(&mut self, file_owner: Option<(u32, u32, u16)>) {
+        if let Some((uid, gid, perm)) = file_owner {
+            if perm & S_ISUID != 0 {
+                self.user.effective = uid;
+            }
+            if perm & S_ISGID != 0 && perm & 0o010 != 0 {
+                self.group.effective = gid;
+            }
+        }
+        self.user.saved = self.user.effective;
+        self.user.fs = self.user.effective;
+        self.group.saved = self.group.effective;
+        self.group.fs = self.group.effective;
+    }
+
+    /// 有效id与真实id不同, 动态链接器据此忽略`LD_*`等环境变量
+    pub fn is_secure(&self) -> bool {
+        self.user.effective != self.user.real || self.group.effective != self.group.real
+    }
+}
+
+pub fn sys_getuid() -> SyscallRet {
+    Ok(current_process().cred().user.real as usize)
+}
+
+pub fn sys_geteuid() -> SyscallRet {
+    Ok(current_process().cred().user.effective as usize)
+}
+
+pub fn sys_getgid() -> SyscallRet {
+    Ok(current_process().cred().group.real as usize)
+}
+
+pub fn sys_getegid() -> SyscallRet {
+    Ok(current_process().cred().group.effective as usize)
+}
+
+pub fn sys_setuid(uid: u32) -> SyscallRet {
+    trace!("[sys_setuid] uid: {}", uid);
+    current_process().inner_handler(|inner| {
+        let privileged = inner.cred.is_privileged();
+        inner.cred.user.set(uid, privileged)
+    })?;
+    Ok(0)
+}
+
+/// 组id的修改同样以有效用户id为0作为特权
+pub fn sys_setgid(gid: u32) -> SyscallRet {
+    trace!("[sys_setgid] gid: {}", gid);
+    current_process().inner_handler(|inner| {
+        let privileged = inner.cred.is_privileged();
+        inner.cred.group.set(gid, privileged)
+    })?;
+    Ok(0)
+}
+
+pub fn sys_setreuid(ruid: u32, euid: u32) -> SyscallRet {
+    trace!("[sys_setreuid] ruid: {}, euid: {}", ruid, euid);
+    current_process().inner_handler(|inner| {
+        let privileged = inner.cred.is_privileged();
+        inner.cred.user.set_re(ruid, euid, privileged)
+    })?;
+    Ok(0)
+}
+
+pub fn sys_setregid(rgid: u32, egid: u32) -> SyscallRet {
+    trace!("[sys_setregid] rgid: {}, egid: {}", rgid, egid);
+    current_process().inner_handler(|inner| {
+        let privileged = inner.cred.is_privileged();
+        inner.cred.group.set_re(rgid, egid, privileged)
+    })?;
+    Ok(0)
+}
+
+pub fn sys_setresuid(ruid: u32, euid: u32, suid: u32) -> SyscallRet {
+    trace!(
+        "[sys_setresuid] ruid: {}, euid: {}, suid: {}",
+        ruid,
+        euid,
+        suid
+    );
+    current_process().inner_handler(|inner| {
+        let privileged = inner.cred.is_privileged();
+        inner.cred.user.set_res(ruid, euid, suid, privileged)
+    })?;
+    Ok(0)
+}
+
+pub fn sys_setresgid(rgid: u32, egid: u32, sgid: u32) -> SyscallRet {
+    trace!(
+        "[sys_setresgid] rgid: {}, egid: {}, sgid: {}",
+        rgid,
+        egid,
+        sgid
+    );
+    current_process().inner_handler(|inner| {
+        let privileged = inner.cred.is_privileged();
+        inner.cred.group.set_res(rgid, egid, sgid, privileged)
+    })?;
+    Ok(0)
+}
+
+/// 把真实, 有效和保存的id依次写到三个用户地址
+fn write_res_ids(ids: IdSet, real: *mut u32, effective: *mut u32, saved: *mut u32) -> SyscallRet {
+    for (ptr, id) in [
+        (real, ids.real),
+        (effective, ids.effective),
+        (saved, ids.saved),
+    ] {
+        UserCheck::new().check_writable_pages(ptr as *mut u8, size_of::<u32>())?;
+        unsafe {
+            *ptr = id;
+        }
+    }
+    Ok(0)
+}
+
+pub fn sys_getresuid(ruid: *mut u32, euid: *mut u32, suid: *mut u32) -> SyscallRet {
+    write_res_ids(current_process().cred().user, ruid, euid, suid)
+}
+
+pub fn sys_getresgid(rgid: *mut u32, egid: *mut u32, sgid: *mut u32) -> SyscallRet {
+    write_res_ids(current_process().cred().group, rgid, egid, sgid)
+}
+
+pub fn sys_setfsuid(fsuid: u32) -> SyscallRet {
+    trace!("[sys_setfsuid] fsuid: {}", fsuid);
+    let old = current_process().inner_handler(|inner| {
+        let privileged = inner.cred.is_privileged();
+        inner.cred.user.set_fs(fsuid, privileged)
+    });
+    Ok(old as usize)
+}
+
+pub fn sys_setfsgid(fsgid: u32) -> SyscallRet {
+    trace!("[sys_setfsgid] fsgid: {}", fsgid);
+    let old = current_process().inner_handler(|inner| {
+        let privileged = inner.cred.is_privileged();
+        inner.cred.group.set_fs(fsgid, privileged)
+    });
+    Ok(old as usize)
+}
+
+/// `size`为0时只返回附加组的数量
+pub fn sys_getgroups(size: i32, list: *mut u32) -> SyscallRet {
+    if size < 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let groups = current_process().cred().groups;
+    if size == 0 {
+        return Ok(groups.len());
+    }
+    if (size as usize) < groups.len() {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    UserCheck::new().check_writable_pages(list as *mut u8, groups.len() * size_of::<u32>())?;
+    unsafe {
+        core::ptr::copy_nonoverlapping(groups.as_ptr(), list, groups.len());
+    }
+    Ok(groups.len())
+}
+
+pub fn sys_setgroups(size: i32, list: *const u32) -> SyscallRet {
+    trace!("[sys_setgroups] size: {}", size);
+    if size < 0 || size as usize > NGROUPS_MAX {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let size = size as usize;
+    let process = current_process();
+    if !process.cred().is_privileged() {
+        return Err(SyscallErr::EPERM.into());
+    }
+    let groups = if size == 0 {
+        Vec::new()
+    } else {
+        UserCheck::new().check_readable_pages(list as *const u8, size * size_of::<u32>())?;
+        unsafe { core::slice::from_raw_parts(list, size) }.to_vec()
+    };
+    process.inner_handler(|inner| inner.cred.groups = groups);
+    Ok(0)
+}
+
+/// 设置新的`umask`, 返回原来的值
+pub fn sys_umask(mask: u32) -> SyscallRet {
+    trace!("[sys_umask] mask: {:#o}", mask);
+    let mask = (mask & 0o777) as u16;
+    let old = current_process().inner_handler(|inner| core::mem::replace(&mut inner.umask, mask));
+    Ok(old as usize)
+}
@@ -12,31 +12,45 @@ use alloc::vec::Vec;
 // use core::fmt::Error;
 use log::{debug, error, info, trace, warn};
 
-use crate::config::SyscallRet;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
+
+use crate::config::{SysResult, SyscallRet};
+use crate::fs::epoll::{EpollEvent, EpollFile, EPOLL_CTL_DEL};
 use crate::fs::fd_table::FdInfo;
 use crate::fs::inode::{Inode, InodeMode};
+use crate::fs::mount::{do_mount, do_umount, fs_root_of, MountFlags, UmountFlags, MOUNT_TABLE};
+use crate::fs::page_cache;
 use crate::fs::path::Path;
 use crate::fs::pipe::Pipe;
+use crate::fs::signalfd::SignalFd;
 use crate::fs::tty::TTY;
 use crate::fs::{
-    create_dir, open_fd, open_inode, open_osinode, Fstat, OSFileType, OpenFlags, AT_FDCWD,
-    AT_REMOVEDIR,
+    create_dir, open_fd, open_inode, open_osinode, File, Fstat, OSFileType, OSInode, OpenFlags,
+    PollEvents, AT_FDCWD, AT_REMOVEDIR, RENAME_EXCHANGE, RENAME_NOREPLACE,
 };
 // use crate::syscall::process;
-// use crate::mm::user_check::UserCheck;
+use crate::mm::user_check::UserCheck;
 // use crate::syscall::process;
 // use crate::syscall::process;
 // use crate::task::current_task;
-use crate::task::processor::current_process;
+use crate::signal::{read_sigmask, wait_with_sigmask, SigBitmap, SIGXFSZ};
+use crate::syscall::cred::{MAY_EXEC, MAY_READ, MAY_WRITE, S_ISGID, S_ISUID, S_ISVTX};
+use crate::syscall::resource::RLIMIT_FSIZE;
+use crate::task::processor::{current_process, current_thread};
 
-use crate::timer::{current_time_spec, TimeSpec};
+use crate::timer::{current_time_spec, read_timeout, TimeSpec};
 use crate::utils::{c_str_to_string, SyscallErr};
+use crate::USER_MAX_VA;
 
 pub async fn sys_write(fd: usize, buf: usize, len: usize) -> SyscallRet {
     // trace!("[sys_write] enter fd:{}, buf:{}, len is {}", fd, buf, len);
     let process = current_process();
     let fdinfo = process.inner_handler(|inner| inner.fd_table.get(fd));
     if let Some(fdinfo) = fdinfo {
+        UserCheck::new().check_readable_pages(buf as *const u8, len)?;
         let ret = fdinfo
             .file
             .write(unsafe { core::slice::from_raw_parts(buf as *const u8, len) })
@@ -56,6 +70,7 @@ pub async fn sys_read(
     /* cannot use `inner` as MutexGuard will cross `await` that way */
     let fdinfo = process.inner_handler(|inner| inner.fd_table.get(fd));
     if let Some(fdinfo) = fdinfo {
+        UserCheck::new().check_writable_pages(buf as *const u8, len)?;
         let ret = fdinfo
             .file
             .read(unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, len) })
@@ -73,50 +88,86 @@ pub fn sys_close(fd: usize) -> SyscallRet {
     ret
 }
 
-pub fn sys_openat(dirfd: isize, pathname: *const u8, flags: u32, _mode: usize) -> SyscallRet {
+pub fn sys_openat(dirfd: isize, pathname: *const u8, flags: u32, mode: usize) -> SyscallRet {
     trace!("[sys_openat] enter.");
     let process = current_process();
     let path = Path::from(c_str_to_string(pathname));
     let flags = OpenFlags::from_bits(flags).ok_or(SyscallErr::EINVAL)?;
     debug!(
-        "[sys_openat] dirfd: {}, pathname: {}, flags: {:?}",
-        dirfd, path, flags,
+        "[sys_openat] dirfd: {}, pathname: {}, flags: {:?}, mode: {:#o}",
+        dirfd, path, flags, mode
     );
 
-    if let Ok(osinode) = open_osinode(dirfd, &path, flags) {
-        let mode = osinode.inner_handler(|inner| inner.inode.as_ref().unwrap().get_meta().mode);
-        if mode == InodeMode::FileDIR
-            && (flags.contains(OpenFlags::WRONLY) || flags.contains(OpenFlags::RDWR))
-        {
-            error!(
-                "[sys_openat] pid {} fail to open file: {}",
-                process.pid, path
-            );
-            return Err(SyscallErr::EISDIR as usize);
-        }
-        let fd = if mode == InodeMode::FileCHR {
-            process
-                .inner_lock()
-                .fd_table
-                .alloc_and_set(0, FdInfo::default_flags(TTY.clone()))?
-        } else {
-            process
-                .inner_lock()
-                .fd_table
-                .alloc_and_set(0, FdInfo::default_flags(osinode))?
-        };
-        info!(
-            "[sys_openat] pid {} succeed to open file: {} -> fd: {}",
-            process.pid, path, fd
-        );
-        Ok(fd)
+    // 只查找一次路径: 先打开父目录, 再在其中查找最后一项, 不存在时在父目录中创建
+    let cred = process.cred();
+    let (inode, created) = if path.len() == 0 {
+        (open_inode(dirfd, &path, OpenFlags::empty())?, false)
     } else {
+        let parent = open_inode(dirfd, &path.parent(), OpenFlags::empty())?;
+        let name = path.get_name();
+        match parent.open_path(&Path::from(name.as_str()), false, false) {
+            Ok(inode) => (inode, false),
+            Err(_) if flags.contains(OpenFlags::CREATE) => {
+                parent
+                    .get_meta()
+                    .check_access(&cred, MAY_WRITE | MAY_EXEC)?;
+                let inode = parent.mknod_v(&name, InodeMode::FileREG)?;
+                inode.get_meta().init_owner(
+                    &cred,
+                    &parent.get_meta(),
+                    mode as u16 & !process.umask(),
+                );
+                inode.sync_attr()?;
+                (inode, true)
+            }
+            Err(_) => return Err(SyscallErr::ENOENT.into()),
+        }
+    };
+
+    // 跟随符号链接后, 对真正打开的文件检查读写权限; 刚创建的文件不受其权限位限制
+    let (readable, writable) = flags.read_write();
+    let osinode = Arc::new(OSInode::new(readable, writable, inode).ok_or(SyscallErr::ENOENT)?);
+    let inode = osinode.inner_handler(|inner| inner.inode.as_ref().unwrap().clone());
+    let meta = inode.get_meta();
+    if !created {
+        let mut access = 0;
+        if readable {
+            access |= MAY_READ;
+        }
+        if writable || flags.contains(OpenFlags::TRUNC) {
+            access |= MAY_WRITE;
+        }
+        meta.check_access(&cred, access)?;
+    }
+    let mode = meta.mode;
+    if mode == InodeMode::FileDIR
+        && (flags.contains(OpenFlags::WRONLY) || flags.contains(OpenFlags::RDWR))
+    {
         error!(
             "[sys_openat] pid {} fail to open file: {}",
             process.pid, path
         );
-        Err(1)
+        return Err(SyscallErr::EISDIR as usize);
     }
+    if flags.contains(OpenFlags::TRUNC) && !created && mode == InodeMode::FileREG {
+        inode.truncate(0)?;
+    }
+    let fd = if mode == InodeMode::FileCHR {
+        process
+            .inner_lock()
+            .fd_table
+            .alloc_and_set(0, FdInfo::default_flags(TTY.clone()))?
+    } else {
+        process
+            .inner_lock()
+            .fd_table
+            .alloc_and_set(0, FdInfo::default_flags(osinode))?
+    };
+    info!(
+        "[sys_openat] pid {} succeed to open file: {} -> fd: {}",
+        process.pid, path, fd
+    );
+    Ok(fd)
 }
 
 pub fn sys_chdir(pathname: *const u8) -> SyscallRet {
@@ -126,21 +177,78 @@ pub fn sys_chdir(pathname: *const u8) -> SyscallRet {
         current_process().getpid(),
         path
     );
-    // simply examine validity of the path
-    match open_osinode(AT_FDCWD, &path, OpenFlags::empty()) {
-        Ok(inode) => {
-            current_process().inner_handler(|inner| inner.cwd = inode.get_path());
-            Ok(0)
-        }
-        Err(_) => Err(1),
+    let osinode = open_osinode(AT_FDCWD, &path, OpenFlags::empty())?;
+    let meta = osinode.inner_handler(|inner| inner.inode.as_ref().unwrap().get_meta());
+    if meta.mode != InodeMode::FileDIR {
+        return Err(SyscallErr::ENOTDIR.into());
     }
+    meta.check_access(&current_process().cred(), MAY_EXEC)?;
+    current_process().inner_handler(|inner| inner.cwd = osinode.get_path());
+    Ok(0)
+}
+
+pub fn sys_mount(
+    source: *const u8,
+    target: *const u8,
+    fstype: *const u8,
+    flags: u32,
+    _data: usize,
+) -> SyscallRet {
+    // source is ignored by pseudo filesystems and may be NULL
+    let source = if source.is_null() {
+        "none".to_string()
+    } else {
+        c_str_to_string(source)
+    };
+    let target = Path::from(c_str_to_string(target));
+    let fstype = if fstype.is_null() {
+        "".to_string()
+    } else {
+        c_str_to_string(fstype)
+    };
+    trace!(
+        "[sys_mount] source: {}, target: {}, fstype: {}, flags: {:#x}",
+        source,
+        target,
+        fstype,
+        flags
+    );
+    // 高 16 位可能是旧式的 MS_MGC_VAL 魔数，未知位一律忽略
+    let flags = MountFlags::from_bits_truncate(flags);
+    do_mount(&source, &target, &fstype, flags)
 }
 
-pub fn sys_mkdirat(dirfd: isize, pathname: *const u8, _mode: usize) -> SyscallRet {
+pub fn sys_umount2(target: *const u8, flags: u32) -> SyscallRet {
+    let target = Path::from(c_str_to_string(target));
+    trace!("[sys_umount2] target: {}, flags: {:#x}", target, flags);
+    let flags = UmountFlags::from_bits_truncate(flags);
+    do_umount(&target, flags)
+}
+
+pub fn sys_mkdirat(dirfd: isize, pathname: *const u8, mode: usize) -> SyscallRet {
     trace!("[sys_mkdirat] enter");
     let path = Path::from(c_str_to_string(pathname));
-    trace!("[sys_mkdirat] dirfd: {}, pathname: {}", dirfd, path);
-    create_dir(dirfd, &path)
+    trace!(
+        "[sys_mkdirat] dirfd: {}, pathname: {}, mode: {:#o}",
+        dirfd,
+        path,
+        mode
+    );
+    if open_inode(dirfd, &path, OpenFlags::empty()).is_ok() {
+        return create_dir(dirfd, &path);
+    }
+    let process = current_process();
+    let cred = process.cred();
+    let parent = open_inode(dirfd, &path.parent(), OpenFlags::empty())?;
+    parent
+        .get_meta()
+        .check_access(&cred, MAY_WRITE | MAY_EXEC)?;
+    create_dir(dirfd, &path)?;
+    let dir = open_inode(dirfd, &path, OpenFlags::empty())?;
+    dir.get_meta()
+        .init_owner(&cred, &parent.get_meta(), mode as u16 & !process.umask());
+    dir.sync_attr()?;
+    Ok(0)
 }
 
 pub fn sys_fstat(fd: usize, buf: *mut Fstat) -> SyscallRet {
@@ -256,18 +364,222 @@ pub fn sys_unlinkat(dirfd: isize, pathname: *const u8, flags: u32) -> SyscallRet
     let path = Path::from(c_str_to_string(pathname));
     match open_inode(dirfd, &path, OpenFlags::empty()) {
         Ok(inode) => {
-            let mode = inode.get_meta().mode;
-            if mode == InodeMode::FileREG || (mode == InodeMode::FileDIR && flags == AT_REMOVEDIR) {
-                inode.delete();
-                Ok(0)
+            check_unlink(&inode)?;
+            let is_dir = inode.get_meta().mode == InodeMode::FileDIR;
+            if is_dir && flags != AT_REMOVEDIR {
+                Err(SyscallErr::EISDIR.into())
+            } else if !is_dir && flags == AT_REMOVEDIR {
+                Err(SyscallErr::ENOTDIR.into())
             } else {
-                Err(1)
+                inode.delete()?;
+                Ok(0)
             }
         }
         Err(_) => Err(1),
     }
 }
 
+pub fn sys_renameat2(
+    olddirfd: isize,
+    oldpath: *const u8,
+    newdirfd: isize,
+    newpath: *const u8,
+    flags: u32,
+) -> SyscallRet {
+    let old_path = Path::from(c_str_to_string(oldpath));
+    let new_path = Path::from(c_str_to_string(newpath));
+    trace!(
+        "[sys_renameat2] olddirfd: {}, oldpath: {}, newdirfd: {}, newpath: {}, flags: {:#x}",
+        olddirfd,
+        old_path,
+        newdirfd,
+        new_path,
+        flags
+    );
+    let exchange = flags & RENAME_EXCHANGE != 0;
+    if flags & !(RENAME_NOREPLACE | RENAME_EXCHANGE) != 0
+        || flags == RENAME_NOREPLACE | RENAME_EXCHANGE
+    {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    // `.`和`..`不能被移动或替换
+    let new_name = new_path.get_name();
+    if [old_path.get_name(), new_name.clone()]
+        .iter()
+        .any(|name| name.is_empty() || name == "." || name == "..")
+    {
+        return Err(SyscallErr::EBUSY.into());
+    }
+
+    let old = open_inode(olddirfd, &old_path, OpenFlags::empty())
+        .map_err(|_| SyscallErr::ENOENT as usize)?;
+    let new_dir = open_inode(newdirfd, &new_path.parent(), OpenFlags::empty())
+        .map_err(|_| SyscallErr::ENOENT as usize)?;
+    if new_dir.get_meta().mode != InodeMode::FileDIR {
+        return Err(SyscallErr::ENOTDIR.into());
+    }
+    let target = new_dir.find(&new_name).ok();
+    if let Some(target) = &target {
+        if Arc::ptr_eq(&target.get_meta(), &old.get_meta()) {
+            return Ok(0);
+        }
+    }
+    {
+        let table = MOUNT_TABLE.lock();
+        if table.is_mount_point(&old) || target.as_ref().is_some_and(|t| table.is_mount_point(t)) {
+            return Err(SyscallErr::EBUSY.into());
+        }
+    }
+    if !Arc::ptr_eq(
+        &fs_root_of(&old).get_meta(),
+        &fs_root_of(&new_dir).get_meta(),
+    ) {
+        return Err(SyscallErr::EXDEV.into());
+    }
+
+    let old_is_dir = old.get_meta().mode == InodeMode::FileDIR;
+    match &target {
+        None if exchange => return Err(SyscallErr::ENOENT.into()),
+        Some(_) if flags & RENAME_NOREPLACE != 0 => return Err(SyscallErr::EEXIST.into()),
+        Some(target) if !exchange => {
+            let target_is_dir = target.get_meta().mode == InodeMode::FileDIR;
+            if old_is_dir && !target_is_dir {
+                return Err(SyscallErr::ENOTDIR.into());
+            } else if !old_is_dir && target_is_dir {
+                return Err(SyscallErr::EISDIR.into());
+            } else if target_is_dir && !target.list()?.is_empty() {
+                return Err(SyscallErr::ENOTEMPTY.into());
+            }
+        }
+        _ => {}
+    }
+    // 目录不能移动到自己的子树中
+    let old_dir = old
+        .get_meta()
+        .inner
+        .lock()
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .ok_or(SyscallErr::EBUSY)?;
+    if old_is_dir && old.is_ancestor_of(&new_dir) {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    if let Some(target) = target.as_ref().filter(|_| exchange) {
+        if target.get_meta().mode == InodeMode::FileDIR && target.is_ancestor_of(&old_dir) {
+            return Err(SyscallErr::EINVAL.into());
+        }
+    }
+
+    check_unlink(&old)?;
+    new_dir
+        .get_meta()
+        .check_access(&current_process().cred(), MAY_WRITE | MAY_EXEC)?;
+    if let Some(target) = &target {
+        check_unlink(target)?;
+    }
+    old.rename_v(&new_dir, &new_name, target, exchange)?;
+    Ok(0)
+}
+
+/// 新建文件需要父目录的写和搜索权限, 返回父目录
+fn create_parent(dirfd: isize, path: &Path) -> SysResult<Arc<dyn Inode>> {
+    if open_inode(dirfd, path, OpenFlags::empty()).is_ok() {
+        return Err(SyscallErr::EEXIST.into());
+    }
+    let parent = open_inode(dirfd, &path.parent(), OpenFlags::empty())?;
+    parent
+        .get_meta()
+        .check_access(&current_process().cred(), MAY_WRITE | MAY_EXEC)?;
+    Ok(parent)
+}
+
+pub fn sys_symlinkat(target: *const u8, newdirfd: isize, linkpath: *const u8) -> SyscallRet {
+    let target = c_str_to_string(target);
+    let path = Path::from(c_str_to_string(linkpath));
+    trace!(
+        "[sys_symlinkat] target: {}, newdirfd: {}, linkpath: {}",
+        target,
+        newdirfd,
+        path
+    );
+    if target.is_empty() {
+        return Err(SyscallErr::ENOENT.into());
+    }
+    let parent = create_parent(newdirfd, &path)?;
+    let inode = parent.symlink_v(&path.get_name(), &target)?;
+    inode
+        .get_meta()
+        .init_owner(&current_process().cred(), &parent.get_meta(), 0o777);
+    inode.sync_attr()?;
+    Ok(0)
+}
+
+pub fn sys_mknodat(dirfd: isize, pathname: *const u8, mode: u32, dev: u64) -> SyscallRet {
+    let path = Path::from(c_str_to_string(pathname));
+    trace!(
+        "[sys_mknodat] dirfd: {}, pathname: {}, mode: {:#o}, dev: {:#x}",
+        dirfd,
+        path,
+        mode,
+        dev
+    );
+    let inode_mode = match mode & 0o170000 {
+        0 => InodeMode::FileREG,
+        0o100000 => InodeMode::FileREG,
+        0o020000 => InodeMode::FileCHR,
+        0o060000 => InodeMode::FileBLK,
+        0o010000 => InodeMode::FileFIFO,
+        0o140000 => InodeMode::FileSOCK,
+        _ => return Err(SyscallErr::EINVAL.into()),
+    };
+    let process = current_process();
+    let cred = process.cred();
+    let is_dev = inode_mode == InodeMode::FileCHR || inode_mode == InodeMode::FileBLK;
+    if is_dev && !cred.is_privileged() {
+        return Err(SyscallErr::EPERM.into());
+    }
+    let parent = create_parent(dirfd, &path)?;
+    let rdev = if is_dev { dev } else { 0 };
+    let inode = parent.mknod_dev_v(&path.get_name(), inode_mode, rdev)?;
+    inode
+        .get_meta()
+        .init_owner(&cred, &parent.get_meta(), mode as u16 & !process.umask());
+    inode.sync_attr()?;
+    Ok(0)
+}
+
+/// 删除需要父目录的写和搜索权限; 父目录有粘滞位时,
+/// 只有文件或目录的属主(或root)才能删除
+fn check_unlink(inode: &Arc<dyn Inode>) -> SysResult<()> {
+    let Some(parent) = inode
+        .get_meta()
+        .inner
+        .lock()
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+    else {
+        return Ok(());
+    };
+    let cred = current_process().cred();
+    let parent_meta = parent.get_meta();
+    parent_meta.check_access(&cred, MAY_WRITE | MAY_EXEC)?;
+    let (dir_uid, dir_perm) = {
+        let inner = parent_meta.inner.lock();
+        (inner.uid, inner.perm)
+    };
+    let file_uid = inode.get_meta().inner.lock().uid;
+    if dir_perm & S_ISVTX != 0
+        && cred.user.fs != 0
+        && cred.user.fs != file_uid
+        && cred.user.fs != dir_uid
+    {
+        return Err(SyscallErr::EPERM.into());
+    }
+    Ok(())
+}
+
 pub fn sys_pipe2(fdset: *const u8, flags: u32) -> SyscallRet {
     trace!("[sys_pipe2] enter, flags: {}", flags);
     let process = current_process();
@@ -416,6 +728,7 @@ pub async fn sys_writev(fd: usize, iov: usize, iovcnt: i32) -> SyscallRet {
     if let Some(fdinfo) = fdinfo {
         let mut ret: usize = 0;
         for slice in iovec.iter() {
+            UserCheck::new().check_readable_pages(slice.as_ptr(), slice.len())?;
             ret += fdinfo.file.write(slice).await?;
         }
         Ok(ret)
@@ -438,6 +751,7 @@ pub async fn sys_readv(fd: usize, iov: usize, iovcnt: i32) -> SyscallRet {
     if let Some(fdinfo) = fdinfo {
         let mut ret: usize = 0;
         for slice in iovec.iter_mut() {
+            UserCheck::new().check_writable_pages(slice.as_ptr(), slice.len())?;
             ret += fdinfo.file.read(slice).await?;
         }
         Ok(ret)
@@ -458,51 +772,330 @@ pub struct PollFd {
     pub revents: i16,
 }
 
-bitflags! {
-    /// Poll events
-    pub struct PollEvents: i16 {
-        /// There is data to read
-        const POLLIN = 1 << 0;
-        /// Execption about fd
-        const POLLPRI = 1 << 1;
-        /// There is data to write
-        const POLLOUT = 1 << 2;
-        /// Error condition
-        const POLLERR = 1 << 3;
-        /// Hang up
-        const POLLHUP = 1 << 4;
-        /// Invalid request: fd not open
-        const POLLNVAL = 1 << 5;
-    }
-}
-
-// do not support timeout and sigmask
-pub fn sys_ppoll(
+/// `select`的`fd_set`最多容纳的fd数
+const FD_SETSIZE: usize = 1024;
+const FD_SET_BITS: usize = 8 * size_of::<usize>();
+
+/// `sys_pselect6`的最后一个参数
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PselectSigmask {
+    ss: usize,
+    ss_len: usize,
+}
+
+enum PollTarget {
+    /// 负数fd, 不关心
+    Ignored,
+    /// fd没有打开
+    Invalid,
+    File(Arc<dyn File>, PollEvents),
+}
+
+/// 等待任一文件就绪, 或者收到未被屏蔽的信号
+struct PollFilesFuture {
+    targets: Vec<PollTarget>,
+}
+
+impl Future for PollFilesFuture {
+    type Output = SysResult<Vec<PollEvents>>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let thread = current_thread().unwrap();
+        // 先登记再检查信号, 避免错过检查之后到达的信号
+        thread.set_interruptible_waker(cx.waker().clone());
+        let revents: Vec<PollEvents> = self
+            .targets
+            .iter()
+            .map(|target| match target {
+                PollTarget::Ignored => PollEvents::empty(),
+                PollTarget::Invalid => PollEvents::POLLNVAL,
+                PollTarget::File(file, events) => file.poll(*events, Some(cx.waker())),
+            })
+            .collect();
+        if revents.iter().any(|revent| !revent.is_empty()) {
+            Poll::Ready(Ok(revents))
+        } else if thread.have_unmasked_signals() {
+            Poll::Ready(Err(SyscallErr::EINTR.into()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// `ppoll`和`pselect6`共用, 超时返回全空的结果
+async fn poll_files(
+    targets: Vec<PollTarget>,
+    timeout: Option<Duration>,
+    sigmask: Option<SigBitmap>,
+) -> SysResult<Vec<PollEvents>> {
+    let nfds = targets.len();
+    wait_with_sigmask(PollFilesFuture { targets }, timeout, sigmask)
+        .await
+        .unwrap_or_else(|| Ok(vec![PollEvents::empty(); nfds]))
+}
+
+pub async fn sys_ppoll(
     fds_ptr: usize,
     nfds: usize,
-    _timeout_ptr: usize,
-    _sigmask_ptr: usize,
+    timeout_ptr: usize,
+    sigmask_ptr: usize,
 ) -> SyscallRet {
     trace!(
-        // "[sys_ppoll] enter. nfds: {}, timeout_ptr: {:#x}, sigmask_ptr: {:#x}",
-        "[sys_ppoll] enter. nfds: {}",
+        "[sys_ppoll] enter. nfds: {}, timeout_ptr: {:#x}, sigmask_ptr: {:#x}",
         nfds,
-        // timeout_ptr,
-        // sigmask_ptr
+        timeout_ptr,
+        sigmask_ptr
     );
-    warn!("[sys_ppoll] not fully implemented");
+    UserCheck::new().check_writable_pages(fds_ptr as *mut u8, nfds * size_of::<PollFd>())?;
+    let timeout = read_timeout(timeout_ptr)?;
+    let sigmask = read_sigmask(sigmask_ptr)?;
     let fds = unsafe { core::slice::from_raw_parts_mut(fds_ptr as *mut PollFd, nfds) };
-    // let _timeout = unsafe { &*(timeout_ptr as *const TimeSpec) };
-    // let _sigmask = unsafe { &*(sigmask_ptr as *const usize) };
+    let process = current_process();
+    let targets = fds
+        .iter()
+        .map(|poll_fd| {
+            if poll_fd.fd < 0 {
+                return PollTarget::Ignored;
+            }
+            match process.inner_handler(|inner| inner.fd_table.get(poll_fd.fd as usize)) {
+                Some(fd_info) => {
+                    PollTarget::File(fd_info.file, PollEvents::from_bits_truncate(poll_fd.events))
+                }
+                None => PollTarget::Invalid,
+            }
+        })
+        .collect();
+    let revents = poll_files(targets, timeout, sigmask).await?;
+    let mut ret = 0;
+    for (poll_fd, revent) in fds.iter_mut().zip(revents) {
+        poll_fd.revents = revent.bits();
+        if !revent.is_empty() {
+            ret += 1;
+        }
+    }
+    Ok(ret)
+}
+
+/// 读出`fd_set`的前`nfds`位, 空指针表示不关心
+fn read_fd_set(ptr: usize, nfds: usize) -> SysResult<Option<Vec<usize>>> {
+    if ptr == 0 {
+        return Ok(None);
+    }
+    let len = (nfds + FD_SET_BITS - 1) / FD_SET_BITS;
+    UserCheck::new().check_writable_pages(ptr as *mut u8, len * size_of::<usize>())?;
+    let set = unsafe { core::slice::from_raw_parts(ptr as *const usize, len) };
+    Ok(Some(set.to_vec()))
+}
+
+fn write_fd_set(ptr: usize, set: &Option<Vec<usize>>) {
+    if let Some(set) = set {
+        let user_set = unsafe { core::slice::from_raw_parts_mut(ptr as *mut usize, set.len()) };
+        user_set.copy_from_slice(set);
+    }
+}
+
+fn fd_set_contains(set: &Option<Vec<usize>>, fd: usize) -> bool {
+    set.as_ref().map_or(false, |set| {
+        set[fd / FD_SET_BITS] & (1 << (fd % FD_SET_BITS)) != 0
+    })
+}
+
+fn fd_set_insert(set: &mut Option<Vec<usize>>, fd: usize) {
+    if let Some(set) = set {
+        set[fd / FD_SET_BITS] |= 1 << (fd % FD_SET_BITS);
+    }
+}
+
+pub async fn sys_pselect6(
+    nfds: usize,
+    readfds: usize,
+    writefds: usize,
+    exceptfds: usize,
+    timeout_ptr: usize,
+    sigmask_ptr: usize,
+) -> SyscallRet {
+    trace!(
+        "[sys_pselect6] enter. nfds: {}, timeout_ptr: {:#x}, sigmask_ptr: {:#x}",
+        nfds,
+        timeout_ptr,
+        sigmask_ptr
+    );
+    if nfds > FD_SETSIZE {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let sets = [
+        read_fd_set(readfds, nfds)?,
+        read_fd_set(writefds, nfds)?,
+        read_fd_set(exceptfds, nfds)?,
+    ];
+    let timeout = read_timeout(timeout_ptr)?;
+    let sigmask = if sigmask_ptr == 0 {
+        None
+    } else {
+        UserCheck::new()
+            .check_readable_pages(sigmask_ptr as *const u8, size_of::<PselectSigmask>())?;
+        let sigmask = unsafe { *(sigmask_ptr as *const PselectSigmask) };
+        read_sigmask(sigmask.ss)?
+    };
+    let wanted = [PollEvents::POLLIN, PollEvents::POLLOUT, PollEvents::POLLPRI];
+    let process = current_process();
+    let mut fds = Vec::new();
+    let mut targets = Vec::new();
+    for fd in 0..nfds {
+        let events = sets
+            .iter()
+            .zip(wanted)
+            .filter(|(set, _)| fd_set_contains(set, fd))
+            .fold(PollEvents::empty(), |events, (_, wanted)| events | wanted);
+        if events.is_empty() {
+            continue;
+        }
+        let fd_info = process
+            .inner_handler(|inner| inner.fd_table.get(fd))
+            .ok_or(SyscallErr::EBADF)?;
+        fds.push(fd);
+        targets.push(PollTarget::File(fd_info.file, events));
+    }
+    let revents = poll_files(targets, timeout, sigmask).await?;
+    // 挂断和出错时读写都不会阻塞, 也算作就绪
+    let ready = [
+        PollEvents::POLLIN | PollEvents::POLLHUP | PollEvents::POLLERR,
+        PollEvents::POLLOUT | PollEvents::POLLERR,
+        PollEvents::POLLPRI,
+    ];
+    let mut ready_sets = sets.clone();
+    for set in ready_sets.iter_mut().flatten() {
+        set.fill(0);
+    }
     let mut ret = 0;
-    for fd in fds {
-        // debug!("[sys_ppoll] fd: {}, events: {}", fd.fd, fd.events);
-        fd.revents = fd.events;
-        ret += 1;
+    for (fd, revent) in fds.into_iter().zip(revents) {
+        for (i, set) in ready_sets.iter_mut().enumerate() {
+            if fd_set_contains(&sets[i], fd) && revent.intersects(ready[i]) {
+                fd_set_insert(set, fd);
+                ret += 1;
+            }
+        }
     }
+    write_fd_set(readfds, &ready_sets[0]);
+    write_fd_set(writefds, &ready_sets[1]);
+    write_fd_set(exceptfds, &ready_sets[2]);
     Ok(ret)
 }
 
+/// `epoll_create1`唯一的标志
+const EPOLL_CLOEXEC: usize = 0x80000;
+
+pub fn sys_epoll_create1(flags: usize) -> SyscallRet {
+    trace!("[sys_epoll_create1] enter. flags: {:#x}", flags);
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let mut fd_info = FdInfo::default_flags(EpollFile::new());
+    if flags & EPOLL_CLOEXEC != 0 {
+        fd_info.flags |= OpenFlags::CLOEXEC;
+    }
+    let fd = current_process()
+        .inner_lock()
+        .fd_table
+        .alloc_and_set(0, fd_info)?;
+    Ok(fd)
+}
+
+pub fn sys_epoll_ctl(epfd: usize, op: usize, fd: usize, event_ptr: usize) -> SyscallRet {
+    trace!(
+        "[sys_epoll_ctl] enter. epfd: {}, op: {}, fd: {}",
+        epfd,
+        op,
+        fd
+    );
+    let process = current_process();
+    let epoll = process
+        .inner_handler(|inner| inner.fd_table.get(epfd))
+        .ok_or(SyscallErr::EBADF)?;
+    let target = process
+        .inner_handler(|inner| inner.fd_table.get(fd))
+        .ok_or(SyscallErr::EBADF)?;
+    let epoll = EpollFile::from_file(epoll.file).ok_or(SyscallErr::EINVAL)?;
+    if fd == epfd {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    // 与Linux一致, 普通文件总是就绪, 不能加入epoll
+    if target.file.get_meta().filetype == OSFileType::OSInode {
+        return Err(SyscallErr::EPERM.into());
+    }
+    let event = if op == EPOLL_CTL_DEL {
+        EpollEvent { events: 0, data: 0 }
+    } else {
+        UserCheck::new().check_readable_pages(event_ptr as *const u8, size_of::<EpollEvent>())?;
+        unsafe { *(event_ptr as *const EpollEvent) }
+    };
+    epoll.ctl(op, fd, target.file, event)
+}
+
+/// 等待epoll中任一项就绪, 或者收到未被屏蔽的信号
+struct EpollWaitFuture {
+    epoll: Arc<EpollFile>,
+    max_events: usize,
+}
+
+impl Future for EpollWaitFuture {
+    type Output = SysResult<Vec<EpollEvent>>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let thread = current_thread().unwrap();
+        thread.set_interruptible_waker(cx.waker().clone());
+        loop {
+            let events = self.epoll.collect_events(self.max_events);
+            if !events.is_empty() {
+                return Poll::Ready(Ok(events));
+            }
+            // 检查期间又有项放入就绪队列时重新检查
+            if self.epoll.register_waiter(cx.waker()) {
+                break;
+            }
+        }
+        if thread.have_unmasked_signals() {
+            Poll::Ready(Err(SyscallErr::EINTR.into()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+pub async fn sys_epoll_pwait(
+    epfd: usize,
+    events_ptr: usize,
+    max_events: i32,
+    timeout_ms: i32,
+    sigmask_ptr: usize,
+) -> SyscallRet {
+    trace!(
+        "[sys_epoll_pwait] enter. epfd: {}, max_events: {}, timeout_ms: {}",
+        epfd,
+        max_events,
+        timeout_ms
+    );
+    if max_events <= 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let max_events = max_events as usize;
+    UserCheck::new()
+        .check_writable_pages(events_ptr as *mut u8, max_events * size_of::<EpollEvent>())?;
+    let sigmask = read_sigmask(sigmask_ptr)?;
+    let epoll = current_process()
+        .inner_handler(|inner| inner.fd_table.get(epfd))
+        .ok_or(SyscallErr::EBADF)?;
+    let epoll = EpollFile::from_file(epoll.file).ok_or(SyscallErr::EINVAL)?;
+    // 负数表示永久等待
+    let timeout = (timeout_ms >= 0).then(|| Duration::from_millis(timeout_ms as u64));
+    let events = wait_with_sigmask(EpollWaitFuture { epoll, max_events }, timeout, sigmask)
+        .await
+        .unwrap_or_else(|| Ok(Vec::new()))?;
+    let user_events =
+        unsafe { core::slice::from_raw_parts_mut(events_ptr as *mut EpollEvent, events.len()) };
+    user_events.copy_from_slice(&events);
+    Ok(events.len())
+}
+
 pub fn sys_fstatat(dirfd: i32, pathname: *const u8, buf: *mut Fstat, flags: i32) -> SyscallRet {
     let pathname = c_str_to_string(pathname);
     trace!(
@@ -536,16 +1129,123 @@ bitflags! {
     }
 }
 
+/// 默认用真实id检查权限, `AT_EACCESS`时用有效id
 pub fn sys_faccessat(fd: i32, path: *const u8, amode: u32, flag: u32) -> SyscallRet {
     trace!("[sys_faccessat] enter.");
-    warn!("[sys_faccessat] not fully implemented.");
-    let _amode = FaccessatFlags::from_bits(amode).ok_or(SyscallErr::EINVAL)?;
-    let _flag = FcntlFlags::from_bits(flag).ok_or(SyscallErr::EINVAL)?;
+    let amode = FaccessatFlags::from_bits(amode).ok_or(SyscallErr::EINVAL)?;
+    let flag = FcntlFlags::from_bits(flag).ok_or(SyscallErr::EINVAL)?;
     let path = Path::from(c_str_to_string(path));
-    let _inode = open_inode(fd as isize, &path, OpenFlags::empty())?;
+    let inode = open_inode(fd as isize, &path, OpenFlags::empty())?;
+    let cred = current_process().cred();
+    let cred = if flag.contains(FcntlFlags::AT_EACCESS) {
+        cred
+    } else {
+        cred.with_real_ids()
+    };
+    inode.get_meta().check_access(&cred, amode.bits() as u16)?;
     Ok(0)
 }
 
+/// `fd`对应的文件的inode
+fn fd_inode(fd: usize) -> SysResult<Arc<dyn Inode>> {
+    let file = open_fd(fd).ok_or(SyscallErr::EBADF)?;
+    let inode = file.get_meta().inner.lock().inode.clone();
+    inode.ok_or(SyscallErr::EBADF.into())
+}
+
+/// `*at`调用的目标: 路径为空且有`AT_EMPTY_PATH`时是`dirfd`本身
+fn at_inode(dirfd: isize, pathname: *const u8, flags: FcntlFlags) -> SysResult<Arc<dyn Inode>> {
+    let pathname = c_str_to_string(pathname);
+    if pathname.is_empty() && flags.contains(FcntlFlags::AT_EMPTY_PATH) {
+        fd_inode(dirfd as usize)
+    } else {
+        open_inode(dirfd, &Path::from(pathname), OpenFlags::empty())
+    }
+}
+
+/// 只有属主和root能修改权限; 不在文件所属组中时不能设置setgid位
+fn do_chmod(inode: &Arc<dyn Inode>, mode: u16) -> SyscallRet {
+    let cred = current_process().cred();
+    let meta = inode.get_meta();
+    let mut inner = meta.inner.lock();
+    if !cred.is_privileged() && cred.user.fs != inner.uid {
+        return Err(SyscallErr::EPERM.into());
+    }
+    let mut perm = mode & 0o7777;
+    if !cred.is_privileged() && !cred.in_group(inner.gid) {
+        perm &= !S_ISGID;
+    }
+    inner.perm = perm;
+    inner.st_ctim = current_time_spec();
+    drop(inner);
+    inode.sync_attr()?;
+    Ok(0)
+}
+
+/// 只有root能修改属主; 属主可以把组改为自己所在的组.
+/// 普通文件的setuid位和(有组执行位时的)setgid位会被清除
+fn do_chown(inode: &Arc<dyn Inode>, uid: u32, gid: u32) -> SyscallRet {
+    let cred = current_process().cred();
+    let meta = inode.get_meta();
+    let mut inner = meta.inner.lock();
+    let uid_changed = uid != u32::MAX && uid != inner.uid;
+    let gid_changed = gid != u32::MAX && gid != inner.gid;
+    if !cred.is_privileged()
+        && (uid_changed || (gid != u32::MAX && (cred.user.fs != inner.uid || !cred.in_group(gid))))
+    {
+        return Err(SyscallErr::EPERM.into());
+    }
+    if uid != u32::MAX {
+        inner.uid = uid;
+    }
+    if gid != u32::MAX {
+        inner.gid = gid;
+    }
+    if (uid_changed || gid_changed) && meta.mode != InodeMode::FileDIR {
+        inner.perm &= !S_ISUID;
+        if inner.perm & 0o010 != 0 {
+            inner.perm &= !S_ISGID;
+        }
+    }
+    inner.st_ctim = current_time_spec();
+    drop(inner);
+    inode.sync_attr()?;
+    Ok(0)
+}
+
+pub fn sys_fchmodat(dirfd: isize, pathname: *const u8, mode: u32, flags: u32) -> SyscallRet {
+    trace!("[sys_fchmodat] dirfd: {}, mode: {:#o}", dirfd, mode);
+    let flags = FcntlFlags::from_bits(flags).ok_or(SyscallErr::EINVAL)?;
+    do_chmod(&at_inode(dirfd, pathname, flags)?, mode as u16)
+}
+
+pub fn sys_fchmod(fd: usize, mode: u32) -> SyscallRet {
+    trace!("[sys_fchmod] fd: {}, mode: {:#o}", fd, mode);
+    do_chmod(&fd_inode(fd)?, mode as u16)
+}
+
+pub fn sys_fchownat(
+    dirfd: isize,
+    pathname: *const u8,
+    uid: u32,
+    gid: u32,
+    flags: u32,
+) -> SyscallRet {
+    trace!(
+        "[sys_fchownat] dirfd: {}, uid: {}, gid: {}",
+        dirfd,
+        uid,
+        gid
+    );
+    let flags = FcntlFlags::from_bits(flags).ok_or(SyscallErr::EINVAL)?;
+    do_chown(&at_inode(dirfd, pathname, flags)?, uid, gid)
+}
+
+pub fn sys_fchown(fd: usize, uid: u32, gid: u32) -> SyscallRet {
+    trace!("[sys_fchown] fd: {}, uid: {}, gid: {}", fd, uid, gid);
+    do_chown(&fd_inode(fd)?, uid, gid)
+}
+
 pub fn sys_readlinkat(dirfd: usize, path_name: usize, buf: usize, buf_size: usize) -> SyscallRet {
     let path = c_str_to_string(path_name as *const u8);
     info!(
@@ -768,13 +1468,74 @@ pub fn sys_socketpair(domain: u32, socket_type: u32, protocol: u32, sv: usize) -
 }
 
 pub async fn sys_sync() -> SyscallRet {
-    // todo: titanix fake implementation may need to do more
     trace!("[sys_sync] start to sync...");
-    warn!("[sys_sync] not implemented.");
+    // pages written through shared mappings are not marked dirty on write
+    current_process().inner_handler(|inner| inner.memory_set.mark_shared_dirty(0, USER_MAX_VA));
+    page_cache::sync_all().await?;
     trace!("[sys_sync] sync finished");
     Ok(0)
 }
 
+pub async fn sys_fsync(fd: usize) -> SyscallRet {
+    trace!("[sys_fsync] fd: {}", fd);
+    let fdinfo = current_process()
+        .inner_handler(|inner| inner.fd_table.get(fd))
+        .ok_or(SyscallErr::EBADF)?;
+    let inode = fdinfo.file.get_meta().inner.lock().inode.clone();
+    match inode {
+        Some(inode) => {
+            inode.sync().await?;
+            Ok(0)
+        }
+        // pipes, ttys and sockets have nothing to sync
+        None => Err(SyscallErr::EINVAL.into()),
+    }
+}
+
+/// 只能修改普通文件的大小, 超过`RLIMIT_FSIZE`时发送SIGXFSZ
+fn do_truncate(inode: &Arc<dyn Inode>, length: isize) -> SyscallRet {
+    if length < 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    match inode.get_meta().mode {
+        InodeMode::FileREG => {}
+        InodeMode::FileDIR => return Err(SyscallErr::EISDIR.into()),
+        _ => return Err(SyscallErr::EINVAL.into()),
+    }
+    let length = length as usize;
+    if length > current_process().rlimit(RLIMIT_FSIZE).rlim_cur {
+        current_thread().unwrap().send_signal(SIGXFSZ);
+        return Err(SyscallErr::EFBIG.into());
+    }
+    inode.truncate(length)?;
+    Ok(0)
+}
+
+/// 跟随符号链接, 需要对文件有写权限
+pub fn sys_truncate(pathname: *const u8, length: isize) -> SyscallRet {
+    let path = Path::from(c_str_to_string(pathname));
+    trace!("[sys_truncate] path: {}, length: {}", path, length);
+    let osinode = open_osinode(AT_FDCWD, &path, OpenFlags::empty())?;
+    let inode = osinode.inner_handler(|inner| inner.inode.as_ref().unwrap().clone());
+    if inode.get_meta().mode != InodeMode::FileDIR {
+        inode
+            .get_meta()
+            .check_access(&current_process().cred(), MAY_WRITE)?;
+    }
+    do_truncate(&inode, length)
+}
+
+/// 文件必须以可写方式打开
+pub fn sys_ftruncate(fd: usize, length: isize) -> SyscallRet {
+    trace!("[sys_ftruncate] fd: {}, length: {}", fd, length);
+    let file = open_fd(fd).ok_or(SyscallErr::EBADF)?;
+    if !file.get_meta().writable {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let inode = file.get_meta().inner.lock().inode.clone();
+    do_truncate(&inode.ok_or(SyscallErr::EINVAL)?, length)
+}
+
 pub async fn sys_splice(
     fd_in: i32,
     offset_in: usize,
@@ -857,3 +1618,40 @@ pub async fn sys_splice(
         return Err(SyscallErr::EINVAL as usize);
     }
 }
+
+/// `flags` of `signalfd4`
+const SFD_NONBLOCK: usize = 0x800;
+const SFD_CLOEXEC: usize = 0x80000;
+
+/// `fd`为-1时创建新的signalfd, 否则修改已有signalfd关心的信号
+pub fn sys_signalfd4(fd: isize, mask_ptr: usize, sizemask: usize, flags: usize) -> SyscallRet {
+    trace!(
+        "[sys_signalfd4] enter. fd: {}, mask: {:#x}, flags: {:#x}",
+        fd,
+        mask_ptr,
+        flags
+    );
+    if sizemask != size_of::<SigBitmap>() || flags & !(SFD_NONBLOCK | SFD_CLOEXEC) != 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let mask = read_sigmask(mask_ptr)?.ok_or(SyscallErr::EFAULT)?;
+    let process = current_process();
+    if fd >= 0 {
+        let file = process
+            .inner_handler(|inner| inner.fd_table.get(fd as usize))
+            .ok_or(SyscallErr::EBADF)?
+            .file;
+        let signalfd = SignalFd::from_file(file).ok_or(SyscallErr::EINVAL)?;
+        signalfd.set_mask(mask);
+        return Ok(fd as usize);
+    }
+    if fd != -1 {
+        return Err(SyscallErr::EBADF.into());
+    }
+    let mut fd_info = FdInfo::default_flags(SignalFd::new(mask, flags & SFD_NONBLOCK != 0));
+    if flags & SFD_CLOEXEC != 0 {
+        fd_info.flags |= OpenFlags::CLOEXEC;
+    }
+    let fd = process.inner_lock().fd_table.alloc_and_set(0, fd_info)?;
+    Ok(fd)
+}
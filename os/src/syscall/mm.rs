@@ -1,11 +1,14 @@
-use crate::mm::{shm::SharedMemoryManager, MapPermission, VirtAddr};
+use crate::mm::shm::{ShmFlags, ShmIdDs, SHARED_MEMORY_MANAGER};
+use crate::mm::user_check::UserCheck;
+use crate::mm::{FileMapping, MapPermission, VPNRange, VirtAddr, VirtPageNum};
 use crate::{config::SyscallRet, utils::SyscallErr};
 
-use crate::config::{MMAP_MIN_ADDR, PAGE_SIZE};
-use crate::ctypes::{MmapFlags, MMAPPROT};
-use crate::mutex::SpinNoIrqLock;
+use crate::config::PAGE_SIZE;
+use crate::ctypes::{MmapFlags, MremapFlags, MMAPPROT};
+use crate::syscall::resource::{RLIMIT_AS, RLIMIT_DATA};
 use crate::task::processor::current_process;
-use log::{info, trace};
+use crate::USER_MAX_VA;
+use log::{info, trace, warn};
 
 // Todo?: 根据测例实际要实现的是sbrk?
 // brk可以不对齐
@@ -13,44 +16,52 @@ pub fn sys_brk(brk: usize) -> SyscallRet {
     // static mut unaligned_brk: usize = 0;
     trace!("[sys_brk] enter. brk: {:#x}", brk);
     let process = current_process();
-    let current_memory_set = &mut process.inner_lock().memory_set;
+    let mut inner = process.inner_lock();
+    let data_limit = inner.rlimits.get(RLIMIT_DATA).rlim_cur;
+    let as_limit = inner.rlimits.get(RLIMIT_AS).rlim_cur;
+    let current_memory_set = &mut inner.memory_set;
     // sbrk(0)是获取当前program brk(堆顶)
     if brk == 0 {
         return Ok(current_memory_set.brk);
     }
+    let mapped_size = current_memory_set.mapped_size();
     let heap_area = current_memory_set
         .heap
-        .as_mut()
+        .as_ref()
         .expect("fail to get heap_area");
-    let page_table = &mut current_memory_set.page_table;
     let start = heap_area.vpn_range.get_start();
     let end = heap_area.vpn_range.get_end();
+    let new_end = VirtAddr::from(brk).ceil();
+    if new_end < start {
+        return Err(SyscallErr::ENOMEM.into());
+    }
+    if new_end > end {
+        // 堆的大小受RLIMIT_DATA限制, 整个地址空间受RLIMIT_AS限制,
+        // 也不能长进其他映射
+        let grow_range = VPNRange::new(end, new_end);
+        if (new_end.0 - start.0) * PAGE_SIZE > data_limit
+            || mapped_size + (new_end.0 - end.0) * PAGE_SIZE > as_limit
+            || !current_memory_set.is_unmapped(grow_range)
+        {
+            return Err(SyscallErr::ENOMEM.into());
+        }
+    }
     current_memory_set.brk = brk;
     // debug!("brk: {:#x}", brk);
-    let new_end = VirtAddr::from(brk).ceil();
-    // 页内偏移, 不用分配新页
-    if new_end == end {
-        return Ok(0);
-    } else if new_end < start {
-        // Todo!: 设置errno为ENOMEM
-        return Err(1);
-    } else if new_end < end {
+    let heap_area = current_memory_set.heap.as_mut().unwrap();
+    let page_table = &mut current_memory_set.page_table;
+    if new_end < end {
         // deallocate heap_area
         heap_area.shrink(page_table, new_end);
-        return Ok(0);
-        // Todo!: 粗略地设置了heap的size最大为10 page
-    } else if new_end > end && new_end - start < 10 {
+    } else if new_end > end {
         // update heap_top
         heap_area.expand(page_table, new_end);
-        return Ok(0);
-    } else {
-        // new_end太大
-        // Todo!: 设置errno为ENOMEM
-        return Err(1);
     }
+    // 页内偏移, 不用分配新页
+    Ok(0)
 }
 
-/// Todo: 支持MAP_FIXED
+/// map anonymous memory or a file, `start` is only a hint unless MAP_FIXED(_NOREPLACE) is set
 pub async fn sys_mmap(
     start: usize,
     len: usize,
@@ -63,7 +74,7 @@ pub async fn sys_mmap(
 
     //处理参数
     let prot = MMAPPROT::from_bits(prot as u32).ok_or(SyscallErr::EINVAL)?;
-    let flags = MmapFlags::from_bits(flags as u32).ok_or(SyscallErr::EINVAL)?;
+    let flags = MmapFlags::from_bits_truncate(flags as u32);
     let proc = current_process();
     trace!(
         "[sys_mmap] start: {:#x}, len: {:#x}, fd: {}, offset: {:#x}, flags: {:?}, prot: {:?}",
@@ -77,84 +88,190 @@ pub async fn sys_mmap(
     if len == 0 {
         return Err(SyscallErr::EINVAL.into());
     }
-    if flags.contains(MmapFlags::MAP_FIXED) {
-        unimplemented!("[sys_mmap] MAP_FIXED")
+    // MAP_SHARED和MAP_PRIVATE只能选一
+    if flags.contains(MmapFlags::MAP_SHARED) == flags.contains(MmapFlags::MAP_PRIVATE) {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let fixed = flags.intersects(MmapFlags::MAP_FIXED | MmapFlags::MAP_FIXED_NOREPLACE);
+    if fixed && (start % PAGE_SIZE != 0 || start == 0) {
+        return Err(SyscallErr::EINVAL.into());
     }
-    // mmap区域最低地址为MMAP_MIN_ADDR
-    let mut start = proc.inner_handler(|inner| inner.memory_set.mmap_start);
-    let mut permission = prot.into();
-    // 注意加上U权限
-    permission |= MapPermission::U;
-    // 匿名映射
+    // 匿名映射需要fd为-1, offset为0, 文件映射需要offset为page aligned
     if flags.contains(MmapFlags::MAP_ANONYMOUS) {
-        log::info!("[sys_mmap] anonymous mmap");
-        //需要fd为-1, offset为0
         if fd != -1 || offset != 0 {
             return Err(SyscallErr::EINVAL.into());
         }
-        let vpn_range = proc
-            .inner_lock()
-            .memory_set
-            .get_unmapped_area(start, len)
-            .ok_or(SyscallErr::ENOMEM)?;
-        log::debug!("[sys_mmap] vpn_range: {}", vpn_range);
-        proc.inner_lock()
-            .memory_set
-            .insert_framed_area(vpn_range, permission);
-        // .insert_anonymous_area(vpn_range, permission);
+    } else if offset % PAGE_SIZE != 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let file = if flags.contains(MmapFlags::MAP_ANONYMOUS) {
+        None
+    } else {
+        Some(
+            proc.inner_handler(|inner| inner.fd_table.get(fd as usize))
+                .ok_or(SyscallErr::EBADF)?
+                .file,
+        )
+    };
+    // 可写的共享映射会写回文件, fd必须可写
+    if let Some(file) = &file {
+        if flags.contains(MmapFlags::MAP_SHARED)
+            && prot.contains(MMAPPROT::PROT_WRITE)
+            && !file.get_meta().writable
+        {
+            return Err(SyscallErr::EACCES.into());
+        }
+    }
+    let mut permission = prot.into();
+    // 注意加上U权限
+    permission |= MapPermission::U;
 
-        let new_start: usize = VirtAddr::from(vpn_range.get_end()).into();
-        proc.inner_handler(|inner| inner.memory_set.mmap_start = new_start);
-        start = VirtAddr::from(vpn_range.get_start()).into();
+    // 不带MAP_FIXED时start只是hint, 否则一定要映射到start
+    let vpn_range = proc.inner_handler(|inner| {
+        let vpn_range = if fixed {
+            let end = start.checked_add(len).ok_or(SyscallErr::ENOMEM)?;
+            if end > USER_MAX_VA + 1 {
+                return Err(SyscallErr::ENOMEM);
+            }
+            VPNRange::new(VirtAddr::from(start).floor(), VirtAddr::from(end).ceil())
+        } else {
+            inner
+                .memory_set
+                .get_unmapped_area(start, len)
+                .ok_or(SyscallErr::ENOMEM)?
+        };
+        // 被MAP_FIXED替换掉的部分不重复计算
+        let new_pages = vpn_range.get_end().0
+            - vpn_range.get_start().0
+            - inner.memory_set.mapped_pages_in(vpn_range);
+        if inner.memory_set.mapped_size() + new_pages * PAGE_SIZE
+            > inner.rlimits.get(RLIMIT_AS).rlim_cur
+        {
+            return Err(SyscallErr::ENOMEM);
+        }
+        if fixed && !inner.memory_set.is_unmapped(vpn_range) {
+            if flags.contains(MmapFlags::MAP_FIXED_NOREPLACE) {
+                return Err(SyscallErr::EEXIST);
+            }
+            inner.memory_set.do_unmap(start, len);
+        }
+        Ok(vpn_range)
+    })?;
+    log::debug!("[sys_mmap] vpn_range: {}", vpn_range);
+    let start: usize = VirtAddr::from(vpn_range.get_start()).into();
 
-        return Ok(start);
-    } else {
-        log::info!("[sys_mmap] file mmap");
-        // 文件映射
-        // 需要offset为page aligned
-        if offset % PAGE_SIZE != 0 {
-            return Err(SyscallErr::EINVAL.into());
+    let file = match file {
+        Some(file) => file,
+        None => {
+            log::info!("[sys_mmap] anonymous mmap");
+            proc.inner_handler(|inner| {
+                if flags.contains(MmapFlags::MAP_SHARED) {
+                    inner.memory_set.insert_shared_area(vpn_range, permission)
+                } else {
+                    inner.memory_set.insert_framed_area(vpn_range, permission)
+                }
+            });
+            return Ok(start);
         }
-        // 读取文件
-        let file = proc
-            .inner_handler(|inner| inner.fd_table.get(fd as usize))
-            .unwrap()
-            .file;
-        let vpn_range = proc
-            .inner_lock()
-            .memory_set
-            .get_unmapped_area(start, len)
-            .ok_or(SyscallErr::ENOMEM)?;
+    };
+
+    log::info!("[sys_mmap] file mmap");
+    let inode = file.get_meta().inner.lock().inode.clone();
+    if let Some(inode) = inode {
+        // map the page cache of the file, nothing is read until accessed
+        let data_size = inode.get_meta().inner.lock().data_size;
+        let file_mapping = FileMapping {
+            inode,
+            offset,
+            len: data_size.saturating_sub(offset),
+            shared: flags.contains(MmapFlags::MAP_SHARED),
+        };
         proc.inner_lock()
             .memory_set
-            .insert_framed_area(vpn_range, permission);
-
-        let new_start: usize = VirtAddr::from(vpn_range.get_end()).into();
-        proc.inner_handler(|inner| inner.memory_set.mmap_start = new_start);
-        start = VirtAddr::from(vpn_range.get_start()).into();
-        let buf = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, len) };
-        let origin_offset = file.seek(offset);
-        if file.read(buf).await.is_err() {
-            return Err(SyscallErr::EINVAL.into());
-        }
-        if let Some(origin_offset) = origin_offset {
-            // file is seekable, then seek back
-            file.seek(origin_offset);
-        }
+            .insert_file_area(vpn_range, permission, file_mapping);
         return Ok(start);
     }
+    // not backed by an inode, copy the content
+    proc.inner_lock()
+        .memory_set
+        .insert_framed_area(vpn_range, permission);
+    let buf = unsafe { core::slice::from_raw_parts_mut(start as *mut u8, len) };
+    let origin_offset = file.seek(offset);
+    if file.read(buf).await.is_err() {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    if let Some(origin_offset) = origin_offset {
+        // file is seekable, then seek back
+        file.seek(origin_offset);
+    }
+    Ok(start)
 }
 
 pub fn sys_munmap(start: usize, len: usize) -> SyscallRet {
     info!("[sys_munmap] start: 0x{:x}, len: 0x{:x}", start, len);
-    // start必须页对齐, 且要大于等于MMAP_MIN_ADDR
-    if start % PAGE_SIZE != 0 || len == 0 || start < MMAP_MIN_ADDR {
+    // start必须页对齐, MAP_FIXED映射可能在MMAP_MIN_ADDR之下
+    let end = start.checked_add(len).ok_or(SyscallErr::EINVAL)?;
+    if start % PAGE_SIZE != 0 || len == 0 || end > USER_MAX_VA + 1 {
         return Err(SyscallErr::EINVAL.into());
     }
     current_process().inner_handler(|inner| inner.memory_set.do_unmap(start, len));
     Ok(0)
 }
 
+pub fn sys_mremap(
+    old_addr: usize,
+    old_size: usize,
+    new_size: usize,
+    flags: u32,
+    new_addr: usize,
+) -> SyscallRet {
+    info!(
+        "[sys_mremap] old_addr: {:#x}, old_size: {:#x}, new_size: {:#x}, flags: {:#x}, new_addr: {:#x}",
+        old_addr, old_size, new_size, flags, new_addr
+    );
+    let flags = MremapFlags::from_bits(flags).ok_or(SyscallErr::EINVAL)?;
+    let old_end = old_addr.checked_add(old_size).ok_or(SyscallErr::EINVAL)?;
+    if old_addr % PAGE_SIZE != 0 || new_size == 0 || old_end > USER_MAX_VA + 1 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let fixed = if flags.contains(MremapFlags::MREMAP_FIXED) {
+        if !flags.contains(MremapFlags::MREMAP_MAYMOVE) || new_addr % PAGE_SIZE != 0 {
+            return Err(SyscallErr::EINVAL.into());
+        }
+        Some(VirtAddr::from(new_addr).floor())
+    } else {
+        None
+    };
+    // old_size为0是复制共享映射, 暂不支持
+    if old_size == 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let old_range = VPNRange::new(
+        VirtAddr::from(old_addr).floor(),
+        VirtAddr::from(old_end).ceil(),
+    );
+    let new_pages = new_size
+        .checked_add(PAGE_SIZE - 1)
+        .ok_or(SyscallErr::ENOMEM)?
+        / PAGE_SIZE;
+    let new_start = current_process().inner_handler(|inner| {
+        let old_pages = old_range.get_end().0 - old_range.get_start().0;
+        if new_pages > old_pages
+            && inner.memory_set.mapped_size() + (new_pages - old_pages) * PAGE_SIZE
+                > inner.rlimits.get(RLIMIT_AS).rlim_cur
+        {
+            return Err(SyscallErr::ENOMEM);
+        }
+        inner.memory_set.do_mremap(
+            old_range,
+            new_pages,
+            flags.contains(MremapFlags::MREMAP_MAYMOVE),
+            fixed,
+        )
+    })?;
+    Ok(VirtAddr::from(new_start).into())
+}
+
 pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SyscallRet {
     let prot = MMAPPROT::from_bits(prot as u32).ok_or(SyscallErr::EINVAL)?;
     let perm: MapPermission = prot.into();
@@ -175,27 +292,166 @@ pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> SyscallRet {
     current_process().inner_handler(|inner| inner.memory_set.do_mprotect(addr, len, perm))
 }
 
+bitflags! {
+    /// flags of `sys_msync`
+    pub struct MsyncFlags: u32 {
+        const MS_ASYNC = 1 << 0;
+        const MS_INVALIDATE = 1 << 1;
+        const MS_SYNC = 1 << 2;
+    }
+}
+
+/// write back shared file mappings in [addr, addr + len)
+pub async fn sys_msync(addr: usize, len: usize, flags: u32) -> SyscallRet {
+    trace!(
+        "[sys_msync] addr: {:#x}, len: {:#x}, flags: {:#x}",
+        addr,
+        len,
+        flags
+    );
+    let flags = MsyncFlags::from_bits(flags).ok_or(SyscallErr::EINVAL)?;
+    if addr % PAGE_SIZE != 0 || flags.contains(MsyncFlags::MS_ASYNC | MsyncFlags::MS_SYNC) {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let inodes = current_process().inner_handler(|inner| {
+        if inner.memory_set.filter_overlap(addr, len).is_empty() {
+            return Err(SyscallErr::ENOMEM);
+        }
+        inner.memory_set.mark_shared_dirty(addr, len);
+        Ok(inner.memory_set.shared_inodes(addr, len))
+    })?;
+    if flags.contains(MsyncFlags::MS_SYNC) {
+        for inode in inodes {
+            inode.sync().await?;
+        }
+    }
+    Ok(0)
+}
+
 /// fake implementation
 #[allow(unused)]
 pub fn sys_madvise(addr: usize, len: usize, advise: i32) -> SyscallRet {
     return Ok(0);
 }
 
-const IPC_PRIVATE: usize = 0; // 这里定义了一个常量 IPC_PRIVATE，其值为 0。这个常量通常用于表示私有共享内存键。
-pub fn sys_shmget(
-    key: usize,    // 用于标识共享内存段。
-    len: usize,    // 共享内存段的长度。
-    _shmflag: u32, // 共享内存段的标志。（未使用）
-) -> SyscallRet {
-    trace!("[sys_shmget] start to sync...");
+pub fn sys_shmget(key: usize, size: usize, shmflg: u32) -> SyscallRet {
+    trace!(
+        "[sys_shmget] key: {:#x}, size: {:#x}, shmflg: {:#o}",
+        key,
+        size,
+        shmflg
+    );
+    let flags = ShmFlags::from_bits_truncate(shmflg);
+    let pid = current_process().getpid();
+    let id = SHARED_MEMORY_MANAGER
+        .lock()
+        .get(key, size, flags, shmflg & 0o777, pid)?;
+    Ok(id)
+}
 
-    if key != IPC_PRIVATE {
-        panic!("[sys_shmget] unsupported operation, key {:#X}", key);
+pub fn sys_shmat(shmid: usize, shmaddr: usize, shmflg: u32) -> SyscallRet {
+    trace!(
+        "[sys_shmat] shmid: {}, shmaddr: {:#x}, shmflg: {:#o}",
+        shmid,
+        shmaddr,
+        shmflg
+    );
+    let flags = ShmFlags::from_bits_truncate(shmflg);
+    let shm = SHARED_MEMORY_MANAGER.lock().get_by_id(shmid)?;
+    let mut permission = MapPermission::R | MapPermission::U;
+    if !flags.contains(ShmFlags::SHM_RDONLY) {
+        permission |= MapPermission::W;
+    }
+    // SHMLBA为PAGE_SIZE
+    let shmaddr = if flags.contains(ShmFlags::SHM_RND) {
+        shmaddr & !(PAGE_SIZE - 1)
+    } else {
+        shmaddr
+    };
+    if shmaddr % PAGE_SIZE != 0 {
+        return Err(SyscallErr::EINVAL.into());
     }
+    let len = shm.frames.len() * PAGE_SIZE;
+    let proc = current_process();
+    let pid = proc.getpid();
+    let vpn_range = proc.inner_handler(|inner| {
+        let vpn_range = match shmaddr {
+            0 => inner.memory_set.get_unmapped_area(0, len),
+            _ => {
+                let start = VirtAddr::from(shmaddr).floor();
+                let vpn_range = VPNRange::new(start, VirtPageNum(start.0 + shm.frames.len()));
+                inner.memory_set.is_unmapped(vpn_range).then_some(vpn_range)
+            }
+        }
+        .ok_or(SyscallErr::EINVAL)?;
+        inner
+            .memory_set
+            .insert_shm_area(vpn_range, permission, shm.clone());
+        Ok::<_, SyscallErr>(vpn_range)
+    })?;
+    shm.touch(pid, true);
+    Ok(VirtAddr::from(vpn_range.get_start()).into())
+}
 
-    trace!("[sys_shmget] finish");
-    Ok(SHARED_MEMORY_MANAGER.lock().alloc(key, len))
+pub fn sys_shmdt(shmaddr: usize) -> SyscallRet {
+    trace!("[sys_shmdt] shmaddr: {:#x}", shmaddr);
+    if shmaddr % PAGE_SIZE != 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let proc = current_process();
+    let (shm, pieces) = proc
+        .inner_handler(|inner| {
+            inner
+                .memory_set
+                .remove_shm_area(VirtAddr::from(shmaddr).floor())
+        })
+        .ok_or(SyscallErr::EINVAL)?;
+    // 每一块都算一个attach
+    for _ in 1..pieces {
+        shm.dec_nattch();
+    }
+    shm.touch(proc.getpid(), false);
+    Ok(0)
 }
 
-pub static SHARED_MEMORY_MANAGER: SpinNoIrqLock<SharedMemoryManager> =
-    SpinNoIrqLock::new(SharedMemoryManager::new());
+const IPC_RMID: u32 = 0;
+const IPC_SET: u32 = 1;
+const IPC_STAT: u32 = 2;
+/// musl把IPC_64和cmd一起传入
+const IPC_64: u32 = 0x100;
+
+pub fn sys_shmctl(shmid: usize, cmd: u32, buf: usize) -> SyscallRet {
+    trace!(
+        "[sys_shmctl] shmid: {}, cmd: {}, buf: {:#x}",
+        shmid,
+        cmd,
+        buf
+    );
+    let buf = buf as *mut ShmIdDs;
+    match cmd & !IPC_64 {
+        IPC_STAT => {
+            let stat = SHARED_MEMORY_MANAGER.lock().stat(shmid)?;
+            UserCheck::new()
+                .check_writable_pages(buf as *const u8, core::mem::size_of::<ShmIdDs>())?;
+            unsafe {
+                buf.write(stat);
+            }
+            Ok(0)
+        }
+        IPC_SET => {
+            UserCheck::new()
+                .check_readable_pages(buf as *const u8, core::mem::size_of::<ShmIdDs>())?;
+            let ds = unsafe { buf.read() };
+            SHARED_MEMORY_MANAGER.lock().set(shmid, &ds)?;
+            Ok(0)
+        }
+        IPC_RMID => {
+            SHARED_MEMORY_MANAGER.lock().remove(shmid)?;
+            Ok(0)
+        }
+        _ => {
+            warn!("[sys_shmctl] unsupported cmd {}", cmd);
+            Err(SyscallErr::EINVAL.into())
+        }
+    }
+}
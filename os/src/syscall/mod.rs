@@ -22,21 +22,30 @@ const SYS_GETDENTS64: usize = 61;
 const SYS_READ: usize = 63;
 const SYS_WRITE: usize = 64;
 const SYS_LINKAT: usize = 37;
+const SYS_MKNODAT: usize = 33;
 const SYS_UNLINKAT: usize = 35;
+const SYS_SYMLINKAT: usize = 36;
+const SYS_RENAMEAT2: usize = 276;
 const SYS_MKDIRAT: usize = 34;
 const SYS_UMOUNT2: usize = 39;
 const SYS_MOUNT: usize = 40;
 const SYS_FSTAT: usize = 80;
+const SYS_FCHMOD: usize = 52;
+const SYS_FCHMODAT: usize = 53;
+const SYS_FCHOWNAT: usize = 54;
+const SYS_FCHOWN: usize = 55;
 
 const SYS_CLONE: usize = 220;
 const SYS_EXECVE: usize = 221;
 const SYS_WAIT4: usize = 260;
+const SYS_WAITID: usize = 95;
 const SYS_EXIT: usize = 93;
 const SYS_GETPPID: usize = 173;
 const SYS_GETPID: usize = 172;
 
 const SYS_BRK: usize = 214;
 const SYS_MUNMAP: usize = 215;
+const SYS_MREMAP: usize = 216;
 const SYS_MMAP: usize = 222;
 const SYS_TIMES: usize = 153;
 const SYS_UNAME: usize = 160;
@@ -46,6 +55,21 @@ const SYS_NANOSLEEP: usize = 101;
 
 const SYS_SET_TID_ADDRESS: usize = 96;
 const SYS_GETUID: usize = 174;
+const SYS_GETGID: usize = 176;
+const SYS_GETEGID: usize = 177;
+const SYS_SETREGID: usize = 143;
+const SYS_SETGID: usize = 144;
+const SYS_SETREUID: usize = 145;
+const SYS_SETUID: usize = 146;
+const SYS_SETRESUID: usize = 147;
+const SYS_GETRESUID: usize = 148;
+const SYS_SETRESGID: usize = 149;
+const SYS_GETRESGID: usize = 150;
+const SYS_SETFSUID: usize = 151;
+const SYS_SETFSGID: usize = 152;
+const SYS_GETGROUPS: usize = 158;
+const SYS_SETGROUPS: usize = 159;
+const SYS_UMASK: usize = 166;
 const SYS_IOCTL: usize = 29;
 const SYS_EXIT_GROUP: usize = 94;
 const SYS_RT_SIGACTION: usize = 134;
@@ -53,6 +77,10 @@ const SYS_RT_SIGPROCMASK: usize = 135;
 const SYS_FCNTL: usize = 25;
 const SYS_WRITEV: usize = 66;
 const SYS_GETEUID: usize = 175;
+const SYS_EPOLL_CREATE1: usize = 20;
+const SYS_EPOLL_CTL: usize = 21;
+const SYS_EPOLL_PWAIT: usize = 22;
+const SYS_PSELECT6: usize = 72;
 const SYS_PPOLL: usize = 73;
 const SYS_CLOCK_GETTIME: usize = 113;
 const SYS_SYSINFO: usize = 179;
@@ -61,13 +89,21 @@ const SYS_FSTATAT: usize = 79;
 const SYS_FACCESSAT: usize = 48;
 const SYS_UTIMENSAT: usize = 88;
 const SYS_RT_SIGRETURN: usize = 139;
+const SYS_SIGALTSTACK: usize = 132;
+const SYS_RT_SIGSUSPEND: usize = 133;
+const SYS_RT_SIGPENDING: usize = 136;
+const SYS_SIGNALFD4: usize = 74;
 const SYS_KILL: usize = 129;
 
 const SYS_MPROTECT: usize = 226;
+const SYS_MSYNC: usize = 227;
 // const SYS_UTIMENSAT: usize = 88;
 const SYS_SENDFILE: usize = 71;
 const SYS_LSEEK: usize = 62;
+const SYS_SETPGID: usize = 154;
 const SYS_GETPGID: usize = 155;
+const SYS_GETSID: usize = 156;
+const SYS_SETSID: usize = 157;
 const SYS_GETTID: usize = 178;
 const SYS_READV: usize = 65;
 const SYS_SCHED_GETAFFINITY: usize = 123;
@@ -75,21 +111,33 @@ const SYS_SCHED_GETSCHEDULER: usize = 120;
 const SYS_SCHED_GETPARAM: usize = 121;
 const SYS_SOCKETPAIR: usize = 199;
 const SYS_SCHED_SETSCHEDULER: usize = 119;
+const SYS_SCHED_SETPARAM: usize = 118;
+const SYS_SCHED_GET_PRIORITY_MAX: usize = 125;
+const SYS_SCHED_GET_PRIORITY_MIN: usize = 126;
+const SYS_SCHED_RR_GET_INTERVAL: usize = 127;
+const SYS_SETPRIORITY: usize = 140;
+const SYS_GETPRIORITY: usize = 141;
 const SYS_CLOCK_GETRES: usize = 114;
-// const SYS_FUTEX: usize = 202;
-// const SYS_FUTEX: usize = 98;
+const SYS_FUTEX: usize = 98;
+const SYS_SET_ROBUST_LIST: usize = 99;
+const SYS_GET_ROBUST_LIST: usize = 100;
 const SYS_MADVISE: usize = 233;
 // const SYS_PRLIMIT: usize = 261;
 // const SYS_SIGTIMEDWAIT: usize = 137;
-// const SYS_TKILL: usize = 130;
-const SYS_GETAFFINITY: usize = 122;
+const SYS_TKILL: usize = 130;
+const SYS_TGKILL: usize = 131;
+const SYS_SCHED_SETAFFINITY: usize = 122;
 // const SYS_CLOCK_NANOSLEEP: usize = 115;
 const SYS_READLINKAT: usize = 78;
 const SYS_SYNC: usize = 81;
 const SYS_FSYNC: usize = 82;
+const SYS_TRUNCATE: usize = 45;
 const SYS_FTRUNCATE64: usize = 46;
 
 const SYS_SHMGET: usize = 194;
+const SYS_SHMCTL: usize = 195;
+const SYS_SHMAT: usize = 196;
+const SYS_SHMDT: usize = 197;
 const SYS_RT_SIGTIMEDWAIT: usize = 137;
 const SYS_PRLIMIT64: usize = 261;
 const SYS_MEMBARRIER: usize = 283;
@@ -98,28 +146,48 @@ const SYS_STATFS: usize = 43;
 
 const SYS_SPLICE: usize = 76;
 
+pub(crate) mod cred;
 mod fs;
 mod mm;
 pub(crate) mod process;
 pub(crate) mod resource;
+mod sched;
 mod util;
 
+use cred::*;
 use fs::*;
 use log::{error, warn};
 use mm::*;
+pub use process::WaitOption;
 use process::*;
-pub use process::{WaitFuture, WaitOption};
+use sched::*;
 use util::{sys_clock_getres, sys_clock_gettime, sys_get_time, sys_sysinfo, sys_times, sys_uname};
 
-use crate::signal::sys_rt_sigtimedwait;
+use crate::signal::{
+    sys_rt_sigpending, sys_rt_sigsuspend, sys_rt_sigtimedwait, sys_tgkill, sys_tkill,
+};
 use crate::syscall::resource::{sys_prlimit64, RLimit};
 use crate::{
     config::SyscallRet,
-    // futex::sys_futex,
-    signal::{sys_kill, sys_rt_sigaction, sys_rt_sigerturn, sys_rt_sigprocmask},
+    futex::{sys_futex, sys_get_robust_list, sys_set_robust_list},
+    signal::{sys_kill, sys_rt_sigaction, sys_rt_sigprocmask, sys_rt_sigreturn, sys_sigaltstack},
 };
 
 /// handle syscall exception with `syscall_id` and other arguments
+/// 被信号打断后, 执行了带`SA_RESTART`的处理函数能否重新执行,
+/// 这些调用与Linux一致, 有处理函数时总是返回`EINTR`
+pub fn restart_after_handler(syscall_id: usize) -> bool {
+    !matches!(
+        syscall_id,
+        SYS_PPOLL
+            | SYS_PSELECT6
+            | SYS_EPOLL_PWAIT
+            | SYS_NANOSLEEP
+            | SYS_RT_SIGTIMEDWAIT
+            | SYS_RT_SIGSUSPEND
+    )
+}
+
 pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> SyscallRet {
     match syscall_id {
         SYS_EXIT => sys_exit(args[0] as i32),
@@ -134,6 +202,7 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> SyscallRet {
 
         SYS_MMAP => sys_mmap(args[0], args[1], args[2], args[3], args[4] as i32, args[5]).await,
         SYS_MUNMAP => sys_munmap(args[0], args[1]),
+        SYS_MREMAP => sys_mremap(args[0], args[1], args[2], args[3] as u32, args[4]),
         SYS_GETCWD => sys_getcwd(args[0], args[1]),
         SYS_OPENAT => sys_openat(
             args[0] as isize,
@@ -147,29 +216,92 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> SyscallRet {
         SYS_CHDIR => sys_chdir(args[0] as *const u8),
         SYS_CLOSE => sys_close(args[0]),
         SYS_FSTAT => sys_fstat(args[0], args[1] as *mut _),
+        SYS_FCHMOD => sys_fchmod(args[0], args[1] as u32),
+        SYS_FCHMODAT => sys_fchmodat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+        ),
+        SYS_FCHOWNAT => sys_fchownat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+            args[4] as u32,
+        ),
+        SYS_FCHOWN => sys_fchown(args[0], args[1] as u32, args[2] as u32),
         SYS_GETDENTS64 => sys_getdents64(args[0], args[1] as usize, args[2]),
         SYS_DUP => sys_dup(args[0]),
         SYS_DUP3 => sys_dup3(args[0], args[1]),
         SYS_UNLINKAT => sys_unlinkat(args[0] as isize, args[1] as *const u8, args[2] as u32),
+        SYS_SYMLINKAT => {
+            sys_symlinkat(args[0] as *const u8, args[1] as isize, args[2] as *const u8)
+        }
+        SYS_RENAMEAT2 => sys_renameat2(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as isize,
+            args[3] as *const u8,
+            args[4] as u32,
+        ),
+        SYS_MKNODAT => sys_mknodat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u64,
+        ),
         SYS_PIPE2 => sys_pipe2(args[0] as *const u8, args[1] as u32),
         SYS_LINKAT => dummy(SYS_LINKAT, "sys_linkat"),
-        SYS_MOUNT => dummy(SYS_MOUNT, "sys_mount"),
-        SYS_UMOUNT2 => dummy(SYS_UMOUNT2, "sys_umount2"),
+        SYS_MOUNT => sys_mount(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *const u8,
+            args[3] as u32,
+            args[4],
+        ),
+        SYS_UMOUNT2 => sys_umount2(args[0] as *const u8, args[1] as u32),
         SYS_NANOSLEEP => sys_nanosleep(args[0]).await,
         SYS_GETPPID => sys_getppid(),
-        SYS_WAIT4 => sys_wait4(args[0] as isize, args[1], args[2] as i32).await,
+        SYS_WAIT4 => sys_wait4(args[0] as isize, args[1], args[2] as i32, args[3]).await,
+        SYS_WAITID => sys_waitid(args[0], args[1], args[2], args[3] as i32, args[4]).await,
 
         SYS_SET_TID_ADDRESS => sys_set_tid_address(args[0] as *const usize),
-        SYS_GETUID => dummy(SYS_GETUID, "sys_getuid"),
+        SYS_GETUID => sys_getuid(),
+        SYS_GETGID => sys_getgid(),
+        SYS_GETEGID => sys_getegid(),
+        SYS_SETUID => sys_setuid(args[0] as u32),
+        SYS_SETGID => sys_setgid(args[0] as u32),
+        SYS_SETREUID => sys_setreuid(args[0] as u32, args[1] as u32),
+        SYS_SETREGID => sys_setregid(args[0] as u32, args[1] as u32),
+        SYS_SETRESUID => sys_setresuid(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYS_SETRESGID => sys_setresgid(args[0] as u32, args[1] as u32, args[2] as u32),
+        SYS_GETRESUID => sys_getresuid(args[0] as *mut _, args[1] as *mut _, args[2] as *mut _),
+        SYS_GETRESGID => sys_getresgid(args[0] as *mut _, args[1] as *mut _, args[2] as *mut _),
+        SYS_SETFSUID => sys_setfsuid(args[0] as u32),
+        SYS_SETFSGID => sys_setfsgid(args[0] as u32),
+        SYS_GETGROUPS => sys_getgroups(args[0] as i32, args[1] as *mut _),
+        SYS_SETGROUPS => sys_setgroups(args[0] as i32, args[1] as *const _),
+        SYS_UMASK => sys_umask(args[0] as u32),
         SYS_IOCTL => sys_ioctl(args[0] as i32, args[1], args[2] as usize),
         SYS_EXIT_GROUP => sys_exit_group(args[0] as i32),
         SYS_RT_SIGACTION => sys_rt_sigaction(args[0], args[1], args[2]),
         SYS_RT_SIGPROCMASK => sys_rt_sigprocmask(args[0] as i32, args[1], args[2]),
-        SYS_RT_SIGRETURN => sys_rt_sigerturn(),
+        SYS_RT_SIGRETURN => sys_rt_sigreturn(),
+        SYS_SIGALTSTACK => sys_sigaltstack(args[0], args[1]),
+        SYS_RT_SIGSUSPEND => sys_rt_sigsuspend(args[0]).await,
+        SYS_RT_SIGPENDING => sys_rt_sigpending(args[0]),
+        SYS_SIGNALFD4 => sys_signalfd4(args[0] as isize, args[1], args[2], args[3]),
         SYS_FCNTL => sys_fcntl(args[0], args[1] as i32, args[2]),
         SYS_WRITEV => sys_writev(args[0], args[1], args[2] as i32).await,
-        SYS_GETEUID => dummy(SYS_GETEUID, "sys_geteuid"),
-        SYS_PPOLL => sys_ppoll(args[0], args[1], args[2], args[3]),
+        SYS_GETEUID => sys_geteuid(),
+        SYS_EPOLL_CREATE1 => sys_epoll_create1(args[0]),
+        SYS_EPOLL_CTL => sys_epoll_ctl(args[0], args[1], args[2], args[3]),
+        SYS_EPOLL_PWAIT => {
+            sys_epoll_pwait(args[0], args[1], args[2] as i32, args[3] as i32, args[4]).await
+        }
+        SYS_PSELECT6 => sys_pselect6(args[0], args[1], args[2], args[3], args[4], args[5]).await,
+        SYS_PPOLL => sys_ppoll(args[0], args[1], args[2], args[3]).await,
         SYS_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut _),
         SYS_SYSINFO => sys_sysinfo(args[0] as *mut _),
         SYS_SYSLOG => dummy(SYS_SYSLOG, "sys_syslog"),
@@ -193,6 +325,7 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> SyscallRet {
         ),
 
         SYS_MPROTECT => sys_mprotect(args[0], args[1], args[2]),
+        SYS_MSYNC => sys_msync(args[0], args[1], args[2] as u32).await,
         SYS_KILL => sys_kill(args[0] as isize, args[1]),
         SYS_SENDFILE => {
             sys_sendfile(
@@ -205,12 +338,17 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> SyscallRet {
         }
 
         SYS_LSEEK => sys_lseek(args[0] as i32, args[1] as isize, args[2] as i32),
-        SYS_GETPGID => sys_getpgid(args[0] as i32),
+        SYS_SETPGID => sys_setpgid(args[0], args[1] as isize),
+        SYS_GETPGID => sys_getpgid(args[0]),
+        SYS_GETSID => sys_getsid(args[0]),
+        SYS_SETSID => sys_setsid(),
         SYS_GETTID => sys_gettid(),
         SYS_READV => sys_readv(args[0], args[1], args[2] as i32).await,
-        SYS_SCHED_GETAFFINITY => dummy(SYS_SCHED_GETAFFINITY, "sys_sched_getaffinity"),
-        SYS_SCHED_GETSCHEDULER => dummy(SYS_SCHED_GETSCHEDULER, "sys_sched_getscheduler"),
-        SYS_SCHED_GETPARAM => dummy(SYS_SCHED_GETPARAM, "sys_sched_getparam"),
+        SYS_SCHED_GETAFFINITY => {
+            sys_sched_getaffinity(args[0] as isize, args[1], args[2] as *mut u8)
+        }
+        SYS_SCHED_GETSCHEDULER => sys_sched_getscheduler(args[0] as isize),
+        SYS_SCHED_GETPARAM => sys_sched_getparam(args[0] as isize, args[1] as *mut SchedParam),
         SYS_SOCKETPAIR => sys_socketpair(
             args[0] as u32,
             args[1] as u32,
@@ -219,42 +357,55 @@ pub async fn syscall(syscall_id: usize, args: [usize; 6]) -> SyscallRet {
         ),
         SYS_SYNC => sys_sync().await,
         SYS_SHMGET => sys_shmget(args[0], args[1], args[2] as u32),
-        SYS_RT_SIGTIMEDWAIT => sys_rt_sigtimedwait(
-            args[0] as *const u32,
-            args[1] as *const u8,
-            args[2] as *const u8,
-        ),
+        SYS_SHMCTL => sys_shmctl(args[0], args[1] as u32, args[2]),
+        SYS_SHMAT => sys_shmat(args[0], args[1], args[2] as u32),
+        SYS_SHMDT => sys_shmdt(args[0]),
+        SYS_RT_SIGTIMEDWAIT => sys_rt_sigtimedwait(args[0], args[1], args[2]).await,
         SYS_PRLIMIT64 => sys_prlimit64(
             args[0],
-            args[1] as u32,
+            args[1],
             args[2] as *const RLimit,
             args[3] as *mut RLimit,
         ),
         // Weird bug, you cannot enter shell with next line enabled.
-        SYS_SCHED_SETSCHEDULER => dummy(SYS_SCHED_SETSCHEDULER, "sys_sched_setscheduler"),
+        SYS_SCHED_SETSCHEDULER => {
+            sys_sched_setscheduler(args[0] as isize, args[1], args[2] as *const SchedParam)
+        }
+        SYS_SCHED_SETPARAM => sys_sched_setparam(args[0] as isize, args[1] as *const SchedParam),
+        SYS_SCHED_GET_PRIORITY_MAX => sys_sched_get_priority_max(args[0]),
+        SYS_SCHED_GET_PRIORITY_MIN => sys_sched_get_priority_min(args[0]),
+        SYS_SCHED_RR_GET_INTERVAL => {
+            sys_sched_rr_get_interval(args[0] as isize, args[1] as *mut crate::timer::TimeSpec)
+        }
+        SYS_SETPRIORITY => sys_setpriority(args[0], args[1], args[2] as i32 as isize),
+        SYS_GETPRIORITY => sys_getpriority(args[0], args[1]),
         SYS_CLOCK_GETRES => sys_clock_getres(args[0], args[1] as *mut _),
         // SYS_GETTID => sys_getpid(),
         SYS_MADVISE => sys_madvise(args[0], args[1], args[2] as i32),
         // SYS_PRLIMIT => dummy(SYS_PRLIMIT, "prlimit64"),
         // SYS_SIGTIMEDWAIT => dummy(SYS_SIGTIMEDWAIT, "sigtimedwait"),
-        //SYS_TKILL => sys_tkill(args[0], args[1]),
-        SYS_GETAFFINITY => dummy(SYS_GETAFFINITY, "getaffinity"),
+        SYS_TKILL => sys_tkill(args[0] as isize, args[1]),
+        SYS_TGKILL => sys_tgkill(args[0] as isize, args[1] as isize, args[2]),
+        SYS_SCHED_SETAFFINITY => sys_sched_setaffinity(args[0] as isize, args[1], args[2]).await,
         // SYS_READLINKAT => dummy(SYS_READLINKAT, "readlinkat"),
         // SYS_SYNC => dummy(SYS_SYNC, "sync"),
-        SYS_FSYNC => dummy(SYS_FSYNC, "fsync"),
-        SYS_FTRUNCATE64 => dummy(SYS_FTRUNCATE64, "ftruncate64"),
+        SYS_FSYNC => sys_fsync(args[0]).await,
+        SYS_TRUNCATE => sys_truncate(args[0] as *const u8, args[1] as isize),
+        SYS_FTRUNCATE64 => sys_ftruncate(args[0], args[1] as isize),
 
-        // SYS_FUTEX => {
-        //     sys_futex(
-        //         args[0],
-        //         args[1] as i32,
-        //         args[2] as u32,
-        //         args[3],
-        //         args[4],
-        //         args[5] as u32,
-        //     )
-        //     .await
-        // }
+        SYS_FUTEX => {
+            sys_futex(
+                args[0],
+                args[1] as i32,
+                args[2] as u32,
+                args[3],
+                args[4],
+                args[5] as u32,
+            )
+            .await
+        }
+        SYS_SET_ROBUST_LIST => sys_set_robust_list(args[0], args[1]),
+        SYS_GET_ROBUST_LIST => sys_get_robust_list(args[0], args[1] as *mut _, args[2] as *mut _),
         SYS_MEMBARRIER => dummy(SYS_MEMBARRIER, "sys_mem_barrier"),
         SYS_STATFS => dummy(SYS_STATFS, "sys_statfs"),
         SYS_READLINKAT => sys_readlinkat(args[0], args[1], args[2], args[3]),
@@ -1,18 +1,31 @@
-use crate::config::SyscallRet;
+use crate::config::{SysResult, SyscallRet};
+use crate::ctypes::Rusage;
+use crate::fs::inode::InodeMode;
 use crate::fs::path::Path;
 use crate::fs::{open_osinode, OpenFlags, AT_FDCWD};
 use crate::loader::get_app_data_by_name;
 use crate::mm::user_check::UserCheck;
+use crate::signal::{
+    SigInfo, CLD_CONTINUED, CLD_DUMPED, CLD_EXITED, CLD_KILLED, CLD_STOPPED, SIGCONT,
+};
+use crate::syscall::cred::MAY_EXEC;
+use crate::syscall::resource::RLIMIT_NPROC;
+use crate::task::all_processes;
+use crate::task::pgroup::{ProcessGroup, Session};
 use crate::task::processor::{current_process, current_thread};
-use crate::task::{exit_current, yield_task, INITPROC};
+use crate::task::task::{JobEvent, Process, PROCESS_MANAGER};
+use crate::task::{exit_current, exit_group_current, yield_task, INITPROC};
 use crate::timer::{TimeSpec, TimeoutFuture};
-use crate::utils::c_str_to_string;
+use crate::utils::{c_str_to_string, SyscallErr};
 // use crate::utils::checksum::calculate_checksum;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::future::Future;
+use core::mem::size_of;
+use core::pin::Pin;
 use core::ptr::null;
-use core::task::Poll;
+use core::task::{Context, Poll};
 use core::time::Duration;
 use log::{error, info, trace, warn};
 
@@ -61,6 +74,17 @@ pub fn sys_fork(stack: Option<usize>) -> SyscallRet {
     trace!("[sys_fork] enter");
     // 复制当前进程
     let current_process = current_process();
+    // 限制同一真实用户的进程数, root不受限制.
+    let cred = current_process.cred();
+    if !cred.is_privileged() {
+        let nproc = all_processes()
+            .iter()
+            .filter(|p| p.cred().user.real == cred.user.real)
+            .count();
+        if nproc >= current_process.rlimit(RLIMIT_NPROC).rlim_cur {
+            return Err(SyscallErr::EAGAIN.into());
+        }
+    }
     let new_peocess = current_process.fork(stack);
 
     let new_pid = new_peocess.getpid();
@@ -123,20 +147,26 @@ pub async fn sys_execve(path: usize, args: usize, envs: usize) -> SyscallRet {
 
     if let Ok(app_inode) = open_osinode(AT_FDCWD, &path, OpenFlags::RDONLY) {
         // app in fs
+        let meta = app_inode.inner_handler(|inner| inner.inode.as_ref().unwrap().get_meta());
+        if meta.mode != InodeMode::FileREG {
+            return Err(SyscallErr::EACCES.into());
+        }
+        meta.check_access(&current_process().cred(), MAY_EXEC)?;
         let all_data = app_inode.read_all().await;
         // debug!(
         //     "[sys_exec] app data len: {}, checksum: {}",
         //     all_data.len(),
         //     calculate_checksum(all_data.as_slice())
         // );
+        let elf_inode = app_inode.inner_handler(|inner| inner.inode.clone());
         let current_process = current_process();
-        current_process.exec(all_data.as_slice(), args_vec, envs_vec);
+        current_process.exec(all_data.as_slice(), elf_inode, args_vec, envs_vec);
         Ok(0)
     } else if path.is_global() {
         // app linked in kernel
         if let Some(all_data) = get_app_data_by_name(&path.get_name()) {
             let current_process = current_process();
-            current_process.exec(all_data, args_vec, envs_vec);
+            current_process.exec(all_data, None, args_vec, envs_vec);
             Ok(0)
         } else {
             Err(1)
@@ -155,93 +185,265 @@ bitflags! {
         /// 只有当子进程终止时，它们的结束状态才会被报告。如果子进程被某种信号（如 SIGSTOP 或 SIGTSTP）停止，
         /// 并且父进程没有设置 WUNTRACED 选项，那么父进程将不会感知到子进程的停止状态，直到子进程被继续执行或终止。
         const WUNTRACED = 1 << 1;
+        /// 报告终止的子进程, `wait4`总是隐含这个选项, `waitid`必须显式指定
+        const WEXITED = 1 << 2;
         /// 当子进程被停止后又继续执行时，使用这个选项。如果子进程之前被一个停止信号（如SIGSTOP 或 SIGTSTP）暂停，
         /// 然后通过继续信号（如 SIGCONT）被继续执行，那么 wait 或 waitpid 将报告这个子进程的状态，
         /// 即使它还没有终止。这允许父进程知道子进程已经从停止状态恢复。
         const WCONTINUED = 1 << 3;
+        /// 只用于`waitid`: 报告状态但不取走, 之后还可以再次等待到
+        const WNOWAIT = 0x0100_0000;
+        /// 以下三个选项与线程有关, 目前忽略
+        const WNOTHREAD = 0x2000_0000;
+        const WALL = 0x4000_0000;
+        const WCLONE = 0x8000_0000u32 as i32;
+    }
+}
+
+/// `idtype` of `waitid`
+const P_ALL: usize = 0;
+const P_PID: usize = 1;
+const P_PGID: usize = 2;
+
+/// 要等待哪些子进程
+#[derive(Clone, Copy)]
+enum WaitTarget {
+    Any,
+    Pid(usize),
+    Pgid(usize),
+}
+
+impl WaitTarget {
+    /// `wait4`的pid: -1为任意子进程, 0为同一进程组, 小于-1为进程组`-pid`
+    fn from_wait4_pid(pid: isize) -> Self {
+        match pid {
+            -1 => Self::Any,
+            0 => Self::Pgid(current_process().get_pgid()),
+            pid if pid < 0 => Self::Pgid(-pid as usize),
+            pid => Self::Pid(pid as usize),
+        }
+    }
+
+    fn matches(&self, child: &Process) -> bool {
+        match *self {
+            Self::Any => true,
+            Self::Pid(pid) => child.getpid() == pid,
+            Self::Pgid(pgid) => child.get_pgid() == pgid,
+        }
+    }
+}
+
+/// `wait`报告的子进程状态变化
+#[derive(Clone, Copy)]
+pub enum ChildEvent {
+    /// 退出, 带有`exit_status`
+    Exited(i32),
+    Stopped(usize),
+    Continued,
+}
+
+impl ChildEvent {
+    /// `wait4`的status
+    fn wait_status(&self) -> i32 {
+        match *self {
+            Self::Exited(exit_status) => exit_status,
+            Self::Stopped(signo) => ((signo as i32) << 8) | 0x7f,
+            Self::Continued => 0xffff,
+        }
+    }
+
+    /// `waitid`和SIGCHLD的`si_code`和`si_status`
+    pub fn siginfo(&self, pid: usize) -> SigInfo {
+        let (code, status) = match *self {
+            Self::Exited(exit_status) if exit_status & 0x7f == 0 => {
+                (CLD_EXITED, (exit_status >> 8) & 0xff)
+            }
+            Self::Exited(exit_status) if exit_status & 0x80 != 0 => {
+                (CLD_DUMPED, exit_status & 0x7f)
+            }
+            Self::Exited(exit_status) => (CLD_KILLED, exit_status & 0x7f),
+            Self::Stopped(signo) => (CLD_STOPPED, signo as i32),
+            Self::Continued => (CLD_CONTINUED, SIGCONT as i32),
+        };
+        SigInfo::child(code, pid, status)
     }
 }
 
-pub async fn sys_wait4(pid: isize, exit_code_ptr: usize, options: i32) -> SyscallRet {
-    trace!("[sys_wait4] enter");
-    let options = WaitOption::from_bits(options).unwrap();
-    WaitFuture::new(options, pid, exit_code_ptr).await
+/// 等待到的子进程
+struct WaitedChild {
+    pid: usize,
+    event: ChildEvent,
+    rusage: Rusage,
 }
 
-pub struct WaitFuture {
+/// 等待子进程状态变化, 由子进程退出/停止/继续时唤醒, 也可以被信号打断
+struct WaitFuture {
+    target: WaitTarget,
     options: WaitOption,
-    pid: isize,
-    exit_status_addr: usize,
 }
 
 impl WaitFuture {
-    pub fn new(options: WaitOption, pid: isize, exit_status_addr: usize) -> Self {
-        Self {
-            options,
-            pid,
-            exit_status_addr,
+    /// 检查一个子进程是否有可报告的状态变化, 需要取走时取走
+    fn take_event(&self, child: &Process) -> Option<ChildEvent> {
+        let consume = !self.options.contains(WaitOption::WNOWAIT);
+        let mut child_inner = child.inner_lock();
+        if child.is_zombie() {
+            return self
+                .options
+                .contains(WaitOption::WEXITED)
+                .then_some(ChildEvent::Exited(child_inner.exit_status));
+        }
+        let event = match child_inner.job_event? {
+            JobEvent::Stopped(signo) if self.options.contains(WaitOption::WUNTRACED) => {
+                ChildEvent::Stopped(signo)
+            }
+            JobEvent::Continued if self.options.contains(WaitOption::WCONTINUED) => {
+                ChildEvent::Continued
+            }
+            _ => return None,
+        };
+        if consume {
+            child_inner.job_event = None;
         }
+        Some(event)
     }
 }
 
 impl Future for WaitFuture {
-    type Output = SyscallRet;
-    fn poll(
-        self: core::pin::Pin<&mut Self>,
-        cx: &mut core::task::Context<'_>,
-    ) -> Poll<Self::Output> {
+    type Output = SysResult<Option<WaitedChild>>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let thread = current_thread().unwrap();
+        thread.set_interruptible_waker(cx.waker().clone());
         let process = current_process();
-        // let task = current_task().unwrap();
-        // find a child process
-        // let mut inner = task.get_inner();
-        let found_pid;
-        let exit_code;
-
         let mut inner = process.inner_lock();
-
-        if !inner
-            .children
-            .iter()
-            .any(|p| self.pid == -1 || self.pid == p.getpid() as isize)
-        {
-            return Poll::Ready(Err(1));
+        let mut found_child = false;
+        let mut waited = None;
+        for (idx, child) in inner.children.iter().enumerate() {
+            if !self.target.matches(child) {
+                continue;
+            }
+            found_child = true;
+            if let Some(event) = self.take_event(child) {
+                waited = Some((idx, event));
+                break;
+            }
         }
-
-        if let Some((idx, _child)) = inner
-            .children
-            .iter()
-            .enumerate()
-            .find(|(_, p)| p.is_zombie() && (self.pid == -1 || self.pid == p.getpid() as isize))
-        {
-            // info!("{}", child.pid.0);
-            let child = inner.children.remove(idx);
-            // confirm that child will be deallocated after being removed from children list
-            found_pid = child.getpid();
-            exit_code = child.inner_lock().exit_code;
-        } else {
+        if !found_child {
+            return Poll::Ready(Err(SyscallErr::ECHILD.into()));
+        }
+        let Some((idx, event)) = waited else {
             if self.options.contains(WaitOption::WNOHANG) {
-                return Poll::Ready(Ok(0));
-            } else {
-                cx.waker().wake_by_ref();
-                return Poll::Pending;
+                return Poll::Ready(Ok(None));
+            }
+            if thread.have_unmasked_signals() {
+                return Poll::Ready(Err(SyscallErr::EINTR.into()));
             }
+            // 持有锁登记, 子进程的状态变化不会在检查之后、登记之前丢失
+            inner.child_wait.register(cx.waker());
+            return Poll::Pending;
+        };
+        let child = inner.children[idx].clone();
+        let cpu_time = {
+            let child_inner = child.inner_lock();
+            child_inner.cpu_time + child_inner.children_cpu_time
+        };
+        if matches!(event, ChildEvent::Exited(_)) && !self.options.contains(WaitOption::WNOWAIT) {
+            // confirm that child will be deallocated after being removed from children list
+            inner.children.remove(idx);
+            inner.children_cpu_time += cpu_time;
+        }
+        Poll::Ready(Ok(Some(WaitedChild {
+            pid: child.getpid(),
+            event,
+            rusage: Rusage::from_cpu_time(cpu_time),
+        })))
+    }
+}
+
+async fn wait_child(target: WaitTarget, options: WaitOption) -> SysResult<Option<WaitedChild>> {
+    let ret = WaitFuture { target, options }.await;
+    current_thread().unwrap().clear_interruptible_waker();
+    ret
+}
+
+fn write_rusage(rusage_ptr: usize, rusage: &Rusage) -> SysResult<()> {
+    if rusage_ptr != 0 {
+        UserCheck::new().check_writable_pages(rusage_ptr as *mut u8, size_of::<Rusage>())?;
+        unsafe {
+            *(rusage_ptr as *mut Rusage) = *rusage;
         }
+    }
+    Ok(())
+}
 
-        drop(inner);
-        if self.exit_status_addr != 0 {
-            UserCheck::new().check_writable_pages(
-                self.exit_status_addr as *mut u8,
-                core::mem::size_of::<i32>(),
-            )?
+pub async fn sys_wait4(
+    pid: isize,
+    exit_status_ptr: usize,
+    options: i32,
+    rusage_ptr: usize,
+) -> SyscallRet {
+    trace!("[sys_wait4] pid: {}, options: {:#x}", pid, options);
+    let options = WaitOption::from_bits(options)
+        .filter(|options| !options.contains(WaitOption::WNOWAIT))
+        .ok_or(SyscallErr::EINVAL)?;
+    let Some(waited) = wait_child(
+        WaitTarget::from_wait4_pid(pid),
+        options | WaitOption::WEXITED,
+    )
+    .await?
+    else {
+        return Ok(0);
+    };
+    if exit_status_ptr != 0 {
+        UserCheck::new().check_writable_pages(exit_status_ptr as *mut u8, size_of::<i32>())?;
+        unsafe {
+            (exit_status_ptr as *mut i32).write_volatile(waited.event.wait_status());
         }
-        let exit_status_ptr = self.exit_status_addr as *mut i32;
-        if exit_status_ptr != core::ptr::null_mut() {
-            unsafe {
-                exit_status_ptr.write_volatile((exit_code & 0xff) << 8);
-            }
+    }
+    write_rusage(rusage_ptr, &waited.rusage)?;
+    Ok(waited.pid)
+}
+
+pub async fn sys_waitid(
+    idtype: usize,
+    id: usize,
+    infop: usize,
+    options: i32,
+    rusage_ptr: usize,
+) -> SyscallRet {
+    trace!(
+        "[sys_waitid] idtype: {}, id: {}, options: {:#x}",
+        idtype,
+        id,
+        options
+    );
+    let options = WaitOption::from_bits(options).ok_or(SyscallErr::EINVAL)?;
+    let events = WaitOption::WEXITED | WaitOption::WUNTRACED | WaitOption::WCONTINUED;
+    if !options.intersects(events) {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let target = match idtype {
+        P_ALL => WaitTarget::Any,
+        P_PID => WaitTarget::Pid(id),
+        P_PGID if id == 0 => WaitTarget::Pgid(current_process().get_pgid()),
+        P_PGID => WaitTarget::Pgid(id),
+        _ => return Err(SyscallErr::EINVAL.into()),
+    };
+    let waited = wait_child(target, options).await?;
+    if infop != 0 {
+        UserCheck::new().check_writable_pages(infop as *mut u8, size_of::<SigInfo>())?;
+        // WNOHANG且没有子进程可报告时, si_pid为0
+        let info = waited.as_ref().map_or(SigInfo::new(0, 0), |waited| {
+            waited.event.siginfo(waited.pid)
+        });
+        unsafe {
+            *(infop as *mut SigInfo) = info;
         }
-        return Poll::Ready(Ok(found_pid));
     }
+    if let Some(waited) = waited {
+        write_rusage(rusage_ptr, &waited.rusage)?;
+    }
+    Ok(0)
 }
 
 pub fn sys_getcwd(buf: usize, size: usize) -> SyscallRet {
@@ -375,7 +577,7 @@ pub fn sys_set_tid_address(tidptr: *const usize) -> SyscallRet {
 
 pub fn sys_exit_group(exit_code: i32) -> SyscallRet {
     trace!("[sys_exit_group] enter");
-    exit_current(exit_code);
+    exit_group_current((exit_code & 0xff) << 8);
     Ok(0)
 }
 
@@ -385,10 +587,85 @@ pub fn sys_exit_group(exit_code: i32) -> SyscallRet {
 //     Ok(0)
 // }
 
-pub fn sys_getpgid(pid: i32) -> SyscallRet {
+/// 根据pid找到进程, 0表示调用者自己
+pub fn find_process(pid: usize) -> SysResult<Arc<Process>> {
+    if pid == 0 {
+        return Ok(current_process());
+    }
+    PROCESS_MANAGER
+        .lock()
+        .get(&pid)
+        .and_then(|process| process.upgrade())
+        .filter(|process| process.getpid() == pid)
+        .ok_or(SyscallErr::ESRCH.into())
+}
+
+pub fn sys_getpgid(pid: usize) -> SyscallRet {
     trace!("[sys_getpgid] pid: {}", pid);
-    warn!("[sys_getpgid] not fully implemented");
-    sys_getpid()
+    Ok(find_process(pid)?.get_pgid())
+}
+
+pub fn sys_getsid(pid: usize) -> SyscallRet {
+    trace!("[sys_getsid] pid: {}", pid);
+    Ok(find_process(pid)?.get_sid())
+}
+
+/// 把调用者自己或者同一会话中的子进程移到进程组`pgid`, 组不存在时只能新建以它为组长的组
+pub fn sys_setpgid(pid: usize, pgid: isize) -> SyscallRet {
+    trace!("[sys_setpgid] pid: {}, pgid: {}", pid, pgid);
+    if pgid < 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let current = current_process();
+    let target = if pid == 0 || pid == current.getpid() {
+        current.clone()
+    } else {
+        let child = current
+            .inner_lock()
+            .children
+            .iter()
+            .find(|child| child.getpid() == pid)
+            .cloned()
+            .ok_or(SyscallErr::ESRCH)?;
+        if child.get_sid() != current.get_sid() {
+            return Err(SyscallErr::EPERM.into());
+        }
+        child
+    };
+    let pgid = if pgid == 0 {
+        target.getpid()
+    } else {
+        pgid as usize
+    };
+    let old = target.pgroup();
+    // 会话首进程不能换组
+    if old.sid() == target.getpid() {
+        return Err(SyscallErr::EPERM.into());
+    }
+    if old.pgid() == pgid {
+        return Ok(0);
+    }
+    let pgroup = match ProcessGroup::find(pgid) {
+        Some(pgroup) if pgroup.sid() == old.sid() => pgroup,
+        Some(_) => return Err(SyscallErr::EPERM.into()),
+        None if pgid == target.getpid() => ProcessGroup::new(pgid, old.session().clone()),
+        None => return Err(SyscallErr::EPERM.into()),
+    };
+    target.set_pgroup(pgroup);
+    Ok(0)
+}
+
+/// 创建新的会话和进程组, 调用者成为两者的首进程, 新会话没有控制终端
+pub fn sys_setsid() -> SyscallRet {
+    trace!("[sys_setsid] enter");
+    let process = current_process();
+    let pid = process.getpid();
+    // 已经是进程组组长, 或者还有进程组在用这个id
+    if ProcessGroup::find(pid).is_some() {
+        return Err(SyscallErr::EPERM.into());
+    }
+    process.set_pgroup(ProcessGroup::new(pid, Session::new(pid)));
+    Ok(pid)
 }
 
 pub fn sys_gettid() -> SyscallRet {
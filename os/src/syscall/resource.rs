@@ -1,46 +1,42 @@
-use crate::config::{SyscallRet, USER_STACK_SIZE};
+use crate::config::{SyscallRet, USER_STACK_LIMIT};
+use crate::fs::fd_table::MAX_FD_NUM;
 use crate::mm::user_check::UserCheck;
+use crate::syscall::process::find_process;
 use crate::task::processor::current_process;
+use crate::utils::SyscallErr;
 use log::trace;
 
 /// Infinity for RLimit
 pub const RLIM_INFINITY: usize = usize::MAX;
 
 /// 实现参考：https://manpages.debian.org/testing/manpages-dev/prlimit64.2.en.html
+pub const RLIMIT_CPU: usize = 0; // CPU 时间限制
+pub const RLIMIT_FSIZE: usize = 1; // 文件大小限制
+pub const RLIMIT_DATA: usize = 2; // 数据段大小限制
+pub const RLIMIT_STACK: usize = 3; // 栈大小限制
+pub const RLIMIT_CORE: usize = 4; // 核心文件大小限制
 #[allow(unused)]
-const RLIMIT_CPU: u32 = 0; // CPU 时间限制
+pub const RLIMIT_RSS: usize = 5; // 常驻内存大小限制
+pub const RLIMIT_NPROC: usize = 6; // 进程数量限制
+pub const RLIMIT_NOFILE: usize = 7; // 文件描述符数量限制
 #[allow(unused)]
-const RLIMIT_FSIZE: u32 = 1; // 文件大小限制
+pub const RLIMIT_MEMLOCK: usize = 8; // 锁定内存大小限制
+pub const RLIMIT_AS: usize = 9; // 地址空间大小限制
 #[allow(unused)]
-const RLIMIT_DATA: u32 = 2; // 数据段大小限制
+pub const RLIMIT_LOCKS: usize = 10; // 文件锁数量限制
 #[allow(unused)]
-const RLIMIT_STACK: u32 = 3; // 栈大小限制
+pub const RLIMIT_SIGPENDING: usize = 11; // 挂起信号数量限制
 #[allow(unused)]
-const RLIMIT_CORE: u32 = 4; // 核心文件大小限制
+pub const RLIMIT_MSGQUEUE: usize = 12; // 消息队列大小限制
+pub const RLIMIT_NICE: usize = 13; // 优先级限制
+pub const RLIMIT_RTPRIO: usize = 14; // 实时优先级限制
 #[allow(unused)]
-const RLIMIT_RSS: u32 = 5; // 常驻内存大小限制
-#[allow(unused)]
-const RLIMIT_NPROC: u32 = 6; // 进程数量限制
-#[allow(unused)]
-const RLIMIT_NOFILE: u32 = 7; // 文件描述符数量限制
-#[allow(unused)]
-const RLIMIT_MEMLOCK: u32 = 8; // 锁定内存大小限制
-#[allow(unused)]
-const RLIMIT_AS: u32 = 9; // 地址空间大小限制
-#[allow(unused)]
-const RLIMIT_LOCKS: u32 = 10; // 文件锁数量限制
-#[allow(unused)]
-const RLIMIT_SIGPENDING: u32 = 11; // 挂起信号数量限制
-#[allow(unused)]
-const RLIMIT_MSGQUEUE: u32 = 12; // 消息队列大小限制
-#[allow(unused)]
-const RLIMIT_NICE: u32 = 13; // 优先级限制
-#[allow(unused)]
-const RLIMIT_RTPRIO: u32 = 14; // 实时优先级限制
-#[allow(unused)]
-const RLIMIT_RTTIME: u32 = 15; // 实时时间限制
+pub const RLIMIT_RTTIME: usize = 15; // 实时时间限制
+/// 资源种类数
+pub const RLIM_NLIMITS: usize = 16;
 
 /// 资源限制结构体
+#[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct RLimit {
     /// 软限制
@@ -51,74 +47,96 @@ pub struct RLimit {
 
 impl RLimit {
     /// New a RLimit
-    pub fn new(cur: usize, max: usize) -> Self {
+    pub const fn new(cur: usize, max: usize) -> Self {
         Self {
             rlim_cur: cur,
             rlim_max: max,
         }
     }
-    /// Set RLimit
-    pub fn set_rlimit(resource: u32, rlimit: &RLimit) -> SyscallRet {
-        trace!("[set_rlimit] try to set limit: {:?}", resource);
-        match resource {
-            RLIMIT_NOFILE => {
-                // 设置文件描述符数量限制
-                current_process().inner_handler(|proc| proc.fd_table.set_rlimit(*rlimit))
-            }
-            _ => {}
-        }
-        Ok(0)
+    pub const fn infinity() -> Self {
+        Self::new(RLIM_INFINITY, RLIM_INFINITY)
     }
-    /// Get RLimit
-    pub fn get_rlimit(resource: u32) -> Self {
-        match resource {
-            // 获取栈大小限制
-            RLIMIT_STACK => Self::new(USER_STACK_SIZE, RLIM_INFINITY),
-            // 获取文件描述符数量限制
-            RLIMIT_NOFILE => current_process().inner_handler(|proc| proc.fd_table.rlimit()),
-            _ => {
-                unimplemented!("[get_rlimit] resource: {:?} not supported", resource)
-            }
-        }
+    /// 软限制是否有限
+    pub fn is_limited(&self) -> bool {
+        self.rlim_cur != RLIM_INFINITY
     }
 }
 
+/// 进程的全部资源限制, fork和exec时继承
+#[derive(Clone, Copy)]
+pub struct RLimits {
+    limits: [RLimit; RLIM_NLIMITS],
+}
+
+impl RLimits {
+    /// init进程的初始限制, 其余没有限制
+    pub fn new() -> Self {
+        let mut limits = [RLimit::infinity(); RLIM_NLIMITS];
+        limits[RLIMIT_STACK] = RLimit::new(USER_STACK_LIMIT, RLIM_INFINITY);
+        limits[RLIMIT_CORE] = RLimit::new(0, RLIM_INFINITY);
+        limits[RLIMIT_NOFILE] = RLimit::new(MAX_FD_NUM, RLIM_INFINITY);
+        limits[RLIMIT_NICE] = RLimit::new(0, 0);
+        limits[RLIMIT_RTPRIO] = RLimit::new(0, 0);
+        Self { limits }
+    }
+    /// 调用者保证`resource`小于[`RLIM_NLIMITS`]
+    pub fn get(&self, resource: usize) -> RLimit {
+        self.limits[resource]
+    }
+    pub fn set(&mut self, resource: usize, rlimit: RLimit) {
+        self.limits[resource] = rlimit;
+    }
+}
+
+/// 读取并设置`pid`进程(0表示当前进程)的资源限制, `getrlimit`/`setrlimit`也由它实现
 pub fn sys_prlimit64(
-    _pid: usize,              // 进程ID，当前未使用
-    resource: u32,            // 资源类型
-    new_limit: *const RLimit, // 新的资源限制，指向RLimit结构的指针
-    old_limit: *mut RLimit,   // 旧的资源限制，指向可变RLimit结构的指针
+    pid: usize,
+    resource: usize,
+    new_limit: *const RLimit,
+    old_limit: *mut RLimit,
 ) -> SyscallRet {
-    // 返回系统调用结果
-    trace!("[sys_prlimit64] enter"); // 记录进入函数的日志
-
-    // 如果old_limit不是空指针
+    trace!(
+        "[sys_prlimit64] pid: {}, resource: {}, new_limit: {:?}, old_limit: {:?}",
+        pid,
+        resource,
+        new_limit,
+        old_limit
+    );
+    if resource >= RLIM_NLIMITS {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let process = find_process(pid)?;
+    // 没有特权时只能访问真实用户id相同的进程
+    let cred = current_process().cred();
+    if !cred.is_privileged() && process.cred().user.real != cred.user.real {
+        return Err(SyscallErr::EPERM.into());
+    }
+    let new_rlimit = if new_limit.is_null() {
+        None
+    } else {
+        UserCheck::new()
+            .check_readable_pages(new_limit as *const u8, core::mem::size_of::<RLimit>())?;
+        let new_rlimit = unsafe { *new_limit };
+        // 软限制不能超过硬限制
+        if new_rlimit.rlim_cur > new_rlimit.rlim_max {
+            return Err(SyscallErr::EINVAL.into());
+        }
+        // 只有特权进程能提高硬限制
+        if new_rlimit.rlim_max > process.rlimit(resource).rlim_max && !cred.is_privileged() {
+            return Err(SyscallErr::EPERM.into());
+        }
+        Some(new_rlimit)
+    };
     if !old_limit.is_null() {
-        // 检查old_limit指向的内存页是否可写
         UserCheck::new()
             .check_writable_pages(old_limit as *mut u8, core::mem::size_of::<RLimit>())?;
-        // 获取当前资源限制
-        let old_rlimit = RLimit::get_rlimit(resource);
-        // 将当前资源限制写入old_limit指向的内存
         unsafe {
-            *old_limit = old_rlimit;
+            *old_limit = process.rlimit(resource);
         }
     }
-    // 如果new_limit是空指针，直接返回成功
-    if new_limit.is_null() {
-        return Ok(0);
-    }
-    // todo：检查new_limit指向的内存页是否可读
-    // titanix对应代码=> UserCheck::new().check_readable_pages(new_limit as *const u8, core::mem::size_of::<RLimit>())?;
-
-    // 将new_limit解引用为RLimit引用
-    let new_rlimit = unsafe { &*new_limit };
-    // 检查新的软限制是否超过硬限制
-    if new_rlimit.rlim_cur > new_rlimit.rlim_max {
-        return Err(22); // EINVAL，表示无效的参数
+    if let Some(new_rlimit) = new_rlimit {
+        trace!("[sys_prlimit64] new_rlimit: {:?}", new_rlimit);
+        process.set_rlimit(resource, new_rlimit);
     }
-    // 记录新资源限制的日志
-    trace!("[sys_prlimit64] finish: new_rlimit: {:?}", new_rlimit);
-    // 设置新的资源限制
-    RLimit::set_rlimit(resource, new_rlimit)
+    Ok(0)
 }
@@ -0,0 +1,245 @@
+//! 调度相关的系统调用, 调度参数都保存在线程的[`SchedEntity`]中
+
+use crate::config::SyscallRet;
+use crate::executor::sched::{MAX_NICE, MIN_NICE, RR_TIMESLICE_NS};
+use crate::executor::{SchedEntity, SchedPolicy};
+use crate::mm::user_check::UserCheck;
+use crate::task::pgroup::ProcessGroup;
+use crate::task::processor::{current_process, current_thread, online_hart_mask};
+use crate::task::task::Thread;
+use crate::task::{all_processes, find_thread, yield_task};
+use crate::timer::TimeSpec;
+use crate::utils::SyscallErr;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use log::trace;
+
+/// 与policy按位或, fork出的子线程恢复普通调度策略
+const SCHED_RESET_ON_FORK: usize = 0x4000_0000;
+
+/// `which` of `sys_setpriority` and `sys_getpriority`
+const PRIO_PROCESS: usize = 0;
+const PRIO_PGRP: usize = 1;
+const PRIO_USER: usize = 2;
+
+/// `struct sched_param`
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SchedParam {
+    pub sched_priority: i32,
+}
+
+/// 根据tid找到线程, 0表示调用者自己
+fn target_thread(tid: isize) -> Result<Arc<Thread>, SyscallErr> {
+    match tid {
+        tid if tid < 0 => Err(SyscallErr::EINVAL),
+        0 => Ok(current_thread().unwrap()),
+        tid => find_thread(tid as usize).ok_or(SyscallErr::ESRCH),
+    }
+}
+
+fn read_param(param: *const SchedParam) -> SyscallRet {
+    if param.is_null() {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    UserCheck::new().check_readable_pages(param as *const u8, size_of::<SchedParam>())?;
+    let priority = unsafe { (*param).sched_priority };
+    if priority < 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    Ok(priority as usize)
+}
+
+fn set_scheduler(
+    sched: &SchedEntity,
+    policy: SchedPolicy,
+    priority: usize,
+    reset_on_fork: bool,
+) -> SyscallRet {
+    let (min, max) = policy.priority_range();
+    if priority < min || priority > max {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    sched.set_policy(policy, priority, reset_on_fork);
+    Ok(0)
+}
+
+pub fn sys_sched_setscheduler(pid: isize, policy: usize, param: *const SchedParam) -> SyscallRet {
+    trace!(
+        "[sys_sched_setscheduler] pid: {}, policy: {:#x}",
+        pid,
+        policy
+    );
+    let reset_on_fork = policy & SCHED_RESET_ON_FORK != 0;
+    let policy = SchedPolicy::from_raw(policy & !SCHED_RESET_ON_FORK).ok_or(SyscallErr::EINVAL)?;
+    let priority = read_param(param)?;
+    let thread = target_thread(pid)?;
+    set_scheduler(&thread.sched, policy, priority, reset_on_fork)
+}
+
+pub fn sys_sched_setparam(pid: isize, param: *const SchedParam) -> SyscallRet {
+    trace!("[sys_sched_setparam] pid: {}", pid);
+    let priority = read_param(param)?;
+    let thread = target_thread(pid)?;
+    let sched = &thread.sched;
+    set_scheduler(sched, sched.policy(), priority, sched.reset_on_fork())
+}
+
+pub fn sys_sched_getscheduler(pid: isize) -> SyscallRet {
+    trace!("[sys_sched_getscheduler] pid: {}", pid);
+    let thread = target_thread(pid)?;
+    let mut policy = thread.sched.policy() as usize;
+    if thread.sched.reset_on_fork() {
+        policy |= SCHED_RESET_ON_FORK;
+    }
+    Ok(policy)
+}
+
+pub fn sys_sched_getparam(pid: isize, param: *mut SchedParam) -> SyscallRet {
+    trace!("[sys_sched_getparam] pid: {}", pid);
+    if param.is_null() {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let thread = target_thread(pid)?;
+    UserCheck::new().check_writable_pages(param as *mut u8, size_of::<SchedParam>())?;
+    unsafe {
+        *param = SchedParam {
+            sched_priority: thread.sched.rt_priority() as i32,
+        };
+    }
+    Ok(0)
+}
+
+pub fn sys_sched_get_priority_max(policy: usize) -> SyscallRet {
+    let policy = SchedPolicy::from_raw(policy).ok_or(SyscallErr::EINVAL)?;
+    Ok(policy.priority_range().1)
+}
+
+pub fn sys_sched_get_priority_min(policy: usize) -> SyscallRet {
+    let policy = SchedPolicy::from_raw(policy).ok_or(SyscallErr::EINVAL)?;
+    Ok(policy.priority_range().0)
+}
+
+pub fn sys_sched_rr_get_interval(pid: isize, interval: *mut TimeSpec) -> SyscallRet {
+    trace!("[sys_sched_rr_get_interval] pid: {}", pid);
+    let thread = target_thread(pid)?;
+    // SCHED_FIFO没有时间片, 普通任务每个时钟周期都可能被切换, 也报告RR的时间片
+    let nanos = match thread.sched.policy() {
+        SchedPolicy::Fifo => 0,
+        _ => RR_TIMESLICE_NS as usize,
+    };
+    UserCheck::new().check_writable_pages(interval as *mut u8, size_of::<TimeSpec>())?;
+    unsafe {
+        *interval = TimeSpec {
+            sec: nanos / 1_000_000_000,
+            nsec: nanos % 1_000_000_000,
+        };
+    }
+    Ok(0)
+}
+
+/// 返回写入的字节数, 即内核中cpu mask的大小
+pub fn sys_sched_getaffinity(pid: isize, len: usize, mask: *mut u8) -> SyscallRet {
+    trace!("[sys_sched_getaffinity] pid: {}, len: {}", pid, len);
+    if len < size_of::<usize>() || len % size_of::<usize>() != 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let thread = target_thread(pid)?;
+    let affinity = thread.sched.affinity() & online_hart_mask();
+    UserCheck::new().check_writable_pages(mask, size_of::<usize>())?;
+    unsafe {
+        *(mask as *mut usize) = affinity;
+    }
+    Ok(size_of::<usize>())
+}
+
+pub async fn sys_sched_setaffinity(pid: isize, len: usize, mask: usize) -> SyscallRet {
+    trace!("[sys_sched_setaffinity] pid: {}, len: {}", pid, len);
+    if mask == 0 {
+        return Err(SyscallErr::EFAULT.into());
+    }
+    // 只关心前usize个字节, 更高位的hart不存在
+    let len = len.min(size_of::<usize>());
+    UserCheck::new().check_readable_pages(mask as *const u8, len)?;
+    let mut bytes = [0u8; size_of::<usize>()];
+    unsafe {
+        core::ptr::copy_nonoverlapping(mask as *const u8, bytes.as_mut_ptr(), len);
+    }
+    let affinity = usize::from_le_bytes(bytes);
+    if affinity & online_hart_mask() == 0 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    let thread = target_thread(pid)?;
+    thread.sched.set_affinity(affinity);
+    // 让出一次, 重新入队时会被放到允许的hart上
+    if pid == 0 || current_thread().unwrap().get_tid() == pid as usize {
+        yield_task().await;
+    }
+    Ok(0)
+}
+
+/// `which`和`who`选中的所有线程
+fn priority_targets(which: usize, who: usize) -> Result<Vec<Arc<Thread>>, SyscallErr> {
+    let processes = match which {
+        PRIO_PROCESS if who == 0 => return Ok(alloc::vec![current_thread().unwrap()]),
+        PRIO_PROCESS => {
+            let thread = find_thread(who).ok_or(SyscallErr::ESRCH)?;
+            // who是线程号时只修改这个线程
+            if thread.process.getpid() != who {
+                return Ok(alloc::vec![thread]);
+            }
+            alloc::vec![thread.process.clone()]
+        }
+        PRIO_PGRP => {
+            let pgid = if who == 0 {
+                current_process().get_pgid()
+            } else {
+                who
+            };
+            ProcessGroup::find(pgid).map_or(Vec::new(), |pgroup| pgroup.members())
+        }
+        // 只有一个用户
+        PRIO_USER => all_processes(),
+        _ => return Err(SyscallErr::EINVAL),
+    };
+    let mut threads: Vec<Arc<Thread>> = Vec::new();
+    for process in processes {
+        for thread in process.inner_lock().threads.values() {
+            if let Some(thread) = thread.upgrade() {
+                if !threads.iter().any(|t| Arc::ptr_eq(t, &thread)) {
+                    threads.push(thread);
+                }
+            }
+        }
+    }
+    if threads.is_empty() {
+        return Err(SyscallErr::ESRCH);
+    }
+    Ok(threads)
+}
+
+pub fn sys_setpriority(which: usize, who: usize, nice: isize) -> SyscallRet {
+    trace!(
+        "[sys_setpriority] which: {}, who: {}, nice: {}",
+        which,
+        who,
+        nice
+    );
+    let nice = nice.clamp(MIN_NICE, MAX_NICE);
+    for thread in priority_targets(which, who)? {
+        thread.sched.set_nice(nice);
+    }
+    Ok(0)
+}
+
+/// 与Linux的系统调用一致, 返回`20 - nice`以避免负数, 由libc换算回nice
+pub fn sys_getpriority(which: usize, who: usize) -> SyscallRet {
+    trace!("[sys_getpriority] which: {}, who: {}", which, who);
+    let nice = priority_targets(which, who)?
+        .iter()
+        .map(|thread| thread.sched.nice())
+        .min()
+        .unwrap();
+    Ok((20 - nice) as usize)
+}
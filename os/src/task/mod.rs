@@ -5,6 +5,7 @@
 //! 用了异步无栈协程进行对应的相关调度
 
 pub mod aux;
+pub mod pgroup;
 mod pid;
 pub(crate) mod processor;
 pub mod schedule;
@@ -12,8 +13,10 @@ pub mod schedule;
 #[allow(rustdoc::private_intra_doc_links)]
 pub mod task;
 
+use crate::futex::futex_exit;
 use crate::loader::get_app_data_by_name;
 use crate::sbi::shutdown;
+use crate::signal::SIGKILL;
 use crate::task::processor::current_process;
 use crate::task::task::{new_initproc, Process, PROCESS_MANAGER};
 use alloc::string::String;
@@ -30,12 +33,43 @@ pub use pid::{id_alloc, IdHandle, RecycleAllocator};
 pub use processor::{current_trap_cx, current_user_token, take_current_thread};
 pub use schedule::yield_task;
 use task::Thread;
+pub use task::{all_processes, find_thread};
 
 /// pid of usertests app in make run TEST=1
 pub const IDLE_PID: usize = 0;
 
 /// 退出当前的线程，如果这是最后一个线程，主线程会进行对应的回收
 pub fn exit_current(exit_code: i32) {
+    exit_current_with_status((exit_code & 0xff) << 8);
+}
+
+/// 结束整个线程组, `exit_status`是父进程`wait`得到的状态,
+/// 其他线程会收到SIGKILL, 在下一次处理信号时退出
+pub fn exit_group_current(exit_status: i32) {
+    let process = current_process();
+    let current = current_thread().unwrap();
+    let others: Vec<Arc<Thread>> = {
+        let mut inner = process.inner_lock();
+        if inner.group_exit_status.is_some() {
+            // 已经有线程在结束线程组了
+            Vec::new()
+        } else {
+            inner.group_exit_status = Some(exit_status);
+            inner
+                .threads
+                .values()
+                .filter_map(|thread| thread.upgrade())
+                .filter(|thread| !Arc::ptr_eq(thread, &current))
+                .collect()
+        }
+    };
+    for thread in others {
+        thread.send_signal(SIGKILL);
+    }
+    exit_current_with_status(exit_status);
+}
+
+fn exit_current_with_status(exit_status: i32) {
     let process = current_process();
     info!(
         "[exit_current] exit task's pagetable: {:?}",
@@ -43,14 +77,17 @@ pub fn exit_current(exit_code: i32) {
     );
 
     let pid = process.getpid();
-    info!("[exit_current] pid = {}, exit_code = {}", pid, exit_code);
+    info!(
+        "[exit_current] pid = {}, exit_status = {:#x}",
+        pid, exit_status
+    );
 
     if pid == IDLE_PID {
         println!(
-            "[kernel] Idle process exit with exit_code {} ...",
-            exit_code
+            "[kernel] Idle process exit with exit_status {:#x} ...",
+            exit_status
         );
-        if exit_code != 0 {
+        if exit_status != 0 {
             shutdown(true) //crate::sbi::shutdown(255); //255 == -1 for err hint
         } else {
             shutdown(false) //crate::sbi::shutdown(0); //0 for success hint
@@ -58,28 +95,36 @@ pub fn exit_current(exit_code: i32) {
     }
 
     let current_thread = current_thread().unwrap();
+    // 地址空间还在, 先释放线程持有的robust futex并唤醒等待它退出的线程
+    futex_exit(&current_thread);
     current_thread.is_terminated.store(true, Relaxed);
 
     let mut process_inner = process.inner_lock();
-    process_inner.exit_code = exit_code; // Record exit code
+    // 线程组被结束时以结束者的状态为准
+    process_inner.exit_status = process_inner.group_exit_status.unwrap_or(exit_status);
     process_inner.threads.remove(&current_thread.get_tid());
+    process_inner.cpu_time += current_thread.sched.exec_runtime();
 
     PROCESS_MANAGER.lock().remove(&current_thread.get_tid());
 
     if process_inner.threads.len() <= 0 {
         process.is_zombie.store(true, Relaxed);
-        {
-            let mut initproc_inner = INITPROC.inner_lock();
-            for child in process_inner.children.iter() {
-                child.inner_lock().parent = Some(Arc::downgrade(&INITPROC));
-                initproc_inner.children.push(child.clone());
-            }
-        }
-
-        process_inner.children.clear();
+        let children = core::mem::take(&mut process_inner.children);
         // deallocate user space
         process_inner.memory_set.recycle_data_pages();
         process_inner.fd_table.table.clear();
+        // 父进程的wait会持有父进程的锁再锁子进程, 这里不能反过来
+        drop(process_inner);
+
+        let has_zombie = children.iter().any(|child| child.is_zombie());
+        for child in children.iter() {
+            child.inner_lock().parent = Some(Arc::downgrade(&INITPROC));
+        }
+        INITPROC.inner_lock().children.extend(children);
+        if has_zombie {
+            INITPROC.wake_child_waiters();
+        }
+        process.notify_parent_exit();
     }
 }
 
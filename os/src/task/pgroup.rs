@@ -0,0 +1,111 @@
+//! 会话和进程组
+//!
+//! 每个进程属于一个进程组, 每个进程组属于一个会话.
+//! 进程组记录自己的成员, 用于`kill`和终端向整个组发送信号;
+//! 控制终端和前台进程组由tty记录.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use lazy_static::lazy_static;
+
+use crate::mutex::SpinNoIrqLock;
+
+use super::task::Process;
+
+lazy_static! {
+    /// 所有进程组, 进程组在最后一个成员离开后被释放
+    static ref PROCESS_GROUPS: SpinNoIrqLock<BTreeMap<usize, Weak<ProcessGroup>>> =
+        SpinNoIrqLock::new(BTreeMap::new());
+}
+
+/// 会话, id为创建它的会话首进程的pid
+pub struct Session {
+    sid: usize,
+}
+
+impl Session {
+    pub fn new(sid: usize) -> Arc<Self> {
+        Arc::new(Self { sid })
+    }
+    pub fn sid(&self) -> usize {
+        self.sid
+    }
+}
+
+pub struct ProcessGroup {
+    pgid: usize,
+    session: Arc<Session>,
+    members: SpinNoIrqLock<BTreeMap<usize, Weak<Process>>>,
+}
+
+impl ProcessGroup {
+    /// 在`session`中创建新的进程组, 调用者保证`pgid`没有被其他进程组使用
+    pub fn new(pgid: usize, session: Arc<Session>) -> Arc<Self> {
+        let group = Arc::new(Self {
+            pgid,
+            session,
+            members: SpinNoIrqLock::new(BTreeMap::new()),
+        });
+        PROCESS_GROUPS.lock().insert(pgid, Arc::downgrade(&group));
+        group
+    }
+
+    /// 还有成员的进程组
+    pub fn find(pgid: usize) -> Option<Arc<Self>> {
+        let mut groups = PROCESS_GROUPS.lock();
+        let group = groups.get(&pgid)?.upgrade();
+        if group.is_none() {
+            groups.remove(&pgid);
+        }
+        group
+    }
+
+    pub fn pgid(&self) -> usize {
+        self.pgid
+    }
+    pub fn session(&self) -> &Arc<Session> {
+        &self.session
+    }
+    pub fn sid(&self) -> usize {
+        self.session.sid
+    }
+
+    pub fn add(&self, process: &Arc<Process>) {
+        let mut members = self.members.lock();
+        members.retain(|_, member| member.strong_count() > 0);
+        members.insert(process.getpid(), Arc::downgrade(process));
+    }
+
+    pub fn remove(&self, pid: usize) {
+        self.members.lock().remove(&pid);
+    }
+
+    pub fn members(&self) -> Vec<Arc<Process>> {
+        self.members
+            .lock()
+            .values()
+            .filter_map(|member| member.upgrade())
+            .collect()
+    }
+
+    /// 向组内所有进程发送信号
+    pub fn send_signal(&self, signo: usize) {
+        for process in self.members() {
+            process.send_signal(signo);
+        }
+    }
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        let mut groups = PROCESS_GROUPS.lock();
+        // pgid可能已经被新的进程组使用
+        if groups
+            .get(&self.pgid)
+            .map_or(false, |group| group.strong_count() == 0)
+        {
+            groups.remove(&self.pgid);
+        }
+    }
+}
@@ -5,6 +5,7 @@ use crate::task::task::Process;
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
 use core::arch::asm;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// 每个CPU核心对应的抽象
 // #[derive(Debug)]
@@ -40,6 +41,9 @@ impl U7Hart {
     }
 }
 
+/// 已经启动的hart, 第i位代表hart i
+static ONLINE_HARTS: AtomicUsize = AtomicUsize::new(0);
+
 /// 把对应的静态引用的id初始化，new之后 tp放的就是对应的hart_addr的地址，直接可以拿来用
 pub fn new_local_hart(hart_id: usize) {
     unsafe {
@@ -47,9 +51,19 @@ pub fn new_local_hart(hart_id: usize) {
         asm!("mv {}, sp", out(reg) sp);
         // warn: 目前是硬编码,放到内核栈底，为了防止溢出, 如果内核栈的大小不是16PAGE，会出事情！
         let s = ((sp & !(PAGE_SIZE - 1)) - 15 * PAGE_SIZE) as *mut U7Hart;
-        (*s).hart_id = hart_id;
+        // 栈上的内容未初始化, 整个写入
+        s.write(U7Hart {
+            hart_id,
+            current: None,
+        });
         asm!("mv tp, {}", in(reg) s as *const _ as usize);
     }
+    ONLINE_HARTS.fetch_or(1 << hart_id, Ordering::SeqCst);
+}
+
+/// 已经启动的hart的掩码, 用于TLB shootdown
+pub fn online_hart_mask() -> usize {
+    ONLINE_HARTS.load(Ordering::Acquire)
 }
 
 /// tp里面装的每个CPU对应OS结构体的地址，看[`new_local_hart`]
@@ -8,7 +8,8 @@ use core::{
 use alloc::sync::Arc;
 
 use crate::{
-    executor,
+    executor::{self, SchedEntity},
+    signal::handle_signals,
     trap::{trap_handler, trap_return},
 };
 
@@ -70,8 +71,9 @@ pub async fn yield_task() {
 
 /// Spawn a new user thread
 pub fn spawn_thread(task_control_block: Arc<Thread>) {
+    let sched = task_control_block.sched.clone();
     let future = UserTaskFuture::new(task_control_block.clone(), thread_loop(task_control_block));
-    let (runnable, task) = executor::spawn(future);
+    let (runnable, task) = executor::spawn(future, sched);
     runnable.schedule();
     task.detach();
 }
@@ -81,7 +83,7 @@ where
     <F as Future>::Output: Send,
 {
     // let future = UserTaskFuture::new(, thread_loop(task_control_block));
-    let (runnable, task) = executor::spawn(future);
+    let (runnable, task) = executor::spawn(future, Arc::new(SchedEntity::default()));
     runnable.schedule();
     task.detach();
 }
@@ -89,6 +91,10 @@ where
 /// The main loop of a user thread
 pub async fn thread_loop(task: Arc<Thread>) {
     loop {
+        handle_signals().await;
+        if task.is_zombie() {
+            break;
+        }
         trap_return();
 
         // next time when user traps into kernel, it will come back here
@@ -1,16 +1,27 @@
 //!Implementation of [`Thread`]
 use super::aux::*;
+use super::pgroup::{ProcessGroup, Session};
 use super::processor::current_thread_uncheck;
 use super::{current_trap_cx, id_alloc, IdHandle};
-use crate::config::SyscallRet;
+use crate::config::{SyscallRet, USER_STACK_SIZE};
+use crate::executor::SchedEntity;
 use crate::fs::fd_table::{FdInfo, FdTable};
+use crate::fs::inode::Inode;
 use crate::fs::path::Path;
-use crate::fs::tty::TtyFile;
+use crate::fs::poll::WaitQueue;
+use crate::fs::tty::{set_console_session, TtyFile};
+use crate::futex::futex::FutexRobustList;
 // use crate::fs::FileMeta;
 use crate::mm::{MemorySet, VirtAddr, KERNEL_SPACE};
 use crate::mutex::SpinNoIrqLock;
-use crate::signal::{SigBitmap, SigHandlers, SigSet};
-use crate::syscall::process::CloneFlags;
+use crate::signal::{
+    InterruptedSyscall, SigAction, SigActionFlags, SigBitmap, SigHandlers, SigInfo, SigPending,
+    SigSet, SignalStack, SIGCHLD, SIGCONT, SIGKILL, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU, SIGXCPU,
+    SI_KERNEL,
+};
+use crate::syscall::cred::{Credentials, DEFAULT_UMASK};
+use crate::syscall::process::{ChildEvent, CloneFlags};
+use crate::syscall::resource::{RLimit, RLimits, RLIMIT_CPU, RLIMIT_NOFILE, RLIMIT_STACK};
 use crate::task::processor::current_thread;
 use crate::task::schedule::spawn_thread;
 use crate::trap::TrapContext;
@@ -23,6 +34,8 @@ use core::cell::UnsafeCell;
 use core::ops::DerefMut;
 use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering::Relaxed;
+use core::task::Waker;
+use core::time::Duration;
 use lazy_static::lazy_static;
 use log::info;
 
@@ -32,6 +45,22 @@ lazy_static! {
         SpinNoIrqLock::new(BTreeMap::new());
 }
 
+/// 根据tid找到线程, 而不是线程所在的进程
+pub fn find_thread(tid: usize) -> Option<Arc<Thread>> {
+    let process = PROCESS_MANAGER.lock().get(&tid)?.upgrade()?;
+    let thread = process.inner_lock().threads.get(&tid)?.upgrade();
+    thread
+}
+
+/// 所有进程. `PROCESS_MANAGER`中每个线程都有一项, 只取以pid为键的
+pub fn all_processes() -> Vec<Arc<Process>> {
+    PROCESS_MANAGER
+        .lock()
+        .iter()
+        .filter_map(|(&id, process)| process.upgrade().filter(|p| p.getpid() == id))
+        .collect()
+}
+
 /// ['Process'] 负责管理进程资源的单位，有着自己的Thread都共享的资源，本身并不参与调度
 /// 记录了主线程，以保持以前现有进程的一致性
 pub struct Process {
@@ -64,17 +93,186 @@ impl Process {
     pub fn is_zombie(&self) -> bool {
         self.is_zombie.load(Relaxed)
     }
-    pub fn get_exit_code(&self) -> i32 {
-        self.inner.lock().exit_code
+    pub fn get_exit_status(&self) -> i32 {
+        self.inner.lock().exit_status
     }
     pub fn get_pgid(&self) -> usize {
-        self.inner.lock().pgid
+        self.inner.lock().pgroup.pgid()
+    }
+    pub fn get_sid(&self) -> usize {
+        self.inner.lock().pgroup.sid()
+    }
+    pub fn pgroup(&self) -> Arc<ProcessGroup> {
+        self.inner.lock().pgroup.clone()
+    }
+    /// 离开原来的进程组, 加入`pgroup`
+    pub fn set_pgroup(self: &Arc<Self>, pgroup: Arc<ProcessGroup>) {
+        pgroup.add(self);
+        let old = core::mem::replace(&mut self.inner.lock().pgroup, pgroup);
+        old.remove(self.getpid());
+    }
+
+    pub fn rlimit(&self, resource: usize) -> RLimit {
+        self.inner.lock().rlimits.get(resource)
+    }
+    pub fn set_rlimit(&self, resource: usize, rlimit: RLimit) {
+        let mut inner = self.inner.lock();
+        inner.rlimits.set(resource, rlimit);
+        if resource == RLIMIT_NOFILE {
+            inner.fd_table.set_rlimit(rlimit);
+        }
     }
 
+    pub fn cred(&self) -> Credentials {
+        self.inner.lock().cred.clone()
+    }
+
+    pub fn umask(&self) -> u16 {
+        self.inner.lock().umask
+    }
+
+    /// 所有线程(包括已退出的)用掉的CPU时间
+    pub fn cpu_time(&self) -> Duration {
+        let exited = self.inner.lock().cpu_time;
+        self.live_threads()
+            .iter()
+            .fold(exited, |sum, thread| sum + thread.sched.exec_runtime())
+    }
+
+    /// CPU时间超过软限制时发送SIGXCPU, 之后软限制每次加一秒,
+    /// 这样每多用一秒就再发一次; 超过硬限制时直接SIGKILL
+    pub fn check_cpu_limit(&self) {
+        let limit = self.rlimit(RLIMIT_CPU);
+        if !limit.is_limited() {
+            return;
+        }
+        let secs = self.cpu_time().as_secs() as usize;
+        if secs >= limit.rlim_max {
+            self.send_signal(SIGKILL);
+        } else if secs >= limit.rlim_cur {
+            self.inner
+                .lock()
+                .rlimits
+                .set(RLIMIT_CPU, RLimit::new(secs + 1, limit.rlim_max));
+            self.send_signal(SIGXCPU);
+        }
+    }
+
+    /// 信号`signo`的处理方式, 同一进程的所有线程共享
+    pub fn sig_action(&self, signo: usize) -> SigAction {
+        self.inner.lock().sig_handlers.sig_handlers[signo]
+    }
+
+    pub fn live_threads(&self) -> Vec<Arc<Thread>> {
+        self.inner
+            .lock()
+            .threads
+            .values()
+            .filter_map(|thread| thread.upgrade())
+            .collect()
+    }
+
+    /// 不管发给进程还是线程, 停止信号和SIGCONT在发送时就互相抵消, 而不是等到处理时
+    fn prepare_signal(&self, signo: usize, threads: &[Arc<Thread>]) {
+        let stop_sigs =
+            SigBitmap::SIGSTOP | SigBitmap::SIGTSTP | SigBitmap::SIGTTIN | SigBitmap::SIGTTOU;
+        let cancelled = match signo {
+            SIGCONT | SIGKILL => stop_sigs,
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => SigBitmap::SIGCONT,
+            _ => return,
+        };
+        let was_stopped = {
+            let mut inner = self.inner.lock();
+            inner.pending_sigs.remove(cancelled);
+            cancelled == stop_sigs && core::mem::replace(&mut inner.stopped, false)
+        };
+        for thread in threads {
+            thread.sig_set.lock().pending_sigs.remove(cancelled);
+            if cancelled == stop_sigs {
+                // SIGCONT即使被忽略也要让停止的线程继续
+                thread.wake_interruptible();
+            }
+        }
+        if was_stopped && signo == SIGCONT {
+            self.notify_parent_stop(JobEvent::Continued);
+        }
+    }
+
+    /// 内核发给整个进程的信号
     pub fn send_signal(&self, signo: usize) {
-        for (_, thread) in self.inner.lock().threads.iter_mut() {
-            let task = thread.upgrade().unwrap();
-            task.send_signal(signo)
+        self.send_signal_info(SigInfo::new(signo, SI_KERNEL));
+    }
+
+    /// 发给整个进程的信号, 放入进程的待处理集合, 由任一没有阻塞它的线程处理
+    pub fn send_signal_info(&self, info: SigInfo) {
+        let signo = info.si_signo as usize;
+        let threads = self.live_threads();
+        self.prepare_signal(signo, &threads);
+        let sig = SigBitmap::from_signo(signo);
+        if threads.is_empty() {
+            return;
+        }
+        let ignored = self.sig_action(signo).is_ignored(signo);
+        let blocked_by_all = threads.iter().all(|thread| thread.sig_mask().contains(sig));
+        let blocked_by_any = threads.iter().any(|thread| thread.sig_mask().contains(sig));
+        if ignored && !blocked_by_any {
+            return;
+        }
+        self.inner.lock().pending_sigs.insert(info);
+        if signo == SIGKILL || blocked_by_all {
+            // SIGKILL让所有线程都尽快退出, 都阻塞时留给sigwait之类的调用
+            threads
+                .iter()
+                .for_each(|thread| thread.wake_interruptible());
+        } else if let Some(target) = threads
+            .iter()
+            .find(|thread| !thread.sig_mask().contains(sig))
+        {
+            target.wake_interruptible();
+        }
+        threads
+            .iter()
+            .for_each(|thread| thread.wake_signal_waiters());
+    }
+
+    fn parent(&self) -> Option<Arc<Process>> {
+        self.inner.lock().parent.as_ref().and_then(|p| p.upgrade())
+    }
+
+    /// 唤醒在`wait`中等待子进程状态变化的线程
+    pub fn wake_child_waiters(&self) {
+        let wakers = self.inner.lock().child_wait.take();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// 退出后唤醒父进程的`wait`并发送SIGCHLD
+    pub fn notify_parent_exit(&self) {
+        let Some(parent) = self.parent() else {
+            return;
+        };
+        parent.wake_child_waiters();
+        let exit_status = self.inner.lock().exit_status;
+        parent.send_signal_info(ChildEvent::Exited(exit_status).siginfo(self.getpid()));
+    }
+
+    /// 停止或继续时记录事件供父进程`wait`, 并向父进程发送SIGCHLD,
+    /// 除非父进程设置了`SA_NOCLDSTOP`
+    pub fn notify_parent_stop(&self, event: JobEvent) {
+        self.inner.lock().job_event = Some(event);
+        let child_event = match event {
+            JobEvent::Stopped(signo) => ChildEvent::Stopped(signo),
+            JobEvent::Continued => ChildEvent::Continued,
+        };
+        let Some(parent) = self.parent() else {
+            return;
+        };
+        parent.wake_child_waiters();
+        let nocldstop = parent
+            .sig_action(SIGCHLD)
+            .sa_flags
+            .contains(SigActionFlags::SA_NOCLDSTOP);
+        if !nocldstop {
+            parent.send_signal_info(child_event.siginfo(self.getpid()));
         }
     }
 
@@ -97,14 +295,44 @@ impl Process {
     }
 
     /// 目前的语义，主线程切换到另一个任务，其余的所有线程直接kill了。【注意不是当前线程，而是主线程】
-    pub fn exec(&self, elf_data: &[u8], args_vec: Vec<String>, envs_vec: Vec<String>) {
+    /// `elf_inode` is the file `elf_data` was read from, if any, so that
+    /// read-only segments can be mapped from its page cache
+    pub fn exec(
+        &self,
+        elf_data: &[u8],
+        elf_inode: Option<Arc<dyn Inode>>,
+        args_vec: Vec<String>,
+        envs_vec: Vec<String>,
+    ) {
         // exec 对原来的线程主要干三件事情
         // 1- 修改memory set （process 层面）
         // 2- 新建trap_context，设置ustack_top （thread 层面）
         // 3- 压入一些初始参数 （a0 -> argc, a1 -> argv, a2 -> envp....）
 
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, user_sp, entry_point, aux_vec) = MemorySet::from_elf(elf_data);
+        let stack_limit = self.rlimit(RLIMIT_STACK).rlim_cur;
+        // setuid/setgid程序以文件属主的身份运行
+        let file_owner = elf_inode.as_ref().map(|inode| {
+            let meta = inode.get_meta();
+            let inner = meta.inner.lock();
+            (inner.uid, inner.gid, inner.perm)
+        });
+        let cred = self.inner_handler(|inner| {
+            inner.cred.exec(file_owner);
+            inner.cred.clone()
+        });
+        let (memory_set, user_sp, entry_point, mut aux_vec) =
+            MemorySet::from_elf(elf_data, elf_inode, stack_limit);
+        for aux in aux_vec.iter_mut() {
+            aux.value = match aux.aux_type {
+                AT_UID => cred.user.real as usize,
+                AT_EUID => cred.user.effective as usize,
+                AT_GID => cred.group.real as usize,
+                AT_EGID => cred.group.effective as usize,
+                AT_SECURE => cred.is_secure() as usize,
+                _ => continue,
+            };
+        }
         // activate user space
         memory_set.activate();
         info!(
@@ -136,12 +364,18 @@ impl Process {
         );
 
         // 修改memory set
-        self.inner_lock().memory_set = memory_set;
+        let mut inner = self.inner_lock();
+        inner.memory_set.detach_all_shm();
+        inner.memory_set = memory_set;
+        inner.sig_handlers.reset_for_exec();
+        drop(inner);
 
         // 修改main thread 的trap_context
         let main_thread_inner = unsafe { &mut (*self.main_thread().inner.get()) };
         main_thread_inner.ustack_top = user_sp;
         main_thread_inner.trap_context = trap_cx;
+        main_thread_inner.sig_altstack = SignalStack::disabled();
+        main_thread_inner.robust_list = None;
         // todo: 相关的传参，需要搬到对应的user_sp里面的，而且对应的参数都需要构造
     }
 
@@ -171,15 +405,27 @@ impl Process {
                 parent: Some(Arc::downgrade(self)),
                 memory_set,
                 children: Vec::new(),
-                exit_code: 0,
+                exit_status: 0,
+                group_exit_status: None,
+                stopped: false,
+                pending_sigs: SigPending::new(),
+                sig_handlers: parent_inner.sig_handlers.clone(),
+                job_event: None,
+                child_wait: WaitQueue::new(),
+                cpu_time: Duration::ZERO,
+                children_cpu_time: Duration::ZERO,
                 fd_table: parent_inner.fd_table.exec_clone(), // 复制 fd table
                 cwd: parent_inner.cwd.clone(),                // 继承 cwd
+                rlimits: parent_inner.rlimits,
+                cred: parent_inner.cred.clone(),
+                umask: parent_inner.umask,
                 threads: BTreeMap::new(),
-                pgid: parent_inner.pgid, // todo: fork暂时的继承了分组
+                pgroup: parent_inner.pgroup.clone(),
             }),
         });
         // 子进程挂载到父进程下
         parent_inner.children.push(child.clone());
+        parent_inner.pgroup.add(&child);
 
         /*--------线程的复制与加入内核线程-------*/
         // 复制线程
@@ -268,11 +514,15 @@ impl Process {
 
 pub fn new_initproc(elf_data: &[u8]) -> Arc<Thread> {
     // memory_set with elf program headers/trampoline/trap context/user stack
-    let (memory_set, user_sp, entry_point, _) = MemorySet::from_elf(elf_data);
+    let (memory_set, user_sp, entry_point, _) =
+        MemorySet::from_elf(elf_data, None, USER_STACK_SIZE);
     // println!("  entry_point: {}", entry_point);
     let kernel_satp = KERNEL_SPACE.lock().token();
     // alloc a pid and a kernel stack in kernel space
     let pid_handle = Arc::new(id_alloc());
+    // init是第一个会话和进程组的首进程, 控制终端是串口
+    let pgroup = ProcessGroup::new(pid_handle.0, Session::new(pid_handle.0));
+    set_console_session(pgroup.sid(), pgroup.pgid());
 
     let process = Arc::new(Process {
         pid: pid_handle.clone(),
@@ -281,7 +531,15 @@ pub fn new_initproc(elf_data: &[u8]) -> Arc<Thread> {
             memory_set,
             parent: None,
             children: Vec::new(),
-            exit_code: 0,
+            exit_status: 0,
+            group_exit_status: None,
+            stopped: false,
+            pending_sigs: SigPending::new(),
+            sig_handlers: SigHandlers::new(),
+            job_event: None,
+            child_wait: WaitQueue::new(),
+            cpu_time: Duration::ZERO,
+            children_cpu_time: Duration::ZERO,
             fd_table: FdTable::new(vec![
                 // 0 -> stdin
                 // Some(FdInfo{file: Arc::new(Stdin), flags: OpenFlags::empty()}),
@@ -309,8 +567,11 @@ pub fn new_initproc(elf_data: &[u8]) -> Arc<Thread> {
                 Some(FdInfo::default_flags(Arc::new(TtyFile::new(false, true)))),
             ]),
             cwd: Path::root(),
+            rlimits: RLimits::new(),
+            cred: Credentials::root(),
+            umask: DEFAULT_UMASK,
             threads: Default::default(),
-            pgid: 0,
+            pgroup: pgroup.clone(),
         }),
     });
 
@@ -331,7 +592,7 @@ pub fn new_initproc(elf_data: &[u8]) -> Arc<Thread> {
     PROCESS_MANAGER
         .lock()
         .insert(process.getpid(), Arc::downgrade(&process));
-    // todo: group manager
+    pgroup.add(&process);
 
     log::info!(
         "[new_initproc] create a new process, pid {}",
@@ -340,19 +601,49 @@ pub fn new_initproc(elf_data: &[u8]) -> Arc<Thread> {
     thread
 }
 
+/// 子进程被停止或继续, 由`wait`的`WUNTRACED`/`WCONTINUED`报告
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum JobEvent {
+    /// 被这个信号停止
+    Stopped(usize),
+    Continued,
+}
+
 pub struct ProcessInner {
     pub parent: Option<Weak<Process>>,
 
     pub memory_set: MemorySet,
     pub children: Vec<Arc<Process>>,
-    /// Exit code of the current process Note that we may need to put this member in every thread
-    pub exit_code: i32,
+    /// `wait`看到的状态: 正常退出时是`(exit_code & 0xff) << 8`, 被信号终止时是信号值
+    pub exit_status: i32,
+    /// 线程组正在被结束, 最后一个线程退出时以此为准
+    pub group_exit_status: Option<i32>,
+    /// 被停止信号停止, 等待SIGCONT
+    pub stopped: bool,
+    /// 发给整个进程的待处理信号, 子进程不继承, exec后保留
+    pub pending_sigs: SigPending,
+    /// 信号处理函数, 所有线程共享, fork时复制
+    pub sig_handlers: SigHandlers,
+    /// 还没有被父进程`wait`取走的停止/继续事件
+    pub job_event: Option<JobEvent>,
+    /// 在`wait`中等待子进程状态变化的线程
+    pub child_wait: WaitQueue,
+    /// 已退出线程的CPU时间
+    pub cpu_time: Duration,
+    /// 已被`wait`回收的子进程(包括它们回收的子进程)的CPU时间
+    pub children_cpu_time: Duration,
     pub fd_table: FdTable,
     pub cwd: Path,
+    /// 资源限制, `RLIMIT_NOFILE`同时记录在`fd_table`中
+    pub rlimits: RLimits,
+    /// 用户和组身份
+    pub cred: Credentials,
+    /// 新建文件时从`mode`中去掉的权限, fork时继承, exec后保留
+    pub umask: u16,
 
     pub threads: BTreeMap<usize, Weak<Thread>>,
-    /// gid, the process group id
-    pub pgid: usize,
+    /// 所属的进程组, 也决定了所属的会话
+    pub pgroup: Arc<ProcessGroup>,
 }
 
 impl ProcessInner {
@@ -373,6 +664,14 @@ pub struct Thread {
     pub process: Arc<Process>,
     /// mutable
     pub is_terminated: AtomicBool,
+    /// 调度参数, 同时作为executor中任务的metadata
+    pub sched: Arc<SchedEntity>,
+    /// 可被信号打断的等待注册的waker, 收到信号时唤醒
+    interruptible_waker: SpinNoIrqLock<Option<Waker>>,
+    /// poll signalfd的等待者, 有新的待处理信号时唤醒, 不管是否被屏蔽
+    signal_wait: SpinNoIrqLock<WaitQueue>,
+    /// 信号屏蔽字和只发给本线程的待处理信号, 其他线程发信号时也会访问
+    pub sig_set: SpinNoIrqLock<SigSet>,
     ///
     pub inner: UnsafeCell<ThreadInner>,
 }
@@ -398,15 +697,86 @@ impl Thread {
         self.tid.0
     }
 
+    /// 内核只发给这个线程的信号, 例如线程组退出时的SIGKILL
     pub fn send_signal(&self, signo: usize) {
-        self.get_inner_mut()
-            .sig_set
-            .pending_sigs
-            .insert(SigBitmap::from_bits(1 << (signo - 1)).unwrap())
+        self.send_signal_info(SigInfo::new(signo, SI_KERNEL));
+    }
+
+    /// 只发给这个线程的信号, 例如`tgkill`和硬件异常
+    pub fn send_signal_info(&self, info: SigInfo) {
+        let signo = info.si_signo as usize;
+        self.process
+            .prepare_signal(signo, &self.process.live_threads());
+        let sig = SigBitmap::from_signo(signo);
+        let ignored = self.process.sig_action(signo).is_ignored(signo);
+        {
+            let mut sig_set = self.sig_set.lock();
+            // 被忽略且未被阻塞的信号直接丢弃
+            if ignored && !sig_set.thread_mask.contains(sig) {
+                return;
+            }
+            sig_set.pending_sigs.insert(info);
+        }
+        self.wake_interruptible();
+        self.wake_signal_waiters();
+    }
+
+    fn wake_signal_waiters(&self) {
+        let wakers = self.signal_wait.lock().take();
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    /// 发给本线程和发给整个进程的待处理信号
+    pub fn pending_signals(&self) -> SigBitmap {
+        let pending = self.sig_set.lock().pending_sigs.set;
+        pending | self.process.inner_lock().pending_sigs.set
+    }
+
+    /// 取出`set`中编号最小的待处理信号和它的信息, 先取发给本线程的
+    pub fn dequeue_signal(&self, set: SigBitmap) -> Option<SigInfo> {
+        let info = self.sig_set.lock().pending_sigs.take_lowest(set);
+        info.or_else(|| self.process.inner_lock().pending_sigs.take_lowest(set))
+    }
+
+    /// 信号屏蔽字
+    pub fn sig_mask(&self) -> SigBitmap {
+        self.sig_set.lock().thread_mask
+    }
+
+    /// 替换信号屏蔽字, 返回原来的
+    pub fn set_sig_mask(&self, mask: SigBitmap) -> SigBitmap {
+        core::mem::replace(&mut self.sig_set.lock().thread_mask, mask)
+    }
+
+    /// 等待新的待处理信号
+    pub fn register_signal_waiter(&self, waker: &Waker) {
+        self.signal_wait.lock().register(waker);
+    }
+
+    /// 唤醒可被信号打断的等待
+    pub fn wake_interruptible(&self) {
+        if let Some(waker) = self.interruptible_waker.lock().take() {
+            waker.wake();
+        }
+    }
+
+    /// 进入可被信号打断的等待
+    pub fn set_interruptible_waker(&self, waker: Waker) {
+        *self.interruptible_waker.lock() = Some(waker);
+    }
+
+    /// 结束可被信号打断的等待
+    pub fn clear_interruptible_waker(&self) {
+        self.interruptible_waker.lock().take();
     }
 
     pub fn have_signals(&self) -> bool {
-        !self.get_inner_mut().sig_set.pending_sigs.is_empty()
+        !self.pending_signals().is_empty()
+    }
+
+    /// 是否有未被屏蔽的待处理信号, 可被打断的等待据此返回`EINTR`
+    pub fn have_unmasked_signals(&self) -> bool {
+        !(self.pending_signals() - self.sig_mask()).is_empty()
     }
 
     /// Get the mutable ref of trap context
@@ -423,15 +793,15 @@ impl Thread {
     ) -> Self {
         // todo: main thread 这里有一个信号的操作
         let sig_set;
-        let sig_handlers;
+        let sched;
         match main_thread {
             Some(main_thread) => {
-                sig_set = SigSet::from_existed_user(&main_thread.get_inner_mut().sig_set);
-                sig_handlers = main_thread.get_inner_mut().sig_handlers.clone();
+                sig_set = SigSet::from_existed_user(&main_thread.sig_set.lock());
+                sched = main_thread.sched.fork();
             }
             None => {
                 sig_set = SigSet::new();
-                sig_handlers = SigHandlers::new();
+                sched = SchedEntity::default();
             }
         };
 
@@ -439,15 +809,19 @@ impl Thread {
             tid: tid.clone(),
             is_terminated: Default::default(),
             process: process.clone(),
+            sched: Arc::new(sched),
+            interruptible_waker: SpinNoIrqLock::new(None),
+            signal_wait: SpinNoIrqLock::new(WaitQueue::new()),
+            sig_set: SpinNoIrqLock::new(sig_set),
             // user_specified_stack,
             inner: UnsafeCell::new(ThreadInner {
                 trap_context,
                 ustack_top,
                 tid_addr: TidAddress::new(),
-                sig_set,
-                sig_handlers,
-                signal_context: None,
-                handling_signo: 0,
+                robust_list: None,
+                sig_altstack: SignalStack::disabled(),
+                interrupted_syscall: None,
+                saved_sigmask: None,
             }),
         };
 
@@ -471,6 +845,10 @@ impl Thread {
             tid: pid.clone(),
             is_terminated: Default::default(),
             process: new_process.clone(),
+            sched: Arc::new(another.sched.fork()),
+            interruptible_waker: SpinNoIrqLock::new(None),
+            signal_wait: SpinNoIrqLock::new(WaitQueue::new()),
+            sig_set: SpinNoIrqLock::new(SigSet::from_existed_user(&another.sig_set.lock())),
             inner: UnsafeCell::new(ThreadInner {
                 trap_context: {
                     let mut trap_context = unsafe { (*another.inner.get()).trap_context };
@@ -481,10 +859,10 @@ impl Thread {
                 },
                 ustack_top: unsafe { (*another.inner.get()).ustack_top },
                 tid_addr: TidAddress::new(),
-                sig_set: SigSet::from_existed_user(&another.get_inner_mut().sig_set),
-                sig_handlers: another.get_inner_mut().sig_handlers.clone(),
-                signal_context: None,
-                handling_signo: 0,
+                robust_list: None,
+                sig_altstack: another.get_inner_mut().sig_altstack,
+                interrupted_syscall: None,
+                saved_sigmask: None,
             }),
         }
     }
@@ -494,13 +872,17 @@ pub struct ThreadInner {
     pub trap_context: TrapContext,
     pub ustack_top: usize,
 
-    pub signal_context: Option<TrapContext>,
-    pub handling_signo: usize,
-    pub sig_set: SigSet,
-    pub sig_handlers: SigHandlers,
+    /// 由`sigaltstack`设置的备用信号栈
+    pub sig_altstack: SignalStack,
+    /// 上一次系统调用被信号打断, 处理信号时决定是否重新执行
+    pub interrupted_syscall: Option<InterruptedSyscall>,
+    /// `sigsuspend`等临时替换了信号屏蔽字, 处理完信号后恢复
+    pub saved_sigmask: Option<SigBitmap>,
 
     /// Tid address, which may be modified by `set_tid_address` syscall
     pub tid_addr: TidAddress,
+    /// 由`set_robust_list`设置, 线程退出时释放上面的锁
+    pub robust_list: Option<FutexRobustList>,
 }
 
 impl ThreadInner {
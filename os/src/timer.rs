@@ -1,13 +1,21 @@
 //! RISC-V timer-related functionality
 
-use crate::config::{SyscallRet, CLOCK_FREQ};
+use crate::config::{SysResult, SyscallRet, CLOCK_FREQ, MAX_HART_NUM};
 use crate::ctypes::NSEC_PER_SEC;
+use crate::fs::tty::check_console_input;
+use crate::mm::user_check::UserCheck;
+use crate::mutex::SpinNoIrqLock;
 use crate::sbi::set_timer;
+use crate::task::processor::get_local_hart;
+use crate::utils::SyscallErr;
+use alloc::collections::BinaryHeap;
+use core::cmp::{Ordering, Reverse};
 use core::future::Future;
+use core::mem::size_of;
 use core::pin::Pin;
-use core::task::{Context, Poll};
+use core::task::{Context, Poll, Waker};
 use core::time::Duration;
-use riscv::register::time;
+use riscv::register::{sip, time};
 
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
@@ -43,6 +51,19 @@ impl From<Duration> for TimeSpec {
     }
 }
 
+/// 读取用户传入的`struct timespec`超时, 空指针表示永久等待
+pub fn read_timeout(timeout_ptr: usize) -> SysResult<Option<Duration>> {
+    if timeout_ptr == 0 {
+        return Ok(None);
+    }
+    UserCheck::new().check_readable_pages(timeout_ptr as *const u8, size_of::<TimeSpec>())?;
+    let timeout = unsafe { *(timeout_ptr as *const TimeSpec) };
+    if timeout.nsec >= 1_000_000_000 {
+        return Err(SyscallErr::EINVAL.into());
+    }
+    Ok(Some(Duration::new(timeout.sec as u64, timeout.nsec as u32)))
+}
+
 /// Return the current clock time in `core::time::Duration`
 pub fn current_time_duration() -> Duration {
     let time = get_time_ms();
@@ -68,14 +89,102 @@ pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
 
+/// 定时器, 到期时唤醒对应的任务
+struct Timer {
+    expire: Duration,
+    waker: Waker,
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.expire == other.expire
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.expire.cmp(&other.expire)
+    }
+}
+
+type TimerQueue = SpinNoIrqLock<Option<BinaryHeap<Reverse<Timer>>>>;
+
+/// 每个hart一个最小堆, 由该hart的时钟中断和executor检查
+static TIMER_QUEUES: [TimerQueue; MAX_HART_NUM] = {
+    const EMPTY: TimerQueue = SpinNoIrqLock::new(None);
+    [EMPTY; MAX_HART_NUM]
+};
+
+/// 注册一个定时器, 在`expire`之后唤醒`waker`
+///
+/// 定时器无法取消, 任务提前结束等待时会多收到一次唤醒, 重新poll即可
+pub fn add_timer(expire: Duration, waker: Waker) {
+    TIMER_QUEUES[get_local_hart().hart_id]
+        .lock()
+        .get_or_insert_with(BinaryHeap::new)
+        .push(Reverse(Timer { expire, waker }));
+}
+
+/// 唤醒本hart所有到期的定时器
+pub fn check_timers() {
+    let now = current_time_duration();
+    let mut expired = alloc::vec::Vec::new();
+    {
+        let mut timers = TIMER_QUEUES[get_local_hart().hart_id].lock();
+        let timers = match timers.as_mut() {
+            Some(timers) => timers,
+            None => return,
+        };
+        while let Some(Reverse(timer)) = timers.peek() {
+            if timer.expire > now {
+                break;
+            }
+            expired.push(timers.pop().unwrap().0.waker);
+        }
+    }
+    // 释放锁之后再唤醒, 唤醒会获取任务队列的锁
+    for waker in expired {
+        waker.wake();
+    }
+}
+
+/// 时钟中断: 设置下一次中断并处理到期的定时器, 顺便检查没有中断的串口输入
+pub fn handle_timer_tick() {
+    set_next_trigger();
+    check_timers();
+    check_console_input();
+}
+
+/// 内核里不开中断, 空闲时`wfi`醒来后时钟中断仍然pending, 需要手动处理
+pub fn handle_pending_timer_tick() {
+    if sip::read().stimer() {
+        handle_timer_tick();
+    }
+}
+
+/// 在`expired_time`之后完成, 等待期间不占用executor
 pub struct TimeoutFuture {
     expired_time: Duration,
+    registered: bool,
 }
 
 impl TimeoutFuture {
     pub fn new(duration: Duration) -> Self {
+        Self::until(current_time_duration() + duration)
+    }
+    /// 绝对时间
+    pub fn until(expired_time: Duration) -> Self {
         Self {
-            expired_time: Duration::from_millis(get_time_ms() as u64) + duration,
+            expired_time,
+            registered: false,
         }
     }
 }
@@ -84,11 +193,49 @@ impl Future for TimeoutFuture {
     type Output = SyscallRet;
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = unsafe { self.get_unchecked_mut() };
-        if get_time_ms() >= this.expired_time.as_millis() as usize {
+        if current_time_duration() >= this.expired_time {
             Poll::Ready(Ok(0))
         } else {
-            cx.waker().wake_by_ref();
+            if !this.registered {
+                add_timer(this.expired_time, cx.waker().clone());
+                this.registered = true;
+            }
             Poll::Pending
         }
     }
 }
+
+/// 给`future`加上截止时间, 超时返回`None`
+///
+/// `future`自己负责在等待的事件发生时唤醒任务
+pub struct TimeLimitedFuture<F: Future> {
+    future: F,
+    timeout: Option<TimeoutFuture>,
+}
+
+impl<F: Future> TimeLimitedFuture<F> {
+    /// `deadline`为`None`时永不超时
+    pub fn new(future: F, deadline: Option<Duration>) -> Self {
+        Self {
+            future,
+            timeout: deadline.map(TimeoutFuture::until),
+        }
+    }
+}
+
+impl<F: Future> Future for TimeLimitedFuture<F> {
+    type Output = Option<F::Output>;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if let Poll::Ready(ret) = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx) {
+            return Poll::Ready(Some(ret));
+        }
+        match this.timeout.as_mut() {
+            Some(timeout) => match Pin::new(timeout).poll(cx) {
+                Poll::Ready(_) => Poll::Ready(None),
+                Poll::Pending => Poll::Pending,
+            },
+            None => Poll::Pending,
+        }
+    }
+}
@@ -12,14 +12,19 @@
 mod context;
 mod irq;
 
-use crate::mm::{handle_recoverable_page_fault, PageTable, VirtAddr};
-use crate::signal::handle_signals;
-use crate::syscall::syscall;
-use crate::task::processor::current_thread;
-use crate::task::{current_trap_cx, exit_current, yield_task};
-use crate::timer::set_next_trigger;
+use crate::executor::need_resched;
+use crate::mm::{handle_recoverable_page_fault, PageTable, VPNRange, VirtAddr, VirtPageNum};
+use crate::signal::{
+    force_sig_fault, InterruptedSyscall, BUS_ADRALN, BUS_ADRERR, ILL_ILLOPC, ILL_ILLTRP,
+    SEGV_ACCERR, SEGV_MAPERR, SIGBUS, SIGILL, SIGSEGV, SIGTRAP, TRAP_BRKPT,
+};
+use crate::syscall::{restart_after_handler, syscall};
+use crate::task::processor::{current_process, current_thread, current_thread_uncheck};
+use crate::task::{current_trap_cx, yield_task};
+use crate::timer::handle_timer_tick;
+use crate::utils::SyscallErr;
 use core::arch::global_asm;
-use log::error;
+use log::{error, warn};
 use riscv::register::satp;
 use riscv::register::{
     mtvec::TrapMode,
@@ -70,63 +75,99 @@ pub async fn trap_handler() {
             let mut cx = current_trap_cx();
             cx.set_entry_point(cx.sepc + 4);
             // get system call return value
-            let result = syscall(
-                cx.x[17],
-                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
-            )
-            .await;
+            let syscall_id = cx.x[17];
+            let args = [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]];
+            let result = syscall(syscall_id, args).await;
+            if result == Err(SyscallErr::EINTR.into()) {
+                // 处理信号时决定是否重新执行
+                current_thread()
+                    .unwrap()
+                    .get_inner_mut()
+                    .interrupted_syscall = Some(InterruptedSyscall {
+                    a0: args[0],
+                    restart_after_handler: restart_after_handler(syscall_id),
+                });
+            }
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result.unwrap_or_else(|err_code| (-(err_code as isize)) as usize);
         }
         Trap::Exception(Exception::InstructionFault)
-        | Trap::Exception(Exception::InstructionPageFault) => {
-            let satp = satp::read().bits();
-            let page_table = PageTable::from_token(satp);
-            //page_table.dump_all();
-            error!(
-                "[kernel] {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
-                scause.cause(),
-                stval,
-                current_trap_cx().sepc,
-            );
-            let pte = page_table.find_pte(VirtAddr::from(stval).floor()).unwrap();
-            error!("pte: {:?}", pte);
-            // page fault exit code
-            exit_current(-2);
-        }
-        Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault)
+        | Trap::Exception(Exception::LoadPageFault)
         | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::StoreFault) => {
             // recoverable page fault:
             // 1. fork COW area
-            // 2. lazy allocation
+            // 2. lazy allocation, text of file backed areas is loaded on first fetch
+            // 3. user stack growth
             // stval is the faulting virtual address, current_trap_cx().sepc is the faulting instruction
             let vpn = VirtAddr::from(stval).floor();
             let satp = satp::read().bits();
             let page_table = PageTable::from_token(satp);
-            if handle_recoverable_page_fault(&page_table, vpn).is_err() {
-                error!(
-                    "[kernel] unrecoverable {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, kernel killed it.",
+            if let Err(err) = handle_recoverable_page_fault(&page_table, vpn, scause.cause()) {
+                // 文件页读不出来时是SIGBUS
+                let (sig, code) = match err {
+                    SyscallErr::EIO => (SIGBUS, BUS_ADRERR),
+                    _ => (SIGSEGV, segv_code(vpn)),
+                };
+                warn!(
+                    "[kernel] unrecoverable {:?} in application, bad addr = {:#x}, bad instruction = {:#x}, send signal {}.",
                     scause.cause(),
                     stval,
                     current_trap_cx().sepc,
+                    sig,
                 );
-                page_table.dump_all();
-                // page fault exit code
-                exit_current(-2);
+                force_sig_fault(sig, code, stval);
             }
             // we should jump back to the faulting instruction after handling the page fault
         }
+        Trap::Exception(Exception::InstructionMisaligned)
+        | Trap::Exception(Exception::StoreMisaligned) => {
+            warn!(
+                "[kernel] {:?} in application, addr = {:#x}",
+                scause.cause(),
+                stval
+            );
+            force_sig_fault(SIGBUS, BUS_ADRALN, stval);
+        }
+        // the riscv crate has no variant for a misaligned load
+        Trap::Exception(Exception::Unknown) if scause.code() == LOAD_ADDRESS_MISALIGNED => {
+            warn!(
+                "[kernel] LoadMisaligned in application, addr = {:#x}",
+                stval
+            );
+            force_sig_fault(SIGBUS, BUS_ADRALN, stval);
+        }
         Trap::Exception(Exception::IllegalInstruction) => {
-            error!("[kernel] IllegalInstruction in application, kernel killed it.");
-            // illegal instruction exit code
-            exit_current(-3);
+            let sepc = current_trap_cx().sepc;
+            warn!(
+                "[kernel] IllegalInstruction in application, pc = {:#x}",
+                sepc
+            );
+            force_sig_fault(SIGILL, ILL_ILLOPC, sepc);
+        }
+        Trap::Exception(Exception::Breakpoint) => {
+            force_sig_fault(SIGTRAP, TRAP_BRKPT, current_trap_cx().sepc);
+        }
+        Trap::Exception(Exception::Unknown) => {
+            let sepc = current_trap_cx().sepc;
+            warn!(
+                "[kernel] unknown exception {} in application, pc = {:#x}",
+                scause.code(),
+                sepc
+            );
+            force_sig_fault(SIGILL, ILL_ILLTRP, sepc);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
-            set_next_trigger();
-            yield_task().await;
+            handle_timer_tick();
+            // RLIMIT_CPU
+            current_thread_uncheck().process.check_cpu_limit();
+            // 实时任务不一定在每个时钟周期让出
+            if need_resched(&current_thread_uncheck().sched) {
+                yield_task().await;
+            }
         }
         _ => {
             panic!(
@@ -138,6 +179,20 @@ pub async fn trap_handler() {
     }
 }
 
+/// scause of a misaligned load
+const LOAD_ADDRESS_MISALIGNED: usize = 4;
+
+/// `si_code` of SIGSEGV at `vpn`: whether the address is mapped at all,
+/// an address beyond the stack guard page is not
+fn segv_code(vpn: VirtPageNum) -> i32 {
+    let range = VPNRange::new(vpn, VirtPageNum(vpn.0 + 1));
+    if current_process().inner_lock().memory_set.is_unmapped(range) {
+        SEGV_MAPERR
+    } else {
+        SEGV_ACCERR
+    }
+}
+
 #[no_mangle]
 /// set the new addr of __restore asm function in TRAMPOLINE page,
 /// set the reg a0 = trap_cx_ptr, reg a1 = phy addr of usr page table,
@@ -147,11 +202,6 @@ pub fn trap_return() {
     // 关闭中断
     //close_interrupt();
     set_user_trap_entry();
-    // todo: 信号嵌套
-    // 现在不支持信号嵌套
-    if current_thread().unwrap().get_inner_mut().handling_signo == 0 {
-        handle_signals();
-    }
     let cx = current_trap_cx();
     extern "C" {
         #[allow(improper_ctypes)]
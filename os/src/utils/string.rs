@@ -1,11 +1,17 @@
 use alloc::string::String;
 
+use crate::{config::PAGE_SIZE, mm::user_check::UserCheck};
+
 /// Convert C-style string(end with '\0') to rust string
 pub fn c_str_to_string(ptr: *const u8) -> String {
     let mut ptr = ptr as usize;
     let mut ret = String::new();
     // trace!("[c_str_to_string] convert ptr at {:#x} to string", ptr);
     loop {
+        // the string may live in a page not touched by the user yet
+        if ret.is_empty() || ptr % PAGE_SIZE == 0 {
+            let _ = UserCheck::new().check_readable_pages(ptr as *const u8, 1);
+        }
         let ch: u8 = unsafe { *(ptr as *const u8) };
         if ch == 0 {
             break;